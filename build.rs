@@ -23,7 +23,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "proto/service_communication.proto",
                 "proto/rpc_search.proto",
                 "proto/service_status.proto",
-                "proto/service_chat.proto"
+                "proto/service_chat.proto",
+                "proto/service_notification.proto",
+                "proto/service_prekey.proto",
+                "proto/service_keys.proto",
+                "proto/service_device.proto",
+                "proto/service_call.proto",
+                "proto/service_voice_room.proto"
             ],
             &["proto/"],
         )?;
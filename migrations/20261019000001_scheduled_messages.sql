@@ -0,0 +1,21 @@
+-- Отложенные сообщения, доставляемые планировщиком сервера
+CREATE TABLE IF NOT EXISTS scheduled_messages (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    chat_id UUID NOT NULL REFERENCES direct_chats(id) ON DELETE CASCADE,
+    sender_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    encrypted_content TEXT NOT NULL,
+    deliver_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    -- PENDING | SENT | CANCELLED | FAILED
+    status TEXT NOT NULL DEFAULT 'PENDING',
+    message_id UUID REFERENCES messages(id) ON DELETE SET NULL,
+    failure_reason TEXT,
+    processed_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS scheduled_messages_due_idx
+    ON scheduled_messages (deliver_at)
+    WHERE status = 'PENDING';
+
+CREATE INDEX IF NOT EXISTS scheduled_messages_sender_idx
+    ON scheduled_messages (sender_id, status);
@@ -0,0 +1,7 @@
+-- Происхождение пересланных сообщений.
+-- Ссылки хранятся всегда, forward_hidden лишь скрывает их от получателей.
+ALTER TABLE messages
+    ADD COLUMN IF NOT EXISTS forwarded_from_message_id UUID REFERENCES messages(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS forwarded_from_chat_id UUID REFERENCES direct_chats(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS forwarded_from_sender_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS forward_hidden BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,7 @@
+-- Персональные настройки участника чата
+ALTER TABLE direct_chats_members
+    ADD COLUMN IF NOT EXISTS muted_until TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS is_archived BOOLEAN NOT NULL DEFAULT false,
+    -- ALL | MENTIONS | NONE
+    ADD COLUMN IF NOT EXISTS notification_level TEXT NOT NULL DEFAULT 'ALL',
+    ADD COLUMN IF NOT EXISTS nickname TEXT;
@@ -0,0 +1,9 @@
+-- Ключ чата, обёрнутый публичным ключом каждого участника
+ALTER TABLE direct_chats_keys
+    ADD COLUMN IF NOT EXISTS key_version INTEGER NOT NULL DEFAULT 1,
+    ADD COLUMN IF NOT EXISTS key_fingerprint TEXT,
+    ADD COLUMN IF NOT EXISTS algorithm TEXT NOT NULL DEFAULT 'RSA-OAEP-SHA256',
+    ADD COLUMN IF NOT EXISTS created_at TIMESTAMP NOT NULL DEFAULT NOW();
+
+CREATE UNIQUE INDEX IF NOT EXISTS direct_chats_keys_member_version_idx
+    ON direct_chats_keys (chat_id, user_id, key_version);
@@ -0,0 +1,40 @@
+-- Наборы предключей X3DH для асинхронной установки E2E-сессий
+CREATE TABLE IF NOT EXISTS prekey_identities (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id TEXT NOT NULL,
+    -- Ed25519, 32 байта
+    identity_key BYTEA NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, device_id)
+);
+
+CREATE TABLE IF NOT EXISTS signed_prekeys (
+    user_id UUID NOT NULL,
+    device_id TEXT NOT NULL,
+    key_id INTEGER NOT NULL,
+    -- X25519, 32 байта
+    public_key BYTEA NOT NULL,
+    -- Ed25519-подпись identity-ключом над public_key
+    signature BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, device_id),
+    FOREIGN KEY (user_id, device_id) REFERENCES prekey_identities (user_id, device_id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS one_time_prekeys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    device_id TEXT NOT NULL,
+    key_id INTEGER NOT NULL,
+    public_key BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (user_id, device_id, key_id),
+    FOREIGN KEY (user_id, device_id) REFERENCES prekey_identities (user_id, device_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS one_time_prekeys_device_idx
+    ON one_time_prekeys (user_id, device_id, created_at);
+
+-- Уведомления, относящиеся к конкретному устройству (PREKEYS_LOW)
+ALTER TABLE notifications
+    ADD COLUMN IF NOT EXISTS device_id TEXT;
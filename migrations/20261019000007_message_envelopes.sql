@@ -0,0 +1,25 @@
+-- Конверты Double Ratchet: сообщение хранит только метаданные,
+-- шифртексты лежат в очереди каждого устройства получателя до подтверждения
+ALTER TABLE messages
+    ADD COLUMN IF NOT EXISTS envelope_version INTEGER,
+    ADD COLUMN IF NOT EXISTS sender_device_id TEXT;
+
+CREATE TABLE IF NOT EXISTS message_device_deliveries (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    recipient_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    recipient_device_id TEXT NOT NULL,
+    ratchet_public_key BYTEA NOT NULL,
+    previous_chain_length INTEGER NOT NULL,
+    message_number INTEGER NOT NULL,
+    ciphertext BYTEA NOT NULL,
+    -- Заполняются только для первого сообщения сессии (X3DH)
+    session_ephemeral_key BYTEA,
+    session_signed_prekey_id INTEGER,
+    session_one_time_prekey_id INTEGER,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (message_id, recipient_id, recipient_device_id)
+);
+
+CREATE INDEX IF NOT EXISTS message_device_deliveries_device_idx
+    ON message_device_deliveries (recipient_id, recipient_device_id, created_at);
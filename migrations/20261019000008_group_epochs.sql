@@ -0,0 +1,36 @@
+-- Служба доставки для групп в стиле MLS: сервер хранит и упорядочивает
+-- proposals/commits внутри эпохи и раздаёт welcome-сообщения новым участникам
+ALTER TABLE direct_chats
+    ADD COLUMN IF NOT EXISTS epoch BIGINT NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS group_handshake_messages (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    chat_id UUID NOT NULL REFERENCES direct_chats(id) ON DELETE CASCADE,
+    -- Эпоха, в которой создано сообщение; commit переводит группу в epoch + 1
+    epoch BIGINT NOT NULL,
+    -- Сквозной порядок внутри группы
+    sequence BIGINT NOT NULL,
+    sender_id UUID NOT NULL REFERENCES users(id),
+    kind TEXT NOT NULL CHECK (kind IN ('PROPOSAL', 'COMMIT')),
+    payload BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (chat_id, sequence)
+);
+
+-- Не больше одного commit на эпоху
+CREATE UNIQUE INDEX IF NOT EXISTS group_handshake_messages_commit_idx
+    ON group_handshake_messages (chat_id, epoch) WHERE kind = 'COMMIT';
+
+CREATE TABLE IF NOT EXISTS group_welcomes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    chat_id UUID NOT NULL REFERENCES direct_chats(id) ON DELETE CASCADE,
+    -- Эпоха, в которую вступает получатель
+    epoch BIGINT NOT NULL,
+    recipient_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    sender_id UUID NOT NULL REFERENCES users(id),
+    payload BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS group_welcomes_recipient_idx
+    ON group_welcomes (recipient_id, created_at);
@@ -0,0 +1,11 @@
+-- Отзыв, ротация и предупреждения об истечении ключей пользователей
+ALTER TABLE user_keys
+    ADD COLUMN IF NOT EXISTS revoked_at TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS revocation_reason TEXT,
+    -- Отпечаток ключа, заменившего этот при ротации
+    ADD COLUMN IF NOT EXISTS replaced_by TEXT,
+    ADD COLUMN IF NOT EXISTS expiry_warned_at TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS user_keys_expiry_idx
+    ON user_keys (expires_at)
+    WHERE is_revoked = false AND expiry_warned_at IS NULL;
@@ -0,0 +1,4 @@
+-- Кто может найти владельца ключа по отпечатку
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS key_lookup_privacy TEXT NOT NULL DEFAULT 'FRIENDS'
+        CHECK (key_lookup_privacy IN ('FRIENDS', 'EVERYONE'));
@@ -0,0 +1,15 @@
+-- Подтверждённые вне канала ключи контактов (по одному отпечатку)
+CREATE TABLE IF NOT EXISTS key_verifications (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    contact_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    fingerprint TEXT NOT NULL,
+    verified_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, contact_id, fingerprint)
+);
+
+CREATE INDEX IF NOT EXISTS key_verifications_contact_idx
+    ON key_verifications (contact_id);
+
+-- Системные события в ленте чата (например, SAFETY_NUMBER_CHANGED); NULL у обычных сообщений
+ALTER TABLE messages
+    ADD COLUMN IF NOT EXISTS system_event TEXT;
@@ -0,0 +1,39 @@
+-- Журнал прозрачности ключей: каждая запись в user_keys добавляет лист в дерево Меркла (RFC 6962).
+-- Данные листа: "nesfinch-key-log:v1:<user_id>:<fingerprint>:<created_at, unix-секунды>"
+CREATE TABLE IF NOT EXISTS key_log_entries (
+    leaf_index BIGINT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    fingerprint TEXT NOT NULL UNIQUE,
+    leaf_data TEXT NOT NULL,
+    -- SHA-256(0x00 || leaf_data)
+    leaf_hash BYTEA NOT NULL,
+    logged_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS key_log_entries_user_idx
+    ON key_log_entries (user_id, leaf_index);
+
+-- Выданные подписанные вершины дерева
+CREATE TABLE IF NOT EXISTS key_log_tree_heads (
+    tree_size BIGINT PRIMARY KEY,
+    root_hash BYTEA NOT NULL,
+    signed_at TIMESTAMP NOT NULL,
+    signature BYTEA NOT NULL
+);
+
+-- Уже зарегистрированные ключи попадают в журнал в порядке создания
+INSERT INTO key_log_entries (leaf_index, user_id, fingerprint, leaf_data, leaf_hash, logged_at)
+SELECT
+    ROW_NUMBER() OVER (ORDER BY k.created_at, k.id) - 1,
+    k.user_id,
+    k.fingerprint,
+    k.leaf_data,
+    sha256('\x00'::bytea || convert_to(k.leaf_data, 'UTF8')),
+    NOW() AT TIME ZONE 'UTC'
+FROM (
+    SELECT id, user_id, fingerprint, created_at,
+           'nesfinch-key-log:v1:' || user_id || ':' || fingerprint || ':'
+               || FLOOR(EXTRACT(EPOCH FROM created_at))::BIGINT AS leaf_data
+    FROM user_keys
+) k
+WHERE NOT EXISTS (SELECT 1 FROM key_log_entries);
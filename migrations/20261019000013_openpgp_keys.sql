@@ -0,0 +1,5 @@
+-- Формат публичного ключа: PKCS#8 PEM (RSA) или ASCII-armored OpenPGP.
+-- Отпечаток OpenPGP - v4 (SHA-1, 40 hex) или v5 (SHA-256, 64 hex) в нижнем регистре.
+ALTER TABLE user_keys
+    ADD COLUMN IF NOT EXISTS key_format TEXT NOT NULL DEFAULT 'PKCS8'
+        CHECK (key_format IN ('PKCS8', 'OPENPGP'));
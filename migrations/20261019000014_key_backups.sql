@@ -0,0 +1,26 @@
+-- Резервные копии приватных ключей, зашифрованные на клиенте ключом из парольной фразы.
+-- Сервер хранит только шифртекст, параметры KDF и SHA-256 от ключа доступа (auth_key_hash).
+CREATE TABLE IF NOT EXISTS key_backups (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL CHECK (version > 0),
+    kdf_algorithm TEXT NOT NULL CHECK (kdf_algorithm IN ('ARGON2ID', 'PBKDF2-SHA256')),
+    kdf_salt BYTEA NOT NULL,
+    kdf_memory_kib INTEGER NOT NULL DEFAULT 0,
+    kdf_iterations INTEGER NOT NULL,
+    kdf_parallelism INTEGER NOT NULL DEFAULT 0,
+    nonce BYTEA NOT NULL,
+    ciphertext BYTEA NOT NULL,
+    auth_key_hash BYTEA NOT NULL,
+    -- Отпечаток ключа, копия которого сохранена (необязательно)
+    key_fingerprint TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, version)
+);
+
+-- Ограничение попыток получить копию с неверным ключом доступа
+CREATE TABLE IF NOT EXISTS key_backup_attempts (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMP,
+    locked_until TIMESTAMP
+);
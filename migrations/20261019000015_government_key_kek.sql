@@ -0,0 +1,3 @@
+-- Идентификатор KEK, которым зашифрован приватный ключ (base64 в private_key_encrypted).
+-- NULL - ключ, сохранённый старыми версиями открытым PEM; его шифрует KekRotationJob.
+ALTER TABLE government_keys ADD COLUMN IF NOT EXISTS private_key_kek_id TEXT;
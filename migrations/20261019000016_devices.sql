@@ -0,0 +1,62 @@
+-- Зарегистрированные устройства пользователей
+CREATE TABLE IF NOT EXISTS devices (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    platform TEXT NOT NULL,
+    -- Ed25519, 32 байта; им подписываются предключи устройства
+    identity_key BYTEA NOT NULL,
+    -- X25519, 32 байта, и его подпись identity-ключом; NULL у устройств,
+    -- перенесённых из prekey_identities
+    encryption_key BYTEA,
+    encryption_key_signature BYTEA,
+    -- Устройство, подтвердившее привязку; NULL у первого устройства
+    approved_by_device_id TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, device_id)
+);
+
+-- Устройства, уже опубликовавшие предключи, считаются зарегистрированными
+INSERT INTO devices (user_id, device_id, name, platform, identity_key, created_at, last_seen_at)
+SELECT user_id, device_id, device_id, 'UNSPECIFIED', identity_key, updated_at, updated_at
+FROM prekey_identities
+ON CONFLICT (user_id, device_id) DO NOTHING;
+
+ALTER TABLE prekey_identities
+    DROP CONSTRAINT IF EXISTS prekey_identities_device_fkey;
+ALTER TABLE prekey_identities
+    ADD CONSTRAINT prekey_identities_device_fkey
+    FOREIGN KEY (user_id, device_id) REFERENCES devices (user_id, device_id) ON DELETE CASCADE;
+
+-- Ожидающие привязки: хранится только хеш кода
+CREATE TABLE IF NOT EXISTS device_pairings (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    platform TEXT NOT NULL,
+    identity_key BYTEA NOT NULL,
+    encryption_key BYTEA NOT NULL,
+    encryption_key_signature BYTEA NOT NULL,
+    code_hash BYTEA NOT NULL,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (user_id, device_id)
+);
+
+CREATE INDEX IF NOT EXISTS device_pairings_code_idx
+    ON device_pairings (user_id, code_hash);
+
+-- Ключи чатов, обёрнутые ключом шифрования каждого устройства участника
+CREATE TABLE IF NOT EXISTS device_chat_keys (
+    chat_id UUID NOT NULL REFERENCES direct_chats(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL,
+    device_id TEXT NOT NULL,
+    key_version INTEGER NOT NULL,
+    algorithm TEXT NOT NULL,
+    encrypted_key BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (chat_id, user_id, device_id, key_version),
+    FOREIGN KEY (user_id, device_id) REFERENCES devices (user_id, device_id) ON DELETE CASCADE
+);
@@ -0,0 +1,10 @@
+-- Постоянные голосовые комнаты групповых чатов; участники комнаты хранятся только в памяти сервера
+CREATE TABLE IF NOT EXISTS voice_rooms (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    chat_id UUID NOT NULL REFERENCES direct_chats(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    max_participants INTEGER NOT NULL,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (chat_id, name)
+);
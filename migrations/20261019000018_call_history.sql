@@ -0,0 +1,30 @@
+-- Завершённые звонки: личные (caller_id, callee_id) и сеансы голосовых комнат (room_id,
+-- caller_id - вошедший первым). Для пропущенного звонка message_id - системное событие в чате.
+CREATE TABLE IF NOT EXISTS call_records (
+    id UUID PRIMARY KEY,
+    chat_id UUID NOT NULL REFERENCES direct_chats(id) ON DELETE CASCADE,
+    caller_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    callee_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    -- Без внешнего ключа: сеанс удалённой комнаты записывается уже после удаления
+    room_id UUID,
+    started_at TIMESTAMP NOT NULL,
+    answered_at TIMESTAMP,
+    ended_at TIMESTAMP NOT NULL,
+    -- COMPLETED, MISSED, DECLINED, BUSY, UNAVAILABLE
+    outcome TEXT NOT NULL,
+    end_reason TEXT NOT NULL,
+    message_id UUID REFERENCES messages(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS call_records_caller_idx ON call_records (caller_id, started_at DESC);
+CREATE INDEX IF NOT EXISTS call_records_callee_idx ON call_records (callee_id, started_at DESC);
+
+-- Участие в сеансах голосовых комнат; повторный вход - отдельная строка
+CREATE TABLE IF NOT EXISTS call_participants (
+    call_id UUID NOT NULL REFERENCES call_records(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    joined_at TIMESTAMP NOT NULL,
+    left_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS call_participants_user_idx ON call_participants (user_id, call_id);
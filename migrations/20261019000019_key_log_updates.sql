@@ -0,0 +1,6 @@
+-- Обновления ключа через HKP тоже попадают в журнал, поэтому отпечаток может встречаться
+-- в нескольких листах; лист с обновлением содержит время обновления вместо времени создания
+ALTER TABLE key_log_entries DROP CONSTRAINT IF EXISTS key_log_entries_fingerprint_key;
+
+CREATE INDEX IF NOT EXISTS key_log_entries_fingerprint_idx
+    ON key_log_entries (fingerprint, leaf_index);
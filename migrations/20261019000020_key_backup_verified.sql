@@ -0,0 +1,3 @@
+-- Версия считается подтверждённой, когда её auth_key предъявлен при получении копии
+-- или при загрузке следующей версии; последняя подтверждённая версия не удаляется
+ALTER TABLE key_backups ADD COLUMN IF NOT EXISTS verified_at TIMESTAMP;
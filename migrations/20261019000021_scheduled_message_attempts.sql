@@ -0,0 +1,3 @@
+-- Число неудачных попыток доставки: временные ошибки откладывают сообщение, а не всю пачку
+ALTER TABLE scheduled_messages
+    ADD COLUMN IF NOT EXISTS attempts INT NOT NULL DEFAULT 0;
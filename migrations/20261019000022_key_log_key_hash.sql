@@ -0,0 +1,4 @@
+-- Листы v2 фиксируют SHA-256 самого публичного ключа: обновление ключа OpenPGP через HKP
+-- может заменить подключ шифрования, не меняя отпечатка. У листов v1 хеша нет.
+ALTER TABLE key_log_entries
+    ADD COLUMN IF NOT EXISTS key_hash BYTEA;
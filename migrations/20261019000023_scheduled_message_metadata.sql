@@ -0,0 +1,4 @@
+-- Упоминания и ответ отложенного сообщения, как у messages
+ALTER TABLE scheduled_messages
+    ADD COLUMN IF NOT EXISTS mentioned_user_ids UUID[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS reply_to_message_id UUID REFERENCES messages(id) ON DELETE SET NULL;
@@ -0,0 +1,4 @@
+-- Предупреждение о нехватке одноразовых предключей отправляется один раз;
+-- загрузка, восстановившая запас, сбрасывает отметку
+ALTER TABLE prekey_identities
+    ADD COLUMN IF NOT EXISTS low_warned_at TIMESTAMP;
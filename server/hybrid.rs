@@ -0,0 +1,335 @@
+// Гибридное шифрование с открытым ключом: случайный ключ данных AES-256-GCM
+// передаётся через RSA-OAEP-SHA256 или эфемерный X25519 + HKDF-SHA256.
+// AAD привязывает шифртекст к контексту (например, чату и сообщению), байт версии
+// входит в AAD, чтобы шифртекст нельзя было выдать за другой формат.
+//
+// Форматы:
+//   v1 (только чтение) - PKCS#1 v1.5 над самим сообщением, без заголовка, длина = модуль RSA
+//   v2 - 0x02 || длина обёртки (u16 BE) || RSA-OAEP(ключ данных) || nonce (12) || AES-GCM
+//   v3 - 0x03 || эфемерный X25519 (32) || nonce (12) || AES-GCM,
+//        ключ = HKDF-SHA256(shared, info = "nesfinch-hybrid:v3" || эфемерный ключ || ключ получателя)
+
+use std::fmt;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use rsa::traits::PublicKeyParts;
+use rsa::{Oaep, Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+use sha2::Sha256;
+use uuid::Uuid;
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+pub const VERSION_LEGACY_PKCS1: u8 = 1;
+pub const VERSION_RSA_OAEP: u8 = 2;
+pub const VERSION_X25519: u8 = 3;
+
+const X25519_INFO: &[u8] = b"nesfinch-hybrid:v3";
+const DATA_KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HybridError {
+    Malformed,
+    UnsupportedVersion(u8),
+    // Версия шифртекста не подходит к типу ключа
+    KeyMismatch,
+    InvalidKey,
+    Encryption,
+    Decryption,
+}
+
+impl fmt::Display for HybridError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HybridError::Malformed => write!(f, "Malformed ciphertext"),
+            HybridError::UnsupportedVersion(version) => write!(f, "Unsupported ciphertext version {}", version),
+            HybridError::KeyMismatch => write!(f, "Ciphertext was not encrypted for this key type"),
+            HybridError::InvalidKey => write!(f, "Invalid public key"),
+            HybridError::Encryption => write!(f, "Encryption failed"),
+            HybridError::Decryption => write!(f, "Decryption failed"),
+        }
+    }
+}
+
+impl std::error::Error for HybridError {}
+
+pub enum PublicKey<'a> {
+    Rsa(&'a RsaPublicKey),
+    X25519(&'a [u8; 32]),
+}
+
+pub enum PrivateKey<'a> {
+    Rsa(&'a RsaPrivateKey),
+    X25519(&'a StaticSecret),
+}
+
+// AAD зашифрованного сообщения чата
+pub fn message_aad(chat_id: Uuid, message_id: Uuid) -> Vec<u8> {
+    format!("nesfinch-message:v1:{}:{}", chat_id, message_id).into_bytes()
+}
+
+fn full_aad(version: u8, aad: &[u8]) -> Vec<u8> {
+    [&[version][..], aad].concat()
+}
+
+fn aead_seal(data_key: &[u8], version: u8, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HybridError> {
+    let mut nonce = [0u8; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+
+    let cipher = Aes256Gcm::new_from_slice(data_key).map_err(|_| HybridError::Encryption)?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &full_aad(version, aad) })
+        .map_err(|_| HybridError::Encryption)?;
+
+    Ok([&nonce[..], &ciphertext].concat())
+}
+
+// sealed: nonce || шифртекст с тегом
+fn aead_open(data_key: &[u8], version: u8, sealed: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, HybridError> {
+    if sealed.len() < NONCE_SIZE + TAG_SIZE {
+        return Err(HybridError::Malformed);
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+
+    let cipher = Aes256Gcm::new_from_slice(data_key).map_err(|_| HybridError::Decryption)?;
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &full_aad(version, aad) })
+        .map(Zeroizing::new)
+        .map_err(|_| HybridError::Decryption)
+}
+
+fn x25519_key(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> Result<Zeroizing<[u8; DATA_KEY_SIZE]>, HybridError> {
+    let info = [X25519_INFO, ephemeral, recipient].concat();
+    let mut key = Zeroizing::new([0u8; DATA_KEY_SIZE]);
+    Hkdf::<Sha256>::new(None, shared)
+        .expand(&info, key.as_mut())
+        .map_err(|_| HybridError::Encryption)?;
+    Ok(key)
+}
+
+// Новые шифртексты всегда в текущем формате для типа ключа (v2 или v3)
+pub fn seal(recipient: PublicKey<'_>, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, HybridError> {
+    match recipient {
+        PublicKey::Rsa(public_key) => {
+            let mut data_key = Zeroizing::new([0u8; DATA_KEY_SIZE]);
+            OsRng.fill_bytes(data_key.as_mut());
+
+            let wrapped_key = public_key
+                .encrypt(&mut OsRng, Oaep::new::<Sha256>(), data_key.as_ref())
+                .map_err(|_| HybridError::Encryption)?;
+            let wrapped_len = u16::try_from(wrapped_key.len()).map_err(|_| HybridError::InvalidKey)?;
+            let sealed = aead_seal(data_key.as_ref(), VERSION_RSA_OAEP, plaintext, aad)?;
+
+            Ok([&[VERSION_RSA_OAEP][..], &wrapped_len.to_be_bytes(), &wrapped_key, &sealed].concat())
+        }
+        PublicKey::X25519(recipient) => {
+            let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+            let ephemeral_public = X25519PublicKey::from(&ephemeral);
+            let shared = ephemeral.diffie_hellman(&X25519PublicKey::from(*recipient));
+            if !shared.was_contributory() {
+                return Err(HybridError::InvalidKey);
+            }
+
+            let data_key = x25519_key(shared.as_bytes(), ephemeral_public.as_bytes(), recipient)?;
+            let sealed = aead_seal(data_key.as_ref(), VERSION_X25519, plaintext, aad)?;
+
+            Ok([&[VERSION_X25519][..], ephemeral_public.as_bytes(), &sealed].concat())
+        }
+    }
+}
+
+// Старый формат v1 расшифровывается только для совместимости: aad в нём не проверяется
+pub fn open(key: PrivateKey<'_>, ciphertext: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, HybridError> {
+    let (&version, body) = ciphertext.split_first().ok_or(HybridError::Malformed)?;
+
+    match key {
+        PrivateKey::Rsa(private_key) => {
+            // У v2 есть заголовок и тег, поэтому он всегда длиннее модуля
+            if ciphertext.len() == private_key.size() {
+                return private_key
+                    .decrypt(Pkcs1v15Encrypt, ciphertext)
+                    .map(Zeroizing::new)
+                    .map_err(|_| HybridError::Decryption);
+            }
+            match version {
+                VERSION_RSA_OAEP => {}
+                VERSION_X25519 => return Err(HybridError::KeyMismatch),
+                _ => return Err(HybridError::UnsupportedVersion(version)),
+            }
+
+            if body.len() < 2 {
+                return Err(HybridError::Malformed);
+            }
+            let (wrapped_len, rest) = body.split_at(2);
+            let wrapped_len = u16::from_be_bytes([wrapped_len[0], wrapped_len[1]]) as usize;
+            if rest.len() < wrapped_len {
+                return Err(HybridError::Malformed);
+            }
+            let (wrapped_key, sealed) = rest.split_at(wrapped_len);
+
+            let data_key = Zeroizing::new(
+                private_key
+                    .decrypt(Oaep::new::<Sha256>(), wrapped_key)
+                    .map_err(|_| HybridError::Decryption)?,
+            );
+            if data_key.len() != DATA_KEY_SIZE {
+                return Err(HybridError::Decryption);
+            }
+
+            aead_open(&data_key, VERSION_RSA_OAEP, sealed, aad)
+        }
+        PrivateKey::X25519(secret) => {
+            match version {
+                VERSION_X25519 => {}
+                VERSION_RSA_OAEP => return Err(HybridError::KeyMismatch),
+                _ => return Err(HybridError::UnsupportedVersion(version)),
+            }
+
+            if body.len() < 32 {
+                return Err(HybridError::Malformed);
+            }
+            let (ephemeral, sealed) = body.split_at(32);
+            let ephemeral: [u8; 32] = ephemeral.try_into().map_err(|_| HybridError::Malformed)?;
+
+            let shared = secret.diffie_hellman(&X25519PublicKey::from(ephemeral));
+            if !shared.was_contributory() {
+                return Err(HybridError::Decryption);
+            }
+            let recipient = X25519PublicKey::from(secret);
+            let data_key = x25519_key(shared.as_bytes(), &ephemeral, recipient.as_bytes())?;
+
+            aead_open(data_key.as_ref(), VERSION_X25519, sealed, aad)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AAD: &[u8] = b"nesfinch-test:v1";
+
+    // 1024 бит хватает для проверки формата и заметно ускоряет генерацию в debug-сборке
+    fn rsa_key() -> RsaPrivateKey {
+        RsaPrivateKey::new(&mut OsRng, 1024).unwrap()
+    }
+
+    #[test]
+    fn rsa_round_trip() {
+        let private_key = rsa_key();
+        let public_key = private_key.to_public_key();
+
+        let ciphertext = seal(PublicKey::Rsa(&public_key), b"secret", AAD).unwrap();
+        assert_eq!(ciphertext[0], VERSION_RSA_OAEP);
+        assert_eq!(u16::from_be_bytes([ciphertext[1], ciphertext[2]]) as usize, public_key.size());
+
+        let plaintext = open(PrivateKey::Rsa(&private_key), &ciphertext, AAD).unwrap();
+        assert_eq!(plaintext.as_slice(), b"secret");
+
+        // Пустое сообщение тоже допустимо
+        let ciphertext = seal(PublicKey::Rsa(&public_key), b"", AAD).unwrap();
+        assert!(open(PrivateKey::Rsa(&private_key), &ciphertext, AAD).unwrap().is_empty());
+    }
+
+    #[test]
+    fn x25519_round_trip() {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let public = X25519PublicKey::from(&secret).to_bytes();
+
+        let ciphertext = seal(PublicKey::X25519(&public), b"secret", AAD).unwrap();
+        assert_eq!(ciphertext[0], VERSION_X25519);
+        assert_eq!(ciphertext.len(), 1 + 32 + NONCE_SIZE + b"secret".len() + TAG_SIZE);
+
+        let plaintext = open(PrivateKey::X25519(&secret), &ciphertext, AAD).unwrap();
+        assert_eq!(plaintext.as_slice(), b"secret");
+
+        // Чужой ключ не расшифровывает
+        let other = StaticSecret::random_from_rng(OsRng);
+        assert_eq!(open(PrivateKey::X25519(&other), &ciphertext, AAD).unwrap_err(), HybridError::Decryption);
+    }
+
+    #[test]
+    fn aad_mismatch_is_rejected() {
+        let private_key = rsa_key();
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let public = X25519PublicKey::from(&secret).to_bytes();
+
+        let chat_id = Uuid::from_u128(1);
+        let aad = message_aad(chat_id, Uuid::from_u128(2));
+        let other_aad = message_aad(chat_id, Uuid::from_u128(3));
+
+        let ciphertext = seal(PublicKey::Rsa(&private_key.to_public_key()), b"secret", &aad).unwrap();
+        assert_eq!(open(PrivateKey::Rsa(&private_key), &ciphertext, &other_aad).unwrap_err(), HybridError::Decryption);
+
+        let ciphertext = seal(PublicKey::X25519(&public), b"secret", &aad).unwrap();
+        assert_eq!(open(PrivateKey::X25519(&secret), &ciphertext, &other_aad).unwrap_err(), HybridError::Decryption);
+        assert_eq!(open(PrivateKey::X25519(&secret), &ciphertext, b"").unwrap_err(), HybridError::Decryption);
+    }
+
+    #[test]
+    fn version_byte_is_checked() {
+        let private_key = rsa_key();
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let public = X25519PublicKey::from(&secret).to_bytes();
+
+        let rsa_ciphertext = seal(PublicKey::Rsa(&private_key.to_public_key()), b"secret", AAD).unwrap();
+        let x25519_ciphertext = seal(PublicKey::X25519(&public), b"secret", AAD).unwrap();
+
+        // Шифртекст другого формата не подходит к ключу
+        assert_eq!(open(PrivateKey::Rsa(&private_key), &x25519_ciphertext, AAD).unwrap_err(), HybridError::KeyMismatch);
+        assert_eq!(open(PrivateKey::X25519(&secret), &rsa_ciphertext, AAD).unwrap_err(), HybridError::KeyMismatch);
+
+        for version in [0, VERSION_LEGACY_PKCS1, 4, 0xFF] {
+            let mut ciphertext = x25519_ciphertext.clone();
+            ciphertext[0] = version;
+            assert_eq!(open(PrivateKey::X25519(&secret), &ciphertext, AAD).unwrap_err(), HybridError::UnsupportedVersion(version));
+
+            let mut ciphertext = rsa_ciphertext.clone();
+            ciphertext[0] = version;
+            assert_eq!(open(PrivateKey::Rsa(&private_key), &ciphertext, AAD).unwrap_err(), HybridError::UnsupportedVersion(version));
+        }
+    }
+
+    #[test]
+    fn tampered_or_truncated_ciphertext_is_rejected() {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let public = X25519PublicKey::from(&secret).to_bytes();
+        let ciphertext = seal(PublicKey::X25519(&public), b"secret", AAD).unwrap();
+
+        for index in 1..ciphertext.len() {
+            let mut tampered = ciphertext.clone();
+            tampered[index] ^= 0x01;
+            assert!(open(PrivateKey::X25519(&secret), &tampered, AAD).is_err());
+        }
+
+        assert_eq!(open(PrivateKey::X25519(&secret), &[], AAD).unwrap_err(), HybridError::Malformed);
+        assert_eq!(open(PrivateKey::X25519(&secret), &ciphertext[..33], AAD).unwrap_err(), HybridError::Malformed);
+        assert_eq!(
+            open(PrivateKey::X25519(&secret), &ciphertext[..1 + 32 + NONCE_SIZE + TAG_SIZE - 1], AAD).unwrap_err(),
+            HybridError::Malformed
+        );
+
+        let private_key = rsa_key();
+        let ciphertext = seal(PublicKey::Rsa(&private_key.to_public_key()), b"secret", AAD).unwrap();
+        assert_eq!(open(PrivateKey::Rsa(&private_key), &ciphertext[..2], AAD).unwrap_err(), HybridError::Malformed);
+        assert_eq!(open(PrivateKey::Rsa(&private_key), &ciphertext[..100], AAD).unwrap_err(), HybridError::Malformed);
+    }
+
+    #[test]
+    fn legacy_pkcs1_ciphertext_is_readable() {
+        let private_key = rsa_key();
+        let ciphertext = private_key
+            .to_public_key()
+            .encrypt(&mut OsRng, Pkcs1v15Encrypt, b"legacy")
+            .unwrap();
+        assert_eq!(ciphertext.len(), private_key.size());
+
+        // aad в v1 не участвует
+        let plaintext = open(PrivateKey::Rsa(&private_key), &ciphertext, b"ignored").unwrap();
+        assert_eq!(plaintext.as_slice(), b"legacy");
+    }
+}
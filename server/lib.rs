@@ -0,0 +1,7 @@
+// Общий код для ботов, тестового окружения, аудиторов журнала ключей, разбора ключей OpenPGP
+// и гибридного шифрования, кодек STUN/TURN
+pub mod ratchet;
+pub mod merkle;
+pub mod openpgp;
+pub mod hybrid;
+pub mod stun;
@@ -9,6 +9,21 @@ use services::seacrh_service::{MySearchService, SearchServiceServer};
 use services::status_user_service::{MyStatusService, StatusServiceServer};
 use services::relationships_service::{MyRelationshipService, RelationshipServiceServer};
 use services::chat_service::{MyChatsService, ChatServiceServer};
+use services::message_scheduler::MessageScheduler;
+use services::notification_service::{MyNotificationService, NotificationServiceServer, Notifier};
+use services::prekey_service::{MyPrekeyService, PrekeyServiceServer};
+use services::key_service::{MyKeyService, KeyServiceServer};
+use services::key_expiry_monitor::KeyExpiryMonitor;
+use services::key_log::KeyLog;
+use services::hkp_server::HkpServer;
+use services::key_manager::KeyManager;
+use services::kms::kms_from_env;
+use services::kek_rotation::KekRotationJob;
+use services::device_service::{MyDeviceService, DeviceServiceServer};
+use services::call_service::{MyCallService, CallServiceServer, CallHub};
+use services::voice_room_service::{MyVoiceRoomService, VoiceRoomServiceServer, RoomHub};
+use services::call_history::CallRecorder;
+use services::turn_server::{TurnConfig, TurnServer};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -18,12 +33,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let db_url = env::var("DATABASE_URL")?;
     let jwt_secret = env::var("JWT_SECRET")?;
 
+    let key_log_signing_key = KeyLog::signing_key_from_env()?;
+    let kms = kms_from_env()?;
+    let turn_config = TurnConfig::from_env()?;
+
     let db = PgPool::connect(&db_url).await?;
-    let service_auth = MyAuthService::new(db.clone(), jwt_secret);
+    let notifier = Notifier::new(db.clone());
+    let key_manager = KeyManager::new(db.clone(), kms);
+    let service_auth = MyAuthService::new(db.clone(), jwt_secret.clone());
     let service_search = MySearchService::new(db.clone());
     let service_status = MyStatusService::new(db.clone());
-    let service_relationship = MyRelationshipService::new(db.clone());
-    let service_chat = MyChatsService::new(db.clone());
+    let service_relationship = MyRelationshipService::new(db.clone(), notifier.clone());
+    let service_prekey = MyPrekeyService::new(db.clone(), notifier.clone());
+    let service_key = MyKeyService::new(db.clone(), notifier.clone(), KeyLog::new(db.clone(), key_log_signing_key), key_manager.clone());
+    let service_notification = MyNotificationService::new(db.clone(), notifier.clone());
+    let service_device = MyDeviceService::new(db.clone(), notifier.clone(), key_manager.clone());
+    let call_recorder = CallRecorder::spawn(db.clone(), notifier.clone());
+    let service_call = MyCallService::new(db.clone(), CallHub::new(call_recorder.clone()), jwt_secret, turn_config.clone());
+    let rooms = RoomHub::new(call_recorder);
+    let service_chat = MyChatsService::new(db.clone(), notifier.clone(), key_manager.clone(), rooms.clone());
+    let service_voice_room = MyVoiceRoomService::new(db.clone(), rooms);
+
+    MessageScheduler::new(db.clone(), notifier.clone()).spawn();
+    KeyExpiryMonitor::new(db.clone(), notifier.clone()).spawn();
+    KekRotationJob::new(db.clone(), key_manager).spawn();
+
+    if let Some(hkp_addr) = HkpServer::addr_from_env()? {
+        HkpServer::new(db.clone(), notifier).spawn(hkp_addr);
+        println!("HKP key server running on {}", hkp_addr);
+    }
+
+    if let Some(turn_config) = turn_config {
+        let turn_addr = turn_config.listen_addr();
+        TurnServer::new(turn_config).spawn();
+        println!("STUN/TURN server running on {}", turn_addr);
+    }
 
     println!("Services running on {}", addr);
     Server::builder()
@@ -32,6 +76,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .add_service(StatusServiceServer::new(service_status))
         .add_service(RelationshipServiceServer::new(service_relationship))
         .add_service(ChatServiceServer::new(service_chat))
+        .add_service(NotificationServiceServer::new(service_notification))
+        .add_service(PrekeyServiceServer::new(service_prekey))
+        .add_service(KeyServiceServer::new(service_key))
+        .add_service(DeviceServiceServer::new(service_device))
+        .add_service(CallServiceServer::new(service_call))
+        .add_service(VoiceRoomServiceServer::new(service_voice_room))
         .serve(addr)
         .await?;
 
@@ -0,0 +1,311 @@
+// Дерево Меркла журнала прозрачности ключей (RFC 6962, проверка доказательств по RFC 9162).
+// Листья передаются уже хешированными через leaf_hash.
+
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    Sha256::new().chain_update([0x00]).chain_update(data).finalize().into()
+}
+
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new().chain_update([0x01]).chain_update(left).chain_update(right).finalize().into()
+}
+
+// Наибольшая степень двойки, строго меньшая n (n > 1)
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+// MTH(D[n]); корень пустого дерева - SHA-256 от пустой строки
+pub fn root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => Sha256::digest([]).into(),
+        1 => leaves[0],
+        n => {
+            let k = split_point(n);
+            node_hash(&root(&leaves[..k]), &root(&leaves[k..]))
+        }
+    }
+}
+
+// PATH(m, D[n]) - доказательство включения листа с индексом index
+pub fn inclusion_proof(leaves: &[Hash], index: usize) -> Option<Vec<Hash>> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut proof = Vec::new();
+    inclusion_path(leaves, index, &mut proof);
+    Some(proof)
+}
+
+fn inclusion_path(leaves: &[Hash], index: usize, proof: &mut Vec<Hash>) {
+    let n = leaves.len();
+    if n <= 1 {
+        return;
+    }
+
+    let k = split_point(n);
+    if index < k {
+        inclusion_path(&leaves[..k], index, proof);
+        proof.push(root(&leaves[k..]));
+    } else {
+        inclusion_path(&leaves[k..], index - k, proof);
+        proof.push(root(&leaves[..k]));
+    }
+}
+
+// PROOF(m, D[n]) - согласованность дерева из первых first_size листьев с деревом leaves
+pub fn consistency_proof(leaves: &[Hash], first_size: usize) -> Option<Vec<Hash>> {
+    if first_size == 0 || first_size > leaves.len() {
+        return None;
+    }
+
+    let mut proof = Vec::new();
+    subproof(leaves, first_size, true, &mut proof);
+    Some(proof)
+}
+
+fn subproof(leaves: &[Hash], m: usize, complete: bool, proof: &mut Vec<Hash>) {
+    let n = leaves.len();
+    if m == n {
+        if !complete {
+            proof.push(root(leaves));
+        }
+        return;
+    }
+
+    let k = split_point(n);
+    if m <= k {
+        subproof(&leaves[..k], m, complete, proof);
+        proof.push(root(&leaves[k..]));
+    } else {
+        subproof(&leaves[k..], m - k, false, proof);
+        proof.push(root(&leaves[..k]));
+    }
+}
+
+pub fn verify_inclusion(leaf: &Hash, index: u64, tree_size: u64, proof: &[Hash], root: &Hash) -> bool {
+    if index >= tree_size {
+        return false;
+    }
+
+    let (mut f, mut s) = (index, tree_size - 1);
+    let mut r = *leaf;
+    for p in proof {
+        if s == 0 {
+            return false;
+        }
+        if f & 1 == 1 || f == s {
+            r = node_hash(p, &r);
+            if f & 1 == 0 {
+                while f & 1 == 0 && f != 0 {
+                    f >>= 1;
+                    s >>= 1;
+                }
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+
+    s == 0 && r == *root
+}
+
+pub fn verify_consistency(
+    first_size: u64,
+    second_size: u64,
+    first_root: &Hash,
+    second_root: &Hash,
+    proof: &[Hash],
+) -> bool {
+    if first_size == 0 || first_size > second_size {
+        return false;
+    }
+    if first_size == second_size {
+        return proof.is_empty() && first_root == second_root;
+    }
+
+    let mut path = Vec::with_capacity(proof.len() + 1);
+    if first_size.is_power_of_two() {
+        path.push(*first_root);
+    }
+    path.extend_from_slice(proof);
+
+    let Some((first, rest)) = path.split_first() else {
+        return false;
+    };
+
+    let (mut f, mut s) = (first_size - 1, second_size - 1);
+    while f & 1 == 1 {
+        f >>= 1;
+        s >>= 1;
+    }
+
+    let (mut fr, mut sr) = (*first, *first);
+    for c in rest {
+        if s == 0 {
+            return false;
+        }
+        if f & 1 == 1 || f == s {
+            fr = node_hash(c, &fr);
+            sr = node_hash(c, &sr);
+            if f & 1 == 0 {
+                while f & 1 == 0 && f != 0 {
+                    f >>= 1;
+                    s >>= 1;
+                }
+            }
+        } else {
+            sr = node_hash(&sr, c);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+
+    fr == *first_root && sr == *second_root && s == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Эталонное дерево из тестов certificate-transparency (RFC 6962, RFC 9162)
+    const LEAVES: [&str; 8] = [
+        "",
+        "00",
+        "10",
+        "2021",
+        "3031",
+        "40414243",
+        "5051525354555657",
+        "606162636465666768696a6b6c6d6e6f",
+    ];
+
+    const ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    // (индекс листа, размер дерева, доказательство)
+    const INCLUSION_PROOFS: [(usize, usize, &[&str]); 4] = [
+        (0, 8, &[
+            "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+            "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+            "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+        ]),
+        (5, 8, &[
+            "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        ]),
+        (2, 3, &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"]),
+        (1, 5, &[
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+            "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+        ]),
+    ];
+
+    // (размер первого дерева, размер второго, доказательство)
+    const CONSISTENCY_PROOFS: [(usize, usize, &[&str]); 4] = [
+        (1, 1, &[]),
+        (1, 8, &[
+            "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+            "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+            "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+        ]),
+        (6, 8, &[
+            "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+            "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        ]),
+        (2, 5, &[
+            "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+            "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+        ]),
+    ];
+
+    fn hash(hex: &str) -> Hash {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
+    fn leaves() -> Vec<Hash> {
+        LEAVES.iter().map(|leaf| leaf_hash(&hex::decode(leaf).unwrap())).collect()
+    }
+
+    #[test]
+    fn roots() {
+        let leaves = leaves();
+        assert_eq!(root(&[]), hash("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"));
+        for (size, expected) in ROOTS.iter().enumerate() {
+            assert_eq!(root(&leaves[..size + 1]), hash(expected), "tree size {}", size + 1);
+        }
+    }
+
+    #[test]
+    fn inclusion_proofs() {
+        let leaves = leaves();
+        for (index, size, expected) in INCLUSION_PROOFS {
+            let expected: Vec<Hash> = expected.iter().map(|h| hash(h)).collect();
+            assert_eq!(inclusion_proof(&leaves[..size], index).unwrap(), expected);
+
+            let root = hash(ROOTS[size - 1]);
+            assert!(verify_inclusion(&leaves[index], index as u64, size as u64, &expected, &root));
+            assert!(!verify_inclusion(&leaves[index], index as u64 + 1, size as u64, &expected, &root));
+            assert!(!verify_inclusion(&leaves[(index + 1) % size], index as u64, size as u64, &expected, &root));
+            assert!(!verify_inclusion(&leaves[index], index as u64, size as u64, &expected[1..], &root));
+        }
+        assert!(inclusion_proof(&leaves, 8).is_none());
+    }
+
+    #[test]
+    fn consistency_proofs() {
+        let leaves = leaves();
+        for (first, second, expected) in CONSISTENCY_PROOFS {
+            let expected: Vec<Hash> = expected.iter().map(|h| hash(h)).collect();
+            assert_eq!(consistency_proof(&leaves[..second], first).unwrap(), expected);
+
+            let (first_root, second_root) = (hash(ROOTS[first - 1]), hash(ROOTS[second - 1]));
+            assert!(verify_consistency(first as u64, second as u64, &first_root, &second_root, &expected));
+            if first != second {
+                assert!(!verify_consistency(first as u64, second as u64, &second_root, &first_root, &expected));
+                assert!(!verify_consistency(first as u64, second as u64, &first_root, &second_root, &expected[1..]));
+            }
+        }
+        assert!(consistency_proof(&leaves, 0).is_none());
+        assert!(consistency_proof(&leaves[..3], 4).is_none());
+    }
+
+    // Все пары размеров: построенные доказательства проходят проверку
+    #[test]
+    fn proofs_verify_for_all_sizes() {
+        let leaves: Vec<Hash> = (0..20u8).map(|i| leaf_hash(&[i])).collect();
+        for size in 1..=leaves.len() {
+            let root = root(&leaves[..size]);
+            for index in 0..size {
+                let proof = inclusion_proof(&leaves[..size], index).unwrap();
+                assert!(verify_inclusion(&leaves[index], index as u64, size as u64, &proof, &root));
+            }
+            for first in 1..=size {
+                let proof = consistency_proof(&leaves[..size], first).unwrap();
+                let first_root = super::root(&leaves[..first]);
+                assert!(verify_consistency(first as u64, size as u64, &first_root, &root, &proof));
+            }
+        }
+    }
+}
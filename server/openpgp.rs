@@ -0,0 +1,1445 @@
+// Разбор публичных ключей OpenPGP (RFC 4880; ключи и подписи v5 по RFC 4880bis / LibrePGP):
+// ASCII armor, пакеты ключей, отпечатки v4/v5 и проверка самоподписей.
+// Поддерживаются RSA, EdDSA (Ed25519) и ECDH/X25519 (Curve25519).
+// Секретные ключи и зашифрованные сообщения не разбираются.
+
+use std::fmt;
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use ed25519_dalek::{Signature as Ed25519Signature, VerifyingKey};
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey, traits::PublicKeyParts};
+use sha1::Sha1;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+
+pub const PUBLIC_KEY_BLOCK: &str = "PGP PUBLIC KEY BLOCK";
+pub const SIGNATURE_BLOCK: &str = "PGP SIGNATURE";
+
+pub const MAX_ARMORED_LEN: usize = 64 * 1024;
+pub const MIN_RSA_BITS: usize = 2048;
+const MAX_USER_ID_LEN: usize = 1024;
+// Допустимое расхождение часов для времени создания подписи
+const CLOCK_SKEW_SECS: u64 = 15 * 60;
+
+const TAG_SIGNATURE: u8 = 2;
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_MARKER: u8 = 10;
+const TAG_TRUST: u8 = 12;
+const TAG_USER_ID: u8 = 13;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+const TAG_USER_ATTRIBUTE: u8 = 17;
+
+const ALGO_RSA: u8 = 1;
+const ALGO_RSA_ENCRYPT_ONLY: u8 = 2;
+const ALGO_RSA_SIGN_ONLY: u8 = 3;
+const ALGO_ECDH: u8 = 18;
+const ALGO_EDDSA_LEGACY: u8 = 22;
+const ALGO_X25519: u8 = 25;
+const ALGO_ED25519: u8 = 27;
+
+const OID_ED25519: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0xDA, 0x47, 0x0F, 0x01];
+const OID_CURVE25519: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0x97, 0x55, 0x01, 0x05, 0x01];
+
+const HASH_SHA256: u8 = 8;
+const HASH_SHA384: u8 = 9;
+const HASH_SHA512: u8 = 10;
+const HASH_SHA224: u8 = 11;
+
+const SIG_BINARY: u8 = 0x00;
+const SIG_SUBKEY_BINDING: u8 = 0x18;
+const SIG_DIRECT_KEY: u8 = 0x1F;
+const SIG_KEY_REVOCATION: u8 = 0x20;
+const SIG_SUBKEY_REVOCATION: u8 = 0x28;
+const SIG_CERT_REVOCATION: u8 = 0x30;
+
+const SUB_CREATION_TIME: u8 = 2;
+const SUB_SIG_EXPIRATION: u8 = 3;
+const SUB_KEY_EXPIRATION: u8 = 9;
+const SUB_ISSUER: u8 = 16;
+const SUB_PRIMARY_USER_ID: u8 = 25;
+const SUB_KEY_FLAGS: u8 = 27;
+const SUB_ISSUER_FINGERPRINT: u8 = 33;
+// Подпакеты, которые можно встретить с флагом critical без потери смысла подписи
+const UNDERSTOOD_SUBPACKETS: &[u8] = &[
+    2, 3, 4, 7, 9, 11, 12, 16, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 37, 39,
+];
+
+pub const KEY_FLAG_SIGN: u8 = 0x02;
+pub const KEY_FLAG_ENCRYPT: u8 = 0x04 | 0x08;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgpError {
+    Armor(&'static str),
+    Malformed(&'static str),
+    Unsupported(String),
+    WeakKey(usize),
+    NoValidUserId,
+    Revoked,
+    Expired,
+}
+
+impl fmt::Display for PgpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgpError::Armor(reason) => write!(f, "Invalid ASCII armor: {}", reason),
+            PgpError::Malformed(reason) => write!(f, "Malformed OpenPGP data: {}", reason),
+            PgpError::Unsupported(what) => write!(f, "Unsupported OpenPGP {}", what),
+            PgpError::WeakKey(bits) => write!(
+                f,
+                "RSA keys must be at least {} bits, got {}",
+                MIN_RSA_BITS, bits
+            ),
+            PgpError::NoValidUserId => write!(f, "Key has no user ID with a valid self-signature"),
+            PgpError::Revoked => write!(f, "Key has been revoked by its owner"),
+            PgpError::Expired => write!(f, "Key has expired"),
+        }
+    }
+}
+
+impl std::error::Error for PgpError {}
+
+// --- ASCII armor ---
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xB704CE;
+    for byte in data {
+        crc ^= (*byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= 0x1864CFB;
+            }
+        }
+    }
+    crc & 0xFFFFFF
+}
+
+pub fn dearmor(text: &str, label: &str) -> Result<Vec<u8>, PgpError> {
+    if text.len() > MAX_ARMORED_LEN {
+        return Err(PgpError::Armor("armored data is too large"));
+    }
+
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| *line != begin)
+        .skip(1)
+        .peekable();
+
+    // Заголовки вида "Key: Value" до пустой строки; пустая строка может отсутствовать
+    while let Some(line) = lines.peek() {
+        if line.is_empty() {
+            lines.next();
+            break;
+        }
+        if !line.contains(':') {
+            break;
+        }
+        lines.next();
+    }
+
+    let mut body = String::new();
+    let mut checksum = None;
+    let mut terminated = false;
+    for line in lines {
+        if line == end {
+            terminated = true;
+            break;
+        }
+        if let Some(crc) = line.strip_prefix('=') {
+            checksum = Some(crc.to_string());
+        } else {
+            body.push_str(line);
+        }
+    }
+
+    if !terminated {
+        return Err(PgpError::Armor("missing armor header or footer"));
+    }
+
+    let data = BASE64
+        .decode(body)
+        .map_err(|_| PgpError::Armor("invalid base64"))?;
+
+    // Контрольная сумма необязательна, но если есть - должна совпадать
+    if let Some(checksum) = checksum {
+        let crc = BASE64
+            .decode(checksum)
+            .map_err(|_| PgpError::Armor("invalid checksum"))?;
+        if crc.len() != 3 || u32::from_be_bytes([0, crc[0], crc[1], crc[2]]) != crc24(&data) {
+            return Err(PgpError::Armor("checksum mismatch"));
+        }
+    }
+
+    Ok(data)
+}
+
+pub fn armor(label: &str, data: &[u8]) -> String {
+    let encoded = BASE64.encode(data);
+    let crc = crc24(data).to_be_bytes();
+
+    let mut text = format!("-----BEGIN {}-----\n\n", label);
+    for chunk in encoded.as_bytes().chunks(64) {
+        // base64 - всегда ASCII
+        text.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        text.push('\n');
+    }
+    text.push('=');
+    text.push_str(&BASE64.encode(&crc[1..]));
+    text.push('\n');
+    text.push_str(&format!("-----END {}-----\n", label));
+    text
+}
+
+// --- Пакеты ---
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], PgpError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(PgpError::Malformed("truncated packet"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let bytes = &self.data[self.pos.min(self.data.len())..];
+        self.pos = self.data.len();
+        bytes
+    }
+
+    fn u8(&mut self) -> Result<u8, PgpError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, PgpError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, PgpError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // MPI: двухбайтовая длина в битах и big-endian значение
+    fn mpi(&mut self) -> Result<&'a [u8], PgpError> {
+        let bits = self.u16()? as usize;
+        self.take(bits.div_ceil(8))
+    }
+}
+
+struct Packet<'a> {
+    tag: u8,
+    body: &'a [u8],
+}
+
+fn read_packets(data: &[u8]) -> Result<Vec<Packet<'_>>, PgpError> {
+    let mut reader = Reader::new(data);
+    let mut packets = Vec::new();
+
+    while !reader.is_empty() {
+        let ctb = reader.u8()?;
+        if ctb & 0x80 == 0 {
+            return Err(PgpError::Malformed("invalid packet header"));
+        }
+
+        let (tag, len) = if ctb & 0x40 != 0 {
+            let first = reader.u8()? as usize;
+            let len = match first {
+                0..=191 => first,
+                192..=223 => ((first - 192) << 8) + reader.u8()? as usize + 192,
+                255 => reader.u32()? as usize,
+                _ => return Err(PgpError::Unsupported("partial body lengths".to_string())),
+            };
+            (ctb & 0x3F, len)
+        } else {
+            let len = match ctb & 0x03 {
+                0 => reader.u8()? as usize,
+                1 => reader.u16()? as usize,
+                2 => reader.u32()? as usize,
+                _ => {
+                    return Err(PgpError::Unsupported(
+                        "indeterminate packet lengths".to_string(),
+                    ));
+                }
+            };
+            ((ctb >> 2) & 0x0F, len)
+        };
+
+        packets.push(Packet {
+            tag,
+            body: reader.take(len)?,
+        });
+    }
+
+    Ok(packets)
+}
+
+// --- Ключи ---
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyMaterial {
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+    Ed25519([u8; 32]),
+    X25519([u8; 32]),
+}
+
+#[derive(Debug, Clone)]
+pub struct PublicKeyPacket {
+    pub version: u8,
+    pub created_at: u32,
+    pub algorithm: u8,
+    pub material: KeyMaterial,
+    body: Vec<u8>,
+}
+
+fn curve_point(reader: &mut Reader<'_>) -> Result<[u8; 32], PgpError> {
+    // Точка в нативном формате с префиксом 0x40
+    match reader.mpi()? {
+        [0x40, point @ ..] => point
+            .try_into()
+            .map_err(|_| PgpError::Malformed("invalid curve point")),
+        _ => Err(PgpError::Malformed("invalid curve point")),
+    }
+}
+
+fn raw_point(reader: &mut Reader<'_>) -> Result<[u8; 32], PgpError> {
+    reader
+        .take(32)?
+        .try_into()
+        .map_err(|_| PgpError::Malformed("invalid curve point"))
+}
+
+fn parse_public_key(body: &[u8]) -> Result<PublicKeyPacket, PgpError> {
+    let mut reader = Reader::new(body);
+
+    let version = reader.u8()?;
+    if version != 4 && version != 5 {
+        return Err(PgpError::Unsupported(format!("version {} keys", version)));
+    }
+    let created_at = reader.u32()?;
+    let algorithm = reader.u8()?;
+    let material_bytes = if version == 5 {
+        let len = reader.u32()? as usize;
+        reader.take(len)?
+    } else {
+        reader.rest()
+    };
+
+    let mut material = Reader::new(material_bytes);
+    let material = match algorithm {
+        ALGO_RSA | ALGO_RSA_ENCRYPT_ONLY | ALGO_RSA_SIGN_ONLY => {
+            let n = material.mpi()?.to_vec();
+            let e = material.mpi()?.to_vec();
+            let bits = BigUint::from_bytes_be(&n).bits();
+            if bits < MIN_RSA_BITS {
+                return Err(PgpError::WeakKey(bits));
+            }
+            KeyMaterial::Rsa { n, e }
+        }
+        ALGO_EDDSA_LEGACY => {
+            let oid_len = material.u8()? as usize;
+            if material.take(oid_len)? != OID_ED25519 {
+                return Err(PgpError::Unsupported("EdDSA curve".to_string()));
+            }
+            KeyMaterial::Ed25519(curve_point(&mut material)?)
+        }
+        ALGO_ECDH => {
+            let oid_len = material.u8()? as usize;
+            if material.take(oid_len)? != OID_CURVE25519 {
+                return Err(PgpError::Unsupported("ECDH curve".to_string()));
+            }
+            // Параметры KDF за точкой не нужны серверу
+            KeyMaterial::X25519(curve_point(&mut material)?)
+        }
+        ALGO_X25519 => KeyMaterial::X25519(raw_point(&mut material)?),
+        ALGO_ED25519 => KeyMaterial::Ed25519(raw_point(&mut material)?),
+        other => {
+            return Err(PgpError::Unsupported(format!(
+                "public key algorithm {}",
+                other
+            )));
+        }
+    };
+
+    Ok(PublicKeyPacket {
+        version,
+        created_at,
+        algorithm,
+        material,
+        body: body.to_vec(),
+    })
+}
+
+impl PublicKeyPacket {
+    // v4: SHA-1(0x99 || длина u16 || тело), v5: SHA-256(0x9A || длина u32 || тело)
+    pub fn fingerprint(&self) -> Vec<u8> {
+        let mut framed = Vec::with_capacity(self.body.len() + 5);
+        self.write_framed(&mut framed);
+        if self.version == 5 {
+            Sha256::digest(&framed).to_vec()
+        } else {
+            Sha1::digest(&framed).to_vec()
+        }
+    }
+
+    pub fn key_id(&self) -> [u8; 8] {
+        let fingerprint = self.fingerprint();
+        let id = if self.version == 5 {
+            &fingerprint[..8]
+        } else {
+            &fingerprint[fingerprint.len() - 8..]
+        };
+        id.try_into().unwrap_or_default()
+    }
+
+    // Ключ в виде, в котором он входит в хешируемые данные подписей
+    fn write_framed(&self, out: &mut Vec<u8>) {
+        if self.version == 5 {
+            out.push(0x9A);
+            out.extend_from_slice(&(self.body.len() as u32).to_be_bytes());
+        } else {
+            out.push(0x99);
+            out.extend_from_slice(&(self.body.len() as u16).to_be_bytes());
+        }
+        out.extend_from_slice(&self.body);
+    }
+
+    // Размер ключа в битах, как его показывает gpg
+    pub fn bits(&self) -> usize {
+        match &self.material {
+            KeyMaterial::Rsa { n, .. } => BigUint::from_bytes_be(n).bits(),
+            KeyMaterial::Ed25519(_) | KeyMaterial::X25519(_) => 255,
+        }
+    }
+
+    fn can_sign(&self) -> bool {
+        matches!(self.material, KeyMaterial::Ed25519(_))
+            || matches!(self.algorithm, ALGO_RSA | ALGO_RSA_SIGN_ONLY)
+    }
+
+    fn can_encrypt(&self) -> bool {
+        matches!(self.material, KeyMaterial::X25519(_))
+            || matches!(self.algorithm, ALGO_RSA | ALGO_RSA_ENCRYPT_ONLY)
+    }
+
+    pub fn rsa_public_key(&self) -> Option<RsaPublicKey> {
+        match &self.material {
+            KeyMaterial::Rsa { n, e } => {
+                RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e)).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+// --- Подписи ---
+
+#[derive(Debug, Default)]
+struct Subpackets {
+    creation_time: Option<u32>,
+    expiration: Option<u32>,
+    key_expiration: Option<u32>,
+    key_flags: Option<u8>,
+    primary_user_id: bool,
+    issuer: Option<[u8; 8]>,
+    issuer_fingerprint: Option<Vec<u8>>,
+    unknown_critical: bool,
+}
+
+fn parse_subpackets(area: &[u8], hashed: bool, out: &mut Subpackets) -> Result<(), PgpError> {
+    let mut reader = Reader::new(area);
+    while !reader.is_empty() {
+        let first = reader.u8()? as usize;
+        let len = match first {
+            0..=191 => first,
+            192..=254 => ((first - 192) << 8) + reader.u8()? as usize + 192,
+            _ => reader.u32()? as usize,
+        };
+        let mut subpacket = Reader::new(reader.take(len)?);
+        let kind = subpacket.u8()?;
+        let critical = kind & 0x80 != 0;
+        let kind = kind & 0x7F;
+        let data = subpacket.rest();
+
+        // Без подписи можно доверять только указанию на издателя
+        if !hashed {
+            match kind {
+                SUB_ISSUER => out.issuer = out.issuer.or(data.try_into().ok()),
+                SUB_ISSUER_FINGERPRINT if data.len() > 1 => {
+                    out.issuer_fingerprint
+                        .get_or_insert_with(|| data[1..].to_vec());
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let mut value = Reader::new(data);
+        match kind {
+            SUB_CREATION_TIME => out.creation_time = Some(value.u32()?),
+            SUB_SIG_EXPIRATION => out.expiration = Some(value.u32()?),
+            SUB_KEY_EXPIRATION => out.key_expiration = Some(value.u32()?),
+            SUB_KEY_FLAGS => out.key_flags = data.first().copied().or(Some(0)),
+            SUB_PRIMARY_USER_ID => {
+                out.primary_user_id = data.first().is_some_and(|flag| *flag != 0)
+            }
+            SUB_ISSUER => out.issuer = Some(value.take(8)?.try_into().unwrap_or_default()),
+            SUB_ISSUER_FINGERPRINT if data.len() > 1 => {
+                out.issuer_fingerprint = Some(data[1..].to_vec())
+            }
+            _ if critical && !UNDERSTOOD_SUBPACKETS.contains(&kind) => out.unknown_critical = true,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct SignaturePacket {
+    version: u8,
+    sig_type: u8,
+    algorithm: u8,
+    hash_algorithm: u8,
+    // Версия, тип, алгоритмы и хешируемые подпакеты - ровно то, что входит в хеш
+    hashed_part: Vec<u8>,
+    subpackets: Subpackets,
+    left16: [u8; 2],
+    signature: Vec<Vec<u8>>,
+}
+
+// Подписи неизвестных версий пропускаются: Ok(None)
+fn parse_signature(body: &[u8]) -> Result<Option<SignaturePacket>, PgpError> {
+    let mut reader = Reader::new(body);
+
+    let version = reader.u8()?;
+    if version != 4 && version != 5 {
+        return Ok(None);
+    }
+    let sig_type = reader.u8()?;
+    let algorithm = reader.u8()?;
+    let hash_algorithm = reader.u8()?;
+
+    let mut subpackets = Subpackets::default();
+    let hashed_len = reader.u16()? as usize;
+    parse_subpackets(reader.take(hashed_len)?, true, &mut subpackets)?;
+    let hashed_part = body[..reader.pos].to_vec();
+
+    let unhashed_len = reader.u16()? as usize;
+    parse_subpackets(reader.take(unhashed_len)?, false, &mut subpackets)?;
+
+    let left16 = [reader.u8()?, reader.u8()?];
+    let signature = match algorithm {
+        ALGO_RSA | ALGO_RSA_SIGN_ONLY => vec![reader.mpi()?.to_vec()],
+        ALGO_EDDSA_LEGACY => vec![reader.mpi()?.to_vec(), reader.mpi()?.to_vec()],
+        ALGO_ED25519 => vec![reader.take(64)?.to_vec()],
+        _ => return Ok(None),
+    };
+
+    Ok(Some(SignaturePacket {
+        version,
+        sig_type,
+        algorithm,
+        hash_algorithm,
+        hashed_part,
+        subpackets,
+        left16,
+        signature,
+    }))
+}
+
+fn digest(hash_algorithm: u8, data: &[u8]) -> Option<Vec<u8>> {
+    // MD5, SHA-1 и RIPEMD-160 не принимаются
+    match hash_algorithm {
+        HASH_SHA256 => Some(Sha256::digest(data).to_vec()),
+        HASH_SHA384 => Some(Sha384::digest(data).to_vec()),
+        HASH_SHA512 => Some(Sha512::digest(data).to_vec()),
+        HASH_SHA224 => Some(Sha224::digest(data).to_vec()),
+        _ => None,
+    }
+}
+
+fn pkcs1v15_scheme(hash_algorithm: u8) -> Option<Pkcs1v15Sign> {
+    match hash_algorithm {
+        HASH_SHA256 => Some(Pkcs1v15Sign::new::<Sha256>()),
+        HASH_SHA384 => Some(Pkcs1v15Sign::new::<Sha384>()),
+        HASH_SHA512 => Some(Pkcs1v15Sign::new::<Sha512>()),
+        HASH_SHA224 => Some(Pkcs1v15Sign::new::<Sha224>()),
+        _ => None,
+    }
+}
+
+// Левое дополнение нулями: MPI не хранит ведущие нулевые байты
+fn left_pad(bytes: &[u8], len: usize) -> Option<Vec<u8>> {
+    let bytes = &bytes[bytes.iter().take_while(|b| **b == 0).count()..];
+    if bytes.len() > len {
+        return None;
+    }
+    let mut padded = vec![0u8; len - bytes.len()];
+    padded.extend_from_slice(bytes);
+    Some(padded)
+}
+
+impl SignaturePacket {
+    fn created_at(&self) -> u32 {
+        self.subpackets.creation_time.unwrap_or(0)
+    }
+
+    fn issued_by(&self, key: &PublicKeyPacket) -> bool {
+        if let Some(fingerprint) = &self.subpackets.issuer_fingerprint {
+            return *fingerprint == key.fingerprint();
+        }
+        self.subpackets
+            .issuer
+            .is_none_or(|issuer| issuer == key.key_id())
+    }
+
+    // data - ключ, user ID или документ, предшествующие подписи в хеше
+    fn verify(&self, key: &PublicKeyPacket, mut data: Vec<u8>, now: u64) -> bool {
+        let subpackets = &self.subpackets;
+        if subpackets.unknown_critical {
+            return false;
+        }
+        let Some(created_at) = subpackets.creation_time else {
+            return false;
+        };
+        if created_at < key.created_at || created_at as u64 > now + CLOCK_SKEW_SECS {
+            return false;
+        }
+        if let Some(expiration) = subpackets.expiration
+            && expiration != 0
+            && created_at as u64 + expiration as u64 <= now
+        {
+            return false;
+        }
+        if self.version != key.version || !key.can_sign() {
+            return false;
+        }
+
+        data.extend_from_slice(&self.hashed_part);
+        if self.version == 5 {
+            // Отделённая подпись v5 хеширует пустые поля литерального пакета
+            if self.sig_type == SIG_BINARY {
+                data.extend_from_slice(&[0; 6]);
+            }
+            data.extend_from_slice(&[0x05, 0xFF]);
+            data.extend_from_slice(&(self.hashed_part.len() as u64).to_be_bytes());
+        } else {
+            data.extend_from_slice(&[0x04, 0xFF]);
+            data.extend_from_slice(&(self.hashed_part.len() as u32).to_be_bytes());
+        }
+
+        let Some(hash) = digest(self.hash_algorithm, &data) else {
+            return false;
+        };
+        if hash[..2] != self.left16 {
+            return false;
+        }
+
+        match (&key.material, self.algorithm) {
+            (KeyMaterial::Rsa { .. }, ALGO_RSA | ALGO_RSA_SIGN_ONLY) => {
+                let (Some(public_key), Some(scheme)) =
+                    (key.rsa_public_key(), pkcs1v15_scheme(self.hash_algorithm))
+                else {
+                    return false;
+                };
+                let Some(signature) = left_pad(&self.signature[0], public_key.size()) else {
+                    return false;
+                };
+                public_key.verify(scheme, &hash, &signature).is_ok()
+            }
+            (KeyMaterial::Ed25519(point), ALGO_EDDSA_LEGACY | ALGO_ED25519) => {
+                let bytes = match self.signature.as_slice() {
+                    [r, s] => match (left_pad(r, 32), left_pad(s, 32)) {
+                        (Some(r), Some(s)) => [r, s].concat(),
+                        _ => return false,
+                    },
+                    [native] => native.clone(),
+                    _ => return false,
+                };
+                let (Ok(verifying_key), Ok(signature)) = (
+                    VerifyingKey::from_bytes(point),
+                    Ed25519Signature::from_slice(&bytes),
+                ) else {
+                    return false;
+                };
+                verifying_key.verify_strict(&hash, &signature).is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+// --- Сертификаты ---
+
+#[derive(Debug, Clone)]
+pub struct Subkey {
+    pub key: PublicKeyPacket,
+    pub flags: u8,
+    pub expires_at: Option<u64>,
+    bound_at: u32,
+}
+
+// Проверенный публичный ключ OpenPGP (transferable public key)
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    pub primary: PublicKeyPacket,
+    pub primary_flags: Option<u8>,
+    // User ID с действующей самоподписью; основной идёт первым
+    pub user_ids: Vec<String>,
+    pub subkeys: Vec<Subkey>,
+    // Unix-время истечения основного ключа
+    pub expires_at: Option<u64>,
+    binary: Vec<u8>,
+}
+
+// Подписи, следующие за пакетом ключа, user ID или подключа
+fn take_signatures(
+    packets: &[Packet<'_>],
+    pos: &mut usize,
+) -> Result<Vec<SignaturePacket>, PgpError> {
+    let mut signatures = Vec::new();
+    while let Some(packet) = packets
+        .get(*pos)
+        .filter(|packet| packet.tag == TAG_SIGNATURE)
+    {
+        if let Some(signature) = parse_signature(packet.body)? {
+            signatures.push(signature);
+        }
+        *pos += 1;
+    }
+    Ok(signatures)
+}
+
+fn keep_newest(slot: &mut Option<SignaturePacket>, signature: SignaturePacket) {
+    if slot
+        .as_ref()
+        .is_none_or(|current| current.created_at() < signature.created_at())
+    {
+        *slot = Some(signature);
+    }
+}
+
+// Ключ и user ID в том виде, в каком они входят в хеш самоподписи
+fn user_id_data(framed_primary: &[u8], user_id: &[u8]) -> Vec<u8> {
+    let mut data = framed_primary.to_vec();
+    data.push(0xB4);
+    data.extend_from_slice(&(user_id.len() as u32).to_be_bytes());
+    data.extend_from_slice(user_id);
+    data
+}
+
+fn expiry(created_at: u32, lifetime: Option<u32>) -> Option<u64> {
+    lifetime
+        .filter(|lifetime| *lifetime != 0)
+        .map(|lifetime| created_at as u64 + lifetime as u64)
+}
+
+impl Certificate {
+    pub fn from_armored(text: &str, now: u64) -> Result<Self, PgpError> {
+        Self::from_bytes(&dearmor(text, PUBLIC_KEY_BLOCK)?, now)
+    }
+
+    pub fn from_bytes(data: &[u8], now: u64) -> Result<Self, PgpError> {
+        let packets: Vec<Packet<'_>> = read_packets(data)?
+            .into_iter()
+            .filter(|packet| packet.tag != TAG_TRUST && packet.tag != TAG_MARKER)
+            .collect();
+
+        let mut pos = 0;
+        let primary = match packets.first() {
+            Some(packet) if packet.tag == TAG_PUBLIC_KEY => parse_public_key(packet.body)?,
+            _ => return Err(PgpError::Malformed("expected a public key packet")),
+        };
+        pos += 1;
+
+        let mut framed_primary = Vec::new();
+        primary.write_framed(&mut framed_primary);
+        let self_signed = |signature: &SignaturePacket, data: &[u8]| {
+            signature.issued_by(&primary) && signature.verify(&primary, data.to_vec(), now)
+        };
+
+        // Подписи непосредственно на ключе: отзыв и direct-key
+        let mut direct_key: Option<SignaturePacket> = None;
+        for signature in take_signatures(&packets, &mut pos)? {
+            match signature.sig_type {
+                SIG_KEY_REVOCATION if self_signed(&signature, &framed_primary) => {
+                    return Err(PgpError::Revoked);
+                }
+                SIG_DIRECT_KEY if self_signed(&signature, &framed_primary) => {
+                    keep_newest(&mut direct_key, signature);
+                }
+                _ => {}
+            }
+        }
+
+        // (user ID, последняя действующая самоподпись)
+        let mut user_ids: Vec<(String, SignaturePacket)> = Vec::new();
+        let mut subkeys = Vec::new();
+
+        while let Some(packet) = packets.get(pos) {
+            pos += 1;
+            match packet.tag {
+                TAG_USER_ID => {
+                    let signatures = take_signatures(&packets, &mut pos)?;
+                    let Ok(user_id) = String::from_utf8(packet.body.to_vec()) else {
+                        continue;
+                    };
+                    if user_id.trim().is_empty() || user_id.len() > MAX_USER_ID_LEN {
+                        continue;
+                    }
+
+                    let data = user_id_data(&framed_primary, packet.body);
+
+                    let mut certification: Option<SignaturePacket> = None;
+                    let mut revoked_at = None;
+                    for signature in signatures {
+                        if !self_signed(&signature, &data) {
+                            continue;
+                        }
+                        match signature.sig_type {
+                            0x10..=0x13 => keep_newest(&mut certification, signature),
+                            SIG_CERT_REVOCATION => {
+                                revoked_at = revoked_at.max(Some(signature.created_at()));
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    // Отзыв действует, пока user ID не подписан заново
+                    if let Some(certification) = certification
+                        && revoked_at
+                            .is_none_or(|revoked_at| revoked_at < certification.created_at())
+                    {
+                        user_ids.push((user_id, certification));
+                    }
+                }
+                TAG_USER_ATTRIBUTE => {
+                    take_signatures(&packets, &mut pos)?;
+                }
+                TAG_PUBLIC_SUBKEY => {
+                    let signatures = take_signatures(&packets, &mut pos)?;
+                    // Подключи с неподдерживаемыми алгоритмами не мешают использовать ключ
+                    let key = match parse_public_key(packet.body) {
+                        Ok(key) => key,
+                        Err(PgpError::Unsupported(_)) | Err(PgpError::WeakKey(_)) => continue,
+                        Err(e) => return Err(e),
+                    };
+
+                    let mut data = framed_primary.clone();
+                    key.write_framed(&mut data);
+
+                    let mut binding: Option<SignaturePacket> = None;
+                    let mut revoked = false;
+                    for signature in signatures {
+                        if !self_signed(&signature, &data) {
+                            continue;
+                        }
+                        match signature.sig_type {
+                            SIG_SUBKEY_BINDING => keep_newest(&mut binding, signature),
+                            SIG_SUBKEY_REVOCATION => revoked = true,
+                            _ => {}
+                        }
+                    }
+
+                    let Some(binding) = binding.filter(|_| !revoked) else {
+                        continue;
+                    };
+                    let expires_at = expiry(key.created_at, binding.subpackets.key_expiration);
+                    if expires_at.is_some_and(|expires_at| expires_at <= now) {
+                        continue;
+                    }
+
+                    // Подключ для подписи требует обратной подписи, которую сервер не проверяет,
+                    // поэтому такие подключи используются только для шифрования
+                    let flags = binding.subpackets.key_flags.unwrap_or(0) & !KEY_FLAG_SIGN;
+                    subkeys.push(Subkey {
+                        key,
+                        flags,
+                        expires_at,
+                        bound_at: binding.created_at(),
+                    });
+                }
+                TAG_PUBLIC_KEY => {
+                    return Err(PgpError::Malformed("expected a single key, got a keyring"));
+                }
+                TAG_SIGNATURE => {
+                    return Err(PgpError::Malformed("unexpected signature packet"));
+                }
+                other => {
+                    return Err(PgpError::Unsupported(format!("packet tag {}", other)));
+                }
+            }
+        }
+
+        if user_ids.is_empty() {
+            return Err(PgpError::NoValidUserId);
+        }
+
+        // Основной user ID: помеченный как primary, при равенстве - с самой свежей подписью
+        user_ids.sort_by_key(|(_, signature)| {
+            std::cmp::Reverse((signature.subpackets.primary_user_id, signature.created_at()))
+        });
+
+        let (primary_flags, key_expiration) = {
+            let certification = &user_ids[0].1.subpackets;
+            let direct = direct_key.as_ref().map(|signature| &signature.subpackets);
+            (
+                certification
+                    .key_flags
+                    .or(direct.and_then(|direct| direct.key_flags)),
+                certification
+                    .key_expiration
+                    .or(direct.and_then(|direct| direct.key_expiration)),
+            )
+        };
+
+        let expires_at = expiry(primary.created_at, key_expiration);
+        if expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(PgpError::Expired);
+        }
+
+        // Самые свежие подключи первыми
+        subkeys.sort_by_key(|subkey| std::cmp::Reverse(subkey.bound_at));
+
+        Ok(Certificate {
+            primary,
+            primary_flags,
+            user_ids: user_ids.into_iter().map(|(user_id, _)| user_id).collect(),
+            subkeys,
+            expires_at,
+            binary: data.to_vec(),
+        })
+    }
+
+    // Отпечаток основного ключа в нижнем регистре: 40 hex-символов для v4, 64 для v5
+    pub fn fingerprint_hex(&self) -> String {
+        hex::encode(self.primary.fingerprint())
+    }
+
+    pub fn armored(&self) -> String {
+        armor(PUBLIC_KEY_BLOCK, &self.binary)
+    }
+
+    // Ключ, которым сервер может зашифровать данные для владельца
+    pub fn encryption_key(&self) -> Option<&PublicKeyPacket> {
+        self.encryption_subkey()
+            .map(|subkey| &subkey.key)
+            .or_else(|| {
+                let flags = self.primary_flags.unwrap_or(KEY_FLAG_ENCRYPT);
+                (flags & KEY_FLAG_ENCRYPT != 0 && self.primary.can_encrypt())
+                    .then_some(&self.primary)
+            })
+    }
+
+    // Unix-время, после которого encryption_key перестанет действовать: срок подключа
+    // для шифрования, но не позже срока основного ключа
+    pub fn encryption_expires_at(&self) -> Option<u64> {
+        let subkey_expires_at = self.encryption_subkey().and_then(|subkey| subkey.expires_at);
+        match (self.expires_at, subkey_expires_at) {
+            (Some(primary), Some(subkey)) => Some(primary.min(subkey)),
+            (primary, subkey) => primary.or(subkey),
+        }
+    }
+
+    fn encryption_subkey(&self) -> Option<&Subkey> {
+        self.subkeys
+            .iter()
+            .find(|subkey| subkey.flags & KEY_FLAG_ENCRYPT != 0 && subkey.key.can_encrypt())
+    }
+
+    // Отделённая подпись документа основным ключом: armored или base64 бинарного пакета.
+    // Err - подпись не разобрана, Ok(false) - не сошлась.
+    pub fn verify_detached(
+        &self,
+        signature: &str,
+        message: &[u8],
+        now: u64,
+    ) -> Result<bool, PgpError> {
+        let data = if signature.contains("-----BEGIN") {
+            dearmor(signature, SIGNATURE_BLOCK)?
+        } else {
+            BASE64
+                .decode(signature.trim())
+                .map_err(|_| PgpError::Armor("invalid base64"))?
+        };
+
+        let packets = read_packets(&data)?;
+        let [packet] = packets.as_slice() else {
+            return Err(PgpError::Malformed("expected a single signature packet"));
+        };
+        if packet.tag != TAG_SIGNATURE {
+            return Err(PgpError::Malformed("expected a signature packet"));
+        }
+        let Some(signature) = parse_signature(packet.body)? else {
+            return Err(PgpError::Unsupported(
+                "signature version or algorithm".to_string(),
+            ));
+        };
+
+        Ok(signature.sig_type == SIG_BINARY
+            && signature.issued_by(&self.primary)
+            && signature.verify(&self.primary, message.to_vec(), now))
+    }
+}
+
+// --- Объединение копий ключа ---
+
+// Пакет ключа, user ID или подключа вместе со следующими за ним подписями
+struct Component<'a> {
+    packet: Packet<'a>,
+    signatures: Vec<&'a [u8]>,
+}
+
+fn components(data: &[u8]) -> Result<Vec<Component<'_>>, PgpError> {
+    let mut components: Vec<Component<'_>> = Vec::new();
+    for packet in read_packets(data)? {
+        match packet.tag {
+            TAG_TRUST | TAG_MARKER => {}
+            TAG_SIGNATURE => match components.last_mut() {
+                Some(component) => component.signatures.push(packet.body),
+                None => return Err(PgpError::Malformed("expected a public key packet")),
+            },
+            _ => components.push(Component {
+                packet,
+                signatures: Vec::new(),
+            }),
+        }
+    }
+    Ok(components)
+}
+
+fn write_packet(out: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    out.push(0xC0 | tag);
+    match body.len() {
+        len @ 0..=191 => out.push(len as u8),
+        len @ 192..=8383 => {
+            let len = len - 192;
+            out.extend_from_slice(&[(len >> 8) as u8 + 192, len as u8]);
+        }
+        len => {
+            out.push(0xFF);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(body);
+}
+
+// Объединяет сохранённую копию ключа с присланной, как это делают серверы ключей:
+// пакеты только добавляются, поэтому старая копия не может убрать отзыв или вернуть
+// заменённую самоподпись. Из присланной копии берутся лишь самоподписи, которые
+// сходятся, и user ID или подключи с ними. None - присланная копия ничего не добавила.
+pub fn merge(
+    stored: &str,
+    incoming: &Certificate,
+    now: u64,
+) -> Result<Option<Certificate>, PgpError> {
+    let stored = dearmor(stored, PUBLIC_KEY_BLOCK)?;
+    let mut merged = components(&stored)?;
+    let primary = &incoming.primary;
+    match merged.first() {
+        Some(component) if component.packet.tag == TAG_PUBLIC_KEY => {
+            if component.packet.body != primary.body.as_slice() {
+                return Err(PgpError::Malformed("key does not match the stored key"));
+            }
+        }
+        _ => return Err(PgpError::Malformed("expected a public key packet")),
+    }
+
+    let mut framed_primary = Vec::new();
+    primary.write_framed(&mut framed_primary);
+
+    let mut changed = false;
+    for component in components(&incoming.binary)? {
+        let data = match component.packet.tag {
+            TAG_PUBLIC_KEY => framed_primary.clone(),
+            TAG_USER_ID => user_id_data(&framed_primary, component.packet.body),
+            TAG_PUBLIC_SUBKEY => match parse_public_key(component.packet.body) {
+                Ok(key) => {
+                    let mut data = framed_primary.clone();
+                    key.write_framed(&mut data);
+                    data
+                }
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+
+        let self_signed = |body: &&[u8]| {
+            matches!(parse_signature(body), Ok(Some(signature))
+                if signature.issued_by(primary) && signature.verify(primary, data.clone(), now))
+        };
+        let signatures: Vec<&[u8]> = component
+            .signatures
+            .iter()
+            .copied()
+            .filter(self_signed)
+            .collect();
+
+        let existing = merged.iter_mut().find(|known| {
+            known.packet.tag == component.packet.tag && known.packet.body == component.packet.body
+        });
+        match existing {
+            Some(known) => {
+                for signature in signatures {
+                    if !known.signatures.contains(&signature) {
+                        known.signatures.push(signature);
+                        changed = true;
+                    }
+                }
+            }
+            None if !signatures.is_empty() => {
+                merged.push(Component {
+                    packet: component.packet,
+                    signatures,
+                });
+                changed = true;
+            }
+            None => {}
+        }
+    }
+
+    if !changed {
+        return Ok(None);
+    }
+
+    let mut data = Vec::new();
+    for component in &merged {
+        write_packet(&mut data, component.packet.tag, component.packet.body);
+        for signature in &component.signatures {
+            write_packet(&mut data, TAG_SIGNATURE, signature);
+        }
+    }
+    Certificate::from_bytes(&data, now).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ключи сгенерированы GnuPG 2.2: Ed25519 с подключом Curve25519, затем добавлен второй
+    // подключ (NEW), затем второй подключ отозван (REVOKED)
+    const NOW: u64 = 1_800_000_000;
+    const FINGERPRINT: &str = "63cdafa39a8c44a8bcd95a5817ce9639d89677cc";
+
+    const OLD: &str = r"-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEatXgABYJKwYBBAHaRw8BAQdA3Zowst7xTt4O1gtr0ROQpIdoUoSiywWKvYjO
+Mc+qYjm0GUFsaWNlIDxhbGljZUBleGFtcGxlLm9yZz6IkAQTFggAOBYhBGPNr6Oa
+jESovNlaWBfOljnYlnfMBQJq1eAAAhsBBQsJCAcCBhUKCQgLAgQWAgMBAh4BAheA
+AAoJEBfOljnYlnfM868A/3Ilf6qP2H5AHVNS4UrYg+zVEA4PqXtl0EqgQvsvsVaR
+AP9zAxCX7J2cdiw4pQAPrgHOlYBkbVuThtOnSCli81e+BLg4BGrV4AASCisGAQQB
+l1UBBQEBB0DdDRHNUtDdOTqIGuIZiMj3YEmpZcvXtdlrCRtpPFx0KQMBCAeIeAQY
+FggAIBYhBGPNr6OajESovNlaWBfOljnYlnfMBQJq1eAAAhsMAAoJEBfOljnYlnfM
+vK0BAKrqE6V8XvRHpvWKD+eZp6hGoozT8jaNsxdGX7gTzL5vAQCYIvQ/KJILAIoa
+r93m/0hRhL1Ck37sVASAxuGXeofSDg==
+=Q4ik
+-----END PGP PUBLIC KEY BLOCK-----
+";
+
+    const NEW: &str = r"-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEatXgABYJKwYBBAHaRw8BAQdA3Zowst7xTt4O1gtr0ROQpIdoUoSiywWKvYjO
+Mc+qYjm0GUFsaWNlIDxhbGljZUBleGFtcGxlLm9yZz6IkAQTFggAOBYhBGPNr6Oa
+jESovNlaWBfOljnYlnfMBQJq1eAAAhsBBQsJCAcCBhUKCQgLAgQWAgMBAh4BAheA
+AAoJEBfOljnYlnfM868A/3Ilf6qP2H5AHVNS4UrYg+zVEA4PqXtl0EqgQvsvsVaR
+AP9zAxCX7J2cdiw4pQAPrgHOlYBkbVuThtOnSCli81e+BLg4BGrV4AASCisGAQQB
+l1UBBQEBB0DdDRHNUtDdOTqIGuIZiMj3YEmpZcvXtdlrCRtpPFx0KQMBCAeIeAQY
+FggAIBYhBGPNr6OajESovNlaWBfOljnYlnfMBQJq1eAAAhsMAAoJEBfOljnYlnfM
+vK0BAKrqE6V8XvRHpvWKD+eZp6hGoozT8jaNsxdGX7gTzL5vAQCYIvQ/KJILAIoa
+r93m/0hRhL1Ck37sVASAxuGXeofSDrg4BGrV4AASCisGAQQBl1UBBQEBB0BPDrwb
+K/dXeHt+jQ1K8Y455zfxMnjbtfpxl6y+2lZPGQMBCAeIeAQYFggAIBYhBGPNr6Oa
+jESovNlaWBfOljnYlnfMBQJq1eAAAhsMAAoJEBfOljnYlnfMm94BANxoxWZHPsP7
+EEuB0C/XZf7ew9t1AL27FQFZ5lVFfsOfAQCMAxzo+Azi336HqL/pnqkiT3Z8UA1h
+WBZUNa0Mtxs3CQ==
+=QYFe
+-----END PGP PUBLIC KEY BLOCK-----
+";
+
+    const REVOKED: &str = r"-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mDMEatXgABYJKwYBBAHaRw8BAQdA3Zowst7xTt4O1gtr0ROQpIdoUoSiywWKvYjO
+Mc+qYjm0GUFsaWNlIDxhbGljZUBleGFtcGxlLm9yZz6IkAQTFggAOBYhBGPNr6Oa
+jESovNlaWBfOljnYlnfMBQJq1eAAAhsBBQsJCAcCBhUKCQgLAgQWAgMBAh4BAheA
+AAoJEBfOljnYlnfM868A/3Ilf6qP2H5AHVNS4UrYg+zVEA4PqXtl0EqgQvsvsVaR
+AP9zAxCX7J2cdiw4pQAPrgHOlYBkbVuThtOnSCli81e+BLg4BGrV4AASCisGAQQB
+l1UBBQEBB0DdDRHNUtDdOTqIGuIZiMj3YEmpZcvXtdlrCRtpPFx0KQMBCAeIeAQY
+FggAIBYhBGPNr6OajESovNlaWBfOljnYlnfMBQJq1eAAAhsMAAoJEBfOljnYlnfM
+vK0BAKrqE6V8XvRHpvWKD+eZp6hGoozT8jaNsxdGX7gTzL5vAQCYIvQ/KJILAIoa
+r93m/0hRhL1Ck37sVASAxuGXeofSDrg4BGrV4AASCisGAQQBl1UBBQEBB0BPDrwb
+K/dXeHt+jQ1K8Y455zfxMnjbtfpxl6y+2lZPGQMBCAeIeAQoFggAIBYhBGPNr6Oa
+jESovNlaWBfOljnYlnfMBQJq1eACAh0AAAoJEBfOljnYlnfMHe0A/2YepRipV6/2
+d3oxCoR6XR9IpKgmKPPco0I/+Uln/h2pAQCD1ov4m2DSKcNHEl0Oh1t+Ytq38c+p
+NiZWj7Vl504IDoh4BBgWCAAgFiEEY82vo5qMRKi82VpYF86WOdiWd8wFAmrV4AAC
+GwwACgkQF86WOdiWd8yb3gEA3GjFZkc+w/sQS4HQL9dl/t7D23UAvbsVAVnmVUV+
+w58BAIwDHOj4DOLffoeov+meqSJPdnxQDWFYFlQ1rQy3GzcJ
+=ipQ4
+-----END PGP PUBLIC KEY BLOCK-----
+";
+
+    #[test]
+    fn merge_adds_new_subkeys_and_revocations() {
+        let old = Certificate::from_armored(OLD, NOW).unwrap();
+        let new = Certificate::from_armored(NEW, NOW).unwrap();
+        assert_eq!(old.fingerprint_hex(), FINGERPRINT);
+        assert_eq!(old.subkeys.len(), 1);
+
+        let merged = merge(OLD, &new, NOW).unwrap().unwrap();
+        assert_eq!(merged.subkeys.len(), 2);
+        assert_eq!(
+            merged.encryption_key().unwrap().fingerprint(),
+            new.encryption_key().unwrap().fingerprint()
+        );
+
+        let revoked = Certificate::from_armored(REVOKED, NOW).unwrap();
+        let merged = merge(&merged.armored(), &revoked, NOW).unwrap().unwrap();
+        assert_eq!(merged.subkeys.len(), 1);
+        assert_eq!(
+            merged.encryption_key().unwrap().fingerprint(),
+            old.encryption_key().unwrap().fingerprint()
+        );
+    }
+
+    #[test]
+    fn merge_never_drops_revocations() {
+        let stored = merge(OLD, &Certificate::from_armored(REVOKED, NOW).unwrap(), NOW)
+            .unwrap()
+            .unwrap();
+
+        // Устаревшие копии ничего не добавляют и не возвращают отозванный подключ
+        for replay in [OLD, NEW] {
+            let replay = Certificate::from_armored(replay, NOW).unwrap();
+            assert!(merge(&stored.armored(), &replay, NOW).unwrap().is_none());
+        }
+        assert!(
+            merge(REVOKED, &Certificate::from_armored(NEW, NOW).unwrap(), NOW)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    // RSA-2048 (v4): основной ключ только для подписи, подключ для шифрования на два года
+    const BOB_FINGERPRINT: &str = "0b87b5b107125902a4fb2c2a853f7606e37f4a76";
+    const BOB_SUBKEY_EXPIRES_AT: u64 = 1_855_474_285;
+
+    const BOB: &str = r"-----BEGIN PGP PUBLIC KEY BLOCK-----
+
+mQENBGrV420BCADuM3kykMC1W0bKIQctLfJ3y4sZJ1epWpJj93k+efO22FuDSL+m
+1cnjpKfm3gqwociab5oem1E5vM6Hm3iqwQtLIWB3j/XUcU0L6An4ww8wLMJczoOc
+bUMtbe4x9jsltr9sGkI1VV57CeTtNf27U11jlrROv8GKmdVOgqgmL3xesZxMQmHT
+gX+KkWCGEb/V4hepnjPT8vJvnZOqprWAUl8159zxVkDLzk6+ee1aUaa0ahsYhpnY
+4KOjRxjjmPUCpxSNBsrVQeQt9YQr45NA3zzSWGkkOUsnX9xTIbwpVoJtQvj4J7ZS
+3PADuOWr8EaqFppkKdrHZK611zj4MlI0gmhdABEBAAG0FUJvYiA8Ym9iQGV4YW1w
+bGUub3JnPokBTgQTAQoAOBYhBAuHtbEHElkCpPssKoU/dgbjf0p2BQJq1eNtAhsD
+BQsJCAcCBhUKCQgLAgQWAgMBAh4BAheAAAoJEIU/dgbjf0p2SGkH/1wSO7Ays5nL
+/Xdc+kMwC+ezVnVG9u6VMt/a7C9k2wjcojzxFfnpwA8sevr6bBPI5GOP63LHKzjZ
+l+dFWTwpU1SN9KRoS7NkmYb1FF4bxQJ0MQTlW1wO3m2e89hdv24A3INJl980NFOL
+4X3teIEqh69+ED+10tusweCU1rGxUHAqOMjOFML6AeUBRpAX/dtjkKC4ymGJYtsc
+FlqFx79yfYKbZX1Fk0K4MTHY83i3+a1agxLZnCIvFkTHuuwnXMKUElq+5RkGbHEA
+daSFKmJT7LnCHElJnLm0A1czIyNut2dNVFy8Xad7quN9vlinn+vmbsNA7xak+qsy
+iSNOkRN6LwS5AQ0EatXjbQEIAN6QvErOxTxtLxFI8ZG2ib9rHx/cGs0wxbtMfhjs
+rO2q/o0BsWpSHlUcGuTCCWaFgzpWTFRRuOzEs0hj3q96xuDN1IM6NvKvwMNIppB8
+ih7ooUY5KJosiEbzPCR751t4P/5T7r4Y7Vnt70OljuPo3GYocZjvN/4Rcr4vlUhX
+D76pxsJovi3/XwpxhE8NfPYnLP/wUIrrD/JwYif5kdmSRQv68Am0mtRESN+5Y4ZI
+6DiekXc8heN7qWZ+GHGGdjE2wdEsCnS7HPsGRrNpjUXoxXcE99CnCUBSpH3vyJum
+kD2ejbLTe/l66N2OeUhiqRPdaE5uAga+Zekkc3vk7prDXGsAEQEAAYkBPAQYAQoA
+JhYhBAuHtbEHElkCpPssKoU/dgbjf0p2BQJq1eNtAhsMBQkDwmcAAAoJEIU/dgbj
+f0p2e/sH/1tL+C1l8MzKBf1PhSFDNA8k1o64/U7c5uLnEOMWnY3mf17B7pItBxSh
+eeHTCl2ANfamSpegMY0eMOLPK4fQMLgWcId2R/vm0SJpaz2Obj2ximpxbqTuHArm
+/+b16d2ZCNJgkJEnJxMw2HvVNirs4qU8d8b7aznAGiN7jl2/xH1//b+kZ3D2wYkX
+Pv25qkxspe+CRLWwFc9ESiIfQDY0g9u6hKwQHrHL+zkJNpp16vgNpSWKTMT0zX/N
+LlUH2I+qHosijZ2jJvmrKo9zN6+ge6fVP/HD9vHzhGeiEmZ6om7cKwCr31PkGRJS
+FPJaQ/HK4wy6Dx470embZeQvmZMSLa0=
+=Qc4Y
+-----END PGP PUBLIC KEY BLOCK-----
+";
+
+    // gpg --detach-sign --armor над "hello nesfinch" ключом BOB
+    const BOB_SIGNATURE: &str = r"-----BEGIN PGP SIGNATURE-----
+
+iQEzBAABCAAdFiEEC4e1sQcSWQKk+ywqhT92BuN/SnYFAmrV43AACgkQhT92BuN/
+SnZKuwgAp++CKZCeaxKGH9EjvlPBO5eR2G3EtP9Bt30nGJxgzvK9Ft/ShZZ1/XGm
+tsM6ik9oRfqpU4xS2jMeUylLnu5ie1grViefsdvqGlkgKcekH/yM9ssSkZGAMHry
+Pp42pB70aTY01qWIVUF9z8kja7mFfr6iZgj8zvwGM7KI/LvID8wqqcb70VRcHtlW
+Qg9S6RhyPaXesQ+EnuWmnH5XbORvBUMKPXbhluVYBr7vMP31N0GdEFmjGWBSDFO7
+ayaXZM58nDMPIowcx0PbllcbLC6Vii3RBtnKkZfAm39bUE1is4RsFpGdnyEr6+Xa
+jz8fnXr12GEnPXlNl2WMUCToFkUxqQ==
+=OGBT
+-----END PGP SIGNATURE-----
+";
+
+    // Переписывает пакеты ключа, портя последний байт пакета с номером index
+    fn tamper(data: &[u8], index: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (i, packet) in read_packets(data).unwrap().iter().enumerate() {
+            let mut body = packet.body.to_vec();
+            if i == index {
+                *body.last_mut().unwrap() ^= 0x01;
+            }
+            write_packet(&mut out, packet.tag, &body);
+        }
+        out
+    }
+
+    #[test]
+    fn parses_v4_keys() {
+        let alice = Certificate::from_armored(OLD, NOW).unwrap();
+        assert_eq!(alice.primary.version, 4);
+        assert_eq!(alice.user_ids, ["Alice <alice@example.org>"]);
+        assert_eq!(alice.expires_at, None);
+        assert!(matches!(
+            alice.encryption_key().unwrap().material,
+            KeyMaterial::X25519(_)
+        ));
+
+        let bob = Certificate::from_armored(BOB, NOW).unwrap();
+        assert_eq!(bob.fingerprint_hex(), BOB_FINGERPRINT);
+        assert_eq!(bob.primary.bits(), 2048);
+        assert_eq!(bob.user_ids, ["Bob <bob@example.org>"]);
+        assert_ne!(
+            bob.encryption_key().unwrap().fingerprint(),
+            bob.primary.fingerprint()
+        );
+        assert!(bob.encryption_key().unwrap().rsa_public_key().is_some());
+        // Основной ключ бессрочный, но шифровать можно только до истечения подключа
+        assert_eq!(bob.expires_at, None);
+        assert_eq!(bob.encryption_expires_at(), Some(BOB_SUBKEY_EXPIRES_AT));
+    }
+
+    #[test]
+    fn expired_encryption_subkey_is_dropped() {
+        let bob = Certificate::from_armored(BOB, BOB_SUBKEY_EXPIRES_AT).unwrap();
+        assert!(bob.subkeys.is_empty());
+        // Основной ключ помечен только для подписи и не заменяет подключ
+        assert!(bob.encryption_key().is_none());
+        assert_eq!(bob.encryption_expires_at(), None);
+    }
+
+    #[test]
+    fn verifies_detached_signatures() {
+        let bob = Certificate::from_armored(BOB, NOW).unwrap();
+        assert_eq!(
+            bob.verify_detached(BOB_SIGNATURE, b"hello nesfinch", NOW),
+            Ok(true)
+        );
+        assert_eq!(
+            bob.verify_detached(BOB_SIGNATURE, b"hello nesfinch!", NOW),
+            Ok(false)
+        );
+
+        let mut binary = dearmor(BOB_SIGNATURE, SIGNATURE_BLOCK).unwrap();
+        *binary.last_mut().unwrap() ^= 0x01;
+        assert_eq!(
+            bob.verify_detached(&BASE64.encode(&binary), b"hello nesfinch", NOW),
+            Ok(false)
+        );
+
+        // Подпись чужого ключа
+        let alice = Certificate::from_armored(OLD, NOW).unwrap();
+        assert_eq!(
+            alice.verify_detached(BOB_SIGNATURE, b"hello nesfinch", NOW),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_self_signatures() {
+        let data = dearmor(OLD, PUBLIC_KEY_BLOCK).unwrap();
+        assert!(Certificate::from_bytes(&tamper(&data, usize::MAX), NOW).is_ok());
+
+        // Пакеты: основной ключ, user ID, его самоподпись, подключ, привязка подключа
+        for index in 0..=2 {
+            assert_eq!(
+                Certificate::from_bytes(&tamper(&data, index), NOW).unwrap_err(),
+                PgpError::NoValidUserId
+            );
+        }
+        for index in 3..=4 {
+            let certificate = Certificate::from_bytes(&tamper(&data, index), NOW).unwrap();
+            assert!(certificate.subkeys.is_empty());
+            assert!(certificate.encryption_key().is_none());
+        }
+    }
+
+    // Ключ v5 (Ed25519) с одним user ID; GnuPG 2.2 таких не создаёт, поэтому собирается здесь
+    fn v5_certificate(signing_key: &ed25519_dalek::SigningKey, user_id: &[u8]) -> Vec<u8> {
+        use ed25519_dalek::Signer;
+
+        let created_at = (NOW - 3600) as u32;
+        let point = signing_key.verifying_key().to_bytes();
+        let mut key_body = vec![5];
+        key_body.extend_from_slice(&created_at.to_be_bytes());
+        key_body.push(ALGO_ED25519);
+        key_body.extend_from_slice(&(point.len() as u32).to_be_bytes());
+        key_body.extend_from_slice(&point);
+        let key = parse_public_key(&key_body).unwrap();
+        let mut framed = Vec::new();
+        key.write_framed(&mut framed);
+
+        let mut subpackets = vec![5, SUB_CREATION_TIME];
+        subpackets.extend_from_slice(&created_at.to_be_bytes());
+        subpackets.extend_from_slice(&[34, SUB_ISSUER_FINGERPRINT, 5]);
+        subpackets.extend_from_slice(&key.fingerprint());
+        subpackets.extend_from_slice(&[2, SUB_KEY_FLAGS, 0x03]);
+
+        let mut hashed_part = vec![5, 0x13, ALGO_ED25519, HASH_SHA256];
+        hashed_part.extend_from_slice(&(subpackets.len() as u16).to_be_bytes());
+        hashed_part.extend_from_slice(&subpackets);
+
+        let mut data = user_id_data(&framed, user_id);
+        data.extend_from_slice(&hashed_part);
+        data.extend_from_slice(&[0x05, 0xFF]);
+        data.extend_from_slice(&(hashed_part.len() as u64).to_be_bytes());
+        let hash = Sha256::digest(&data);
+
+        let mut signature = hashed_part;
+        signature.extend_from_slice(&[0, 0]);
+        signature.extend_from_slice(&hash[..2]);
+        signature.extend_from_slice(&signing_key.sign(&hash).to_bytes());
+
+        let mut out = Vec::new();
+        write_packet(&mut out, TAG_PUBLIC_KEY, &key_body);
+        write_packet(&mut out, TAG_USER_ID, user_id);
+        write_packet(&mut out, TAG_SIGNATURE, &signature);
+        out
+    }
+
+    #[test]
+    fn parses_v5_keys() {
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+        let data = v5_certificate(&signing_key, b"Carol <carol@example.org>");
+
+        let carol = Certificate::from_bytes(&data, NOW).unwrap();
+        assert_eq!(carol.primary.version, 5);
+        assert_eq!(carol.fingerprint_hex().len(), 64);
+        assert_eq!(carol.primary.key_id(), carol.primary.fingerprint()[..8]);
+        assert_eq!(carol.user_ids, ["Carol <carol@example.org>"]);
+        assert_eq!(carol.primary_flags, Some(0x03));
+        assert!(carol.encryption_key().is_none());
+
+        for index in 0..=2 {
+            assert_eq!(
+                Certificate::from_bytes(&tamper(&data, index), NOW).unwrap_err(),
+                PgpError::NoValidUserId
+            );
+        }
+    }
+}
@@ -0,0 +1,391 @@
+// Эталонная реализация Double Ratchet (https://signal.org/docs/specifications/doubleratchet/)
+// для ботов и тестового окружения. Формат совпадает с MessageEnvelope из service_chat.proto.
+//
+// KDF_RK: HKDF-SHA256 (salt = root key, ikm = DH output) -> новый root key и chain key
+// KDF_CK: HMAC-SHA256(chain key, 0x01) -> message key, HMAC-SHA256(chain key, 0x02) -> chain key
+// ENCRYPT: AES-256-GCM, ключ и nonce из HKDF-SHA256(message key),
+//          AAD = associated data || закодированный заголовок
+
+use std::collections::HashMap;
+use std::fmt;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroize;
+
+// Версия конверта, которую принимает сервер
+pub const ENVELOPE_VERSION: u32 = 1;
+pub const KEY_SIZE: usize = 32;
+pub const HEADER_SIZE: usize = KEY_SIZE + 8;
+// Предел пропущенных ключей в одной цепочке
+pub const MAX_SKIP: u32 = 1000;
+
+const ROOT_INFO: &[u8] = b"nesfinch-ratchet-root:v1";
+const MESSAGE_INFO: &[u8] = b"nesfinch-ratchet-message:v1";
+const NONCE_SIZE: usize = 12;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RatchetError {
+    // Получатель ещё не знает ключ собеседника и не может отправлять
+    NotInitialized,
+    TooManySkipped,
+    // Номер сообщения уже использован или ключ удалён
+    DuplicateMessage,
+    DecryptionFailed,
+    InvalidHeader,
+}
+
+impl fmt::Display for RatchetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RatchetError::NotInitialized => write!(f, "Sending chain is not initialized"),
+            RatchetError::TooManySkipped => write!(f, "Too many skipped messages"),
+            RatchetError::DuplicateMessage => write!(f, "Message key already used"),
+            RatchetError::DecryptionFailed => write!(f, "Decryption failed"),
+            RatchetError::InvalidHeader => write!(f, "Invalid ratchet header"),
+        }
+    }
+}
+
+impl std::error::Error for RatchetError {}
+
+// Заголовок, передаваемый открыто вместе с шифртекстом
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RatchetHeader {
+    pub ratchet_public_key: [u8; KEY_SIZE],
+    pub previous_chain_length: u32,
+    pub message_number: u32,
+}
+
+impl RatchetHeader {
+    // ratchet_public_key || pn (BE) || n (BE)
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut out = [0u8; HEADER_SIZE];
+        out[..KEY_SIZE].copy_from_slice(&self.ratchet_public_key);
+        out[KEY_SIZE..KEY_SIZE + 4].copy_from_slice(&self.previous_chain_length.to_be_bytes());
+        out[KEY_SIZE + 4..].copy_from_slice(&self.message_number.to_be_bytes());
+        out
+    }
+
+    pub fn from_parts(ratchet_public_key: &[u8], previous_chain_length: u32, message_number: u32) -> Result<Self, RatchetError> {
+        let ratchet_public_key = ratchet_public_key
+            .try_into()
+            .map_err(|_| RatchetError::InvalidHeader)?;
+        Ok(Self { ratchet_public_key, previous_chain_length, message_number })
+    }
+}
+
+#[derive(Clone)]
+pub struct RatchetSession {
+    dh_self: StaticSecret,
+    dh_remote: Option<[u8; KEY_SIZE]>,
+    root_key: [u8; KEY_SIZE],
+    sending_chain: Option<[u8; KEY_SIZE]>,
+    receiving_chain: Option<[u8; KEY_SIZE]>,
+    sent_count: u32,
+    received_count: u32,
+    previous_chain_length: u32,
+    skipped: HashMap<([u8; KEY_SIZE], u32), [u8; KEY_SIZE]>,
+}
+
+impl fmt::Debug for RatchetSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RatchetSession")
+            .field("sent_count", &self.sent_count)
+            .field("received_count", &self.received_count)
+            .field("skipped", &self.skipped.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for RatchetSession {
+    fn drop(&mut self) {
+        self.root_key.zeroize();
+        self.sending_chain.zeroize();
+        self.receiving_chain.zeroize();
+        for key in self.skipped.values_mut() {
+            key.zeroize();
+        }
+    }
+}
+
+impl RatchetSession {
+    // Инициатор: shared_secret - результат X3DH, remote_ratchet_key - подписанный предключ собеседника
+    pub fn init_sender(shared_secret: [u8; KEY_SIZE], remote_ratchet_key: [u8; KEY_SIZE]) -> Self {
+        let dh_self = StaticSecret::random_from_rng(OsRng);
+        let dh_out = dh_self.diffie_hellman(&PublicKey::from(remote_ratchet_key));
+        let (root_key, sending_chain) = kdf_root(&shared_secret, dh_out.as_bytes());
+
+        Self {
+            dh_self,
+            dh_remote: Some(remote_ratchet_key),
+            root_key,
+            sending_chain: Some(sending_chain),
+            receiving_chain: None,
+            sent_count: 0,
+            received_count: 0,
+            previous_chain_length: 0,
+            skipped: HashMap::new(),
+        }
+    }
+
+    // Получатель: ratchet_secret - секрет подписанного предключа
+    pub fn init_receiver(shared_secret: [u8; KEY_SIZE], ratchet_secret: StaticSecret) -> Self {
+        Self {
+            dh_self: ratchet_secret,
+            dh_remote: None,
+            root_key: shared_secret,
+            sending_chain: None,
+            receiving_chain: None,
+            sent_count: 0,
+            received_count: 0,
+            previous_chain_length: 0,
+            skipped: HashMap::new(),
+        }
+    }
+
+    pub fn ratchet_public_key(&self) -> [u8; KEY_SIZE] {
+        PublicKey::from(&self.dh_self).to_bytes()
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8], associated_data: &[u8]) -> Result<(RatchetHeader, Vec<u8>), RatchetError> {
+        let chain = self.sending_chain.as_mut().ok_or(RatchetError::NotInitialized)?;
+        let message_key = kdf_chain(chain);
+
+        let header = RatchetHeader {
+            ratchet_public_key: self.ratchet_public_key(),
+            previous_chain_length: self.previous_chain_length,
+            message_number: self.sent_count,
+        };
+        self.sent_count += 1;
+
+        let ciphertext = seal(&message_key, plaintext, associated_data, &header)?;
+        Ok((header, ciphertext))
+    }
+
+    // При ошибке состояние сессии не меняется
+    pub fn decrypt(&mut self, header: &RatchetHeader, ciphertext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, RatchetError> {
+        let skipped_id = (header.ratchet_public_key, header.message_number);
+        if let Some(message_key) = self.skipped.get(&skipped_id) {
+            let plaintext = open(message_key, ciphertext, associated_data, header)?;
+            self.skipped.remove(&skipped_id);
+            return Ok(plaintext);
+        }
+
+        let mut next = self.clone();
+        if next.dh_remote != Some(header.ratchet_public_key) {
+            next.skip_message_keys(header.previous_chain_length)?;
+            next.dh_ratchet(header.ratchet_public_key);
+        }
+        if header.message_number < next.received_count {
+            return Err(RatchetError::DuplicateMessage);
+        }
+        next.skip_message_keys(header.message_number)?;
+
+        let chain = next.receiving_chain.as_mut().ok_or(RatchetError::DecryptionFailed)?;
+        let message_key = kdf_chain(chain);
+        next.received_count += 1;
+
+        let plaintext = open(&message_key, ciphertext, associated_data, header)?;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    fn skip_message_keys(&mut self, until: u32) -> Result<(), RatchetError> {
+        let Some(chain) = self.receiving_chain.as_mut() else {
+            return Ok(());
+        };
+        if until.saturating_sub(self.received_count) > MAX_SKIP {
+            return Err(RatchetError::TooManySkipped);
+        }
+        let remote = self.dh_remote.ok_or(RatchetError::InvalidHeader)?;
+
+        while self.received_count < until {
+            let message_key = kdf_chain(chain);
+            self.skipped.insert((remote, self.received_count), message_key);
+            self.received_count += 1;
+        }
+        Ok(())
+    }
+
+    fn dh_ratchet(&mut self, remote_ratchet_key: [u8; KEY_SIZE]) {
+        self.previous_chain_length = self.sent_count;
+        self.sent_count = 0;
+        self.received_count = 0;
+        self.dh_remote = Some(remote_ratchet_key);
+
+        let remote = PublicKey::from(remote_ratchet_key);
+        let dh_out = self.dh_self.diffie_hellman(&remote);
+        let (root_key, receiving_chain) = kdf_root(&self.root_key, dh_out.as_bytes());
+        self.root_key = root_key;
+        self.receiving_chain = Some(receiving_chain);
+
+        self.dh_self = StaticSecret::random_from_rng(OsRng);
+        let dh_out = self.dh_self.diffie_hellman(&remote);
+        let (root_key, sending_chain) = kdf_root(&self.root_key, dh_out.as_bytes());
+        self.root_key = root_key;
+        self.sending_chain = Some(sending_chain);
+    }
+}
+
+fn kdf_root(root_key: &[u8; KEY_SIZE], dh_out: &[u8]) -> ([u8; KEY_SIZE], [u8; KEY_SIZE]) {
+    let mut okm = [0u8; KEY_SIZE * 2];
+    Hkdf::<Sha256>::new(Some(root_key), dh_out)
+        .expand(ROOT_INFO, &mut okm)
+        .expect("HKDF output length is valid");
+
+    let mut root = [0u8; KEY_SIZE];
+    let mut chain = [0u8; KEY_SIZE];
+    root.copy_from_slice(&okm[..KEY_SIZE]);
+    chain.copy_from_slice(&okm[KEY_SIZE..]);
+    okm.zeroize();
+    (root, chain)
+}
+
+// Продвигает цепочку и возвращает ключ сообщения
+fn kdf_chain(chain: &mut [u8; KEY_SIZE]) -> [u8; KEY_SIZE] {
+    let step = |constant: u8| -> [u8; KEY_SIZE] {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(chain).expect("HMAC accepts any key length");
+        mac.update(&[constant]);
+        mac.finalize().into_bytes().into()
+    };
+
+    let message_key = step(0x01);
+    let next_chain = step(0x02);
+    *chain = next_chain;
+    message_key
+}
+
+fn message_cipher(message_key: &[u8; KEY_SIZE]) -> (Aes256Gcm, [u8; NONCE_SIZE]) {
+    let mut okm = [0u8; KEY_SIZE + NONCE_SIZE];
+    Hkdf::<Sha256>::new(None, message_key)
+        .expand(MESSAGE_INFO, &mut okm)
+        .expect("HKDF output length is valid");
+
+    let cipher = Aes256Gcm::new_from_slice(&okm[..KEY_SIZE]).expect("AES-256 key length is valid");
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce.copy_from_slice(&okm[KEY_SIZE..]);
+    okm.zeroize();
+    (cipher, nonce)
+}
+
+fn aad(associated_data: &[u8], header: &RatchetHeader) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(associated_data.len() + HEADER_SIZE);
+    aad.extend_from_slice(associated_data);
+    aad.extend_from_slice(&header.encode());
+    aad
+}
+
+fn seal(message_key: &[u8; KEY_SIZE], plaintext: &[u8], associated_data: &[u8], header: &RatchetHeader) -> Result<Vec<u8>, RatchetError> {
+    let (cipher, nonce) = message_cipher(message_key);
+    let aad = aad(associated_data, header);
+    cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &aad })
+        .map_err(|_| RatchetError::DecryptionFailed)
+}
+
+fn open(message_key: &[u8; KEY_SIZE], ciphertext: &[u8], associated_data: &[u8], header: &RatchetHeader) -> Result<Vec<u8>, RatchetError> {
+    let (cipher, nonce) = message_cipher(message_key);
+    let aad = aad(associated_data, header);
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: ciphertext, aad: &aad })
+        .map_err(|_| RatchetError::DecryptionFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AD: &[u8] = b"alice->bob";
+
+    fn pair() -> (RatchetSession, RatchetSession) {
+        let shared_secret = [7u8; KEY_SIZE];
+        let bob_prekey = StaticSecret::random_from_rng(OsRng);
+        let alice = RatchetSession::init_sender(shared_secret, PublicKey::from(&bob_prekey).to_bytes());
+        let bob = RatchetSession::init_receiver(shared_secret, bob_prekey);
+        (alice, bob)
+    }
+
+    #[test]
+    fn dh_ratchet_round_trip() {
+        let (mut alice, mut bob) = pair();
+        assert_eq!(bob.encrypt(b"too early", AD), Err(RatchetError::NotInitialized));
+
+        // Каждая смена направления - новый шаг DH-храповика
+        for round in 0..3u8 {
+            let (header, ciphertext) = alice.encrypt(&[round; 3], AD).unwrap();
+            assert_eq!(bob.decrypt(&header, &ciphertext, AD).unwrap(), vec![round; 3]);
+
+            let (reply_header, reply) = bob.encrypt(&[round + 100; 5], AD).unwrap();
+            assert_ne!(reply_header.ratchet_public_key, header.ratchet_public_key);
+            assert_eq!(alice.decrypt(&reply_header, &reply, AD).unwrap(), vec![round + 100; 5]);
+        }
+    }
+
+    #[test]
+    fn out_of_order_and_skipped_messages() {
+        let (mut alice, mut bob) = pair();
+        let messages: Vec<_> = (0..4u8).map(|i| alice.encrypt(&[i], AD).unwrap()).collect();
+
+        let (header, ciphertext) = &messages[2];
+        assert_eq!(bob.decrypt(header, ciphertext, AD).unwrap(), vec![2]);
+        let (header, ciphertext) = &messages[0];
+        assert_eq!(bob.decrypt(header, ciphertext, AD).unwrap(), vec![0]);
+
+        // Ответ Боба сдвигает храповик; пропущенное сообщение старой цепочки всё ещё читается
+        let (reply_header, reply) = bob.encrypt(b"ack", AD).unwrap();
+        assert_eq!(alice.decrypt(&reply_header, &reply, AD).unwrap(), b"ack");
+        let (next_header, next) = alice.encrypt(b"next", AD).unwrap();
+        assert_eq!(next_header.previous_chain_length, 4);
+        assert_eq!(bob.decrypt(&next_header, &next, AD).unwrap(), b"next");
+
+        for i in [3, 1] {
+            let (header, ciphertext) = &messages[i];
+            assert_eq!(bob.decrypt(header, ciphertext, AD).unwrap(), vec![i as u8]);
+        }
+    }
+
+    #[test]
+    fn replay_and_tampering_are_rejected() {
+        let (mut alice, mut bob) = pair();
+        let (header, ciphertext) = alice.encrypt(b"once", AD).unwrap();
+        bob.decrypt(&header, &ciphertext, AD).unwrap();
+        assert_eq!(bob.decrypt(&header, &ciphertext, AD), Err(RatchetError::DuplicateMessage));
+
+        let (header, mut ciphertext) = alice.encrypt(b"twice", AD).unwrap();
+        assert_eq!(bob.decrypt(&header, &ciphertext, b"other"), Err(RatchetError::DecryptionFailed));
+        ciphertext[0] ^= 1;
+        assert_eq!(bob.decrypt(&header, &ciphertext, AD), Err(RatchetError::DecryptionFailed));
+
+        // Неудачная расшифровка не портит состояние
+        ciphertext[0] ^= 1;
+        assert_eq!(bob.decrypt(&header, &ciphertext, AD).unwrap(), b"twice");
+    }
+
+    #[test]
+    fn too_many_skipped_messages() {
+        let (mut alice, mut bob) = pair();
+        let (header, ciphertext) = alice.encrypt(b"first", AD).unwrap();
+        bob.decrypt(&header, &ciphertext, AD).unwrap();
+
+        let (mut header, ciphertext) = alice.encrypt(b"far", AD).unwrap();
+        header.message_number = MAX_SKIP + 2;
+        assert_eq!(bob.decrypt(&header, &ciphertext, AD), Err(RatchetError::TooManySkipped));
+    }
+
+    #[test]
+    fn header_encoding() {
+        let header = RatchetHeader { ratchet_public_key: [9; KEY_SIZE], previous_chain_length: 3, message_number: 258 };
+        let encoded = header.encode();
+        assert_eq!(&encoded[KEY_SIZE..], &[0, 0, 0, 3, 0, 0, 1, 2]);
+        assert_eq!(RatchetHeader::from_parts(&encoded[..KEY_SIZE], 3, 258), Ok(header));
+        assert_eq!(RatchetHeader::from_parts(&[0; 31], 0, 0), Err(RatchetError::InvalidHeader));
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+// Ретрансляция зашифрованных аудиокадров через сервер. Сервер не расшифровывает кадры:
+// он только отбрасывает повторы и сильно опоздавшие кадры отправителя и держит для
+// каждого получателя ограниченную очередь. Медленный получатель теряет самые старые
+// кадры и не задерживает отправителя и других получателей.
+
+// ~1 с звука при кадрах по 20 мс
+const MAX_QUEUED_FRAMES: usize = 50;
+// Кадр, пролежавший в очереди дольше, уже бесполезен для воспроизведения
+const MAX_FRAME_AGE: Duration = Duration::from_millis(300);
+// Максимальный пакет Opus (1275 байт) с запасом на шифрование
+pub(crate) const MAX_FRAME_PAYLOAD: usize = 1500;
+// Насколько кадр может отстать от самого нового, чтобы его ещё имело смысл переслать:
+// по биту на каждый номер окна в SequenceFilter
+const REORDER_WINDOW: u32 = u64::BITS;
+
+#[derive(Debug)]
+struct QueueState<T> {
+    frames: VecDeque<(Instant, T)>,
+    closed: bool,
+}
+
+// Очередь кадров одного получателя: при переполнении вытесняются самые старые кадры,
+// при выдаче пропускаются устаревшие
+#[derive(Debug)]
+pub(crate) struct FrameQueue<T> {
+    state: Mutex<QueueState<T>>,
+    notify: Notify,
+}
+
+impl<T> FrameQueue<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                frames: VecDeque::with_capacity(MAX_QUEUED_FRAMES),
+                closed: false,
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn push(&self, frame: T) {
+        let mut state = self.lock();
+        if state.closed {
+            return;
+        }
+        if state.frames.len() == MAX_QUEUED_FRAMES {
+            state.frames.pop_front();
+        }
+        state.frames.push_back((Instant::now(), frame));
+        drop(state);
+        self.notify.notify_one();
+    }
+
+    // Закрытая очередь отдаёт None, оставшиеся кадры отбрасываются
+    pub(crate) fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        state.frames.clear();
+        drop(state);
+        self.notify.notify_one();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    pub(crate) async fn pop(&self) -> Option<T> {
+        loop {
+            {
+                let mut state = self.lock();
+                if state.closed {
+                    return None;
+                }
+                while let Some((queued_at, frame)) = state.frames.pop_front() {
+                    if queued_at.elapsed() <= MAX_FRAME_AGE {
+                        return Some(frame);
+                    }
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+// Фильтр номеров кадров одного отправителя (номера 32-битные и переполняются)
+#[derive(Debug, Default)]
+pub(crate) struct SequenceFilter {
+    highest: Option<u32>,
+    // Бит i - кадр с номером highest - i уже пропущен
+    seen: u64,
+}
+
+impl SequenceFilter {
+    // Повторы любого кадра окна и кадры старше окна перестановки отбрасываются
+    pub(crate) fn accept(&mut self, sequence: u32) -> bool {
+        let Some(highest) = self.highest else {
+            self.highest = Some(sequence);
+            self.seen = 1;
+            return true;
+        };
+
+        let diff = sequence.wrapping_sub(highest) as i32;
+        if diff > 0 {
+            let shift = diff as u32;
+            let window = if shift < REORDER_WINDOW { self.seen << shift } else { 0 };
+            self.seen = window | 1;
+            self.highest = Some(sequence);
+            return true;
+        }
+
+        let offset = highest.wrapping_sub(sequence);
+        if offset >= REORDER_WINDOW || self.seen & (1 << offset) != 0 {
+            return false;
+        }
+        self.seen |= 1 << offset;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence_filter_rejects_replays() {
+        let mut filter = SequenceFilter::default();
+        assert!(filter.accept(10));
+        assert!(!filter.accept(10));
+        assert!(filter.accept(12));
+
+        // Опоздавший кадр пропускается один раз
+        assert!(filter.accept(11));
+        assert!(!filter.accept(11));
+        assert!(!filter.accept(12));
+
+        // Пропущенный номер на краю окна всё ещё принимается, повтор на краю - нет
+        assert!(filter.accept(13 + REORDER_WINDOW - 1));
+        assert!(filter.accept(13));
+        assert!(!filter.accept(13));
+        assert!(!filter.accept(12));
+    }
+
+    #[test]
+    fn sequence_filter_drops_frames_older_than_window() {
+        let mut filter = SequenceFilter::default();
+        assert!(filter.accept(1000));
+        assert!(filter.accept(1000 - (REORDER_WINDOW - 1)));
+        assert!(!filter.accept(1000 - REORDER_WINDOW));
+
+        // После большого скачка старое окно забывается
+        assert!(filter.accept(1000 + 10 * REORDER_WINDOW));
+        assert!(!filter.accept(1000));
+        assert!(filter.accept(1000 + 10 * REORDER_WINDOW - 1));
+    }
+
+    #[test]
+    fn sequence_filter_handles_wraparound() {
+        let mut filter = SequenceFilter::default();
+        assert!(filter.accept(u32::MAX - 1));
+        assert!(filter.accept(1));
+        // Кадры до переполнения остаются в окне
+        assert!(filter.accept(u32::MAX));
+        assert!(filter.accept(0));
+        assert!(!filter.accept(u32::MAX - 1));
+        assert!(!filter.accept(u32::MAX));
+        assert!(!filter.accept(0));
+        assert!(filter.accept(2));
+        assert!(!filter.accept(1u32.wrapping_sub(REORDER_WINDOW)));
+    }
+
+    #[tokio::test]
+    async fn frame_queue_drops_oldest_frames() {
+        let queue = FrameQueue::new();
+        for frame in 0..MAX_QUEUED_FRAMES + 5 {
+            queue.push(frame);
+        }
+
+        for expected in 5..MAX_QUEUED_FRAMES + 5 {
+            assert_eq!(queue.pop().await, Some(expected));
+        }
+        assert!(queue.lock().frames.is_empty());
+    }
+
+    #[tokio::test]
+    async fn frame_queue_skips_stale_frames() {
+        let queue = FrameQueue::new();
+        queue.push(1);
+        std::thread::sleep(MAX_FRAME_AGE + Duration::from_millis(50));
+        queue.push(2);
+        assert_eq!(queue.pop().await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn closed_frame_queue_returns_none() {
+        let queue = std::sync::Arc::new(FrameQueue::new());
+        let waiter = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.pop().await }
+        });
+        tokio::task::yield_now().await;
+
+        queue.close();
+        assert_eq!(waiter.await.unwrap(), None::<u32>);
+        assert!(queue.is_closed());
+
+        // После закрытия новые кадры не принимаются
+        queue.push(1);
+        assert_eq!(queue.pop().await, None);
+    }
+}
@@ -6,8 +6,6 @@ use bcrypt::verify;
 use jsonwebtoken::{encode, decode, Header, EncodingKey, DecodingKey, Algorithm, Validation};
 use uuid::Uuid;
 
-use crate::services::key_manager::KeyManager;
-
 mod auth {
     tonic::include_proto!("auth");
 }
@@ -27,6 +25,16 @@ struct Claims {
     exp: usize,
 }
 
+// Пользователь из действующего JWT токена (для других сервисов)
+pub(crate) fn user_id_from_token(token: &str, secret: &str) -> Option<Uuid> {
+    let token_data = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::new(Algorithm::HS256),
+    ).ok()?;
+    Uuid::parse_str(&token_data.claims.sub).ok()
+}
+
 // Генерация JWT токенов аутентификации 
 fn create_jwt(user_id: &str, secret: &str, expiration_sec: i64) -> Result<String, Status> {
     let claims = Claims {
@@ -95,11 +103,7 @@ impl AuthService for MyAuthService {
         .await
         .map_err(|_| Status::internal("Insert failed"))?;
 
-        let key_manager = KeyManager::new(self.db.clone());
-        key_manager.generate_user_keys(record.id).await.map_err(|e| {
-            println!("Error generating user keys: {:?}", e);
-            Status::internal("Key generation error")
-        })?;
+        // Ключи пользователь генерирует на клиенте и регистрирует через ExchangePublicKeys
 
         let user = Some(User {
             id: record.id.to_string(),
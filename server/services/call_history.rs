@@ -0,0 +1,190 @@
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tonic::Status;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::services::chat_service::post_system_event;
+use crate::services::notification_service::{Notifier, NewNotification, NotificationKind};
+
+// История звонков. Звонки и сеансы комнат живут в памяти сервера, а по завершении
+// передаются в фоновую запись; пропущенный личный звонок дополнительно оставляет
+// системное событие в чате и уведомление вызываемому.
+
+pub const OUTCOME_COMPLETED: &str = "COMPLETED";
+pub const OUTCOME_MISSED: &str = "MISSED";
+pub const OUTCOME_DECLINED: &str = "DECLINED";
+pub const OUTCOME_BUSY: &str = "BUSY";
+// Вызываемый заблокировал вызывающего; такие записи видит только вызывающий
+pub const OUTCOME_UNAVAILABLE: &str = "UNAVAILABLE";
+
+const MISSED_CALL_EVENT: &str = "MISSED_CALL";
+
+// Личный звонок (callee_id) или сеанс комнаты (room_id, caller_id - вошедший первым)
+#[derive(Debug)]
+pub struct FinishedCall {
+    pub chat_id: Uuid,
+    pub caller_id: Uuid,
+    pub callee_id: Option<Uuid>,
+    pub room_id: Option<Uuid>,
+    pub started_at: NaiveDateTime,
+    pub answered_at: Option<NaiveDateTime>,
+    pub ended_at: NaiveDateTime,
+    pub outcome: &'static str,
+    pub end_reason: &'static str,
+    // (user_id, joined_at, left_at) участников комнаты
+    pub participants: Vec<(Uuid, NaiveDateTime, NaiveDateTime)>,
+}
+
+pub struct CallHistoryEntry {
+    pub id: Uuid,
+    pub chat_id: Uuid,
+    pub caller_id: Option<Uuid>,
+    pub callee_id: Option<Uuid>,
+    pub room_id: Option<Uuid>,
+    pub started_at: NaiveDateTime,
+    pub answered_at: Option<NaiveDateTime>,
+    pub ended_at: NaiveDateTime,
+    pub outcome: String,
+    pub end_reason: String,
+    pub participant_ids: Vec<Uuid>,
+}
+
+// Очередь записи завершённых звонков; запись не задерживает сигнализацию
+#[derive(Debug, Clone)]
+pub struct CallRecorder {
+    tx: mpsc::UnboundedSender<FinishedCall>,
+}
+
+impl CallRecorder {
+    pub fn spawn(db: PgPool, notifier: Notifier) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(call) = rx.recv().await {
+                if let Err(e) = store_call(&db, &notifier, call).await {
+                    error!("Failed to record call: {:?}", e);
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    pub(crate) fn record(&self, call: FinishedCall) {
+        let _ = self.tx.send(call);
+    }
+}
+
+async fn store_call(db: &PgPool, notifier: &Notifier, call: FinishedCall) -> Result<(), Status> {
+    let missed_callee = call.callee_id.filter(|_| call.outcome == OUTCOME_MISSED);
+
+    let mut tx = db.begin().await.map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    let message_id = match missed_callee {
+        Some(_) => Some(post_system_event(&mut tx, call.chat_id, call.caller_id, MISSED_CALL_EVENT).await?),
+        None => None,
+    };
+
+    let record_id = uuid::Builder::from_random_bytes(rand::random()).into_uuid();
+    sqlx::query!(
+        r#"
+        INSERT INTO call_records
+            (id, chat_id, caller_id, callee_id, room_id, started_at, answered_at, ended_at, outcome, end_reason, message_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        "#,
+        record_id,
+        call.chat_id,
+        call.caller_id,
+        call.callee_id,
+        call.room_id,
+        call.started_at,
+        call.answered_at,
+        call.ended_at,
+        call.outcome,
+        call.end_reason,
+        message_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    for (user_id, joined_at, left_at) in &call.participants {
+        sqlx::query!(
+            "INSERT INTO call_participants (call_id, user_id, joined_at, left_at) VALUES ($1, $2, $3, $4)",
+            record_id,
+            user_id,
+            joined_at,
+            left_at
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+    }
+
+    tx.commit().await.map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    if let Some(callee_id) = missed_callee {
+        notifier
+            .notify_all(vec![NewNotification {
+                user_id: callee_id,
+                kind: NotificationKind::NotificationMissedCall,
+                actor_id: Some(call.caller_id),
+                chat_id: Some(call.chat_id),
+                message_id,
+                device_id: None,
+            }])
+            .await;
+    }
+
+    Ok(())
+}
+
+// Звонки пользователя, новые первыми; missed_only - только пропущенные им входящие
+pub(crate) async fn list_call_history(
+    db: &PgPool,
+    user_id: Uuid,
+    missed_only: bool,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<CallHistoryEntry>, Status> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT r.id, r.chat_id, r.caller_id, r.callee_id, r.room_id, r.started_at, r.answered_at,
+               r.ended_at, r.outcome, r.end_reason,
+               ARRAY(SELECT DISTINCT p.user_id FROM call_participants p WHERE p.call_id = r.id) AS "participant_ids!"
+        FROM call_records r
+        WHERE (r.caller_id = $1
+               OR (r.callee_id = $1 AND r.outcome <> $2)
+               OR EXISTS (SELECT 1 FROM call_participants p WHERE p.call_id = r.id AND p.user_id = $1))
+          AND (NOT $3 OR (r.callee_id = $1 AND r.outcome = $4))
+        ORDER BY r.started_at DESC, r.id
+        LIMIT $5 OFFSET $6
+        "#,
+        user_id,
+        OUTCOME_UNAVAILABLE,
+        missed_only,
+        OUTCOME_MISSED,
+        limit,
+        offset
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| CallHistoryEntry {
+            id: row.id,
+            chat_id: row.chat_id,
+            caller_id: row.caller_id,
+            callee_id: row.callee_id,
+            room_id: row.room_id,
+            started_at: row.started_at,
+            answered_at: row.answered_at,
+            ended_at: row.ended_at,
+            outcome: row.outcome,
+            end_reason: row.end_reason,
+            participant_ids: row.participant_ids,
+        })
+        .collect())
+}
@@ -0,0 +1,815 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use chrono::{NaiveDateTime, Utc};
+use futures_core::Stream;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::services::auth_service::user_id_from_token;
+use crate::services::audio_relay::{FrameQueue, SequenceFilter, MAX_FRAME_PAYLOAD};
+use crate::services::call_history::{
+    list_call_history, CallHistoryEntry, CallRecorder, FinishedCall, OUTCOME_BUSY, OUTCOME_COMPLETED,
+    OUTCOME_DECLINED, OUTCOME_MISSED, OUTCOME_UNAVAILABLE,
+};
+use crate::services::chat_service::timestamp_from_naive;
+use crate::services::turn_server::TurnConfig;
+
+mod calls {
+    tonic::include_proto!("calls");
+}
+
+pub use calls::call_service_server::{CallService, CallServiceServer};
+use calls::{
+    CallSignal, CallSignalKind, CallEndReason, CallOutcome, AudioFrame, CallRecord, ListCallHistoryRequest,
+    ListCallHistoryResponse, GetTurnCredentialsRequest, GetTurnCredentialsResponse,
+};
+
+// Сколько звонит вызываемому до завершения с NO_ANSWER
+const RING_TIMEOUT: Duration = Duration::from_secs(45);
+const MAX_SDP_LEN: usize = 64 * 1024;
+const MAX_CANDIDATE_LEN: usize = 1024;
+const MAX_SDP_MID_LEN: usize = 64;
+// ICE-кандидаты вызывающего, сохраняемые для вызываемого, подключившегося во время RINGING
+const MAX_PENDING_CANDIDATES: usize = 64;
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+type SignalSender = mpsc::UnboundedSender<Result<CallSignal, Status>>;
+type Connections = HashMap<Uuid, Vec<(u64, SignalSender)>>;
+type RelayQueue = Arc<FrameQueue<AudioFrame>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CallState {
+    Ringing,
+    Active,
+}
+
+#[derive(Debug)]
+struct Call {
+    chat_id: Uuid,
+    caller_id: Uuid,
+    callee_id: Uuid,
+    caller_conn: u64,
+    // Поток вызываемого, на котором принят звонок
+    callee_conn: Option<u64>,
+    state: CallState,
+    started_at: NaiveDateTime,
+    answered_at: Option<NaiveDateTime>,
+    offer_sdp: String,
+    caller_candidates: Vec<CallSignal>,
+}
+
+impl Call {
+    // true для вызывающего; сигналы принимаются только с потока, участвующего в звонке
+    fn side(&self, user_id: Uuid, conn_id: u64) -> Result<bool, String> {
+        if user_id == self.caller_id {
+            if conn_id != self.caller_conn {
+                return Err("Call belongs to another session".to_string());
+            }
+            return Ok(true);
+        }
+        if user_id == self.callee_id {
+            if self.callee_conn.is_some_and(|conn| conn != conn_id) {
+                return Err("Call was answered on another device".to_string());
+            }
+            return Ok(false);
+        }
+        Err("Not a participant of this call".to_string())
+    }
+
+    fn involves(&self, user_id: Uuid) -> bool {
+        self.caller_id == user_id || self.callee_id == user_id
+    }
+
+    fn offer_signal(&self, call_id: Uuid) -> CallSignal {
+        CallSignal {
+            kind: CallSignalKind::CallSignalOffer as i32,
+            call_id: call_id.to_string(),
+            chat_id: self.chat_id.to_string(),
+            sdp: self.offer_sdp.clone(),
+            from_user_id: self.caller_id.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+fn send_to(connections: &Connections, user_id: Uuid, conn_id: u64, signal: CallSignal) {
+    let sender = connections
+        .get(&user_id)
+        .and_then(|conns| conns.iter().find(|(id, _)| *id == conn_id));
+    if let Some((_, tx)) = sender {
+        let _ = tx.send(Ok(signal));
+    }
+}
+
+fn send_to_all(connections: &Connections, user_id: Uuid, signal: &CallSignal, except: Option<u64>) {
+    for (id, tx) in connections.get(&user_id).into_iter().flatten() {
+        if Some(*id) != except {
+            let _ = tx.send(Ok(signal.clone()));
+        }
+    }
+}
+
+// До ответа сигналы вызывающего получают все потоки вызываемого
+fn send_to_peer(connections: &Connections, call: &Call, from_caller: bool, signal: CallSignal) {
+    if !from_caller {
+        send_to(connections, call.caller_id, call.caller_conn, signal);
+        return;
+    }
+    match call.callee_conn {
+        Some(conn) => send_to(connections, call.callee_id, conn, signal),
+        None => send_to_all(connections, call.callee_id, &signal, None),
+    }
+}
+
+fn end_reason_to_str(reason: CallEndReason) -> &'static str {
+    match reason {
+        CallEndReason::Hangup => "HANGUP",
+        CallEndReason::Declined => "DECLINED",
+        CallEndReason::Busy => "BUSY",
+        CallEndReason::NoAnswer => "NO_ANSWER",
+        CallEndReason::Cancelled => "CANCELLED",
+        CallEndReason::Unavailable => "UNAVAILABLE",
+        CallEndReason::Disconnected => "DISCONNECTED",
+        CallEndReason::AnsweredElsewhere => "ANSWERED_ELSEWHERE",
+        CallEndReason::Unspecified => "UNSPECIFIED",
+    }
+}
+
+fn end_reason_from_str(reason: &str) -> CallEndReason {
+    match reason {
+        "HANGUP" => CallEndReason::Hangup,
+        "DECLINED" => CallEndReason::Declined,
+        "BUSY" => CallEndReason::Busy,
+        "NO_ANSWER" => CallEndReason::NoAnswer,
+        "CANCELLED" => CallEndReason::Cancelled,
+        "UNAVAILABLE" => CallEndReason::Unavailable,
+        "DISCONNECTED" => CallEndReason::Disconnected,
+        "ANSWERED_ELSEWHERE" => CallEndReason::AnsweredElsewhere,
+        _ => CallEndReason::Unspecified,
+    }
+}
+
+fn outcome_from_str(outcome: &str) -> CallOutcome {
+    match outcome {
+        OUTCOME_COMPLETED => CallOutcome::Completed,
+        OUTCOME_MISSED => CallOutcome::Missed,
+        OUTCOME_DECLINED => CallOutcome::Declined,
+        OUTCOME_BUSY => CallOutcome::Busy,
+        OUTCOME_UNAVAILABLE => CallOutcome::Unavailable,
+        _ => CallOutcome::Unspecified,
+    }
+}
+
+// Принятый звонок считается состоявшимся при любой причине завершения
+fn finished_call(
+    chat_id: Uuid,
+    caller_id: Uuid,
+    callee_id: Uuid,
+    started_at: NaiveDateTime,
+    answered_at: Option<NaiveDateTime>,
+    reason: CallEndReason,
+) -> FinishedCall {
+    let outcome = match (answered_at, reason) {
+        (Some(_), _) => OUTCOME_COMPLETED,
+        (None, CallEndReason::Declined) => OUTCOME_DECLINED,
+        (None, CallEndReason::Busy) => OUTCOME_BUSY,
+        (None, CallEndReason::Unavailable) => OUTCOME_UNAVAILABLE,
+        (None, _) => OUTCOME_MISSED,
+    };
+
+    FinishedCall {
+        chat_id,
+        caller_id,
+        callee_id: Some(callee_id),
+        room_id: None,
+        started_at,
+        answered_at,
+        ended_at: Utc::now().naive_utc(),
+        outcome,
+        end_reason: end_reason_to_str(reason),
+        participants: Vec::new(),
+    }
+}
+
+fn ended_signal(call_id: Uuid, chat_id: Uuid, reason: CallEndReason) -> CallSignal {
+    CallSignal {
+        kind: CallSignalKind::CallSignalEnded as i32,
+        call_id: call_id.to_string(),
+        chat_id: chat_id.to_string(),
+        end_reason: reason as i32,
+        ..Default::default()
+    }
+}
+
+#[derive(Debug)]
+struct Hub {
+    recorder: CallRecorder,
+    next_conn_id: u64,
+    // user_id -> открытые потоки сигнализации
+    connections: Connections,
+    calls: HashMap<Uuid, Call>,
+    // (call_id, user_id) -> очередь кадров, ретранслируемых участнику
+    relays: HashMap<(Uuid, Uuid), RelayQueue>,
+}
+
+impl Hub {
+    fn is_busy(&self, user_id: Uuid) -> bool {
+        self.calls.values().any(|call| call.involves(user_id))
+    }
+
+    // Обе стороны получают ENDED; во время RINGING - все потоки вызываемого
+    fn end_call(&mut self, call_id: Uuid, reason: CallEndReason) {
+        let Some(call) = self.calls.remove(&call_id) else {
+            return;
+        };
+
+        self.relays.retain(|(relay_call_id, _), queue| {
+            if *relay_call_id == call_id {
+                queue.close();
+            }
+            *relay_call_id != call_id
+        });
+
+        let ended = ended_signal(call_id, call.chat_id, reason);
+        send_to(&self.connections, call.caller_id, call.caller_conn, ended.clone());
+        match call.callee_conn {
+            Some(conn) => send_to(&self.connections, call.callee_id, conn, ended),
+            None => send_to_all(&self.connections, call.callee_id, &ended, None),
+        }
+
+        self.recorder.record(finished_call(
+            call.chat_id,
+            call.caller_id,
+            call.callee_id,
+            call.started_at,
+            call.answered_at,
+            reason,
+        ));
+    }
+}
+
+// Состояние звонков в памяти сервера
+#[derive(Debug, Clone)]
+pub struct CallHub {
+    inner: Arc<Mutex<Hub>>,
+}
+
+impl CallHub {
+    pub fn new(recorder: CallRecorder) -> Self {
+        let hub = Hub {
+            recorder,
+            next_conn_id: 0,
+            connections: HashMap::new(),
+            calls: HashMap::new(),
+            relays: HashMap::new(),
+        };
+        Self { inner: Arc::new(Mutex::new(hub)) }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Hub> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn connect(&self, user_id: Uuid) -> (u64, mpsc::UnboundedReceiver<Result<CallSignal, Status>>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut hub = self.lock();
+        hub.next_conn_id += 1;
+        let conn_id = hub.next_conn_id;
+
+        // Звонки, начавшиеся до подключения этого потока
+        for (call_id, call) in hub.calls.iter() {
+            if call.callee_id == user_id && call.state == CallState::Ringing {
+                let _ = tx.send(Ok(call.offer_signal(*call_id)));
+                for candidate in &call.caller_candidates {
+                    let _ = tx.send(Ok(candidate.clone()));
+                }
+            }
+        }
+
+        hub.connections.entry(user_id).or_default().push((conn_id, tx));
+        (conn_id, rx)
+    }
+
+    // Звонки, в которых участвовал закрытый поток, завершаются
+    fn disconnect(&self, user_id: Uuid, conn_id: u64) {
+        let mut hub = self.lock();
+        if let Some(conns) = hub.connections.get_mut(&user_id) {
+            conns.retain(|(id, _)| *id != conn_id);
+            if conns.is_empty() {
+                hub.connections.remove(&user_id);
+            }
+        }
+
+        let dropped: Vec<Uuid> = hub
+            .calls
+            .iter()
+            .filter(|(_, call)| {
+                (call.caller_id == user_id && call.caller_conn == conn_id)
+                    || (call.callee_id == user_id && call.callee_conn == Some(conn_id))
+            })
+            .map(|(call_id, _)| *call_id)
+            .collect();
+        for call_id in dropped {
+            hub.end_call(call_id, CallEndReason::Disconnected);
+        }
+    }
+
+    fn reply(&self, user_id: Uuid, conn_id: u64, signal: CallSignal) {
+        send_to(&self.lock().connections, user_id, conn_id, signal);
+    }
+
+    // Звонок, завершённый до начала вызова (занят или недоступен)
+    fn reject_call(&self, call_id: Uuid, chat_id: Uuid, caller_id: Uuid, conn_id: u64, callee_id: Uuid, reason: CallEndReason) {
+        let hub = self.lock();
+        send_to(&hub.connections, caller_id, conn_id, ended_signal(call_id, chat_id, reason));
+        let now = Utc::now().naive_utc();
+        hub.recorder.record(finished_call(chat_id, caller_id, callee_id, now, None, reason));
+    }
+
+    fn start_call(&self, call_id: Uuid, chat_id: Uuid, caller_id: Uuid, conn_id: u64, callee_id: Uuid, sdp: String) -> Result<(), String> {
+        let mut hub = self.lock();
+        if hub.calls.contains_key(&call_id) {
+            return Err("Call already exists".to_string());
+        }
+        if hub.is_busy(caller_id) {
+            return Err("Already in a call".to_string());
+        }
+        if hub.is_busy(callee_id) {
+            drop(hub);
+            self.reject_call(call_id, chat_id, caller_id, conn_id, callee_id, CallEndReason::Busy);
+            return Ok(());
+        }
+
+        let call = Call {
+            chat_id,
+            caller_id,
+            callee_id,
+            caller_conn: conn_id,
+            callee_conn: None,
+            state: CallState::Ringing,
+            started_at: Utc::now().naive_utc(),
+            answered_at: None,
+            offer_sdp: sdp,
+            caller_candidates: Vec::new(),
+        };
+        send_to_all(&hub.connections, callee_id, &call.offer_signal(call_id), None);
+        hub.calls.insert(call_id, call);
+        drop(hub);
+
+        let calls = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(RING_TIMEOUT).await;
+            calls.ring_timeout(call_id);
+        });
+
+        Ok(())
+    }
+
+    fn ring_timeout(&self, call_id: Uuid) {
+        let mut hub = self.lock();
+        if hub.calls.get(&call_id).is_some_and(|call| call.state == CallState::Ringing) {
+            hub.end_call(call_id, CallEndReason::NoAnswer);
+        }
+    }
+
+    // SDP повторного согласования и ICE-кандидаты пересылаются другой стороне без изменений
+    fn relay(&self, call_id: Uuid, user_id: Uuid, conn_id: u64, kind: CallSignalKind, signal: CallSignal) -> Result<(), String> {
+        let mut guard = self.lock();
+        let Hub { connections, calls, .. } = &mut *guard;
+        let call = calls.get_mut(&call_id).ok_or("Call not found")?;
+        let from_caller = call.side(user_id, conn_id)?;
+
+        if kind != CallSignalKind::CallSignalIceCandidate && call.state != CallState::Active {
+            return Err("Call is not active".to_string());
+        }
+
+        let outgoing = CallSignal {
+            kind: kind as i32,
+            call_id: call_id.to_string(),
+            chat_id: call.chat_id.to_string(),
+            sdp: signal.sdp,
+            candidate: signal.candidate,
+            sdp_mid: signal.sdp_mid,
+            sdp_mline_index: signal.sdp_mline_index,
+            from_user_id: user_id.to_string(),
+            ..Default::default()
+        };
+
+        if from_caller && call.state == CallState::Ringing && call.caller_candidates.len() < MAX_PENDING_CANDIDATES {
+            call.caller_candidates.push(outgoing.clone());
+        }
+        send_to_peer(connections, call, from_caller, outgoing);
+
+        Ok(())
+    }
+
+    // RINGING, ACCEPT, REJECT, BUSY от вызываемого и HANGUP от любой стороны
+    fn transition(&self, call_id: Uuid, user_id: Uuid, conn_id: u64, kind: CallSignalKind, sdp: String) -> Result<(), String> {
+        let mut guard = self.lock();
+        let hub = &mut *guard;
+        let call = hub.calls.get_mut(&call_id).ok_or("Call not found")?;
+        let from_caller = call.side(user_id, conn_id)?;
+
+        if kind == CallSignalKind::CallSignalHangup {
+            let reason = match (call.state, from_caller) {
+                (CallState::Active, _) => CallEndReason::Hangup,
+                (CallState::Ringing, true) => CallEndReason::Cancelled,
+                (CallState::Ringing, false) => CallEndReason::Declined,
+            };
+            hub.end_call(call_id, reason);
+            return Ok(());
+        }
+
+        if from_caller {
+            return Err("Only the callee can answer a call".to_string());
+        }
+        if call.state != CallState::Ringing {
+            return Err("Call is already answered".to_string());
+        }
+
+        match kind {
+            CallSignalKind::CallSignalRinging => {
+                let ringing = CallSignal {
+                    kind: kind as i32,
+                    call_id: call_id.to_string(),
+                    chat_id: call.chat_id.to_string(),
+                    from_user_id: user_id.to_string(),
+                    ..Default::default()
+                };
+                send_to(&hub.connections, call.caller_id, call.caller_conn, ringing);
+            }
+            CallSignalKind::CallSignalAccept => {
+                if sdp.is_empty() {
+                    return Err("Missing SDP answer".to_string());
+                }
+                call.state = CallState::Active;
+                call.answered_at = Some(Utc::now().naive_utc());
+                call.callee_conn = Some(conn_id);
+                call.caller_candidates.clear();
+
+                let accept = CallSignal {
+                    kind: kind as i32,
+                    call_id: call_id.to_string(),
+                    chat_id: call.chat_id.to_string(),
+                    sdp,
+                    from_user_id: user_id.to_string(),
+                    ..Default::default()
+                };
+                send_to(&hub.connections, call.caller_id, call.caller_conn, accept);
+
+                let elsewhere = ended_signal(call_id, call.chat_id, CallEndReason::AnsweredElsewhere);
+                send_to_all(&hub.connections, user_id, &elsewhere, Some(conn_id));
+            }
+            CallSignalKind::CallSignalReject => hub.end_call(call_id, CallEndReason::Declined),
+            CallSignalKind::CallSignalBusy => hub.end_call(call_id, CallEndReason::Busy),
+            _ => return Err("Unsupported signal".to_string()),
+        }
+
+        Ok(())
+    }
+
+    // Повторное подключение участника заменяет его прежний поток ретрансляции
+    fn join_relay(&self, call_id: Uuid, user_id: Uuid) -> Result<(Uuid, RelayQueue), String> {
+        let mut hub = self.lock();
+        let call = hub.calls.get(&call_id).ok_or("Call not found")?;
+        if !call.involves(user_id) {
+            return Err("Not a participant of this call".to_string());
+        }
+        if call.state != CallState::Active {
+            return Err("Call is not active".to_string());
+        }
+        let peer_id = if call.caller_id == user_id { call.callee_id } else { call.caller_id };
+
+        let queue = Arc::new(FrameQueue::new());
+        if let Some(previous) = hub.relays.insert((call_id, user_id), queue.clone()) {
+            previous.close();
+        }
+        Ok((peer_id, queue))
+    }
+
+    fn relay_queue(&self, call_id: Uuid, user_id: Uuid) -> Option<RelayQueue> {
+        self.lock().relays.get(&(call_id, user_id)).cloned()
+    }
+
+    fn leave_relay(&self, call_id: Uuid, user_id: Uuid, queue: &RelayQueue) {
+        let mut hub = self.lock();
+        if hub.relays.get(&(call_id, user_id)).is_some_and(|current| Arc::ptr_eq(current, queue)) {
+            hub.relays.remove(&(call_id, user_id));
+        }
+        queue.close();
+    }
+}
+
+fn call_record_to_proto(entry: CallHistoryEntry) -> CallRecord {
+    // Для сеанса комнаты длительность считается от его начала
+    let connected_at = match entry.room_id {
+        Some(_) => Some(entry.started_at),
+        None => entry.answered_at,
+    };
+    let duration_seconds = connected_at
+        .map(|at| (entry.ended_at - at).num_seconds().max(0))
+        .unwrap_or(0);
+
+    CallRecord {
+        id: entry.id.to_string(),
+        chat_id: entry.chat_id.to_string(),
+        caller_id: entry.caller_id.map(|id| id.to_string()).unwrap_or_default(),
+        callee_id: entry.callee_id.map(|id| id.to_string()).unwrap_or_default(),
+        room_id: entry.room_id.map(|id| id.to_string()).unwrap_or_default(),
+        started_at: Some(timestamp_from_naive(entry.started_at)),
+        answered_at: entry.answered_at.map(timestamp_from_naive),
+        ended_at: Some(timestamp_from_naive(entry.ended_at)),
+        duration_seconds,
+        outcome: outcome_from_str(&entry.outcome) as i32,
+        end_reason: end_reason_from_str(&entry.end_reason) as i32,
+        participant_ids: entry.participant_ids.iter().map(|id| id.to_string()).collect(),
+    }
+}
+
+fn error_signal(call_id: String, message: String) -> CallSignal {
+    CallSignal {
+        kind: CallSignalKind::CallSignalError as i32,
+        call_id,
+        error: message,
+        ..Default::default()
+    }
+}
+
+fn validate_signal(signal: &CallSignal) -> Result<(), String> {
+    if signal.sdp.len() > MAX_SDP_LEN {
+        return Err("SDP is too large".to_string());
+    }
+    if signal.candidate.len() > MAX_CANDIDATE_LEN || signal.sdp_mid.len() > MAX_SDP_MID_LEN {
+        return Err("ICE candidate is too large".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct MyCallService {
+    db: PgPool,
+    calls: CallHub,
+    jwt_secret: String,
+    // Встроенный STUN/TURN-сервер, если включён
+    turn: Option<TurnConfig>,
+}
+
+impl MyCallService {
+    pub fn new(db: PgPool, calls: CallHub, jwt_secret: String, turn: Option<TurnConfig>) -> Self {
+        Self { db, calls, jwt_secret, turn }
+    }
+
+    // Собеседник в личном чате, если пользователь в нём состоит
+    async fn dm_peer(&self, chat_id: Uuid, user_id: Uuid) -> Result<Option<Uuid>, Status> {
+        let peers = sqlx::query_scalar!(
+            r#"
+            SELECT peer.user_id
+            FROM direct_chats c
+            JOIN direct_chats_members me ON me.chat_id = c.id AND me.user_id = $2
+            JOIN direct_chats_members peer ON peer.chat_id = c.id AND peer.user_id <> $2
+            WHERE c.id = $1 AND NOT c.is_group
+            "#,
+            chat_id,
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(match peers.as_slice() {
+            [peer] => Some(*peer),
+            _ => None,
+        })
+    }
+
+    async fn is_blocked(&self, user_id: Uuid, other_id: Uuid) -> Result<bool, Status> {
+        let blocked = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM user_relationships
+                WHERE status = 'BLOCKED'
+                  AND ((user_id = $1 AND target_user_id = $2) OR (user_id = $2 AND target_user_id = $1))
+            ) AS "blocked!"
+            "#,
+            user_id,
+            other_id
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(blocked)
+    }
+
+    async fn start_call(&self, call_id: Uuid, user_id: Uuid, conn_id: u64, signal: CallSignal) -> Result<(), String> {
+        let chat_id = Uuid::parse_str(&signal.chat_id).map_err(|_| "Invalid chat_id UUID".to_string())?;
+        if signal.sdp.is_empty() {
+            return Err("Missing SDP offer".to_string());
+        }
+
+        let internal = |e: Status| {
+            error!("Call setup failed: {:?}", e);
+            "Internal error".to_string()
+        };
+        let callee_id = self
+            .dm_peer(chat_id, user_id)
+            .await
+            .map_err(internal)?
+            .ok_or("Direct chat not found")?;
+
+        // Вызывающий не узнаёт, что заблокирован: звонок просто недоступен
+        if self.is_blocked(user_id, callee_id).await.map_err(internal)? {
+            self.calls.reject_call(call_id, chat_id, user_id, conn_id, callee_id, CallEndReason::Unavailable);
+            return Ok(());
+        }
+
+        self.calls.start_call(call_id, chat_id, user_id, conn_id, callee_id, signal.sdp)
+    }
+
+    async fn handle_signal(&self, user_id: Uuid, conn_id: u64, signal: CallSignal) -> Result<(), String> {
+        let kind = CallSignalKind::try_from(signal.kind).map_err(|_| "Unknown signal kind".to_string())?;
+        let call_id = Uuid::parse_str(&signal.call_id).map_err(|_| "Invalid call_id UUID".to_string())?;
+        validate_signal(&signal)?;
+
+        match kind {
+            CallSignalKind::CallSignalOffer => {
+                let exists = self.calls.lock().calls.contains_key(&call_id);
+                if exists {
+                    self.calls.relay(call_id, user_id, conn_id, kind, signal)
+                } else {
+                    self.start_call(call_id, user_id, conn_id, signal).await
+                }
+            }
+            CallSignalKind::CallSignalAnswer | CallSignalKind::CallSignalIceCandidate => {
+                self.calls.relay(call_id, user_id, conn_id, kind, signal)
+            }
+            CallSignalKind::CallSignalRinging
+            | CallSignalKind::CallSignalAccept
+            | CallSignalKind::CallSignalReject
+            | CallSignalKind::CallSignalBusy
+            | CallSignalKind::CallSignalHangup => {
+                self.calls.transition(call_id, user_id, conn_id, kind, signal.sdp)
+            }
+            CallSignalKind::CallSignalConnect => Err("Stream is already connected".to_string()),
+            CallSignalKind::CallSignalEnded
+            | CallSignalKind::CallSignalError
+            | CallSignalKind::CallSignalUnspecified => Err("Unsupported signal".to_string()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl CallService for MyCallService {
+    type SignalStream =
+        Pin<Box<dyn Stream<Item = Result<CallSignal, Status>> + Send + Sync + 'static>>;
+    type RelayAudioStream =
+        Pin<Box<dyn Stream<Item = Result<AudioFrame, Status>> + Send + Sync + 'static>>;
+
+    async fn signal(
+        &self,
+        request: Request<Streaming<CallSignal>>,
+    ) -> Result<Response<Self::SignalStream>, Status> {
+        let mut inbound = request.into_inner();
+
+        let first = inbound
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("Empty signal stream"))?;
+        if first.kind != CallSignalKind::CallSignalConnect as i32 {
+            return Err(Status::failed_precondition("First signal must be CONNECT"));
+        }
+        let user_id = user_id_from_token(&first.access_token, &self.jwt_secret)
+            .ok_or_else(|| Status::unauthenticated("Invalid token"))?;
+
+        let user_exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1)", user_id)
+            .fetch_one(&self.db)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+            .unwrap_or(false);
+        if !user_exists {
+            return Err(Status::not_found("User not found"));
+        }
+
+        let (conn_id, rx) = self.calls.connect(user_id);
+        let service = self.clone();
+        tokio::spawn(async move {
+            // Ошибка сигнала возвращается отправителю и не закрывает поток
+            while let Ok(Some(signal)) = inbound.message().await {
+                let call_id = signal.call_id.clone();
+                if let Err(message) = service.handle_signal(user_id, conn_id, signal).await {
+                    service.calls.reply(user_id, conn_id, error_signal(call_id, message));
+                }
+            }
+            service.calls.disconnect(user_id, conn_id);
+        });
+
+        Ok(Response::new(Box::pin(UnboundedReceiverStream::new(rx))))
+    }
+
+    async fn relay_audio(
+        &self,
+        request: Request<Streaming<AudioFrame>>,
+    ) -> Result<Response<Self::RelayAudioStream>, Status> {
+        let mut inbound = request.into_inner();
+
+        let first = inbound
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("Empty audio stream"))?;
+        let user_id = user_id_from_token(&first.access_token, &self.jwt_secret)
+            .ok_or_else(|| Status::unauthenticated("Invalid token"))?;
+        let call_id = Uuid::parse_str(&first.call_id)
+            .map_err(|_| Status::invalid_argument("Invalid call_id UUID"))?;
+
+        let (peer_id, queue) = self.calls
+            .join_relay(call_id, user_id)
+            .map_err(Status::failed_precondition)?;
+
+        let calls = self.calls.clone();
+        let own_queue = queue.clone();
+        tokio::spawn(async move {
+            let mut sequence = SequenceFilter::default();
+            while let Ok(Some(frame)) = inbound.message().await {
+                // Очередь закрыта: звонок завершён или участник переподключился
+                if own_queue.is_closed() {
+                    break;
+                }
+                if frame.payload.is_empty() || frame.payload.len() > MAX_FRAME_PAYLOAD || !sequence.accept(frame.sequence) {
+                    continue;
+                }
+                // Кадры до подключения собеседника к ретрансляции не сохраняются
+                if let Some(target) = calls.relay_queue(call_id, peer_id) {
+                    target.push(AudioFrame {
+                        call_id: call_id.to_string(),
+                        access_token: String::new(),
+                        sequence: frame.sequence,
+                        timestamp: frame.timestamp,
+                        payload: frame.payload,
+                        from_user_id: user_id.to_string(),
+                    });
+                }
+            }
+            calls.leave_relay(call_id, user_id, &own_queue);
+        });
+
+        let output_stream = async_stream::stream! {
+            while let Some(frame) = queue.pop().await {
+                yield Ok(frame);
+            }
+        };
+
+        Ok(Response::new(Box::pin(output_stream)))
+    }
+
+    async fn list_call_history(
+        &self,
+        request: Request<ListCallHistoryRequest>,
+    ) -> Result<Response<ListCallHistoryResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = user_id_from_token(&req.access_token, &self.jwt_secret)
+            .ok_or_else(|| Status::unauthenticated("Invalid token"))?;
+
+        let limit = match req.limit as i64 {
+            n if n <= 0 => DEFAULT_PAGE_SIZE,
+            n => n.min(MAX_PAGE_SIZE),
+        };
+        let offset = (req.offset as i64).max(0);
+
+        let entries = list_call_history(&self.db, user_id, req.missed_only, limit, offset).await?;
+
+        Ok(Response::new(ListCallHistoryResponse {
+            calls: entries.into_iter().map(call_record_to_proto).collect(),
+        }))
+    }
+
+    async fn get_turn_credentials(
+        &self,
+        request: Request<GetTurnCredentialsRequest>,
+    ) -> Result<Response<GetTurnCredentialsResponse>, Status> {
+        let token = request.into_inner().access_token;
+
+        let user_id = user_id_from_token(&token, &self.jwt_secret)
+            .ok_or_else(|| Status::unauthenticated("Invalid token"))?;
+
+        let turn = self.turn.as_ref().ok_or_else(|| Status::unavailable("TURN server is not enabled"))?;
+        let credentials = turn.mint_credentials(user_id);
+
+        Ok(Response::new(GetTurnCredentialsResponse {
+            username: credentials.username,
+            password: credentials.password,
+            expires_at: Some(prost_types::Timestamp {
+                seconds: credentials.expires_at as i64,
+                nanos: 0,
+            }),
+            uris: credentials.uris,
+        }))
+    }
+}
@@ -0,0 +1,223 @@
+use chrono::{NaiveDateTime, Utc};
+use futures_core::Stream;
+use serde::Serialize;
+use sqlx::PgPool;
+use tonic::Status;
+use uuid::Uuid;
+
+// Версия формата JSON Lines, описанного в service_chat.proto
+const EXPORT_FORMAT_VERSION: u32 = 2;
+const MESSAGE_BATCH_SIZE: i64 = 500;
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ExportRecord {
+    Header {
+        version: u32,
+        chat_id: String,
+        exported_by: String,
+        exported_at: String,
+    },
+    Chat {
+        id: String,
+        is_group: bool,
+        created_at: String,
+    },
+    Member {
+        user_id: String,
+        username: String,
+        display_name: Option<String>,
+    },
+    // Одна запись на каждую версию ключа чата: сообщения до ротации зашифрованы прежними
+    Key {
+        user_id: String,
+        key_version: i32,
+        algorithm: String,
+        key_fingerprint: Option<String>,
+        encrypted_key: String,
+    },
+    Message(Box<ExportMessage>),
+    End {
+        message_count: u64,
+    },
+}
+
+#[derive(Serialize)]
+struct ExportMessage {
+    id: String,
+    sender_id: String,
+    sent_at: String,
+    encrypted_content: String,
+    is_deleted: bool,
+    reply_to_message_id: Option<String>,
+    mentioned_user_ids: Vec<String>,
+    forwarded_from: Option<ExportForward>,
+    // Для конвертов Double Ratchet encrypted_content пуст: шифртексты есть только на устройствах
+    envelope_version: Option<i32>,
+    sender_device_id: Option<String>,
+    system_event: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ExportForward {
+    original_message_id: Option<String>,
+    original_chat_id: Option<String>,
+    original_sender_id: Option<String>,
+    hidden: bool,
+}
+
+// Записи состоят только из строк, чисел и флагов, поэтому сериализация не может упасть
+fn to_line(record: &ExportRecord) -> String {
+    serde_json::to_string(record).expect("Export record serialization failed")
+}
+
+fn rfc3339(dt: NaiveDateTime) -> String {
+    dt.and_utc().to_rfc3339()
+}
+
+// Поток строк экспорта. Членство exported_by должно быть проверено вызывающим.
+pub(crate) fn export_chat_lines(
+    db: PgPool,
+    chat_id: Uuid,
+    exported_by: Uuid,
+) -> impl Stream<Item = Result<String, Status>> + Send + 'static {
+    async_stream::try_stream! {
+        yield to_line(&ExportRecord::Header {
+            version: EXPORT_FORMAT_VERSION,
+            chat_id: chat_id.to_string(),
+            exported_by: exported_by.to_string(),
+            exported_at: Utc::now().to_rfc3339(),
+        });
+
+        let chat = sqlx::query!(
+            "SELECT id, is_group, created_at FROM direct_chats WHERE id = $1",
+            chat_id
+        )
+        .fetch_optional(&db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .ok_or_else(|| Status::not_found("Chat not found"))?;
+
+        yield to_line(&ExportRecord::Chat {
+            id: chat.id.to_string(),
+            is_group: chat.is_group,
+            created_at: rfc3339(chat.created_at),
+        });
+
+        let members = sqlx::query!(
+            r#"
+            SELECT u.id::uuid AS "id!", u.username, u.display_name
+            FROM direct_chats_members dcm
+            JOIN users u ON u.id = dcm.user_id
+            WHERE dcm.chat_id = $1
+            ORDER BY u.username
+            "#,
+            chat_id
+        )
+        .fetch_all(&db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        for member in members {
+            yield to_line(&ExportRecord::Member {
+                user_id: member.id.to_string(),
+                username: member.username,
+                display_name: member.display_name,
+            });
+        }
+
+        // Только копия ключа экспортирующего: расшифровка выполняется на клиенте
+        let keys = sqlx::query!(
+            r#"
+            SELECT user_id, key_version, algorithm, key_fingerprint, encrypted_key
+            FROM direct_chats_keys
+            WHERE chat_id = $1 AND user_id = $2
+            ORDER BY key_version
+            "#,
+            chat_id,
+            exported_by
+        )
+        .fetch_all(&db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        for key in keys {
+            yield to_line(&ExportRecord::Key {
+                user_id: key.user_id.to_string(),
+                key_version: key.key_version,
+                algorithm: key.algorithm,
+                key_fingerprint: key.key_fingerprint,
+                encrypted_key: key.encrypted_key,
+            });
+        }
+
+        let mut message_count: u64 = 0;
+        let mut cursor: Option<(NaiveDateTime, Uuid)> = None;
+
+        loop {
+            let (after_sent_at, after_id) = cursor.unzip();
+
+            let batch = sqlx::query!(
+                r#"
+                SELECT id, sender_id, sent_at, encrypted_content, is_deleted,
+                       reply_to_message_id, mentioned_user_ids,
+                       forwarded_from_message_id, forwarded_from_chat_id,
+                       forwarded_from_sender_id, forward_hidden,
+                       envelope_version, sender_device_id, system_event
+                FROM messages
+                WHERE chat_id = $1
+                  AND ($2::timestamp IS NULL OR (sent_at, id) > ($2, $3))
+                ORDER BY sent_at, id
+                LIMIT $4
+                "#,
+                chat_id,
+                after_sent_at,
+                after_id,
+                MESSAGE_BATCH_SIZE
+            )
+            .fetch_all(&db)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+            let batch_len = batch.len();
+            cursor = batch.last().map(|row| (row.sent_at, row.id));
+
+            for row in batch {
+                let is_forward = row.forwarded_from_message_id.is_some()
+                    || row.forwarded_from_sender_id.is_some();
+                let forwarded_from = is_forward.then(|| {
+                    let visible = |id: Option<Uuid>| {
+                        id.filter(|_| !row.forward_hidden).map(|id| id.to_string())
+                    };
+                    ExportForward {
+                        original_message_id: visible(row.forwarded_from_message_id),
+                        original_chat_id: visible(row.forwarded_from_chat_id),
+                        original_sender_id: visible(row.forwarded_from_sender_id),
+                        hidden: row.forward_hidden,
+                    }
+                });
+
+                yield to_line(&ExportRecord::Message(Box::new(ExportMessage {
+                    id: row.id.to_string(),
+                    sender_id: row.sender_id.to_string(),
+                    sent_at: rfc3339(row.sent_at),
+                    encrypted_content: row.encrypted_content,
+                    is_deleted: row.is_deleted,
+                    reply_to_message_id: row.reply_to_message_id.map(|id| id.to_string()),
+                    mentioned_user_ids: row.mentioned_user_ids.iter().map(Uuid::to_string).collect(),
+                    forwarded_from,
+                    envelope_version: row.envelope_version,
+                    sender_device_id: row.sender_device_id,
+                    system_event: row.system_event,
+                })));
+                message_count += 1;
+            }
+
+            if batch_len < MESSAGE_BATCH_SIZE as usize {
+                break;
+            }
+        }
+
+        yield to_line(&ExportRecord::End { message_count });
+    }
+}
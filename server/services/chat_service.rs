@@ -1,11 +1,22 @@
 use tonic::{Request, Response, Status};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use uuid::Uuid;
 use chrono::{Utc, NaiveDateTime};
-use rsa::{RsaPublicKey, pkcs8::DecodePublicKey};
 use prost_types::Timestamp;
+use std::pin::Pin;
+use futures_core::Stream;
+use futures_util::TryStreamExt;
+use tracing::warn;
 
-use crate::services::key_manager::KeyManager;
+use voicechat_pgp::ratchet::{ENVELOPE_VERSION, KEY_SIZE as RATCHET_KEY_SIZE};
+
+use crate::services::chat_export::export_chat_lines;
+
+use crate::services::device_service::touch_device;
+use crate::services::key_manager::{KeyManager, WrapError, WrappedChatKey, WrappedDeviceChatKey};
+use crate::services::notification_service::{Notifier, NewNotification, NotificationKind};
+use crate::services::key_service::handle_key_change;
+use crate::services::voice_room_service::RoomHub;
 
 mod chats {
     tonic::include_proto!("chats"); 
@@ -17,12 +28,26 @@ use chats::*;
 
 #[derive(Debug)]
 pub struct MyChatsService {
-    db: PgPool
+    db: PgPool,
+    notifier: Notifier,
+    key_manager: KeyManager,
+    // Голосовые комнаты: исключённые из группы участники покидают их сразу
+    rooms: RoomHub,
 }
 
+const MAX_FORWARD_TARGETS: usize = 20;
+const INITIAL_CHAT_KEY_VERSION: i32 = 1;
+const MAX_CHAT_NICKNAME_LEN: usize = 64;
+const MAX_ENVELOPE_CIPHERTEXT: usize = 64 * 1024;
+const DEFAULT_DEVICE_FETCH_LIMIT: i64 = 100;
+const MAX_DEVICE_FETCH_LIMIT: i64 = 500;
+const MAX_HANDSHAKE_PAYLOAD: usize = 256 * 1024;
+const DEFAULT_HANDSHAKE_FETCH_LIMIT: i64 = 100;
+const MAX_HANDSHAKE_FETCH_LIMIT: i64 = 500;
+
 impl MyChatsService {
-    pub fn new(db: PgPool) -> Self {
-        Self { db }
+    pub fn new(db: PgPool, notifier: Notifier, key_manager: KeyManager, rooms: RoomHub) -> Self {
+        Self { db, notifier, key_manager, rooms }
     }
 }
 
@@ -57,6 +82,13 @@ impl ChatService for MyChatsService {
             return Err(Status::already_exists(format!("Chat already exists with id: {}", chat_id)));
         }
 
+        let member_keys = self.key_manager.member_keys(&[current_user, target_user]).await?;
+        let member_devices = self.key_manager.member_devices(&[current_user, target_user]).await?;
+        let (wrapped_keys, wrapped_device_keys) = KeyManager::wrap_new_chat_key(&member_keys, &member_devices)?;
+
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
         let created_at = Utc::now().naive_utc();
         let record = sqlx::query!(
             r#"
@@ -66,7 +98,7 @@ impl ChatService for MyChatsService {
             "#,
             created_at
         )
-        .fetch_one(&self.db)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
 
@@ -79,43 +111,22 @@ impl ChatService for MyChatsService {
             current_user,
             target_user
         )
-        .execute(&self.db)
+        .execute(&mut *tx)
         .await
         .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
 
-        let key_manager = KeyManager::new(self.db.clone());
+        store_chat_keys(&mut tx, record.id, INITIAL_CHAT_KEY_VERSION, &wrapped_keys).await?;
+        store_device_chat_keys(&mut tx, record.id, INITIAL_CHAT_KEY_VERSION, &wrapped_device_keys).await?;
 
-        let current_user_key = key_manager.generate_user_keys(current_user).await?;
-        let target_user_key =key_manager.generate_user_keys(target_user).await?;
-
-        let session_key = key_manager.get_encryption_key();
-        let encrypted_for_current = key_manager.encrypt_data(&session_key)
-            .map_err(|e| Status::internal(format!("Encryption error: {}", e)))?;
-        let encrypted_for_target = key_manager.encrypt_data(&session_key)
-            .map_err(|e| Status::internal(format!("Encryption error: {}", e)))?;
-
-        sqlx::query!(
-            r#"
-            INSERT INTO direct_chats_keys (chat_id, user_id, encrypted_key)
-            VALUES ($1, $2, $3), ($1, $4, $5)
-            "#,
-            record.id,
-            current_user,
-            hex::encode(&encrypted_for_current),
-            target_user,
-            hex::encode(&encrypted_for_target)
-        )
-        .execute(&self.db)
-        .await
-        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
 
         let response = CreateChatDmResponse {
             chat_id: record.id.to_string(),
-            encrypted_key: Some(EncryptedKey { 
-                encrypted_data: hex::encode(encrypted_for_current), 
-                iv: hex::encode(&session_key[..16]), 
-                expires_at: Some(timestamp_from_naive(created_at)),
-            }),
+            encrypted_key: wrapped_keys
+                .iter()
+                .find(|key| key.user_id == current_user)
+                .map(|key| encrypted_key_proto(key, INITIAL_CHAT_KEY_VERSION)),
         };
 
         Ok(Response::new(response))
@@ -138,9 +149,25 @@ impl ChatService for MyChatsService {
         for user in req.target_users {
             let user_id = Uuid::parse_str(&user)
                 .map_err(|_| Status::invalid_argument(format!("Invalid user Uuid: {}", user)))?;
-            target_users.push(user_id);
+            if user_id != current_user && !target_users.contains(&user_id) {
+                target_users.push(user_id);
+            }
+        }
+
+        if target_users.len() < 2 {
+            return Err(Status::invalid_argument("Group chat requires at least 2 other members"));
         }
 
+        let mut members = target_users.clone();
+        members.push(current_user);
+
+        let member_keys = self.key_manager.member_keys(&members).await?;
+        let member_devices = self.key_manager.member_devices(&members).await?;
+        let (wrapped_keys, wrapped_device_keys) = KeyManager::wrap_new_chat_key(&member_keys, &member_devices)?;
+
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
         let created_at = Utc::now().naive_utc();
         let record = sqlx::query!(
             r#"
@@ -150,12 +177,9 @@ impl ChatService for MyChatsService {
             "#,
             created_at
         )
-        .fetch_one(&self.db)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
-        
-        let mut members = target_users.clone();
-        members.push(current_user);
 
         for member in &members {
             sqlx::query!(
@@ -166,45 +190,117 @@ impl ChatService for MyChatsService {
                 record.id,
                 member
             )
-            .execute(&self.db)
+            .execute(&mut *tx)
             .await
             .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
         }
 
-        let key_manager = KeyManager::new(self.db.clone());
-        let session_key = key_manager.get_encryption_key();
-        let encrypted_session = key_manager.encrypt_data(&session_key)
-            .map_err(|e| Status::internal(format!("Encryption error: {}", e)))?;
+        store_chat_keys(&mut tx, record.id, INITIAL_CHAT_KEY_VERSION, &wrapped_keys).await?;
+        store_device_chat_keys(&mut tx, record.id, INITIAL_CHAT_KEY_VERSION, &wrapped_device_keys).await?;
 
-        for member in &members {
-            let user_key = key_manager.generate_user_keys(*member).await?;
-            let encrypted_key = key_manager.encrypt_data(&session_key)
-                .map_err(|e| Status::internal(format!("Encryption error: {}", e)))?;
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
 
-            sqlx::query!(
+        self.notifier.notify_all(
+            target_users
+                .iter()
+                .map(|user_id| NewNotification {
+                    user_id: *user_id,
+                    kind: NotificationKind::NotificationGroupInvite,
+                    actor_id: Some(current_user),
+                    chat_id: Some(record.id),
+                    message_id: None,
+                    device_id: None,
+                })
+                .collect(),
+        ).await;
+
+        let response = CreateChatGroupResponse {
+            chat_id: record.id.to_string(),
+            session_key: wrapped_keys
+                .iter()
+                .find(|key| key.user_id == current_user)
+                .map(|key| encrypted_key_proto(key, INITIAL_CHAT_KEY_VERSION)),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn get_chat_key(
+        &self,
+        request: Request<GetChatKeyRequest>,
+    ) -> Result<Response<GetChatKeyResponse>, Status> {
+        let req = request.into_inner();
+
+        let chat_id = Uuid::parse_str(&req.chat_id)
+            .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?;
+        let current_user = Uuid::parse_str(&req.current_user)
+            .map_err(|_| Status::invalid_argument("Invalid current_user UUID"))?;
+
+        if !is_chat_member(&self.db, chat_id, current_user).await? {
+            return Err(Status::permission_denied("User is not a member of the chat!"));
+        }
+
+        if !req.device_id.is_empty() {
+            let record = sqlx::query!(
                 r#"
-                INSERT INTO direct_chats_keys (chat_id, user_id, encrypted_key)
-                VALUES ($1, $2, $3)
+                SELECT encrypted_key, key_version, algorithm
+                FROM device_chat_keys
+                WHERE chat_id = $1 AND user_id = $2 AND device_id = $3
+                ORDER BY key_version DESC
+                LIMIT 1
                 "#,
-                record.id,
-                member, 
-                hex::encode(&encrypted_key)
+                chat_id,
+                current_user,
+                req.device_id
             )
-            .execute(&self.db)
+            .fetch_optional(&self.db)
             .await
-            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+            .ok_or_else(|| Status::not_found("No chat key for this device"))?;
+
+            return Ok(Response::new(GetChatKeyResponse {
+                chat_id: chat_id.to_string(),
+                encrypted_key: Some(EncryptedKey {
+                    encrypted_data: hex::encode(&record.encrypted_key),
+                    iv: String::new(),
+                    expires_at: None,
+                    key_version: record.key_version,
+                    algorithm: record.algorithm,
+                    key_fingerprint: String::new(),
+                }),
+            }));
         }
 
-        let response = CreateChatGroupResponse {
-            chat_id: record.id.to_string(),
-            session_key: Some(EncryptedKey {
-                encrypted_data: hex::encode(encrypted_session),
-                iv: hex::encode(&session_key[..16]),
-                expires_at: Some(timestamp_from_naive(created_at)),
-            }),
-        };
+        let record = sqlx::query!(
+            r#"
+            SELECT dck.encrypted_key, dck.key_version, dck.algorithm,
+                   dck.key_fingerprint, uk.expires_at AS "expires_at?"
+            FROM direct_chats_keys dck
+            LEFT JOIN user_keys uk ON uk.fingerprint = dck.key_fingerprint
+            WHERE dck.chat_id = $1 AND dck.user_id = $2
+            ORDER BY dck.key_version DESC
+            LIMIT 1
+            "#,
+            chat_id,
+            current_user
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .ok_or_else(|| Status::not_found("No chat key for this member"))?;
 
-        Ok(Response::new(response))
+        Ok(Response::new(GetChatKeyResponse {
+            chat_id: chat_id.to_string(),
+            encrypted_key: Some(EncryptedKey {
+                encrypted_data: record.encrypted_key,
+                iv: String::new(),
+                expires_at: record.expires_at.map(timestamp_from_naive),
+                key_version: record.key_version,
+                algorithm: record.algorithm,
+                key_fingerprint: record.key_fingerprint.unwrap_or_default(),
+            }),
+        }))
     }
 
     async fn send_message(
@@ -218,109 +314,1583 @@ impl ChatService for MyChatsService {
         let sender_id = Uuid::parse_str(&req.sender_id)
             .map_err(|_| Status::invalid_argument("Invalid sender_id UUID"))?;
 
-        let is_member: bool = sqlx::query_scalar!(
-            r#"
-            SELECT EXISTS(
-                SELECT 1 FROM direct_chats_members
-                WHERE chat_id = $1 AND user_id = $2
-            )
-            "#,
+        let mentioned_user_ids = parse_mentions(&req.mentioned_user_ids)
+            .map_err(|user| Status::invalid_argument(format!("Invalid mentioned user UUID: {}", user)))?;
+        let reply_to_message_id = parse_reply_to(&req.reply_to_message_id)
+            .map_err(|_| Status::invalid_argument("Invalid reply_to_message_id UUID"))?;
+
+        if req.envelope.is_some() && !req.encrypted_content.is_empty() {
+            return Err(Status::invalid_argument("encrypted_content must be empty when an envelope is sent"));
+        }
+
+        let message = NewMessage {
             chat_id,
-            sender_id
-        )
-        .fetch_one(&self.db)
-        .await
-        .map_err(|e| Status::internal(format!("DB error: {}", e)))?.expect("Error exists db");
+            sender_id,
+            encrypted_content: req.encrypted_content,
+            forwarded_from: None,
+            mentioned_user_ids,
+            reply_to_message_id,
+            envelope_version: req.envelope.as_ref().map(|e| e.version as i32),
+            sender_device_id: req.envelope.as_ref().map(|e| e.sender_device_id.clone()),
+        };
 
-        if !is_member {
-            return Err(Status::permission_denied("User is not a member of the chat!"));
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let posted = post_message(&mut tx, &message).await?;
+
+        if let Some(envelope) = &req.envelope {
+            store_envelope(&mut tx, chat_id, sender_id, posted.id, envelope).await?;
         }
 
-        let sent_at = Utc::now().naive_utc();
-        let record = sqlx::query!(
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        self.notifier.notify_all(posted.notifications).await;
+
+        let response = SendMessageResponse {
+            message_id: posted.id.to_string(),
+            sent_at: Some(timestamp_from_naive(posted.sent_at)),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn forward_message(
+        &self,
+        request: Request<ForwardMessageRequest>,
+    ) -> Result<Response<ForwardMessageResponse>, Status> {
+        let req = request.into_inner();
+
+        let current_user = Uuid::parse_str(&req.current_user)
+            .map_err(|_| Status::invalid_argument("Invalid current_user UUID"))?;
+        let source_id = Uuid::parse_str(&req.source_message_id)
+            .map_err(|_| Status::invalid_argument("Invalid source_message_id UUID"))?;
+
+        if req.targets.is_empty() {
+            return Err(Status::invalid_argument("At least one target chat is required"));
+        }
+        if req.targets.len() > MAX_FORWARD_TARGETS {
+            return Err(Status::invalid_argument(format!(
+                "Cannot forward to more than {} chats at once", MAX_FORWARD_TARGETS
+            )));
+        }
+
+        let mut targets = Vec::with_capacity(req.targets.len());
+        for target in req.targets {
+            let chat_id = Uuid::parse_str(&target.chat_id)
+                .map_err(|_| Status::invalid_argument(format!("Invalid chat_id UUID: {}", target.chat_id)))?;
+            if targets.iter().any(|(id, _)| *id == chat_id) {
+                return Err(Status::invalid_argument(format!("Duplicate target chat: {}", chat_id)));
+            }
+            if target.encrypted_content.is_empty() {
+                return Err(Status::invalid_argument(format!("Missing re-encrypted content for chat {}", chat_id)));
+            }
+            targets.push((chat_id, target.encrypted_content));
+        }
+
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        // Пересылка пересылки ссылается на исходного автора, скрытие наследуется
+        let source = sqlx::query!(
             r#"
-            INSERT INTO messages (chat_id, sender_id, encrypted_content, sent_at)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id
+            SELECT
+                m.chat_id,
+                COALESCE(m.forwarded_from_message_id, m.id) AS "original_message_id!",
+                COALESCE(m.forwarded_from_chat_id, m.chat_id) AS "original_chat_id!",
+                COALESCE(m.forwarded_from_sender_id, m.sender_id) AS "original_sender_id!",
+                m.forward_hidden
+            FROM messages m
+            WHERE m.id = $1 AND m.is_deleted = false
             "#,
-            chat_id,
-            sender_id,
-            req.encrypted_content,
-            sent_at
+            source_id
         )
-        .fetch_one(&self.db)
+        .fetch_optional(&mut *tx)
         .await
-        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
-        
-        sqlx::query!(
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .ok_or_else(|| Status::not_found("Source message not found"))?;
+
+        if !is_chat_member(&mut *tx, source.chat_id, current_user).await? {
+            return Err(Status::permission_denied("User cannot read the source message"));
+        }
+
+        let origin = ForwardOrigin {
+            message_id: source.original_message_id,
+            chat_id: source.original_chat_id,
+            sender_id: source.original_sender_id,
+            hidden: source.forward_hidden || req.hide_origin,
+        };
+
+        let mut messages = Vec::with_capacity(targets.len());
+        let mut notifications = Vec::new();
+        for (chat_id, encrypted_content) in targets {
+            let posted = post_message(&mut tx, &NewMessage {
+                chat_id,
+                sender_id: current_user,
+                encrypted_content,
+                forwarded_from: Some(origin.clone()),
+                mentioned_user_ids: Vec::new(),
+                reply_to_message_id: None,
+                envelope_version: None,
+                sender_device_id: None,
+            }).await?;
+            notifications.extend(posted.notifications);
+
+            messages.push(ForwardedMessage {
+                chat_id: chat_id.to_string(),
+                message_id: posted.id.to_string(),
+                sent_at: Some(timestamp_from_naive(posted.sent_at)),
+            });
+        }
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        self.notifier.notify_all(notifications).await;
+
+        Ok(Response::new(ForwardMessageResponse {
+            messages,
+            forwarded_from: Some(origin.to_proto()),
+        }))
+    }
+
+    async fn fetch_device_messages(
+        &self,
+        request: Request<FetchDeviceMessagesRequest>,
+    ) -> Result<Response<FetchDeviceMessagesResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        touch_device(&self.db, user_id, &req.device_id).await?;
+
+        let limit = match req.limit as i64 {
+            n if n <= 0 => DEFAULT_DEVICE_FETCH_LIMIT,
+            n => n.min(MAX_DEVICE_FETCH_LIMIT),
+        };
+
+        let rows = sqlx::query!(
             r#"
-            UPDATE direct_chats
-            SET last_message = $1
-            WHERE id = $2
+            SELECT
+                d.id, d.message_id, m.chat_id, m.sender_id, m.sender_device_id, m.sent_at, m.envelope_version,
+                d.ratchet_public_key, d.previous_chain_length, d.message_number, d.ciphertext,
+                d.session_ephemeral_key, d.session_signed_prekey_id, d.session_one_time_prekey_id,
+                pi.identity_key AS "sender_identity_key?"
+            FROM message_device_deliveries d
+            JOIN messages m ON m.id = d.message_id
+            LEFT JOIN prekey_identities pi ON pi.user_id = m.sender_id AND pi.device_id = m.sender_device_id
+            WHERE d.recipient_id = $1 AND d.recipient_device_id = $2 AND m.is_deleted = false
+            ORDER BY d.created_at, d.id
+            LIMIT $3
             "#,
-            record.id,
-            chat_id
+            user_id,
+            req.device_id,
+            limit
         )
-        .execute(&self.db)
+        .fetch_all(&self.db)
         .await
         .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
 
-        let response = SendMessageResponse {
-            message_id: record.id.to_string(),
-            sent_at: Some(timestamp_from_naive(sent_at)),
-        };
+        let messages = rows
+            .into_iter()
+            .map(|row| DeviceMessage {
+                delivery_id: row.id.to_string(),
+                message_id: row.message_id.to_string(),
+                chat_id: row.chat_id.to_string(),
+                sender_id: row.sender_id.to_string(),
+                sender_device_id: row.sender_device_id.unwrap_or_default(),
+                sent_at: Some(timestamp_from_naive(row.sent_at)),
+                envelope_version: row.envelope_version.unwrap_or_default() as u32,
+                header: Some(RatchetHeader {
+                    ratchet_public_key: row.ratchet_public_key,
+                    previous_chain_length: row.previous_chain_length as u32,
+                    message_number: row.message_number as u32,
+                }),
+                ciphertext: row.ciphertext,
+                session_init: row.session_ephemeral_key.map(|ephemeral_key| SessionInit {
+                    ephemeral_key,
+                    signed_prekey_id: row.session_signed_prekey_id.unwrap_or_default() as u32,
+                    one_time_prekey_id: row.session_one_time_prekey_id.map(|id| id as u32),
+                }),
+                sender_identity_key: row.sender_identity_key.unwrap_or_default(),
+            })
+            .collect();
 
-        Ok(Response::new(response))
+        Ok(Response::new(FetchDeviceMessagesResponse { messages }))
     }
 
-    async fn exchange_public_keys(
+    // Подтверждённые шифртексты удаляются: повторно они не понадобятся
+    async fn ack_device_messages(
         &self,
-        request: Request<ExchangeKeysRequest>,
-    ) -> Result<Response<ExchangeKeysResponse>, Status> {
+        request: Request<AckDeviceMessagesRequest>,
+    ) -> Result<Response<AckDeviceMessagesResponse>, Status> {
         let req = request.into_inner();
 
         let user_id = Uuid::parse_str(&req.user_id)
             .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
 
-        let _ = RsaPublicKey::from_public_key_pem(&req.public_key)
-            .map_err(|e| Status::invalid_argument(format!("Invalid public key: {}", e)))?;
-
-        let fingerprint = KeyManager::generate_fingerprint(&req.public_key)
-            .map_err(|e| Status::internal(format!("Fingerprint error: {}", e)))?;
-
-        let expires_at = Utc::now() + chrono::Duration::days(30);
+        let mut ids = Vec::with_capacity(req.delivery_ids.len());
+        for id in &req.delivery_ids {
+            ids.push(Uuid::parse_str(id)
+                .map_err(|_| Status::invalid_argument(format!("Invalid delivery UUID: {}", id)))?);
+        }
 
-        sqlx::query!(
+        let result = sqlx::query!(
             r#"
-            INSERT INTO user_keys (user_id, public_key, fingerprint, expires_at)
-            VALUES ($1, $2, $3, $4)
-            ON CONFLICT (fingerprint) DO UPDATE
-            SET public_key = EXCLUDED.public_key,
-                expires_at = EXCLUDED.expires_at,
-                is_revoked = false
+            DELETE FROM message_device_deliveries
+            WHERE recipient_id = $1 AND recipient_device_id = $2 AND id = ANY($3)
             "#,
             user_id,
-            req.public_key,
-            fingerprint,
-            expires_at.naive_utc()
+            req.device_id,
+            &ids
         )
         .execute(&self.db)
         .await
         .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
 
-        let response = ExchangeKeysResponse {
-            target_user_id: user_id.to_string(),
-            public_key: req.public_key,
-        };
+        Ok(Response::new(AckDeviceMessagesResponse {
+            acknowledged: result.rows_affected() as i32,
+        }))
+    }
 
-        Ok(Response::new(response))
+    async fn get_group_epoch(
+        &self,
+        request: Request<GetGroupEpochRequest>,
+    ) -> Result<Response<GetGroupEpochResponse>, Status> {
+        let req = request.into_inner();
+
+        let chat_id = Uuid::parse_str(&req.chat_id)
+            .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?;
+        let current_user = Uuid::parse_str(&req.current_user)
+            .map_err(|_| Status::invalid_argument("Invalid current_user UUID"))?;
+
+        let mut conn = self.db.acquire().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let epoch = group_epoch(&mut conn, chat_id, current_user, false).await?;
+
+        let last_sequence = sqlx::query_scalar!(
+            r#"SELECT COALESCE(MAX(sequence), 0) AS "sequence!" FROM group_handshake_messages WHERE chat_id = $1"#,
+            chat_id
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(Response::new(GetGroupEpochResponse {
+            epoch: epoch as u64,
+            last_sequence: last_sequence as u64,
+        }))
     }
-}
 
-fn timestamp_from_naive(dt: NaiveDateTime) -> Timestamp {
-    let utc_dt = chrono::DateTime::<Utc>::from_utc(dt, Utc);
-    Timestamp {
-        seconds: utc_dt.timestamp(),
-        nanos: utc_dt.timestamp_subsec_nanos() as i32,
+    async fn send_group_proposal(
+        &self,
+        request: Request<SendGroupProposalRequest>,
+    ) -> Result<Response<SendGroupProposalResponse>, Status> {
+        let req = request.into_inner();
+
+        let chat_id = Uuid::parse_str(&req.chat_id)
+            .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?;
+        let sender_id = Uuid::parse_str(&req.sender_id)
+            .map_err(|_| Status::invalid_argument("Invalid sender_id UUID"))?;
+
+        if req.payload.is_empty() || req.payload.len() > MAX_HANDSHAKE_PAYLOAD {
+            return Err(Status::invalid_argument(format!(
+                "Payload must be between 1 and {} bytes", MAX_HANDSHAKE_PAYLOAD
+            )));
+        }
+
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let epoch = group_epoch(&mut tx, chat_id, sender_id, true).await?;
+        if let Some(status) = stale_epoch(req.epoch, epoch) {
+            return Err(status);
+        }
+
+        let proposal = append_handshake(
+            &mut tx, chat_id, epoch, sender_id, HandshakeKind::HandshakeProposal, &req.payload,
+        ).await?;
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(Response::new(SendGroupProposalResponse { proposal: Some(proposal) }))
     }
+
+    async fn send_group_commit(
+        &self,
+        request: Request<SendGroupCommitRequest>,
+    ) -> Result<Response<SendGroupCommitResponse>, Status> {
+        let req = request.into_inner();
+
+        let chat_id = Uuid::parse_str(&req.chat_id)
+            .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?;
+        let sender_id = Uuid::parse_str(&req.sender_id)
+            .map_err(|_| Status::invalid_argument("Invalid sender_id UUID"))?;
+
+        if req.payload.is_empty() || req.payload.len() > MAX_HANDSHAKE_PAYLOAD {
+            return Err(Status::invalid_argument(format!(
+                "Payload must be between 1 and {} bytes", MAX_HANDSHAKE_PAYLOAD
+            )));
+        }
+
+        let mut added = Vec::with_capacity(req.added_user_ids.len());
+        for user in &req.added_user_ids {
+            let user_id = Uuid::parse_str(user)
+                .map_err(|_| Status::invalid_argument(format!("Invalid added user UUID: {}", user)))?;
+            if !added.contains(&user_id) {
+                added.push(user_id);
+            }
+        }
+
+        let mut removed = Vec::with_capacity(req.removed_user_ids.len());
+        for user in &req.removed_user_ids {
+            let user_id = Uuid::parse_str(user)
+                .map_err(|_| Status::invalid_argument(format!("Invalid removed user UUID: {}", user)))?;
+            if added.contains(&user_id) {
+                return Err(Status::invalid_argument(format!("User {} is both added and removed", user_id)));
+            }
+            if !removed.contains(&user_id) {
+                removed.push(user_id);
+            }
+        }
+
+        if removed.contains(&sender_id) {
+            return Err(Status::invalid_argument("Committer cannot remove themselves"));
+        }
+
+        let mut welcomes = Vec::with_capacity(req.welcomes.len());
+        for welcome in req.welcomes {
+            let recipient_id = Uuid::parse_str(&welcome.recipient_id)
+                .map_err(|_| Status::invalid_argument(format!("Invalid welcome recipient UUID: {}", welcome.recipient_id)))?;
+            if welcomes.iter().any(|(id, _)| *id == recipient_id) {
+                return Err(Status::invalid_argument(format!("Duplicate welcome for user {}", recipient_id)));
+            }
+            if welcome.payload.is_empty() || welcome.payload.len() > MAX_HANDSHAKE_PAYLOAD {
+                return Err(Status::invalid_argument(format!(
+                    "Welcome payload must be between 1 and {} bytes", MAX_HANDSHAKE_PAYLOAD
+                )));
+            }
+            welcomes.push((recipient_id, welcome.payload));
+        }
+
+        if let Some(user_id) = added.iter().find(|id| !welcomes.iter().any(|(r, _)| r == *id)) {
+            return Err(Status::invalid_argument(format!("Missing welcome for added user {}", user_id)));
+        }
+
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        // Блокировка строки чата упорядочивает конкурирующие commits: побеждает первый
+        let epoch = group_epoch(&mut tx, chat_id, sender_id, true).await?;
+        if let Some(status) = stale_epoch(req.epoch, epoch) {
+            return Err(status);
+        }
+
+        let members = sqlx::query_scalar!(
+            "SELECT user_id FROM direct_chats_members WHERE chat_id = $1",
+            chat_id
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if let Some(user_id) = added.iter().find(|id| members.contains(id)) {
+            return Err(Status::failed_precondition(format!("User {} is already a member", user_id)));
+        }
+        if let Some(user_id) = removed.iter().find(|id| !members.contains(id)) {
+            return Err(Status::failed_precondition(format!("User {} is not a member", user_id)));
+        }
+
+        let existing_users = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM users WHERE id = ANY($1)"#,
+            &added
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if existing_users as usize != added.len() {
+            return Err(Status::not_found("Added user not found"));
+        }
+
+        // Welcome получают только участники новой эпохи
+        for (recipient_id, _) in &welcomes {
+            let is_member = added.contains(recipient_id)
+                || (members.contains(recipient_id) && !removed.contains(recipient_id));
+            if !is_member {
+                return Err(Status::invalid_argument(format!(
+                    "Welcome recipient {} is not a member of the next epoch", recipient_id
+                )));
+            }
+        }
+
+        let commit = append_handshake(
+            &mut tx, chat_id, epoch, sender_id, HandshakeKind::HandshakeCommit, &req.payload,
+        ).await?;
+        let next_epoch = epoch + 1;
+
+        sqlx::query!(
+            "UPDATE direct_chats SET epoch = $1 WHERE id = $2",
+            next_epoch,
+            chat_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        sqlx::query!(
+            "DELETE FROM direct_chats_members WHERE chat_id = $1 AND user_id = ANY($2)",
+            chat_id,
+            &removed
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        for user_id in &added {
+            sqlx::query!(
+                "INSERT INTO direct_chats_members (chat_id, user_id) VALUES ($1, $2)",
+                chat_id,
+                user_id
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+        }
+
+        for (recipient_id, payload) in &welcomes {
+            sqlx::query!(
+                r#"
+                INSERT INTO group_welcomes (chat_id, epoch, recipient_id, sender_id, payload, created_at)
+                VALUES ($1, $2, $3, $4, $5, NOW() AT TIME ZONE 'UTC')
+                "#,
+                chat_id,
+                next_epoch,
+                recipient_id,
+                sender_id,
+                payload
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+        }
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        for user_id in &removed {
+            self.rooms.evict(chat_id, *user_id);
+        }
+
+        self.notifier.notify_all(
+            added
+                .iter()
+                .map(|user_id| NewNotification {
+                    user_id: *user_id,
+                    kind: NotificationKind::NotificationGroupInvite,
+                    actor_id: Some(sender_id),
+                    chat_id: Some(chat_id),
+                    message_id: None,
+                    device_id: None,
+                })
+                .collect(),
+        ).await;
+
+        Ok(Response::new(SendGroupCommitResponse {
+            commit: Some(commit),
+            epoch: next_epoch as u64,
+        }))
+    }
+
+    async fn fetch_group_handshakes(
+        &self,
+        request: Request<FetchGroupHandshakesRequest>,
+    ) -> Result<Response<FetchGroupHandshakesResponse>, Status> {
+        let req = request.into_inner();
+
+        let chat_id = Uuid::parse_str(&req.chat_id)
+            .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?;
+        let current_user = Uuid::parse_str(&req.current_user)
+            .map_err(|_| Status::invalid_argument("Invalid current_user UUID"))?;
+
+        let limit = match req.limit as i64 {
+            n if n <= 0 => DEFAULT_HANDSHAKE_FETCH_LIMIT,
+            n => n.min(MAX_HANDSHAKE_FETCH_LIMIT),
+        };
+
+        let mut conn = self.db.acquire().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let epoch = group_epoch(&mut conn, chat_id, current_user, false).await?;
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, epoch, sequence, sender_id, kind, payload, created_at
+            FROM group_handshake_messages
+            WHERE chat_id = $1 AND sequence > $2
+            ORDER BY sequence
+            LIMIT $3
+            "#,
+            chat_id,
+            req.after_sequence as i64,
+            limit
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let messages = rows
+            .into_iter()
+            .map(|row| GroupHandshakeMessage {
+                id: row.id.to_string(),
+                chat_id: chat_id.to_string(),
+                epoch: row.epoch as u64,
+                sequence: row.sequence as u64,
+                sender_id: row.sender_id.to_string(),
+                kind: handshake_kind_from_str(&row.kind) as i32,
+                payload: row.payload,
+                created_at: Some(timestamp_from_naive(row.created_at)),
+            })
+            .collect();
+
+        Ok(Response::new(FetchGroupHandshakesResponse {
+            messages,
+            epoch: epoch as u64,
+        }))
+    }
+
+    async fn fetch_welcomes(
+        &self,
+        request: Request<FetchWelcomesRequest>,
+    ) -> Result<Response<FetchWelcomesResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, chat_id, epoch, sender_id, payload, created_at
+            FROM group_welcomes
+            WHERE recipient_id = $1
+            ORDER BY created_at, epoch
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let welcomes = rows
+            .into_iter()
+            .map(|row| GroupWelcome {
+                id: row.id.to_string(),
+                chat_id: row.chat_id.to_string(),
+                epoch: row.epoch as u64,
+                sender_id: row.sender_id.to_string(),
+                payload: row.payload,
+                created_at: Some(timestamp_from_naive(row.created_at)),
+            })
+            .collect();
+
+        Ok(Response::new(FetchWelcomesResponse { welcomes }))
+    }
+
+    // Welcome удаляется только после подтверждения, чтобы обрыв связи не лишил участника группы
+    async fn ack_welcomes(
+        &self,
+        request: Request<AckWelcomesRequest>,
+    ) -> Result<Response<AckWelcomesResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let mut ids = Vec::with_capacity(req.welcome_ids.len());
+        for id in &req.welcome_ids {
+            ids.push(Uuid::parse_str(id)
+                .map_err(|_| Status::invalid_argument(format!("Invalid welcome UUID: {}", id)))?);
+        }
+
+        let result = sqlx::query!(
+            "DELETE FROM group_welcomes WHERE recipient_id = $1 AND id = ANY($2)",
+            user_id,
+            &ids
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(Response::new(AckWelcomesResponse {
+            acknowledged: result.rows_affected() as i32,
+        }))
+    }
+
+    async fn exchange_public_keys(
+        &self,
+        request: Request<ExchangeKeysRequest>,
+    ) -> Result<Response<ExchangeKeysResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let registered = self.key_manager
+            .register_public_key(user_id, &req.public_key, &req.signature)
+            .await?;
+
+        if registered.is_new {
+            handle_key_change(&self.db, &self.notifier, user_id, !registered.is_first).await?;
+        }
+
+        let response = ExchangeKeysResponse {
+            target_user_id: user_id.to_string(),
+            public_key: registered.public_key,
+            fingerprint: registered.fingerprint,
+            expires_at: Some(timestamp_from_naive(registered.expires_at)),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    type ExportChatStream =
+        Pin<Box<dyn Stream<Item = Result<ExportChatLine, Status>> + Send + 'static>>;
+
+    async fn export_chat(
+        &self,
+        request: Request<ExportChatRequest>,
+    ) -> Result<Response<Self::ExportChatStream>, Status> {
+        let req = request.into_inner();
+
+        let chat_id = Uuid::parse_str(&req.chat_id)
+            .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?;
+        let current_user = Uuid::parse_str(&req.current_user)
+            .map_err(|_| Status::invalid_argument("Invalid current_user UUID"))?;
+
+        match ExportFormat::try_from(req.format) {
+            Ok(ExportFormat::ExportJsonLines) => {}
+            Err(_) => return Err(Status::invalid_argument("Unsupported export format")),
+        }
+
+        if !is_chat_member(&self.db, chat_id, current_user).await? {
+            return Err(Status::permission_denied("User is not a member of the chat!"));
+        }
+
+        let lines = export_chat_lines(self.db.clone(), chat_id, current_user)
+            .map_ok(|json| ExportChatLine { json });
+
+        Ok(Response::new(Box::pin(lines)))
+    }
+
+    async fn update_chat_preferences(
+        &self,
+        request: Request<UpdateChatPreferencesRequest>,
+    ) -> Result<Response<UpdateChatPreferencesResponse>, Status> {
+        let req = request.into_inner();
+
+        let chat_id = Uuid::parse_str(&req.chat_id)
+            .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?;
+        let current_user = Uuid::parse_str(&req.current_user)
+            .map_err(|_| Status::invalid_argument("Invalid current_user UUID"))?;
+
+        // Нулевая метка времени снимает заглушку
+        let muted_until = match req.muted_until.as_ref() {
+            Some(ts) if ts.seconds == 0 && ts.nanos == 0 => Some(None),
+            Some(ts) => Some(Some(naive_from_timestamp(ts)
+                .ok_or_else(|| Status::invalid_argument("Invalid muted_until timestamp"))?)),
+            None => None,
+        };
+
+        let notification_level = match req.notification_level {
+            Some(level) => Some(
+                notification_level_to_str(NotificationLevel::try_from(level)
+                    .map_err(|_| Status::invalid_argument("Invalid notification level"))?)
+            ),
+            None => None,
+        };
+
+        if req.nickname.as_ref().is_some_and(|nickname| nickname.chars().count() > MAX_CHAT_NICKNAME_LEN) {
+            return Err(Status::invalid_argument(format!(
+                "Nickname must not exceed {} characters", MAX_CHAT_NICKNAME_LEN
+            )));
+        }
+
+        let record = sqlx::query_as!(
+            PreferencesRecord,
+            r#"
+            UPDATE direct_chats_members
+            SET muted_until = CASE WHEN $3 THEN $4 ELSE muted_until END,
+                is_archived = COALESCE($5, is_archived),
+                notification_level = COALESCE($6, notification_level),
+                nickname = CASE WHEN $7::text IS NULL THEN nickname ELSE NULLIF($7, '') END
+            WHERE chat_id = $1 AND user_id = $2
+            RETURNING chat_id, muted_until, is_archived, notification_level, nickname
+            "#,
+            chat_id,
+            current_user,
+            muted_until.is_some(),
+            muted_until.flatten(),
+            req.is_archived,
+            notification_level,
+            req.nickname
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .ok_or_else(|| Status::permission_denied("User is not a member of the chat!"))?;
+
+        Ok(Response::new(UpdateChatPreferencesResponse {
+            preferences: Some(record.into_proto()),
+        }))
+    }
+
+    async fn get_chat_preferences(
+        &self,
+        request: Request<GetChatPreferencesRequest>,
+    ) -> Result<Response<GetChatPreferencesResponse>, Status> {
+        let req = request.into_inner();
+
+        let current_user = Uuid::parse_str(&req.current_user)
+            .map_err(|_| Status::invalid_argument("Invalid current_user UUID"))?;
+        let chat_id = if req.chat_id.is_empty() {
+            None
+        } else {
+            Some(Uuid::parse_str(&req.chat_id)
+                .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?)
+        };
+
+        let records = sqlx::query_as!(
+            PreferencesRecord,
+            r#"
+            SELECT chat_id, muted_until, is_archived, notification_level, nickname
+            FROM direct_chats_members
+            WHERE user_id = $1
+              AND ($2::uuid IS NULL OR chat_id = $2)
+            ORDER BY chat_id
+            "#,
+            current_user,
+            chat_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if chat_id.is_some() && records.is_empty() {
+            return Err(Status::permission_denied("User is not a member of the chat!"));
+        }
+
+        Ok(Response::new(GetChatPreferencesResponse {
+            preferences: records.into_iter().map(PreferencesRecord::into_proto).collect(),
+        }))
+    }
+
+    async fn schedule_message(
+        &self,
+        request: Request<ScheduleMessageRequest>,
+    ) -> Result<Response<ScheduleMessageResponse>, Status> {
+        let req = request.into_inner();
+
+        let chat_id = Uuid::parse_str(&req.chat_id)
+            .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?;
+        let sender_id = Uuid::parse_str(&req.sender_id)
+            .map_err(|_| Status::invalid_argument("Invalid sender_id UUID"))?;
+
+        if req.encrypted_content.is_empty() {
+            return Err(Status::invalid_argument("Message content is empty"));
+        }
+
+        // Проверяются при доставке, как и у SendMessage
+        let mentioned_user_ids = parse_mentions(&req.mentioned_user_ids)
+            .map_err(|user| Status::invalid_argument(format!("Invalid mentioned user UUID: {}", user)))?;
+        let reply_to_message_id = parse_reply_to(&req.reply_to_message_id)
+            .map_err(|_| Status::invalid_argument("Invalid reply_to_message_id UUID"))?;
+
+        let deliver_at = req.deliver_at
+            .as_ref()
+            .and_then(naive_from_timestamp)
+            .ok_or_else(|| Status::invalid_argument("Invalid deliver_at timestamp"))?;
+
+        if deliver_at <= Utc::now().naive_utc() {
+            return Err(Status::invalid_argument("deliver_at must be in the future"));
+        }
+
+        if !is_chat_member(&self.db, chat_id, sender_id).await? {
+            return Err(Status::permission_denied("User is not a member of the chat!"));
+        }
+
+        let record = sqlx::query!(
+            r#"
+            INSERT INTO scheduled_messages (
+                chat_id, sender_id, encrypted_content, deliver_at, mentioned_user_ids, reply_to_message_id
+            )
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, created_at
+            "#,
+            chat_id,
+            sender_id,
+            req.encrypted_content,
+            deliver_at,
+            &mentioned_user_ids,
+            reply_to_message_id
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(Response::new(ScheduleMessageResponse {
+            scheduled: Some(ScheduledMessage {
+                id: record.id.to_string(),
+                chat_id: chat_id.to_string(),
+                sender_id: sender_id.to_string(),
+                encrypted_content: req.encrypted_content,
+                deliver_at: Some(timestamp_from_naive(deliver_at)),
+                created_at: Some(timestamp_from_naive(record.created_at)),
+                mentioned_user_ids: mentioned_user_ids.iter().map(Uuid::to_string).collect(),
+                reply_to_message_id: reply_to_message_id.map(|id| id.to_string()).unwrap_or_default(),
+            }),
+        }))
+    }
+
+    async fn list_scheduled(
+        &self,
+        request: Request<ListScheduledRequest>,
+    ) -> Result<Response<ListScheduledResponse>, Status> {
+        let req = request.into_inner();
+
+        let sender_id = Uuid::parse_str(&req.sender_id)
+            .map_err(|_| Status::invalid_argument("Invalid sender_id UUID"))?;
+        let chat_id = if req.chat_id.is_empty() {
+            None
+        } else {
+            Some(Uuid::parse_str(&req.chat_id)
+                .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?)
+        };
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, chat_id, sender_id, encrypted_content, deliver_at, created_at,
+                   mentioned_user_ids, reply_to_message_id
+            FROM scheduled_messages
+            WHERE sender_id = $1
+              AND status = 'PENDING'
+              AND ($2::uuid IS NULL OR chat_id = $2)
+            ORDER BY deliver_at
+            "#,
+            sender_id,
+            chat_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let messages = rows
+            .into_iter()
+            .map(|row| ScheduledMessage {
+                id: row.id.to_string(),
+                chat_id: row.chat_id.to_string(),
+                sender_id: row.sender_id.to_string(),
+                encrypted_content: row.encrypted_content,
+                deliver_at: Some(timestamp_from_naive(row.deliver_at)),
+                created_at: Some(timestamp_from_naive(row.created_at)),
+                mentioned_user_ids: row.mentioned_user_ids.iter().map(Uuid::to_string).collect(),
+                reply_to_message_id: row.reply_to_message_id.map(|id| id.to_string()).unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(Response::new(ListScheduledResponse { messages }))
+    }
+
+    async fn cancel_scheduled(
+        &self,
+        request: Request<CancelScheduledRequest>,
+    ) -> Result<Response<CancelScheduledResponse>, Status> {
+        let req = request.into_inner();
+
+        let scheduled_id = Uuid::parse_str(&req.scheduled_id)
+            .map_err(|_| Status::invalid_argument("Invalid scheduled_id UUID"))?;
+        let sender_id = Uuid::parse_str(&req.sender_id)
+            .map_err(|_| Status::invalid_argument("Invalid sender_id UUID"))?;
+
+        // Планировщик держит строку под FOR UPDATE, поэтому уже отправленное сообщение отменить нельзя
+        let result = sqlx::query!(
+            r#"
+            UPDATE scheduled_messages
+            SET status = 'CANCELLED', processed_at = NOW() AT TIME ZONE 'UTC'
+            WHERE id = $1 AND sender_id = $2 AND status = 'PENDING'
+            "#,
+            scheduled_id,
+            sender_id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if result.rows_affected() == 0 {
+            return Err(Status::not_found("No pending scheduled message found"));
+        }
+
+        Ok(Response::new(CancelScheduledResponse { success: true }))
+    }
+}
+
+// Упомянутые пользователи без повторов; ошибка — первая некорректная строка
+fn parse_mentions(users: &[String]) -> Result<Vec<Uuid>, &str> {
+    let mut mentioned_user_ids = Vec::with_capacity(users.len());
+    for user in users {
+        let user_id = Uuid::parse_str(user).map_err(|_| user.as_str())?;
+        if !mentioned_user_ids.contains(&user_id) {
+            mentioned_user_ids.push(user_id);
+        }
+    }
+    Ok(mentioned_user_ids)
+}
+
+fn parse_reply_to(message_id: &str) -> Result<Option<Uuid>, uuid::Error> {
+    if message_id.is_empty() {
+        return Ok(None);
+    }
+    Uuid::parse_str(message_id).map(Some)
+}
+
+// Настройки участника чата из direct_chats_members
+struct PreferencesRecord {
+    chat_id: Uuid,
+    muted_until: Option<NaiveDateTime>,
+    is_archived: bool,
+    notification_level: String,
+    nickname: Option<String>,
+}
+
+impl PreferencesRecord {
+    fn into_proto(self) -> ChatPreferences {
+        ChatPreferences {
+            chat_id: self.chat_id.to_string(),
+            muted_until: self.muted_until.map(timestamp_from_naive),
+            is_archived: self.is_archived,
+            notification_level: notification_level_from_str(&self.notification_level) as i32,
+            nickname: self.nickname.unwrap_or_default(),
+        }
+    }
+}
+
+// Новое сообщение для общего пути отправки
+pub(crate) struct NewMessage {
+    pub chat_id: Uuid,
+    pub sender_id: Uuid,
+    pub encrypted_content: String,
+    pub forwarded_from: Option<ForwardOrigin>,
+    pub mentioned_user_ids: Vec<Uuid>,
+    pub reply_to_message_id: Option<Uuid>,
+    // Для конвертов Double Ratchet; шифртексты хранит store_envelope
+    pub envelope_version: Option<i32>,
+    pub sender_device_id: Option<String>,
+}
+
+// Исходное сообщение пересылки
+#[derive(Debug, Clone)]
+pub(crate) struct ForwardOrigin {
+    pub message_id: Uuid,
+    pub chat_id: Uuid,
+    pub sender_id: Uuid,
+    pub hidden: bool,
+}
+
+impl ForwardOrigin {
+    fn to_proto(&self) -> ForwardInfo {
+        if self.hidden {
+            return ForwardInfo { hidden: true, ..Default::default() };
+        }
+
+        ForwardInfo {
+            original_message_id: self.message_id.to_string(),
+            original_chat_id: self.chat_id.to_string(),
+            original_sender_id: self.sender_id.to_string(),
+            hidden: false,
+        }
+    }
+}
+
+pub(crate) struct PostedMessage {
+    pub id: Uuid,
+    pub sent_at: NaiveDateTime,
+    // Упоминания и ответы; отправляются вызывающим после фиксации транзакции
+    pub notifications: Vec<NewNotification>,
+}
+
+// Общий путь отправки для SendMessage, пересылки и планировщика:
+// проверка членства, запись сообщения и обновление last_message
+pub(crate) async fn post_message(
+    conn: &mut PgConnection,
+    message: &NewMessage,
+) -> Result<PostedMessage, Status> {
+    if !is_chat_member(&mut *conn, message.chat_id, message.sender_id).await? {
+        return Err(Status::permission_denied("User is not a member of the chat!"));
+    }
+
+    if !message.mentioned_user_ids.is_empty() {
+        let members = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!" FROM direct_chats_members
+            WHERE chat_id = $1 AND user_id = ANY($2)
+            "#,
+            message.chat_id,
+            &message.mentioned_user_ids
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if members as usize != message.mentioned_user_ids.len() {
+            return Err(Status::invalid_argument("Mentioned users must be members of the chat"));
+        }
+    }
+
+    let reply_to_sender = match message.reply_to_message_id {
+        Some(reply_id) => Some(
+            sqlx::query_scalar!(
+                "SELECT sender_id FROM messages WHERE id = $1 AND chat_id = $2",
+                reply_id,
+                message.chat_id
+            )
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+            .ok_or_else(|| Status::invalid_argument("Replied message not found in this chat"))?
+        ),
+        None => None,
+    };
+
+    let sent_at = Utc::now().naive_utc();
+    let forward = message.forwarded_from.as_ref();
+    let record = sqlx::query!(
+        r#"
+        INSERT INTO messages (
+            chat_id, sender_id, encrypted_content, sent_at,
+            forwarded_from_message_id, forwarded_from_chat_id, forwarded_from_sender_id, forward_hidden,
+            mentioned_user_ids, reply_to_message_id, envelope_version, sender_device_id
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        RETURNING id
+        "#,
+        message.chat_id,
+        message.sender_id,
+        message.encrypted_content,
+        sent_at,
+        forward.map(|f| f.message_id),
+        forward.map(|f| f.chat_id),
+        forward.map(|f| f.sender_id),
+        forward.is_some_and(|f| f.hidden),
+        &message.mentioned_user_ids,
+        message.reply_to_message_id,
+        message.envelope_version,
+        message.sender_device_id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    sqlx::query!(
+        r#"
+        UPDATE direct_chats
+        SET last_message = $1
+        WHERE id = $2
+        "#,
+        record.id,
+        message.chat_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    // Новое сообщение возвращает чат из архива, если участник его не заглушил
+    sqlx::query!(
+        r#"
+        UPDATE direct_chats_members
+        SET is_archived = false
+        WHERE chat_id = $1
+          AND is_archived
+          AND (muted_until IS NULL OR muted_until <= $2)
+        "#,
+        message.chat_id,
+        sent_at
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    Ok(PostedMessage {
+        id: record.id,
+        sent_at,
+        notifications: message_notifications(message, record.id, reply_to_sender),
+    })
+}
+
+// Системное событие в ленте чата от имени участника, которого оно касается
+pub(crate) async fn post_system_event(
+    conn: &mut PgConnection,
+    chat_id: Uuid,
+    actor_id: Uuid,
+    event: &str,
+) -> Result<Uuid, Status> {
+    let record = sqlx::query!(
+        r#"
+        INSERT INTO messages (chat_id, sender_id, encrypted_content, sent_at, system_event)
+        VALUES ($1, $2, '', $3, $4)
+        RETURNING id
+        "#,
+        chat_id,
+        actor_id,
+        Utc::now().naive_utc(),
+        event
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    sqlx::query!(
+        "UPDATE direct_chats SET last_message = $1 WHERE id = $2",
+        record.id,
+        chat_id
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    Ok(record.id)
+}
+
+// Структурная проверка конверта Double Ratchet и раскладка шифртекстов по устройствам.
+// Содержимое сервер не расшифровывает: проверяются версия, размеры ключей и покрытие устройств.
+async fn store_envelope(
+    conn: &mut PgConnection,
+    chat_id: Uuid,
+    sender_id: Uuid,
+    message_id: Uuid,
+    envelope: &MessageEnvelope,
+) -> Result<(), Status> {
+    if envelope.version != ENVELOPE_VERSION {
+        return Err(Status::invalid_argument(format!("Unsupported envelope version: {}", envelope.version)));
+    }
+
+    let is_group = sqlx::query_scalar!(
+        "SELECT is_group FROM direct_chats WHERE id = $1",
+        chat_id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    if is_group {
+        return Err(Status::failed_precondition("Envelopes are only supported in direct chats"));
+    }
+
+    // Устройства участников, опубликовавшие предключи
+    let devices = sqlx::query!(
+        r#"
+        SELECT pi.user_id, pi.device_id
+        FROM prekey_identities pi
+        JOIN direct_chats_members dcm ON dcm.user_id = pi.user_id
+        WHERE dcm.chat_id = $1
+        "#,
+        chat_id
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    let sender_device = envelope.sender_device_id.as_str();
+    if !devices.iter().any(|d| d.user_id == sender_id && d.device_id == sender_device) {
+        return Err(Status::failed_precondition("Sender device has not published prekeys"));
+    }
+
+    if envelope.recipients.is_empty() {
+        return Err(Status::invalid_argument("Envelope has no recipients"));
+    }
+
+    let mut covered: Vec<(Uuid, &str)> = Vec::with_capacity(envelope.recipients.len());
+    let mut deliveries = Vec::with_capacity(envelope.recipients.len());
+    for recipient in &envelope.recipients {
+        let recipient_id = Uuid::parse_str(&recipient.recipient_user_id)
+            .map_err(|_| Status::invalid_argument(format!("Invalid recipient UUID: {}", recipient.recipient_user_id)))?;
+        let device_id = recipient.recipient_device_id.as_str();
+
+        if recipient_id == sender_id && device_id == sender_device {
+            return Err(Status::invalid_argument("Envelope cannot be addressed to the sending device"));
+        }
+        if !devices.iter().any(|d| d.user_id == recipient_id && d.device_id == device_id) {
+            return Err(Status::failed_precondition(format!("Unknown device {} of user {}", device_id, recipient_id)));
+        }
+        if covered.contains(&(recipient_id, device_id)) {
+            return Err(Status::invalid_argument(format!("Duplicate recipient device {} of user {}", device_id, recipient_id)));
+        }
+        covered.push((recipient_id, device_id));
+
+        let header = recipient.header.as_ref()
+            .ok_or_else(|| Status::invalid_argument("Ratchet header is required"))?;
+        if header.ratchet_public_key.len() != RATCHET_KEY_SIZE {
+            return Err(Status::invalid_argument(format!("Ratchet public key must be {} bytes", RATCHET_KEY_SIZE)));
+        }
+        if recipient.ciphertext.is_empty() || recipient.ciphertext.len() > MAX_ENVELOPE_CIPHERTEXT {
+            return Err(Status::invalid_argument(format!(
+                "Ciphertext must be between 1 and {} bytes", MAX_ENVELOPE_CIPHERTEXT
+            )));
+        }
+        if let Some(init) = &recipient.session_init
+            && init.ephemeral_key.len() != RATCHET_KEY_SIZE
+        {
+            return Err(Status::invalid_argument(format!("Ephemeral key must be {} bytes", RATCHET_KEY_SIZE)));
+        }
+
+        deliveries.push((recipient_id, recipient, header));
+    }
+
+    // Клиент должен зашифровать сообщение для каждого устройства собеседника,
+    // иначе он получит FailedPrecondition и заново запросит наборы предключей
+    if let Some(missing) = devices.iter().find(|d| {
+        d.user_id != sender_id && !covered.contains(&(d.user_id, d.device_id.as_str()))
+    }) {
+        return Err(Status::failed_precondition(format!(
+            "Envelope is missing device {} of user {}", missing.device_id, missing.user_id
+        )));
+    }
+
+    for (recipient_id, recipient, header) in deliveries {
+        let init = recipient.session_init.as_ref();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO message_device_deliveries (
+                message_id, recipient_id, recipient_device_id,
+                ratchet_public_key, previous_chain_length, message_number, ciphertext,
+                session_ephemeral_key, session_signed_prekey_id, session_one_time_prekey_id, created_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, NOW() AT TIME ZONE 'UTC')
+            "#,
+            message_id,
+            recipient_id,
+            recipient.recipient_device_id,
+            header.ratchet_public_key,
+            header.previous_chain_length as i32,
+            header.message_number as i32,
+            recipient.ciphertext,
+            init.map(|i| i.ephemeral_key.clone()),
+            init.map(|i| i.signed_prekey_id as i32),
+            init.and_then(|i| i.one_time_prekey_id).map(|id| id as i32)
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+// Текущая эпоха группы с проверкой членства; lock блокирует строку чата до конца транзакции
+async fn group_epoch(
+    conn: &mut PgConnection,
+    chat_id: Uuid,
+    user_id: Uuid,
+    lock: bool,
+) -> Result<i64, Status> {
+    let chat = if lock {
+        sqlx::query!(
+            "SELECT is_group, epoch FROM direct_chats WHERE id = $1 FOR UPDATE",
+            chat_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map(|row| row.map(|r| (r.is_group, r.epoch)))
+    } else {
+        sqlx::query!(
+            "SELECT is_group, epoch FROM direct_chats WHERE id = $1",
+            chat_id
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map(|row| row.map(|r| (r.is_group, r.epoch)))
+    }
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    let (is_group, epoch) = chat.ok_or_else(|| Status::not_found("Chat not found"))?;
+
+    if !is_group {
+        return Err(Status::failed_precondition("Group key agreement is only available in group chats"));
+    }
+    if !is_chat_member(&mut *conn, chat_id, user_id).await? {
+        return Err(Status::permission_denied("User is not a member of the chat!"));
+    }
+
+    Ok(epoch)
+}
+
+// Proposals и commits принимаются только для текущей эпохи
+fn stale_epoch(requested: u64, current: i64) -> Option<Status> {
+    (requested != current as u64).then(|| Status::failed_precondition(format!(
+        "Stale epoch {}: group is at epoch {}", requested, current
+    )))
+}
+
+async fn append_handshake(
+    conn: &mut PgConnection,
+    chat_id: Uuid,
+    epoch: i64,
+    sender_id: Uuid,
+    kind: HandshakeKind,
+    payload: &[u8],
+) -> Result<GroupHandshakeMessage, Status> {
+    // Вызывается под блокировкой строки чата, поэтому MAX + 1 не даёт гонок
+    let record = sqlx::query!(
+        r#"
+        INSERT INTO group_handshake_messages (chat_id, epoch, sequence, sender_id, kind, payload, created_at)
+        SELECT $1, $2, COALESCE(MAX(sequence), 0) + 1, $3, $4, $5, NOW() AT TIME ZONE 'UTC'
+        FROM group_handshake_messages
+        WHERE chat_id = $1
+        RETURNING id, sequence, created_at
+        "#,
+        chat_id,
+        epoch,
+        sender_id,
+        handshake_kind_to_str(kind),
+        payload
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    Ok(GroupHandshakeMessage {
+        id: record.id.to_string(),
+        chat_id: chat_id.to_string(),
+        epoch: epoch as u64,
+        sequence: record.sequence as u64,
+        sender_id: sender_id.to_string(),
+        kind: kind as i32,
+        payload: payload.to_vec(),
+        created_at: Some(timestamp_from_naive(record.created_at)),
+    })
+}
+
+fn handshake_kind_to_str(kind: HandshakeKind) -> &'static str {
+    match kind {
+        HandshakeKind::HandshakeProposal => "PROPOSAL",
+        HandshakeKind::HandshakeCommit => "COMMIT",
+    }
+}
+
+fn handshake_kind_from_str(kind: &str) -> HandshakeKind {
+    match kind {
+        "COMMIT" => HandshakeKind::HandshakeCommit,
+        _ => HandshakeKind::HandshakeProposal,
+    }
+}
+
+// Уведомления об упоминаниях и ответах; упомянутый автор ответа получает одно уведомление
+fn message_notifications(message: &NewMessage, message_id: Uuid, reply_to_sender: Option<Uuid>) -> Vec<NewNotification> {
+    let mut notifications: Vec<NewNotification> = message.mentioned_user_ids
+        .iter()
+        .map(|user_id| NewNotification {
+            user_id: *user_id,
+            kind: NotificationKind::NotificationMention,
+            actor_id: Some(message.sender_id),
+            chat_id: Some(message.chat_id),
+            message_id: Some(message_id),
+            device_id: None,
+        })
+        .collect();
+
+    if let Some(author) = reply_to_sender
+        && !message.mentioned_user_ids.contains(&author)
+    {
+        notifications.push(NewNotification {
+            user_id: author,
+            kind: NotificationKind::NotificationReply,
+            actor_id: Some(message.sender_id),
+            chat_id: Some(message.chat_id),
+            message_id: Some(message_id),
+            device_id: None,
+        });
+    }
+
+    notifications
+}
+
+// После ротации ключа пользователя каждый его чат получает новую версию ключа, обёрнутую
+// для действующих ключей участников и всех их устройств. Чат, у участника которого нет
+// действующего ключа, остаётся на прежней версии.
+pub(crate) async fn rotate_user_chat_keys(db: &PgPool, key_manager: &KeyManager, user_id: Uuid) -> Result<(), Status> {
+    let chats = sqlx::query_scalar!("SELECT chat_id FROM direct_chats_members WHERE user_id = $1", user_id)
+        .fetch_all(db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    for chat_id in chats {
+        let members = sqlx::query_scalar!(
+            "SELECT user_id FROM direct_chats_members WHERE chat_id = $1 ORDER BY user_id",
+            chat_id
+        )
+        .fetch_all(db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let member_keys = match key_manager.member_keys(&members).await {
+            Ok(member_keys) => member_keys,
+            Err(status) if status.code() == tonic::Code::FailedPrecondition => {
+                warn!("Chat {} keeps its key version after rotation: {}", chat_id, status.message());
+                continue;
+            }
+            Err(status) => return Err(status),
+        };
+        let member_devices = key_manager.member_devices(&members).await?;
+        let (wrapped_keys, wrapped_device_keys) = match KeyManager::wrap_new_chat_key(&member_keys, &member_devices) {
+            Ok(wrapped) => wrapped,
+            Err(e @ WrapError::UnusableKey { .. }) => {
+                warn!("Chat {} keeps its key version after rotation: {}", chat_id, e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut tx = db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        // Блокировка чата упорядочивает конкурирующие ротации
+        sqlx::query_scalar!("SELECT id FROM direct_chats WHERE id = $1 FOR UPDATE", chat_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let key_version = sqlx::query_scalar!(
+            r#"SELECT COALESCE(MAX(key_version), 0) + 1 AS "key_version!" FROM direct_chats_keys WHERE chat_id = $1"#,
+            chat_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        store_chat_keys(&mut tx, chat_id, key_version, &wrapped_keys).await?;
+        store_device_chat_keys(&mut tx, chat_id, key_version, &wrapped_device_keys).await?;
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+async fn store_chat_keys(
+    conn: &mut PgConnection,
+    chat_id: Uuid,
+    key_version: i32,
+    keys: &[WrappedChatKey],
+) -> Result<(), Status> {
+    for key in keys {
+        sqlx::query!(
+            r#"
+            INSERT INTO direct_chats_keys (chat_id, user_id, encrypted_key, key_version, key_fingerprint, algorithm)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            chat_id,
+            key.user_id,
+            hex::encode(&key.encrypted_key),
+            key_version,
+            key.fingerprint,
+            key.algorithm
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+async fn store_device_chat_keys(
+    conn: &mut PgConnection,
+    chat_id: Uuid,
+    key_version: i32,
+    keys: &[WrappedDeviceChatKey],
+) -> Result<(), Status> {
+    for key in keys {
+        sqlx::query!(
+            r#"
+            INSERT INTO device_chat_keys (chat_id, user_id, device_id, key_version, algorithm, encrypted_key)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            chat_id,
+            key.user_id,
+            key.device_id,
+            key_version,
+            key.algorithm,
+            key.encrypted_key
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+fn encrypted_key_proto(key: &WrappedChatKey, key_version: i32) -> EncryptedKey {
+    EncryptedKey {
+        encrypted_data: hex::encode(&key.encrypted_key),
+        iv: String::new(),
+        expires_at: Some(timestamp_from_naive(key.expires_at)),
+        key_version,
+        algorithm: key.algorithm.to_string(),
+        key_fingerprint: key.fingerprint.clone(),
+    }
+}
+
+async fn is_chat_member<'e, E>(executor: E, chat_id: Uuid, user_id: Uuid) -> Result<bool, Status>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let is_member = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM direct_chats_members
+            WHERE chat_id = $1 AND user_id = $2
+        ) AS "exists!"
+        "#,
+        chat_id,
+        user_id
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    Ok(is_member)
+}
+
+pub(crate) fn timestamp_from_naive(dt: NaiveDateTime) -> Timestamp {
+    let utc_dt = chrono::DateTime::<Utc>::from_utc(dt, Utc);
+    Timestamp {
+        seconds: utc_dt.timestamp(),
+        nanos: utc_dt.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn notification_level_to_str(level: NotificationLevel) -> &'static str {
+    match level {
+        NotificationLevel::NotifyAll => "ALL",
+        NotificationLevel::NotifyMentions => "MENTIONS",
+        NotificationLevel::NotifyNone => "NONE",
+    }
+}
+
+fn notification_level_from_str(level: &str) -> NotificationLevel {
+    match level {
+        "MENTIONS" => NotificationLevel::NotifyMentions,
+        "NONE" => NotificationLevel::NotifyNone,
+        _ => NotificationLevel::NotifyAll,
+    }
+}
+
+fn naive_from_timestamp(ts: &Timestamp) -> Option<NaiveDateTime> {
+    chrono::DateTime::<Utc>::from_timestamp(ts.seconds, ts.nanos.try_into().ok()?)
+        .map(|dt| dt.naive_utc())
 }
\ No newline at end of file
@@ -0,0 +1,704 @@
+use tonic::{Request, Response, Status};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use ed25519_dalek::{Signature, VerifyingKey};
+use rand::rngs::OsRng;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::services::chat_service::timestamp_from_naive;
+use crate::services::key_manager::{KeyManager, DEVICE_CHAT_KEY_ALGORITHM};
+use crate::services::notification_service::{Notifier, NewNotification, NotificationKind};
+
+mod devices {
+    tonic::include_proto!("devices");
+}
+
+pub use devices::device_service_server::{DeviceService, DeviceServiceServer};
+use devices::{
+    Device, NewDevice, DevicePlatform, DeviceChatKey, RegisterDeviceRequest, RegisterDeviceResponse,
+    ListDevicesRequest, ListDevicesResponse, RemoveDeviceRequest, RemoveDeviceResponse,
+    StartDevicePairingRequest, StartDevicePairingResponse, GetDevicePairingRequest, GetDevicePairingResponse,
+    ApproveDevicePairingRequest, ApproveDevicePairingResponse,
+};
+
+const KEY_SIZE: usize = 32;
+const MAX_DEVICE_ID_LEN: usize = 128;
+const MAX_DEVICE_NAME_LEN: usize = 64;
+const PAIRING_CODE_DIGITS: u32 = 8;
+const PAIRING_TTL_MINUTES: i64 = 5;
+const MAX_PAIRING_ATTEMPTS: i32 = 5;
+const MAX_PENDING_PAIRINGS: i64 = 5;
+const MAX_APPROVAL_CHAT_KEYS: usize = 1000;
+const MAX_DEVICE_CHAT_KEY_LEN: usize = 1024;
+const REMOVAL_MAX_SKEW_SECS: i64 = 5 * 60;
+
+#[derive(Debug)]
+pub struct MyDeviceService {
+    db: PgPool,
+    notifier: Notifier,
+    key_manager: KeyManager,
+}
+
+impl MyDeviceService {
+    pub fn new(db: PgPool, notifier: Notifier, key_manager: KeyManager) -> Self {
+        Self { db, notifier, key_manager }
+    }
+}
+
+struct DeviceRecord {
+    device_id: String,
+    name: String,
+    platform: String,
+    identity_key: Vec<u8>,
+    encryption_key: Option<Vec<u8>>,
+    approved_by_device_id: Option<String>,
+    created_at: NaiveDateTime,
+    last_seen_at: NaiveDateTime,
+}
+
+// Отметка активности устройства; незарегистрированные устройства игнорируются
+pub(crate) async fn touch_device(db: &PgPool, user_id: Uuid, device_id: &str) -> Result<(), Status> {
+    sqlx::query!(
+        "UPDATE devices SET last_seen_at = NOW() AT TIME ZONE 'UTC' WHERE user_id = $1 AND device_id = $2",
+        user_id,
+        device_id
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    Ok(())
+}
+
+fn platform_to_str(platform: DevicePlatform) -> &'static str {
+    match platform {
+        DevicePlatform::Windows => "WINDOWS",
+        DevicePlatform::Macos => "MACOS",
+        DevicePlatform::Linux => "LINUX",
+        DevicePlatform::Android => "ANDROID",
+        DevicePlatform::Ios => "IOS",
+        DevicePlatform::Web => "WEB",
+        DevicePlatform::Unspecified => "UNSPECIFIED",
+    }
+}
+
+fn platform_from_str(platform: &str) -> DevicePlatform {
+    match platform {
+        "WINDOWS" => DevicePlatform::Windows,
+        "MACOS" => DevicePlatform::Macos,
+        "LINUX" => DevicePlatform::Linux,
+        "ANDROID" => DevicePlatform::Android,
+        "IOS" => DevicePlatform::Ios,
+        "WEB" => DevicePlatform::Web,
+        _ => DevicePlatform::Unspecified,
+    }
+}
+
+fn device_to_proto(device: DeviceRecord) -> Device {
+    Device {
+        device_id: device.device_id,
+        name: device.name,
+        platform: platform_from_str(&device.platform) as i32,
+        identity_key: device.identity_key,
+        encryption_key: device.encryption_key.unwrap_or_default(),
+        created_at: Some(timestamp_from_naive(device.created_at)),
+        last_seen_at: Some(timestamp_from_naive(device.last_seen_at)),
+        approved_by_device_id: device.approved_by_device_id.unwrap_or_default(),
+    }
+}
+
+fn verify_signature(identity_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(identity_key) = <[u8; KEY_SIZE]>::try_from(identity_key) else {
+        return false;
+    };
+    let (Ok(verifying_key), Ok(signature)) = (VerifyingKey::from_bytes(&identity_key), Signature::from_slice(signature)) else {
+        return false;
+    };
+    verifying_key.verify_strict(message, &signature).is_ok()
+}
+
+// Проверка данных нового устройства; ключ шифрования должен быть подписан identity-ключом
+fn validate_new_device(device: &NewDevice) -> Result<(String, &'static str), String> {
+    if device.device_id.is_empty() || device.device_id.len() > MAX_DEVICE_ID_LEN {
+        return Err("Invalid device_id".to_string());
+    }
+
+    let name = device.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_DEVICE_NAME_LEN {
+        return Err(format!("Device name must be 1-{} characters", MAX_DEVICE_NAME_LEN));
+    }
+
+    let platform = DevicePlatform::try_from(device.platform)
+        .map_err(|_| "Unknown device platform".to_string())?;
+
+    if device.identity_key.len() != KEY_SIZE {
+        return Err(format!("Identity key must be {} bytes", KEY_SIZE));
+    }
+    if device.encryption_key.len() != KEY_SIZE {
+        return Err(format!("Encryption key must be {} bytes", KEY_SIZE));
+    }
+    if !verify_signature(&device.identity_key, &device.encryption_key, &device.encryption_key_signature) {
+        return Err("Encryption key signature verification failed".to_string());
+    }
+
+    Ok((name.to_string(), platform_to_str(platform)))
+}
+
+// Сообщение, которое подписывает подтверждающее устройство
+fn link_message(user_id: Uuid, device_id: &str, identity_key: &[u8], encryption_key: &[u8]) -> Vec<u8> {
+    let mut message = format!("nesfinch-device-link:v1:{}:{}:", user_id, device_id).into_bytes();
+    message.extend_from_slice(identity_key);
+    message.extend_from_slice(encryption_key);
+    message
+}
+
+// Сообщение, которое пользователь подписывает своим ключом при регистрации первого устройства
+fn registration_message(user_id: Uuid, device_id: &str, identity_key: &[u8], encryption_key: &[u8]) -> Vec<u8> {
+    let mut message = format!("nesfinch-device-registration:v1:{}:{}:", user_id, device_id).into_bytes();
+    message.extend_from_slice(identity_key);
+    message.extend_from_slice(encryption_key);
+    message
+}
+
+// Сообщение, которое подписывает устройство, удаляющее устройство пользователя
+fn removal_message(user_id: Uuid, device_id: &str, timestamp: i64) -> Vec<u8> {
+    format!("nesfinch-device-removal:v1:{}:{}:{}", user_id, device_id, timestamp).into_bytes()
+}
+
+fn pairing_code_hash(user_id: Uuid, code: &str) -> Vec<u8> {
+    Sha256::new()
+        .chain_update(b"nesfinch-device-pairing:v1:")
+        .chain_update(user_id.as_bytes())
+        .chain_update(code.trim().as_bytes())
+        .finalize()
+        .to_vec()
+}
+
+async fn lock_user(conn: &mut PgConnection, user_id: Uuid) -> Result<(), Status> {
+    sqlx::query_scalar!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .ok_or_else(|| Status::not_found("User not found"))?;
+
+    Ok(())
+}
+
+// identity-ключ зарегистрированного устройства
+async fn registered_identity(conn: &mut PgConnection, user_id: Uuid, device_id: &str) -> Result<Option<Vec<u8>>, Status> {
+    sqlx::query_scalar!(
+        "SELECT identity_key FROM devices WHERE user_id = $1 AND device_id = $2",
+        user_id,
+        device_id
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))
+}
+
+async fn fetch_device(conn: &mut PgConnection, user_id: Uuid, device_id: &str) -> Result<DeviceRecord, Status> {
+    sqlx::query_as!(
+        DeviceRecord,
+        r#"
+        SELECT device_id, name, platform, identity_key, encryption_key, approved_by_device_id, created_at, last_seen_at
+        FROM devices
+        WHERE user_id = $1 AND device_id = $2
+        "#,
+        user_id,
+        device_id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))
+}
+
+struct PendingDevice {
+    device_id: String,
+    name: String,
+    platform: String,
+    identity_key: Vec<u8>,
+    encryption_key: Vec<u8>,
+    encryption_key_signature: Vec<u8>,
+    created_at: NaiveDateTime,
+}
+
+// Ожидающая привязка по коду. Неверный код засчитывается всем ожидающим привязкам
+// пользователя, чтобы короткий код нельзя было подобрать; счётчик фиксируется сразу.
+async fn pending_by_code(db: &PgPool, user_id: Uuid, code: &str) -> Result<PendingDevice, Status> {
+    let pending = sqlx::query_as!(
+        PendingDevice,
+        r#"
+        SELECT device_id, name, platform, identity_key, encryption_key, encryption_key_signature, created_at
+        FROM device_pairings
+        WHERE user_id = $1 AND code_hash = $2 AND expires_at > NOW() AT TIME ZONE 'UTC'
+        "#,
+        user_id,
+        pairing_code_hash(user_id, code)
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    if let Some(pending) = pending {
+        return Ok(pending);
+    }
+
+    sqlx::query!(
+        "UPDATE device_pairings SET failed_attempts = failed_attempts + 1 WHERE user_id = $1",
+        user_id
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    sqlx::query!(
+        "DELETE FROM device_pairings WHERE user_id = $1 AND failed_attempts >= $2",
+        user_id,
+        MAX_PAIRING_ATTEMPTS
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    Err(Status::not_found("Invalid or expired pairing code"))
+}
+
+// Обёртки ключей существующих чатов от подтверждающего устройства
+async fn store_approved_chat_keys(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    device_id: &str,
+    chat_keys: &[DeviceChatKey],
+) -> Result<(), Status> {
+    if chat_keys.len() > MAX_APPROVAL_CHAT_KEYS {
+        return Err(Status::invalid_argument(format!(
+            "Cannot attach more than {} chat keys", MAX_APPROVAL_CHAT_KEYS
+        )));
+    }
+
+    for chat_key in chat_keys {
+        let chat_id = Uuid::parse_str(&chat_key.chat_id)
+            .map_err(|_| Status::invalid_argument(format!("Invalid chat UUID: {}", chat_key.chat_id)))?;
+        if chat_key.key_version <= 0
+            || chat_key.encrypted_key.is_empty()
+            || chat_key.encrypted_key.len() > MAX_DEVICE_CHAT_KEY_LEN
+        {
+            return Err(Status::invalid_argument(format!("Invalid chat key for chat {}", chat_id)));
+        }
+
+        // Обёртка принимается только для существующей версии ключа чата, в котором состоит пользователь
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO device_chat_keys (chat_id, user_id, device_id, key_version, algorithm, encrypted_key)
+            SELECT $1, $2, $3, $4, $5, $6
+            WHERE EXISTS (
+                SELECT 1 FROM direct_chats_keys dck
+                JOIN direct_chats_members dcm ON dcm.chat_id = dck.chat_id AND dcm.user_id = dck.user_id
+                WHERE dck.chat_id = $1 AND dck.user_id = $2 AND dck.key_version = $4
+            )
+            ON CONFLICT (chat_id, user_id, device_id, key_version) DO NOTHING
+            "#,
+            chat_id,
+            user_id,
+            device_id,
+            chat_key.key_version,
+            DEVICE_CHAT_KEY_ALGORITHM,
+            chat_key.encrypted_key
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if result.rows_affected() == 0 {
+            return Err(Status::failed_precondition(format!(
+                "No key version {} of chat {} for this user", chat_key.key_version, chat_id
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[tonic::async_trait]
+impl DeviceService for MyDeviceService {
+    async fn register_device(
+        &self,
+        request: Request<RegisterDeviceRequest>,
+    ) -> Result<Response<RegisterDeviceResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let device = req.device.ok_or_else(|| Status::invalid_argument("device is required"))?;
+        let (name, platform) = validate_new_device(&device).map_err(Status::invalid_argument)?;
+
+        // Первому устройству доверяют все следующие привязки, поэтому его подтверждает
+        // действующий ключ пользователя; без ключа устройство не регистрируется
+        let message = registration_message(user_id, &device.device_id, &device.identity_key, &device.encryption_key);
+        self.key_manager
+            .verify_user_signature(user_id, &message, &req.registration_signature)
+            .await?;
+
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        // Блокировка пользователя исключает две параллельные регистрации первого устройства
+        lock_user(&mut tx, user_id).await?;
+
+        let has_devices = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM devices WHERE user_id = $1) AS "exists!""#,
+            user_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if has_devices {
+            return Err(Status::failed_precondition(
+                "Additional devices must be linked with a pairing code from an existing device",
+            ));
+        }
+
+        sqlx::query!(
+            r#"
+            INSERT INTO devices (
+                user_id, device_id, name, platform, identity_key, encryption_key, encryption_key_signature,
+                created_at, last_seen_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, NOW() AT TIME ZONE 'UTC', NOW() AT TIME ZONE 'UTC')
+            "#,
+            user_id,
+            device.device_id,
+            name,
+            platform,
+            device.identity_key,
+            device.encryption_key,
+            device.encryption_key_signature
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let registered = fetch_device(&mut tx, user_id, &device.device_id).await?;
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(Response::new(RegisterDeviceResponse {
+            device: Some(device_to_proto(registered)),
+        }))
+    }
+
+    async fn list_devices(
+        &self,
+        request: Request<ListDevicesRequest>,
+    ) -> Result<Response<ListDevicesResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let devices = sqlx::query_as!(
+            DeviceRecord,
+            r#"
+            SELECT device_id, name, platform, identity_key, encryption_key, approved_by_device_id, created_at, last_seen_at
+            FROM devices
+            WHERE user_id = $1
+            ORDER BY created_at, device_id
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(Response::new(ListDevicesResponse {
+            devices: devices.into_iter().map(device_to_proto).collect(),
+        }))
+    }
+
+    async fn remove_device(
+        &self,
+        request: Request<RemoveDeviceRequest>,
+    ) -> Result<Response<RemoveDeviceResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        // Метка времени ограничивает повтор перехваченной подписи
+        if (Utc::now().timestamp() - req.timestamp).abs() > REMOVAL_MAX_SKEW_SECS {
+            return Err(Status::invalid_argument("Removal timestamp is too far from server time"));
+        }
+
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let requester_identity = registered_identity(&mut tx, user_id, &req.requester_device_id)
+            .await?
+            .ok_or_else(|| Status::permission_denied("Requester device is not registered"))?;
+
+        let message = removal_message(user_id, &req.device_id, req.timestamp);
+        if !verify_signature(&requester_identity, &message, &req.removal_signature) {
+            return Err(Status::permission_denied("Removal signature verification failed"));
+        }
+
+        // Предключи и обёрнутые ключи чатов удаляются каскадно
+        let result = sqlx::query!(
+            "DELETE FROM devices WHERE user_id = $1 AND device_id = $2",
+            user_id,
+            req.device_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        sqlx::query!(
+            "DELETE FROM message_device_deliveries WHERE recipient_id = $1 AND recipient_device_id = $2",
+            user_id,
+            req.device_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let removed = result.rows_affected() > 0;
+        if removed {
+            info!("Device {} of user {} removed by {}", req.device_id, user_id, req.requester_device_id);
+        }
+
+        Ok(Response::new(RemoveDeviceResponse { removed }))
+    }
+
+    async fn start_device_pairing(
+        &self,
+        request: Request<StartDevicePairingRequest>,
+    ) -> Result<Response<StartDevicePairingResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let device = req.device.ok_or_else(|| Status::invalid_argument("device is required"))?;
+        let (name, platform) = validate_new_device(&device).map_err(Status::invalid_argument)?;
+
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        lock_user(&mut tx, user_id).await?;
+
+        let devices = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "count!", COUNT(*) FILTER (WHERE device_id = $2) AS "existing!"
+            FROM devices
+            WHERE user_id = $1
+            "#,
+            user_id,
+            device.device_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if devices.count == 0 {
+            return Err(Status::failed_precondition("User has no devices; register the first device directly"));
+        }
+        if devices.existing > 0 {
+            return Err(Status::already_exists("Device is already registered"));
+        }
+
+        sqlx::query!(
+            "DELETE FROM device_pairings WHERE user_id = $1 AND expires_at <= NOW() AT TIME ZONE 'UTC'",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let pending = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM device_pairings WHERE user_id = $1 AND device_id <> $2"#,
+            user_id,
+            device.device_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if pending >= MAX_PENDING_PAIRINGS {
+            return Err(Status::resource_exhausted("Too many pending device pairings"));
+        }
+
+        let code = format!(
+            "{:0width$}",
+            OsRng.gen_range(0..10u32.pow(PAIRING_CODE_DIGITS)),
+            width = PAIRING_CODE_DIGITS as usize
+        );
+        let expires_at = Utc::now().naive_utc() + ChronoDuration::minutes(PAIRING_TTL_MINUTES);
+
+        // Повторный запрос с того же устройства выдаёт новый код
+        sqlx::query!(
+            r#"
+            INSERT INTO device_pairings (
+                user_id, device_id, name, platform, identity_key, encryption_key, encryption_key_signature,
+                code_hash, failed_attempts, created_at, expires_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, 0, NOW() AT TIME ZONE 'UTC', $9)
+            ON CONFLICT (user_id, device_id) DO UPDATE
+            SET name = EXCLUDED.name,
+                platform = EXCLUDED.platform,
+                identity_key = EXCLUDED.identity_key,
+                encryption_key = EXCLUDED.encryption_key,
+                encryption_key_signature = EXCLUDED.encryption_key_signature,
+                code_hash = EXCLUDED.code_hash,
+                failed_attempts = 0,
+                created_at = EXCLUDED.created_at,
+                expires_at = EXCLUDED.expires_at
+            "#,
+            user_id,
+            device.device_id,
+            name,
+            platform,
+            device.identity_key,
+            device.encryption_key,
+            device.encryption_key_signature,
+            pairing_code_hash(user_id, &code),
+            expires_at
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(Response::new(StartDevicePairingResponse {
+            pairing_code: code,
+            expires_at: Some(timestamp_from_naive(expires_at)),
+        }))
+    }
+
+    async fn get_device_pairing(
+        &self,
+        request: Request<GetDevicePairingRequest>,
+    ) -> Result<Response<GetDevicePairingResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let mut conn = self.db.acquire().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+        if registered_identity(&mut conn, user_id, &req.approver_device_id).await?.is_none() {
+            return Err(Status::permission_denied("Approver device is not registered"));
+        }
+        drop(conn);
+
+        let pending = pending_by_code(&self.db, user_id, &req.pairing_code).await?;
+
+        Ok(Response::new(GetDevicePairingResponse {
+            device: Some(device_to_proto(DeviceRecord {
+                device_id: pending.device_id,
+                name: pending.name,
+                platform: pending.platform,
+                identity_key: pending.identity_key,
+                encryption_key: Some(pending.encryption_key),
+                approved_by_device_id: None,
+                created_at: pending.created_at,
+                last_seen_at: pending.created_at,
+            })),
+        }))
+    }
+
+    async fn approve_device_pairing(
+        &self,
+        request: Request<ApproveDevicePairingRequest>,
+    ) -> Result<Response<ApproveDevicePairingResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let mut conn = self.db.acquire().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+        let approver_identity = registered_identity(&mut conn, user_id, &req.approver_device_id)
+            .await?
+            .ok_or_else(|| Status::permission_denied("Approver device is not registered"))?;
+        drop(conn);
+
+        let pending = pending_by_code(&self.db, user_id, &req.pairing_code).await?;
+
+        let message = link_message(user_id, &pending.device_id, &pending.identity_key, &pending.encryption_key);
+        if !verify_signature(&approver_identity, &message, &req.approval_signature) {
+            return Err(Status::permission_denied("Approval signature verification failed"));
+        }
+
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        lock_user(&mut tx, user_id).await?;
+
+        // Привязка завершается один раз: повторное подтверждение того же кода не найдёт запись
+        let claimed = sqlx::query!(
+            "DELETE FROM device_pairings WHERE user_id = $1 AND device_id = $2 AND expires_at > NOW() AT TIME ZONE 'UTC'",
+            user_id,
+            pending.device_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if claimed.rows_affected() == 0 {
+            return Err(Status::not_found("Invalid or expired pairing code"));
+        }
+
+        if registered_identity(&mut tx, user_id, &pending.device_id).await?.is_some() {
+            return Err(Status::already_exists("Device is already registered"));
+        }
+
+        sqlx::query!(
+            r#"
+            INSERT INTO devices (
+                user_id, device_id, name, platform, identity_key, encryption_key, encryption_key_signature,
+                approved_by_device_id, created_at, last_seen_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, NOW() AT TIME ZONE 'UTC', NOW() AT TIME ZONE 'UTC')
+            "#,
+            user_id,
+            pending.device_id,
+            pending.name,
+            pending.platform,
+            pending.identity_key,
+            pending.encryption_key,
+            pending.encryption_key_signature,
+            req.approver_device_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        store_approved_chat_keys(&mut tx, user_id, &pending.device_id, &req.chat_keys).await?;
+
+        let device = fetch_device(&mut tx, user_id, &pending.device_id).await?;
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        self.notifier.notify_all(vec![NewNotification {
+            user_id,
+            kind: NotificationKind::NotificationDeviceLinked,
+            actor_id: None,
+            chat_id: None,
+            message_id: None,
+            device_id: Some(device.device_id.clone()),
+        }]).await;
+
+        Ok(Response::new(ApproveDevicePairingResponse {
+            device: Some(device_to_proto(device)),
+        }))
+    }
+}
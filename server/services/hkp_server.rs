@@ -0,0 +1,325 @@
+use std::env;
+use std::net::SocketAddr;
+use axum::extract::{DefaultBodyLimit, Form, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use voicechat_pgp::openpgp::{self, Certificate};
+
+use crate::services::key_log;
+use crate::services::key_service::handle_key_change;
+use crate::services::notification_service::Notifier;
+
+// HTTP-сервер ключей по протоколу HKP (draft-shaw-openpgp-hkp), чтобы
+// `gpg --keyserver hkp://<HKP_ADDR>` находил ключи пользователей NesFinch.
+// Запросы анонимны, поэтому, как и в LookupFingerprint без дружбы, видны только
+// действующие ключи OpenPGP пользователей с key_lookup_privacy = 'EVERYONE'.
+// Новые ключи регистрируются только через приложение: /pks/add лишь обновляет
+// копию уже зарегистрированного ключа (новые подписи, user ID, подключи), и каждое
+// обновление записывается в журнал прозрачности.
+
+const HKP_ADDR_ENV: &str = "HKP_ADDR";
+const MAX_RESULTS: i64 = 20;
+const KEY_MEDIA_TYPE: &str = "application/pgp-keys";
+const TEXT_MEDIA_TYPE: &str = "text/plain; charset=utf-8";
+
+#[derive(Clone)]
+pub struct HkpServer {
+    db: PgPool,
+    notifier: Notifier,
+}
+
+#[derive(Deserialize)]
+struct LookupQuery {
+    op: String,
+    search: String,
+}
+
+#[derive(Deserialize)]
+struct AddForm {
+    keytext: String,
+}
+
+enum Search {
+    Fingerprint(String),
+    KeyId(String),
+    Username(String),
+}
+
+struct HkpKey {
+    public_key: String,
+    fingerprint: String,
+    created_at: chrono::NaiveDateTime,
+    expires_at: chrono::NaiveDateTime,
+}
+
+impl HkpServer {
+    pub fn new(db: PgPool, notifier: Notifier) -> Self {
+        Self { db, notifier }
+    }
+
+    // Сервер включается, только если задан HKP_ADDR
+    pub fn addr_from_env() -> Result<Option<SocketAddr>, Box<dyn std::error::Error>> {
+        match env::var(HKP_ADDR_ENV) {
+            Ok(addr) => Ok(Some(addr.parse()?)),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn spawn(self, addr: SocketAddr) -> JoinHandle<()> {
+        let app = Router::new()
+            .route("/pks/lookup", get(lookup))
+            .route("/pks/add", post(add))
+            // keytext приходит в urlencoded-форме и может быть втрое длиннее самого ключа
+            .layer(DefaultBodyLimit::max(3 * openpgp::MAX_ARMORED_LEN + 1024))
+            .with_state(self);
+
+        tokio::spawn(async move {
+            info!("HKP key server listening on {}", addr);
+            if let Err(e) = axum::Server::bind(&addr).serve(app.into_make_service()).await {
+                error!("HKP key server failed: {:?}", e);
+            }
+        })
+    }
+}
+
+fn text_response(status: StatusCode, body: impl Into<String>) -> Response {
+    (status, [(header::CONTENT_TYPE, TEXT_MEDIA_TYPE)], body.into()).into_response()
+}
+
+fn db_error(e: sqlx::Error) -> Response {
+    error!("Database error: {:?}", e);
+    text_response(StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+}
+
+// "0x" + key ID (16 hex) или отпечаток (40/64 hex); иначе - точное имя пользователя
+fn parse_search(search: &str) -> Option<Search> {
+    let search = search.trim();
+    if let Some(hex) = search.strip_prefix("0x").or_else(|| search.strip_prefix("0X")) {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let hex = hex.to_lowercase();
+        return match hex.len() {
+            16 => Some(Search::KeyId(hex)),
+            40 | 64 => Some(Search::Fingerprint(hex)),
+            _ => None,
+        };
+    }
+
+    // Поиск по подстроке и по email не поддерживается, чтобы нельзя было перебирать пользователей
+    let username = search.trim_start_matches('<').trim_end_matches('>');
+    (!username.is_empty()).then(|| Search::Username(username.to_string()))
+}
+
+async fn find_keys(db: &PgPool, search: &Search) -> Result<Vec<HkpKey>, sqlx::Error> {
+    let (fingerprint, key_id, username) = match search {
+        Search::Fingerprint(fingerprint) => (Some(fingerprint.as_str()), None, None),
+        Search::KeyId(key_id) => (None, Some(key_id.as_str()), None),
+        Search::Username(username) => (None, None, Some(username.as_str())),
+    };
+
+    // Key ID - младшие 8 байт отпечатка v4 или старшие 8 байт отпечатка v5
+    sqlx::query_as!(
+        HkpKey,
+        r#"
+        SELECT k.public_key, k.fingerprint, k.created_at, k.expires_at
+        FROM user_keys k
+        JOIN users u ON u.id = k.user_id
+        WHERE k.key_format = 'OPENPGP'
+          AND k.is_revoked = false
+          AND k.expires_at > NOW() AT TIME ZONE 'UTC'
+          AND u.key_lookup_privacy = 'EVERYONE'
+          AND (
+              k.fingerprint = $1
+              OR (length(k.fingerprint) = 40 AND right(k.fingerprint, 16) = $2)
+              OR (length(k.fingerprint) = 64 AND left(k.fingerprint, 16) = $2)
+              OR lower(u.username) = lower($3)
+          )
+        ORDER BY k.created_at DESC
+        LIMIT $4
+        "#,
+        fingerprint,
+        key_id,
+        username,
+        MAX_RESULTS
+    )
+    .fetch_all(db)
+    .await
+}
+
+// Экранирование полей машиночитаемого индекса: ':', '%' и управляющие символы как %XX
+fn escape_index_field(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == ':' || c == '%' || c.is_control() {
+            let mut buf = [0u8; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                escaped.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+fn index_entries(keys: &[HkpKey]) -> String {
+    let now = Utc::now().timestamp().max(0) as u64;
+    let mut entries = Vec::new();
+
+    for key in keys {
+        let Ok(certificate) = Certificate::from_armored(&key.public_key, now) else {
+            continue;
+        };
+
+        let created_at = key.created_at.and_utc().timestamp();
+        let expires_at = key.expires_at.and_utc().timestamp();
+        let mut entry = format!(
+            "pub:{}:{}:{}:{}:{}:\n",
+            key.fingerprint.to_uppercase(),
+            certificate.primary.algorithm,
+            certificate.primary.bits(),
+            certificate.primary.created_at,
+            expires_at,
+        );
+        for user_id in &certificate.user_ids {
+            entry.push_str(&format!("uid:{}:{}::\n", escape_index_field(user_id), created_at));
+        }
+        entries.push(entry);
+    }
+
+    format!("info:1:{}\n{}", entries.len(), entries.concat())
+}
+
+async fn lookup(State(server): State<HkpServer>, Query(query): Query<LookupQuery>) -> Response {
+    let Some(search) = parse_search(&query.search) else {
+        return text_response(StatusCode::BAD_REQUEST, "Unsupported search");
+    };
+
+    match query.op.as_str() {
+        "get" => {
+            let keys = match find_keys(&server.db, &search).await {
+                Ok(keys) => keys,
+                Err(e) => return db_error(e),
+            };
+            if keys.is_empty() {
+                return text_response(StatusCode::NOT_FOUND, "No keys found");
+            }
+
+            let armored: String = keys.into_iter().map(|key| key.public_key).collect();
+            (StatusCode::OK, [(header::CONTENT_TYPE, KEY_MEDIA_TYPE)], armored).into_response()
+        }
+        // Индекс всегда отдаётся в машиночитаемом формате (options=mr)
+        "index" | "vindex" => {
+            let keys = match find_keys(&server.db, &search).await {
+                Ok(keys) => keys,
+                Err(e) => return db_error(e),
+            };
+            if keys.is_empty() {
+                return text_response(StatusCode::NOT_FOUND, "No keys found");
+            }
+
+            text_response(StatusCode::OK, index_entries(&keys))
+        }
+        _ => text_response(StatusCode::NOT_IMPLEMENTED, "Unsupported operation"),
+    }
+}
+
+async fn add(State(server): State<HkpServer>, Form(form): Form<AddForm>) -> Response {
+    let now = Utc::now();
+    let certificate = match Certificate::from_armored(&form.keytext, now.timestamp().max(0) as u64) {
+        Ok(certificate) => certificate,
+        Err(e) => return text_response(StatusCode::UNPROCESSABLE_ENTITY, e.to_string()),
+    };
+    let fingerprint = certificate.fingerprint_hex();
+
+    let mut tx = match server.db.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e),
+    };
+
+    let stored = sqlx::query!(
+        r#"
+        SELECT user_id, public_key
+        FROM user_keys
+        WHERE fingerprint = $1
+          AND key_format = 'OPENPGP'
+          AND is_revoked = false
+        FOR UPDATE
+        "#,
+        fingerprint
+    )
+    .fetch_optional(&mut *tx)
+    .await;
+
+    let stored = match stored {
+        Ok(Some(stored)) => stored,
+        Ok(None) => {
+            return text_response(StatusCode::FORBIDDEN, "Only keys registered through NesFinch can be updated");
+        }
+        Err(e) => return db_error(e),
+    };
+
+    // Запрос анонимный, поэтому присланная копия не заменяет сохранённую, а объединяется с ней:
+    // устаревшая копия не может убрать отзыв подключа или вернуть старую самоподпись
+    let merged = match openpgp::merge(&stored.public_key, &certificate, now.timestamp().max(0) as u64) {
+        Ok(Some(merged)) => merged,
+        Ok(None) => return text_response(StatusCode::OK, format!("Key {} unchanged", fingerprint.to_uppercase())),
+        Err(e) => return text_response(StatusCode::UNPROCESSABLE_ENTITY, e.to_string()),
+    };
+    if merged.encryption_key().is_none() {
+        return text_response(StatusCode::UNPROCESSABLE_ENTITY, "OpenPGP key has no RSA or Curve25519 encryption key");
+    }
+
+    let key_expires_at = merged
+        .encryption_expires_at()
+        .and_then(|expires_at| chrono::DateTime::<Utc>::from_timestamp(expires_at as i64, 0))
+        .map(|expires_at| expires_at.naive_utc());
+
+    // Обновлённый ключ не продлевает срок действия, но может его сократить
+    let armored = merged.armored();
+    let updated = sqlx::query!(
+        r#"
+        UPDATE user_keys
+        SET public_key = $2,
+            expires_at = LEAST(expires_at, COALESCE($3, expires_at))
+        WHERE fingerprint = $1
+          AND key_format = 'OPENPGP'
+          AND is_revoked = false
+        "#,
+        fingerprint,
+        armored,
+        key_expires_at
+    )
+    .execute(&mut *tx)
+    .await;
+    if let Err(e) = updated {
+        return db_error(e);
+    }
+
+    if let Err(status) = key_log::append_key(&mut tx, stored.user_id, &fingerprint, &armored, now.naive_utc()).await {
+        error!("Key log error: {}", status.message());
+        return text_response(StatusCode::INTERNAL_SERVER_ERROR, "Database error");
+    }
+
+    if let Err(e) = tx.commit().await {
+        return db_error(e);
+    }
+    info!("HKP update of key {}", fingerprint);
+
+    // Отпечаток не изменился, поэтому номер безопасности остаётся прежним
+    if let Err(status) = handle_key_change(&server.db, &server.notifier, stored.user_id, false).await {
+        error!("Failed to notify contacts about key update: {}", status.message());
+    }
+
+    text_response(StatusCode::OK, format!("Key {} updated", fingerprint.to_uppercase()))
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use sqlx::PgPool;
+use tonic::Status;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::services::key_manager::{government_key_aad, KeyManager};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const BATCH_SIZE: i64 = 100;
+
+// Фоновое перешифрование секретов текущим KEK: после ротации ключа и для
+// приватных ключей, сохранённых старыми версиями без шифрования.
+// Первый проход выполняется сразу при запуске.
+pub struct KekRotationJob {
+    db: PgPool,
+    key_manager: KeyManager,
+}
+
+impl KekRotationJob {
+    pub fn new(db: PgPool, key_manager: KeyManager) -> Self {
+        Self { db, key_manager }
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.reencrypt_government_keys().await {
+                    error!("Key re-encryption failed: {:?}", e);
+                }
+            }
+        })
+    }
+
+    async fn reencrypt_government_keys(&self) -> Result<(), Status> {
+        let current_kek_id = self.key_manager.current_kek_id().to_string();
+        // Ключи, которые не удалось расшифровать, пропускаются, поэтому обход идёт по id
+        let mut last_id = Uuid::nil();
+        let mut reencrypted = 0;
+
+        loop {
+            let rows = sqlx::query!(
+                r#"
+                SELECT id, private_key_encrypted, private_key_kek_id
+                FROM government_keys
+                WHERE private_key_kek_id IS DISTINCT FROM $1 AND id > $2
+                ORDER BY id
+                LIMIT $3
+                "#,
+                current_kek_id,
+                last_id,
+                BATCH_SIZE
+            )
+            .fetch_all(&self.db)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+            let count = rows.len();
+            for row in rows {
+                last_id = row.id;
+
+                let private_key = match self.key_manager
+                    .government_private_key(row.id, &row.private_key_encrypted, row.private_key_kek_id.as_deref())
+                    .await
+                {
+                    Ok(private_key) => private_key,
+                    Err(e) => {
+                        error!("Cannot decrypt government key {}: {}", row.id, e);
+                        continue;
+                    }
+                };
+
+                let encrypted = self.key_manager
+                    .encrypt_data(&private_key, &government_key_aad(row.id))
+                    .await
+                    .map_err(|e| Status::internal(format!("Encryption error: {}", e)))?;
+
+                // Строка могла измениться с момента чтения - тогда её пропускаем
+                let result = sqlx::query!(
+                    r#"
+                    UPDATE government_keys
+                    SET private_key_encrypted = $2, private_key_kek_id = $3
+                    WHERE id = $1 AND private_key_kek_id IS NOT DISTINCT FROM $4
+                    "#,
+                    row.id,
+                    BASE64.encode(&encrypted.ciphertext),
+                    encrypted.kek_id,
+                    row.private_key_kek_id
+                )
+                .execute(&self.db)
+                .await
+                .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+                reencrypted += result.rows_affected();
+            }
+
+            if count < BATCH_SIZE as usize {
+                break;
+            }
+        }
+
+        if reencrypted > 0 {
+            info!("Re-encrypted {} government keys with key encryption key {}", reencrypted, current_kek_id);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+use chrono::{Duration as ChronoDuration, NaiveDateTime, Utc};
+use constant_time_eq::constant_time_eq;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tonic::Status;
+use uuid::Uuid;
+
+// Резервные копии приватных ключей. Копия шифруется на клиенте ключом из парольной
+// фразы; для получения копии клиент предъявляет auth_key, выведенный той же KDF,
+// а сервер сверяет его SHA-256 и ограничивает число неверных попыток. Заменить копию
+// можно тем же auth_key или подписью действующим ключом пользователя.
+
+pub const KDF_ARGON2ID: &str = "ARGON2ID";
+pub const KDF_PBKDF2_SHA256: &str = "PBKDF2-SHA256";
+
+const MIN_SALT_LEN: usize = 16;
+const MAX_SALT_LEN: usize = 64;
+const NONCE_LEN: usize = 12;
+const AUTH_KEY_LEN: usize = 32;
+// Тег AES-GCM занимает 16 байт
+const MIN_CIPHERTEXT_LEN: usize = 16;
+const MAX_CIPHERTEXT_LEN: usize = 64 * 1024;
+const MAX_FINGERPRINT_LEN: usize = 128;
+const MAX_STORED_VERSIONS: i32 = 5;
+
+// Нижние границы по рекомендациям OWASP; верхние не дают сохранить копию,
+// которую другое устройство не сможет расшифровать за разумное время
+const ARGON2_MIN_MEMORY_KIB: i32 = 19 * 1024;
+const ARGON2_MAX_MEMORY_KIB: i32 = 4 * 1024 * 1024;
+const ARGON2_MIN_ITERATIONS: i32 = 2;
+const ARGON2_MAX_ITERATIONS: i32 = 64;
+const ARGON2_MAX_PARALLELISM: i32 = 16;
+const PBKDF2_MIN_ITERATIONS: i32 = 600_000;
+const PBKDF2_MAX_ITERATIONS: i32 = 10_000_000;
+
+// После стольких неверных попыток подряд включается блокировка: 1, 2, 4... минут, не больше суток
+const FREE_ATTEMPTS: i32 = 5;
+const MAX_LOCKOUT_MINUTES: i64 = 24 * 60;
+
+pub struct KdfParams {
+    pub algorithm: String,
+    pub salt: Vec<u8>,
+    pub memory_kib: i32,
+    pub iterations: i32,
+    pub parallelism: i32,
+}
+
+// Подтверждение права загрузить новую версию
+pub enum UploadProof {
+    // auth_key версии previous_version
+    AuthKey(Vec<u8>),
+    // Подпись backup_challenge действующим ключом, уже проверенная KeyManager
+    KeySignature,
+}
+
+pub struct NewKeyBackup {
+    pub previous_version: i32,
+    pub proof: UploadProof,
+    pub kdf: KdfParams,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub auth_key_hash: Vec<u8>,
+    pub key_fingerprint: Option<String>,
+}
+
+pub struct KeyBackup {
+    pub version: i32,
+    pub kdf: KdfParams,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub key_fingerprint: Option<String>,
+    pub created_at: NaiveDateTime,
+    auth_key_hash: Vec<u8>,
+}
+
+fn validate_kdf(kdf: &KdfParams) -> Result<(), String> {
+    if kdf.salt.len() < MIN_SALT_LEN || kdf.salt.len() > MAX_SALT_LEN {
+        return Err(format!("KDF salt must be {}-{} bytes", MIN_SALT_LEN, MAX_SALT_LEN));
+    }
+
+    match kdf.algorithm.as_str() {
+        KDF_ARGON2ID => {
+            if !(ARGON2_MIN_MEMORY_KIB..=ARGON2_MAX_MEMORY_KIB).contains(&kdf.memory_kib)
+                || !(ARGON2_MIN_ITERATIONS..=ARGON2_MAX_ITERATIONS).contains(&kdf.iterations)
+                || !(1..=ARGON2_MAX_PARALLELISM).contains(&kdf.parallelism)
+            {
+                return Err(format!(
+                    "Argon2id requires memory_kib >= {}, iterations >= {} and parallelism >= 1",
+                    ARGON2_MIN_MEMORY_KIB, ARGON2_MIN_ITERATIONS
+                ));
+            }
+        }
+        KDF_PBKDF2_SHA256 => {
+            if !(PBKDF2_MIN_ITERATIONS..=PBKDF2_MAX_ITERATIONS).contains(&kdf.iterations) {
+                return Err(format!("PBKDF2 requires at least {} iterations", PBKDF2_MIN_ITERATIONS));
+            }
+        }
+        _ => return Err("Unsupported KDF".to_string()),
+    }
+
+    Ok(())
+}
+
+fn validate_backup(backup: &NewKeyBackup) -> Result<(), String> {
+    validate_kdf(&backup.kdf)?;
+
+    if backup.nonce.len() != NONCE_LEN {
+        return Err(format!("Nonce must be {} bytes", NONCE_LEN));
+    }
+    if backup.ciphertext.len() < MIN_CIPHERTEXT_LEN || backup.ciphertext.len() > MAX_CIPHERTEXT_LEN {
+        return Err(format!("Ciphertext must be {}-{} bytes", MIN_CIPHERTEXT_LEN, MAX_CIPHERTEXT_LEN));
+    }
+    if backup.auth_key_hash.len() != AUTH_KEY_LEN {
+        return Err(format!("auth_key_hash must be {} bytes", AUTH_KEY_LEN));
+    }
+    if backup.key_fingerprint.as_ref().is_some_and(|fingerprint| fingerprint.len() > MAX_FINGERPRINT_LEN) {
+        return Err("Key fingerprint is too long".to_string());
+    }
+
+    Ok(())
+}
+
+fn lockout(failed_attempts: i32) -> Option<ChronoDuration> {
+    if failed_attempts < FREE_ATTEMPTS {
+        return None;
+    }
+    let exponent = (failed_attempts - FREE_ATTEMPTS).min(11) as u32;
+    Some(ChronoDuration::minutes((1i64 << exponent).min(MAX_LOCKOUT_MINUTES)))
+}
+
+// Новая версия создаётся, только если клиент видел последнюю существующую
+pub(crate) async fn store_backup(
+    db: &PgPool,
+    user_id: Uuid,
+    backup: NewKeyBackup,
+) -> Result<(i32, NaiveDateTime), Status> {
+    validate_backup(&backup).map_err(Status::invalid_argument)?;
+
+    let auth_key_presented = match &backup.proof {
+        UploadProof::AuthKey(_) if backup.previous_version == 0 => {
+            return Err(Status::permission_denied("The first backup must be signed with the registered key"));
+        }
+        UploadProof::AuthKey(auth_key) => {
+            let current = find_backup(db, user_id, backup.previous_version).await?;
+            check_auth_key(db, user_id, &current.auth_key_hash, auth_key).await?;
+            true
+        }
+        UploadProof::KeySignature => false,
+    };
+
+    let mut tx = db.begin().await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    // Блокировка пользователя упорядочивает конкурирующие загрузки
+    sqlx::query_scalar!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .ok_or_else(|| Status::not_found("User not found"))?;
+
+    let latest = sqlx::query_scalar!(
+        r#"SELECT COALESCE(MAX(version), 0) AS "latest!" FROM key_backups WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    if latest != backup.previous_version {
+        return Err(Status::aborted(format!(
+            "Backup version conflict: the latest version is {}", latest
+        )));
+    }
+    let version = latest + 1;
+
+    if auth_key_presented {
+        sqlx::query!(
+            "UPDATE key_backups SET verified_at = NOW() AT TIME ZONE 'UTC' WHERE user_id = $1 AND version = $2",
+            user_id,
+            latest
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+    }
+
+    let created_at = sqlx::query_scalar!(
+        r#"
+        INSERT INTO key_backups (
+            user_id, version, kdf_algorithm, kdf_salt, kdf_memory_kib, kdf_iterations, kdf_parallelism,
+            nonce, ciphertext, auth_key_hash, key_fingerprint, created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, NOW() AT TIME ZONE 'UTC')
+        RETURNING created_at
+        "#,
+        user_id,
+        version,
+        backup.kdf.algorithm,
+        backup.kdf.salt,
+        backup.kdf.memory_kib,
+        backup.kdf.iterations,
+        backup.kdf.parallelism,
+        backup.nonce,
+        backup.ciphertext,
+        backup.auth_key_hash,
+        backup.key_fingerprint
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    // Последняя подтверждённая версия остаётся, даже если за ней загружено много новых
+    sqlx::query!(
+        r#"
+        DELETE FROM key_backups
+        WHERE user_id = $1
+          AND version <= $2
+          AND version <> COALESCE(
+              (SELECT MAX(version) FROM key_backups WHERE user_id = $1 AND verified_at IS NOT NULL),
+              0
+          )
+        "#,
+        user_id,
+        version - MAX_STORED_VERSIONS
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    tx.commit().await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    Ok((version, created_at))
+}
+
+// version = 0 - последняя версия
+pub(crate) async fn find_backup(db: &PgPool, user_id: Uuid, version: i32) -> Result<KeyBackup, Status> {
+    let row = sqlx::query!(
+        r#"
+        SELECT version, kdf_algorithm, kdf_salt, kdf_memory_kib, kdf_iterations, kdf_parallelism,
+               nonce, ciphertext, auth_key_hash, key_fingerprint, created_at
+        FROM key_backups
+        WHERE user_id = $1 AND ($2 = 0 OR version = $2)
+        ORDER BY version DESC
+        LIMIT 1
+        "#,
+        user_id,
+        version
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+    .ok_or_else(|| Status::not_found("Key backup not found"))?;
+
+    Ok(KeyBackup {
+        version: row.version,
+        kdf: KdfParams {
+            algorithm: row.kdf_algorithm,
+            salt: row.kdf_salt,
+            memory_kib: row.kdf_memory_kib,
+            iterations: row.kdf_iterations,
+            parallelism: row.kdf_parallelism,
+        },
+        nonce: row.nonce,
+        ciphertext: row.ciphertext,
+        key_fingerprint: row.key_fingerprint,
+        created_at: row.created_at,
+        auth_key_hash: row.auth_key_hash,
+    })
+}
+
+// Выдача копии по auth_key с ограничением неверных попыток
+pub(crate) async fn fetch_backup(
+    db: &PgPool,
+    user_id: Uuid,
+    version: i32,
+    auth_key: &[u8],
+) -> Result<KeyBackup, Status> {
+    let backup = find_backup(db, user_id, version).await?;
+    check_auth_key(db, user_id, &backup.auth_key_hash, auth_key).await?;
+
+    sqlx::query!(
+        "UPDATE key_backups SET verified_at = NOW() AT TIME ZONE 'UTC' WHERE user_id = $1 AND version = $2",
+        user_id,
+        backup.version
+    )
+    .execute(db)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    Ok(backup)
+}
+
+// Сверка auth_key; неверные попытки при получении и при замене копии считаются вместе
+async fn check_auth_key(
+    db: &PgPool,
+    user_id: Uuid,
+    expected_hash: &[u8],
+    auth_key: &[u8],
+) -> Result<(), Status> {
+    if auth_key.len() != AUTH_KEY_LEN {
+        return Err(Status::invalid_argument(format!("auth_key must be {} bytes", AUTH_KEY_LEN)));
+    }
+
+    let mut tx = db.begin().await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    sqlx::query!(
+        "INSERT INTO key_backup_attempts (user_id) VALUES ($1) ON CONFLICT (user_id) DO NOTHING",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    let attempts = sqlx::query!(
+        "SELECT failed_attempts, locked_until FROM key_backup_attempts WHERE user_id = $1 FOR UPDATE",
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    let now = Utc::now().naive_utc();
+    if let Some(locked_until) = attempts.locked_until
+        && locked_until > now
+    {
+        return Err(Status::resource_exhausted(format!(
+            "Too many failed attempts, retry in {} seconds",
+            (locked_until - now).num_seconds().max(1)
+        )));
+    }
+
+    let auth_key_hash = Sha256::digest(auth_key);
+    if constant_time_eq(&auth_key_hash, expected_hash) {
+        sqlx::query!(
+            "UPDATE key_backup_attempts SET failed_attempts = 0, locked_until = NULL WHERE user_id = $1",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        return Ok(());
+    }
+
+    let failed_attempts = attempts.failed_attempts + 1;
+    let locked_until = lockout(failed_attempts).map(|duration| now + duration);
+
+    sqlx::query!(
+        r#"
+        UPDATE key_backup_attempts
+        SET failed_attempts = $2, last_failed_at = $3, locked_until = $4
+        WHERE user_id = $1
+        "#,
+        user_id,
+        failed_attempts,
+        now,
+        locked_until
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    // Неудачная попытка учитывается, даже если клиент сразу отключится
+    tx.commit().await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    Err(Status::permission_denied("Invalid recovery passphrase"))
+}
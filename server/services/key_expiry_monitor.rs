@@ -0,0 +1,93 @@
+use std::time::Duration;
+use sqlx::PgPool;
+use tonic::Status;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::services::notification_service::{Notifier, NewNotification, NotificationKind};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// За сколько дней до истечения владелец получает предупреждение
+const WARNING_WINDOW_DAYS: i32 = 7;
+const BATCH_SIZE: i64 = 100;
+
+// Фоновое предупреждение владельцев об истекающих ключах.
+// Каждый ключ предупреждается один раз (expiry_warned_at); ключ, у которого
+// уже есть более поздняя замена, не считается истекающим.
+pub struct KeyExpiryMonitor {
+    db: PgPool,
+    notifier: Notifier,
+}
+
+impl KeyExpiryMonitor {
+    pub fn new(db: PgPool, notifier: Notifier) -> Self {
+        Self { db, notifier }
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.warn_expiring().await {
+                    error!("Key expiry check failed: {:?}", e);
+                }
+            }
+        })
+    }
+
+    async fn warn_expiring(&self) -> Result<(), Status> {
+        loop {
+            let owners = sqlx::query_scalar!(
+                r#"
+                UPDATE user_keys
+                SET expiry_warned_at = NOW() AT TIME ZONE 'UTC'
+                WHERE id IN (
+                    SELECT k.id FROM user_keys k
+                    WHERE k.is_revoked = false
+                      AND k.expiry_warned_at IS NULL
+                      AND k.expires_at > NOW() AT TIME ZONE 'UTC'
+                      AND k.expires_at <= NOW() AT TIME ZONE 'UTC' + make_interval(days => $1)
+                      AND NOT EXISTS (
+                          SELECT 1 FROM user_keys newer
+                          WHERE newer.user_id = k.user_id
+                            AND newer.is_revoked = false
+                            AND newer.expires_at > k.expires_at
+                      )
+                    LIMIT $2
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING user_id
+                "#,
+                WARNING_WINDOW_DAYS,
+                BATCH_SIZE
+            )
+            .fetch_all(&self.db)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+            if !owners.is_empty() {
+                info!("Warning {} users about expiring keys", owners.len());
+            }
+
+            let count = owners.len();
+            self.notifier.notify_all(
+                owners
+                    .into_iter()
+                    .map(|user_id| NewNotification {
+                        user_id,
+                        kind: NotificationKind::NotificationKeyExpiring,
+                        actor_id: None,
+                        chat_id: None,
+                        message_id: None,
+                        device_id: None,
+                    })
+                    .collect(),
+            ).await;
+
+            if count < BATCH_SIZE as usize {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use ed25519_dalek::{Signer, SigningKey};
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool};
+use tonic::Status;
+use uuid::Uuid;
+
+use voicechat_pgp::merkle::{self, Hash};
+
+// Журнал прозрачности ключей. Каждая запись в user_keys добавляет лист,
+// сервер периодически выдаёт подписанные вершины дерева (STH), а клиенты
+// проверяют включение своих ключей и согласованность вершин между собой.
+
+const LEAF_PREFIX: &str = "nesfinch-key-log:v2";
+const TREE_HEAD_PREFIX: &[u8] = b"nesfinch-key-log-sth:v1";
+// Ed25519 seed в hex, 32 байта
+const SIGNING_KEY_ENV: &str = "KEY_LOG_SIGNING_KEY";
+
+pub struct SignedTreeHead {
+    pub tree_size: i64,
+    pub root_hash: Vec<u8>,
+    pub signed_at: NaiveDateTime,
+    pub signature: Vec<u8>,
+}
+
+pub struct KeyLogEntry {
+    pub leaf_index: i64,
+    pub user_id: Uuid,
+    pub fingerprint: String,
+    pub leaf_data: String,
+    // Пусто у листов v1
+    pub key_hash: Option<Vec<u8>>,
+}
+
+// SHA-256 публичного ключа в том виде, в каком он хранится в user_keys.public_key
+pub fn key_hash(public_key: &str) -> Hash {
+    Sha256::digest(public_key.as_bytes()).into()
+}
+
+pub fn leaf_data(user_id: Uuid, fingerprint: &str, key_hash: &Hash, created_at: NaiveDateTime) -> String {
+    format!(
+        "{}:{}:{}:{}:{}",
+        LEAF_PREFIX,
+        user_id,
+        fingerprint,
+        hex::encode(key_hash),
+        created_at.and_utc().timestamp()
+    )
+}
+
+// Подписываемое сообщение: префикс || tree_size (BE) || signed_at в мс (BE) || root_hash
+pub fn tree_head_message(tree_size: i64, signed_at: NaiveDateTime, root_hash: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(TREE_HEAD_PREFIX.len() + 16 + root_hash.len());
+    message.extend_from_slice(TREE_HEAD_PREFIX);
+    message.extend_from_slice(&(tree_size as u64).to_be_bytes());
+    message.extend_from_slice(&(signed_at.and_utc().timestamp_millis() as u64).to_be_bytes());
+    message.extend_from_slice(root_hash);
+    message
+}
+
+// Добавление ключа или его обновления в журнал в той же транзакции, что и запись в user_keys
+pub(crate) async fn append_key(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    fingerprint: &str,
+    public_key: &str,
+    created_at: NaiveDateTime,
+) -> Result<i64, Status> {
+    let key_hash = key_hash(public_key);
+    let data = leaf_data(user_id, fingerprint, &key_hash, created_at);
+    let hash = merkle::leaf_hash(data.as_bytes());
+
+    // Индексы листов должны идти без пропусков, поэтому добавления выполняются строго по одному
+    sqlx::query!("LOCK TABLE key_log_entries IN EXCLUSIVE MODE")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO key_log_entries (leaf_index, user_id, fingerprint, leaf_data, leaf_hash, key_hash, logged_at)
+        SELECT COALESCE(MAX(leaf_index) + 1, 0), $1, $2, $3, $4, $5, NOW() AT TIME ZONE 'UTC'
+        FROM key_log_entries
+        RETURNING leaf_index
+        "#,
+        user_id,
+        fingerprint,
+        data,
+        &hash[..],
+        &key_hash[..]
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))
+}
+
+#[derive(Clone)]
+pub struct KeyLog {
+    db: PgPool,
+    signing_key: Arc<SigningKey>,
+}
+
+impl fmt::Debug for KeyLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyLog")
+            .field("public_key", &hex::encode(self.public_key()))
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyLog {
+    pub fn new(db: PgPool, signing_key: SigningKey) -> Self {
+        Self { db, signing_key: Arc::new(signing_key) }
+    }
+
+    pub fn signing_key_from_env() -> Result<SigningKey, Box<dyn std::error::Error>> {
+        let seed = hex::decode(env::var(SIGNING_KEY_ENV)?)?;
+        let seed: [u8; 32] = seed
+            .try_into()
+            .map_err(|_| format!("{} must be 32 bytes of hex", SIGNING_KEY_ENV))?;
+        Ok(SigningKey::from_bytes(&seed))
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    // Журнал целиком держится в памяти на время запроса: 32 байта на ключ
+    async fn leaves(&self) -> Result<Vec<Hash>, Status> {
+        let rows = sqlx::query_scalar!(
+            "SELECT leaf_hash FROM key_log_entries ORDER BY leaf_index"
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let mut leaves = Vec::with_capacity(rows.len());
+        for row in rows {
+            let hash: Hash = row
+                .try_into()
+                .map_err(|_| Status::internal("Corrupted key log leaf"))?;
+            leaves.push(hash);
+        }
+        Ok(leaves)
+    }
+
+    // Вершина для текущего размера журнала; для одного размера подпись выдаётся один раз
+    pub async fn latest_tree_head(&self) -> Result<SignedTreeHead, Status> {
+        let leaves = self.leaves().await?;
+        let tree_size = leaves.len() as i64;
+
+        if let Some(head) = self.tree_head(tree_size).await? {
+            return Ok(head);
+        }
+
+        let root_hash = merkle::root(&leaves);
+        let signed_at = DateTime::<Utc>::from_timestamp_millis(Utc::now().timestamp_millis())
+            .ok_or_else(|| Status::internal("Invalid timestamp"))?
+            .naive_utc();
+        let signature = self.signing_key
+            .sign(&tree_head_message(tree_size, signed_at, &root_hash))
+            .to_bytes();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO key_log_tree_heads (tree_size, root_hash, signed_at, signature)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (tree_size) DO NOTHING
+            "#,
+            tree_size,
+            &root_hash[..],
+            signed_at,
+            &signature[..]
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        // При гонке побеждает подпись, записанная первой
+        self.tree_head(tree_size)
+            .await?
+            .ok_or_else(|| Status::internal("Tree head was not stored"))
+    }
+
+    pub async fn tree_head(&self, tree_size: i64) -> Result<Option<SignedTreeHead>, Status> {
+        sqlx::query_as!(
+            SignedTreeHead,
+            "SELECT tree_size, root_hash, signed_at, signature FROM key_log_tree_heads WHERE tree_size = $1",
+            tree_size
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))
+    }
+
+    // Вершина заданного размера; 0 - текущая
+    async fn head_for_size(&self, tree_size: u64) -> Result<SignedTreeHead, Status> {
+        if tree_size == 0 {
+            return self.latest_tree_head().await;
+        }
+
+        self.tree_head(tree_size as i64)
+            .await?
+            .ok_or_else(|| Status::not_found(format!("No signed tree head of size {}", tree_size)))
+    }
+
+    // Доказательство включения текущего ключа пользователя
+    pub async fn inclusion_proof(
+        &self,
+        user_id: Uuid,
+        tree_size: u64,
+    ) -> Result<(KeyLogEntry, Vec<Hash>, SignedTreeHead), Status> {
+        let entry = sqlx::query_as!(
+            KeyLogEntry,
+            r#"
+            SELECT e.leaf_index, e.user_id, e.fingerprint, e.leaf_data, e.key_hash
+            FROM key_log_entries e
+            JOIN user_keys k ON k.fingerprint = e.fingerprint
+            WHERE e.user_id = $1
+              AND k.is_revoked = false
+              AND k.expires_at > NOW() AT TIME ZONE 'UTC'
+            ORDER BY k.created_at DESC, e.leaf_index DESC
+            LIMIT 1
+            "#,
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .ok_or_else(|| Status::not_found("User has no active logged key"))?;
+
+        let head = self.head_for_size(tree_size).await?;
+        if entry.leaf_index >= head.tree_size {
+            return Err(Status::failed_precondition("Key was logged after the requested tree head"));
+        }
+
+        let leaves = self.leaves().await?;
+        let proof = merkle::inclusion_proof(&leaves[..head.tree_size as usize], entry.leaf_index as usize)
+            .ok_or_else(|| Status::internal("Leaf index out of range"))?;
+
+        Ok((entry, proof, head))
+    }
+
+    pub async fn consistency_proof(
+        &self,
+        first_size: u64,
+        second_size: u64,
+    ) -> Result<(Vec<Hash>, SignedTreeHead, SignedTreeHead), Status> {
+        if first_size == 0 {
+            return Err(Status::invalid_argument("first_size must be positive"));
+        }
+
+        let second = self.head_for_size(second_size).await?;
+        let first = self.head_for_size(first_size).await?;
+        if first.tree_size > second.tree_size {
+            return Err(Status::invalid_argument("first_size must not exceed second_size"));
+        }
+
+        let leaves = self.leaves().await?;
+        let proof = merkle::consistency_proof(&leaves[..second.tree_size as usize], first.tree_size as usize)
+            .ok_or_else(|| Status::internal("Tree size out of range"))?;
+
+        Ok((proof, first, second))
+    }
+}
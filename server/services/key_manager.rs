@@ -1,55 +1,185 @@
+use std::fmt;
+use std::sync::Arc;
 use tonic::Status;
 use rsa::{
-    pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, DecodePublicKey}, 
-    Pkcs1v15Encrypt,
+    pkcs8::{EncodePrivateKey, EncodePublicKey, DecodePrivateKey, DecodePublicKey}, 
+    pss::{Signature, VerifyingKey},
+    signature::Verifier,
+    traits::PublicKeyParts,
     RsaPrivateKey, 
     RsaPublicKey
 };
 use rand::rngs::OsRng;
 use rsa::pkcs8::LineEnding;
 use sha2::{Digest, Sha256};
-use chrono::{Utc, Duration as ChronoDuration};
-use sqlx::PgPool;
-use std::path::PathBuf;
-use ring::aead;
+use chrono::{DateTime, NaiveDateTime, Utc, Duration as ChronoDuration};
+use sqlx::{PgConnection, PgPool};
 use tracing::{error, info};
 use zeroize::Zeroizing;
 use rand::RngCore;
-use rand::Rng;
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
 use uuid::Uuid;
 
+use voicechat_pgp::hybrid;
+use voicechat_pgp::openpgp::{self, Certificate, KeyMaterial as PgpKeyMaterial};
+
+use crate::services::key_log;
+use crate::services::kms::{Kms, KmsError};
+
 // Конфигурация безопасности
 const RSA_KEY_SIZE: usize = 2048;
-const KEY_FILE_NAME: &str = "encrypted_private_key.bin";
-const ENCRYPTION_KEY_SIZE: usize = 32; 
+const MIN_USER_RSA_KEY_BITS: usize = 2048;
+const KEY_LIFETIME_DAYS: i64 = 30;
+const CHAT_KEY_SIZE: usize = 32;
+const SAFETY_NUMBER_ITERATIONS: usize = 5200;
+// Обёртки ключа чата: форматы v2 (RSA) и v3 (X25519, в том числе для устройств) из hybrid
+pub const RSA_CHAT_KEY_ALGORITHM: &str = "NESFINCH-HYBRID-RSA";
+pub const X25519_CHAT_KEY_ALGORITHM: &str = "NESFINCH-HYBRID-X25519";
+pub const DEVICE_CHAT_KEY_ALGORITHM: &str = X25519_CHAT_KEY_ALGORITHM;
+
+pub const KEY_FORMAT_PKCS8: &str = "PKCS8";
+pub const KEY_FORMAT_OPENPGP: &str = "OPENPGP";
 
 // Защищенная структура для хранения ключевой пары
 #[derive(Debug)]
 pub struct KeyPair {
     private_key: Zeroizing<String>, 
     public_key: String,
+}
+
+// Зарегистрированный публичный ключ пользователя
+#[derive(Debug)]
+pub struct RegisteredKey {
+    pub public_key: String,
+    pub fingerprint: String,
+    pub expires_at: chrono::NaiveDateTime,
+    // false при повторной регистрации уже известного ключа
+    pub is_new: bool,
+    // У пользователя до этого не было ни одного ключа
+    pub is_first: bool,
+}
+
+enum ClientKeyKind {
+    Pkcs8(RsaPublicKey),
+    OpenPgp(Box<Certificate>),
+}
+
+// Присланный клиентом ключ в каноническом виде
+struct ClientKey {
+    kind: ClientKeyKind,
+    // PEM или переупакованный armored-блок OpenPGP
+    encoded: String,
     fingerprint: String,
+    // Срок, заданный самим ключом OpenPGP
+    expires_at: Option<NaiveDateTime>,
+}
+
+impl ClientKey {
+    fn format(&self) -> &'static str {
+        match self.kind {
+            ClientKeyKind::Pkcs8(_) => KEY_FORMAT_PKCS8,
+            ClientKeyKind::OpenPgp(_) => KEY_FORMAT_OPENPGP,
+        }
+    }
+}
+
+// Действующий публичный ключ участника, которым оборачивается ключ чата
+#[derive(Debug)]
+pub struct MemberKey {
+    pub user_id: Uuid,
+    pub public_key: String,
+    pub fingerprint: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+// Ключ чата, обёрнутый для одного участника
+#[derive(Debug)]
+pub struct WrappedChatKey {
+    pub user_id: Uuid,
+    pub fingerprint: String,
+    pub algorithm: &'static str,
+    pub encrypted_key: Vec<u8>,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+// Ключ шифрования устройства участника
+#[derive(Debug)]
+pub struct DeviceKey {
+    pub user_id: Uuid,
+    pub device_id: String,
+    pub encryption_key: [u8; 32],
+}
+
+// Ключ чата, обёрнутый для одного устройства участника
+#[derive(Debug)]
+pub struct WrappedDeviceChatKey {
+    pub user_id: Uuid,
+    pub device_id: String,
+    pub algorithm: &'static str,
+    pub encrypted_key: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum WrapError {
+    // Ключ участника больше не годится для шифрования, например истёк подключ OpenPGP
+    UnusableKey { user_id: Uuid, reason: String },
+    Encryption(String),
+}
+
+impl fmt::Display for WrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WrapError::UnusableKey { user_id, reason } => write!(
+                f,
+                "Public key of user {} can no longer be used ({}); a new key must be registered",
+                user_id, reason
+            ),
+            WrapError::Encryption(reason) => write!(f, "Encryption error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for WrapError {}
+
+impl From<WrapError> for Status {
+    fn from(e: WrapError) -> Self {
+        match e {
+            WrapError::UnusableKey { .. } => Status::failed_precondition(e.to_string()),
+            WrapError::Encryption(_) => Status::internal(e.to_string()),
+        }
+    }
 }
 
+// Шифртекст секрета вместе с идентификатором KEK, которым он зашифрован
+pub struct EncryptedSecret {
+    pub kek_id: String,
+    pub ciphertext: Vec<u8>,
+}
+
+// Один экземпляр на сервер; клоны разделяют KMS
+#[derive(Clone)]
 pub struct KeyManager {
     db: PgPool,
-    encryption_key: [u8; ENCRYPTION_KEY_SIZE],
+    kms: Arc<dyn Kms>,
+}
+
+impl fmt::Debug for KeyManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyManager")
+            .field("kek_id", &self.kms.current_key_id())
+            .finish_non_exhaustive()
+    }
 }
 
 impl KeyManager {
-    pub fn new(db: PgPool) -> Self {
-        // Генерация случайного ключа при создании менеджера
-        let mut encryption_key = [0u8; ENCRYPTION_KEY_SIZE];
-        OsRng.fill_bytes(&mut encryption_key);
-        
-        info!("Initialized KeyManager with new encryption key");
-        
-        Self { db, encryption_key }
+    pub fn new(db: PgPool, kms: Arc<dyn Kms>) -> Self {
+        info!("Initialized KeyManager with key encryption key {}", kms.current_key_id());
+
+        Self { db, kms }
     }
 
-    // Метод для получения текущего ключа шифрования
-    pub fn get_encryption_key(&self) -> [u8; ENCRYPTION_KEY_SIZE] {
-        self.encryption_key
+    pub fn current_kek_id(&self) -> &str {
+        self.kms.current_key_id()
     }
 
     // Генерация ключевой пары с защитой в памяти
@@ -62,12 +192,9 @@ impl KeyManager {
         let private_pem = Zeroizing::new(private_key.to_pkcs8_pem(LineEnding::LF)?.to_string());
         let public_pem = public_key.to_public_key_pem(LineEnding::LF)?;
 
-        let fingerprint = Self::generate_fingerprint(&public_pem)?;
-
         Ok(KeyPair {
             private_key: private_pem,
             public_key: public_pem,
-            fingerprint,
         })
     }
 
@@ -78,157 +205,409 @@ impl KeyManager {
         Ok(hex::encode(hasher.finalize()))
     }
 
-    // Получение пути к AppData/Local с созданием директории
-    fn get_appdata_local_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
-        if let Some(path) = dirs_next::data_local_dir() {
-            let path = path.join("NesFinch");
-            std::fs::create_dir_all(&path)?;
-            return Ok(path);
+    // Safety number пары пользователей; не зависит от порядка аргументов
+    pub fn safety_number(a: (Uuid, &str), b: (Uuid, &str)) -> String {
+        let mut halves = [Self::safety_number_half(a.0, a.1), Self::safety_number_half(b.0, b.1)];
+        halves.sort();
+        halves.concat()
+    }
+
+    fn safety_number_half(user_id: Uuid, fingerprint: &str) -> String {
+        let mut hash = Sha256::new()
+            .chain_update(b"nesfinch-safety-number:v1")
+            .chain_update(user_id.as_bytes())
+            .chain_update(fingerprint.as_bytes())
+            .finalize();
+        for _ in 1..SAFETY_NUMBER_ITERATIONS {
+            hash = Sha256::new()
+                .chain_update(hash)
+                .chain_update(fingerprint.as_bytes())
+                .finalize();
         }
-        
-        let path = std::env::current_dir()?.join("NesFinch_keys");
-        std::fs::create_dir_all(&path)?;
-        Ok(path)
-    }
-
-    // Шифрование данных с AEAD
-    pub fn encrypt_data(&self, data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let mut nonce = [0u8; 12];
-        OsRng.try_fill(&mut nonce)?;
-        
-        let sealing_key = aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_256_GCM, &self.encryption_key)?);
-        
-        let mut in_out = data.to_vec();
-        sealing_key.seal_in_place_append_tag(
-            aead::Nonce::assume_unique_for_key(nonce),
-            aead::Aad::empty(), 
-            &mut in_out
-        )?;
-        
-        let mut result = nonce.to_vec();
-        result.extend_from_slice(&in_out);
-        
-        Ok(result)
-    }
-
-    // Дешифрование данных
-    fn decrypt_data(&self, encrypted: &[u8]) -> Result<Zeroizing<Vec<u8>>, Box<dyn std::error::Error>> {
-        if encrypted.len() < 12 {
-            return Err("Invalid encrypted data".into());
+
+        hash[..30]
+            .chunks(5)
+            .map(|chunk| {
+                let value = chunk.iter().fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+                format!("{:05}", value % 100_000)
+            })
+            .collect()
+    }
+
+    // Шифрование секрета текущим KEK; aad привязывает шифртекст к месту хранения
+    pub async fn encrypt_data(&self, data: &[u8], aad: &[u8]) -> Result<EncryptedSecret, KmsError> {
+        let kek_id = self.kms.current_key_id().to_string();
+        let ciphertext = self.kms.encrypt(&kek_id, data, aad).await?;
+
+        Ok(EncryptedSecret { kek_id, ciphertext })
+    }
+
+    // Дешифрование секрета ключом, указанным рядом с шифртекстом
+    pub async fn decrypt_data(&self, kek_id: &str, encrypted: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, KmsError> {
+        self.kms.decrypt(kek_id, encrypted, aad).await
+    }
+
+    // Сообщение, которое клиент подписывает своим приватным ключом (RSA-PSS, SHA-256,
+    // для ключей OpenPGP - отделённой подписью) в доказательство владения регистрируемым ключом
+    pub fn registration_challenge(user_id: Uuid, fingerprint: &str) -> String {
+        format!("nesfinch-key-registration:v1:{}:{}", user_id, fingerprint)
+    }
+
+    // Сообщение, которое клиент подписывает старым ключом при ротации
+    pub fn rotation_challenge(user_id: Uuid, old_fingerprint: &str, new_fingerprint: &str) -> String {
+        format!("nesfinch-key-rotation:v1:{}:{}:{}", user_id, old_fingerprint, new_fingerprint)
+    }
+
+    // Сообщение, которое клиент подписывает отзываемым ключом
+    pub fn revocation_challenge(user_id: Uuid, fingerprint: &str, reason: &str) -> String {
+        format!("nesfinch-key-revocation:v1:{}:{}:{}", user_id, fingerprint, reason)
+    }
+
+    // Сообщение, которое клиент подписывает действующим ключом при загрузке резервной копии
+    pub fn backup_challenge(user_id: Uuid, version: i32, ciphertext: &[u8]) -> String {
+        format!("nesfinch-key-backup:v1:{}:{}:{}", user_id, version, hex::encode(Sha256::digest(ciphertext)))
+    }
+
+    // Проверка подписи последним действующим ключом пользователя
+    pub async fn verify_user_signature(&self, user_id: Uuid, message: &[u8], signature_b64: &str) -> Result<(), Status> {
+        let member = self.member_keys(&[user_id]).await?.remove(0);
+        let key = parse_verifying_key(&member.public_key).map_err(|e| {
+            error!("Stored public key is invalid: {}", e);
+            Status::internal("Stored public key is invalid")
+        })?;
+
+        let verified = signature_verified(&key, message, signature_b64)
+            .ok_or_else(|| Status::invalid_argument("Malformed signature"))?;
+        if !verified {
+            return Err(Status::permission_denied("Signature verification failed"));
         }
-        
-        let (nonce_bytes, ciphertext) = encrypted.split_at(12);
-        let nonce = aead::Nonce::try_assume_unique_for_key(nonce_bytes)?;
-        
-        let opening_key = aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_256_GCM, &self.encryption_key)?);
-        
-        let mut in_out = ciphertext.to_vec();
-        opening_key.open_in_place(nonce, aead::Aad::empty(), &mut in_out)?;
-        in_out.truncate(in_out.len() - 16);
-        
-        Ok(Zeroizing::new(in_out))
-    }
-
-    // Сохранение приватного ключа с шифрованием
-    fn save_private_key(&self, private_key: &Zeroizing<String>) -> Result<(), Box<dyn std::error::Error>> {
-        let appdata_path = Self::get_appdata_local_path()
-            .map_err(|e| format!("Failed to get appdata path: {}", e))?;
-        
-        info!("Saving private key to: {:?}", appdata_path);
-        
-        let key_path = appdata_path.join(KEY_FILE_NAME);
-
-        let private_key_bytes = private_key.as_bytes();
-        
-        let encrypted_data = self.encrypt_data(private_key_bytes)
-            .map_err(|e| format!("Encryption failed: {}", e))?;
-
-        std::fs::write(&key_path, encrypted_data)
-            .map_err(|e| format!("Failed to write to file {:?}: {}", key_path, e))?;
-
-        info!("Private key successfully saved");
         Ok(())
     }
 
-    // Загрузка приватного ключа
-    fn load_private_key(&self) -> Result<Zeroizing<String>, Box<dyn std::error::Error>> {
-        let appdata_path = Self::get_appdata_local_path()?;
-        let key_path = appdata_path.join(KEY_FILE_NAME);
-        
-        info!("Loading private key from: {:?}", key_path);
-        
-        let encrypted_data = std::fs::read(key_path)?;
-        
-        let decrypted = self.decrypt_data(&encrypted_data)?;
-        
-        let private_key = String::from_utf8(decrypted.to_vec())?;
-        Ok(Zeroizing::new(private_key))
+    // Регистрация публичного ключа, сгенерированного клиентом.
+    // Приватные ключи пользователей сервер не создаёт и не хранит.
+    pub async fn register_public_key(
+        &self,
+        user_id: Uuid,
+        public_key: &str,
+        signature_b64: &str,
+    ) -> Result<RegisteredKey, Status> {
+        let key = parse_client_key(public_key).map_err(Status::invalid_argument)?;
+        let verified = possession_verified(user_id, &key, signature_b64)
+            .ok_or_else(|| Status::invalid_argument("Malformed proof of possession"))?;
+        if !verified {
+            return Err(Status::permission_denied("Proof of possession verification failed"));
+        }
+
+        let mut tx = self.db.begin().await.map_err(|e| {
+            error!("Database error: {:?}", e);
+            Status::internal("Database error")
+        })?;
+
+        let registered = store_user_key(&mut tx, user_id, key).await?;
+
+        tx.commit().await.map_err(|e| {
+            error!("Database error: {:?}", e);
+            Status::internal("Database error")
+        })?;
+
+        Ok(registered)
     }
 
-    // Генерация ключей для пользователя
-    pub async fn generate_user_keys(
+    // Замена действующего ключа: новый ключ подтверждается им самим, а ротация - старым ключом
+    pub async fn rotate_key(
         &self,
-        user_id: uuid::Uuid,
-    ) -> Result<KeyPair, Status> {
-        let keypair = Self::generate_keypair().map_err(|e| {
-            error!("Key generation error: {:?}", e);
-            Status::internal("Key generation error")
+        user_id: Uuid,
+        old_fingerprint: &str,
+        new_public_key: &str,
+        new_key_signature_b64: &str,
+        rotation_signature_b64: &str,
+    ) -> Result<RegisteredKey, Status> {
+        let key = parse_client_key(new_public_key).map_err(Status::invalid_argument)?;
+        let verified = possession_verified(user_id, &key, new_key_signature_b64)
+            .ok_or_else(|| Status::invalid_argument("Malformed proof of possession"))?;
+        if !verified {
+            return Err(Status::permission_denied("Proof of possession verification failed"));
+        }
+
+        if key.fingerprint == old_fingerprint {
+            return Err(Status::invalid_argument("New key must differ from the old key"));
+        }
+
+        let mut tx = self.db.begin().await.map_err(|e| {
+            error!("Database error: {:?}", e);
+            Status::internal("Database error")
         })?;
 
-        let exists: Option<uuid::Uuid> = sqlx::query_scalar!(
-            "SELECT id FROM user_keys WHERE fingerprint = $1 AND is_revoked = false",
-            keypair.fingerprint
+        let old_key = sqlx::query!(
+            r#"
+            SELECT public_key, is_revoked, expires_at <= NOW() AT TIME ZONE 'UTC' AS "is_expired!"
+            FROM user_keys
+            WHERE user_id = $1 AND fingerprint = $2
+            FOR UPDATE
+            "#,
+            user_id,
+            old_fingerprint
         )
-        .fetch_optional(&self.db)
+        .fetch_optional(&mut *tx)
         .await
         .map_err(|e| {
             error!("Database error: {:?}", e);
             Status::internal("Database error")
+        })?
+        .ok_or_else(|| Status::not_found("Key not found"))?;
+
+        if old_key.is_revoked {
+            return Err(Status::failed_precondition("Old key has been revoked"));
+        }
+        if old_key.is_expired {
+            return Err(Status::failed_precondition("Old key has expired; register a new key instead"));
+        }
+
+        let old_public_key = parse_verifying_key(&old_key.public_key).map_err(|e| {
+            error!("Stored public key is invalid: {}", e);
+            Status::internal("Stored public key is invalid")
         })?;
 
-        if exists.is_some() {
-            return Err(Status::already_exists("Key already exists and is not revoked"));
+        let challenge = Self::rotation_challenge(user_id, old_fingerprint, &key.fingerprint);
+        let verified = signature_verified(&old_public_key, challenge.as_bytes(), rotation_signature_b64)
+            .ok_or_else(|| Status::invalid_argument("Malformed rotation signature"))?;
+        if !verified {
+            return Err(Status::permission_denied("Rotation signature verification failed"));
         }
 
-        let expires_at = Utc::now() + ChronoDuration::days(30);
+        let registered = store_user_key(&mut tx, user_id, key).await?;
 
         sqlx::query!(
             r#"
-            INSERT INTO user_keys (user_id, public_key, fingerprint, expires_at)
-            VALUES ($1, $2, $3, $4)
+            UPDATE user_keys
+            SET is_revoked = true,
+                revoked_at = NOW() AT TIME ZONE 'UTC',
+                revocation_reason = 'superseded',
+                replaced_by = $3
+            WHERE user_id = $1 AND fingerprint = $2
             "#,
             user_id,
-            keypair.public_key,
-            keypair.fingerprint,
-            expires_at.naive_utc()
+            old_fingerprint,
+            registered.fingerprint
         )
-        .execute(&self.db)
+        .execute(&mut *tx)
         .await
         .map_err(|e| {
             error!("Database error: {:?}", e);
             Status::internal("Database error")
         })?;
 
-        self.save_private_key(&keypair.private_key).map_err(|e| {
-            error!("Failed to save private key: {:?}", e);
-            Status::internal("Failed to save private key")
+        tx.commit().await.map_err(|e| {
+            error!("Database error: {:?}", e);
+            Status::internal("Database error")
         })?;
 
-        Ok(keypair)
+        info!("Rotated key {} -> {} for user {}", old_fingerprint, registered.fingerprint, user_id);
+
+        Ok(registered)
     }
 
-    // Получение приватного ключа
-    pub async fn get_private_key(&self) -> Result<RsaPrivateKey, Status> {
-        let private_key_pem = self.load_private_key().map_err(|e| {
-            error!("Failed to load private key: {:?}", e);
-            Status::internal("Failed to load private key")
+    // Отзыв необратим: повторная регистрация отозванного ключа отклоняется.
+    // Отзыв подписывается самим отзываемым ключом, как ротация - старым ключом.
+    pub async fn revoke_key(
+        &self,
+        user_id: Uuid,
+        fingerprint: &str,
+        reason: &str,
+        revocation_signature_b64: &str,
+    ) -> Result<chrono::NaiveDateTime, Status> {
+        let mut tx = self.db.begin().await.map_err(|e| {
+            error!("Database error: {:?}", e);
+            Status::internal("Database error")
         })?;
 
-        RsaPrivateKey::from_pkcs8_pem(&private_key_pem)
-            .map_err(|e| {
-                error!("Failed to parse private key: {:?}", e);
-                Status::internal("Invalid private key format")
-            })
+        let key = sqlx::query!(
+            r#"
+            SELECT public_key, is_revoked, expires_at <= NOW() AT TIME ZONE 'UTC' AS "is_expired!"
+            FROM user_keys
+            WHERE user_id = $1 AND fingerprint = $2
+            FOR UPDATE
+            "#,
+            user_id,
+            fingerprint
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!("Database error: {:?}", e);
+            Status::internal("Database error")
+        })?
+        .ok_or_else(|| Status::not_found("Key not found"))?;
+
+        if key.is_revoked {
+            return Err(Status::failed_precondition("Key is already revoked"));
+        }
+        if key.is_expired {
+            return Err(Status::failed_precondition("Key has expired"));
+        }
+
+        let public_key = parse_verifying_key(&key.public_key).map_err(|e| {
+            error!("Stored public key is invalid: {}", e);
+            Status::internal("Stored public key is invalid")
+        })?;
+
+        let challenge = Self::revocation_challenge(user_id, fingerprint, reason);
+        let verified = signature_verified(&public_key, challenge.as_bytes(), revocation_signature_b64)
+            .ok_or_else(|| Status::invalid_argument("Malformed revocation signature"))?;
+        if !verified {
+            return Err(Status::permission_denied("Revocation signature verification failed"));
+        }
+
+        let reason = if reason.is_empty() { None } else { Some(reason) };
+        let revoked_at = sqlx::query_scalar!(
+            r#"
+            UPDATE user_keys
+            SET is_revoked = true,
+                revoked_at = NOW() AT TIME ZONE 'UTC',
+                revocation_reason = $3
+            WHERE user_id = $1 AND fingerprint = $2
+            RETURNING revoked_at AS "revoked_at!"
+            "#,
+            user_id,
+            fingerprint,
+            reason
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| {
+            error!("Database error: {:?}", e);
+            Status::internal("Database error")
+        })?;
+
+        tx.commit().await.map_err(|e| {
+            error!("Database error: {:?}", e);
+            Status::internal("Database error")
+        })?;
+
+        info!("Revoked key {} of user {}", fingerprint, user_id);
+
+        Ok(revoked_at)
+    }
+
+    // Последние действующие ключи участников; без такого ключа чат не создаётся
+    pub async fn member_keys(&self, members: &[Uuid]) -> Result<Vec<MemberKey>, Status> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT DISTINCT ON (user_id) user_id, public_key, fingerprint, expires_at
+            FROM user_keys
+            WHERE user_id = ANY($1)
+              AND is_revoked = false
+              AND expires_at > NOW() AT TIME ZONE 'UTC'
+            ORDER BY user_id, created_at DESC
+            "#,
+            members
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            error!("Database error: {:?}", e);
+            Status::internal("Database error")
+        })?;
+
+        let mut keys = Vec::with_capacity(members.len());
+        for member in members {
+            let Some(row) = rows.iter().find(|row| row.user_id == *member) else {
+                return Err(Status::failed_precondition(format!(
+                    "User {} has no active public key", member
+                )));
+            };
+
+            keys.push(MemberKey {
+                user_id: row.user_id,
+                public_key: row.public_key.clone(),
+                fingerprint: row.fingerprint.clone(),
+                expires_at: row.expires_at,
+            });
+        }
+
+        Ok(keys)
+    }
+
+    // Ключи шифрования устройств участников; устройства без ключа шифрования пропускаются
+    pub async fn member_devices(&self, members: &[Uuid]) -> Result<Vec<DeviceKey>, Status> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT user_id, device_id, encryption_key AS "encryption_key!"
+            FROM devices
+            WHERE user_id = ANY($1) AND encryption_key IS NOT NULL
+            ORDER BY user_id, device_id
+            "#,
+            members
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| {
+            error!("Database error: {:?}", e);
+            Status::internal("Database error")
+        })?;
+
+        let mut devices = Vec::with_capacity(rows.len());
+        for row in rows {
+            let Ok(encryption_key) = row.encryption_key.try_into() else {
+                error!("Invalid encryption key of device {} of user {}", row.device_id, row.user_id);
+                continue;
+            };
+            devices.push(DeviceKey {
+                user_id: row.user_id,
+                device_id: row.device_id,
+                encryption_key,
+            });
+        }
+
+        Ok(devices)
+    }
+
+    // Генерация нового ключа чата и его обёртка ключом каждого участника
+    // (RSA либо X25519 для ключей OpenPGP с подключом Curve25519)
+    // и ключом шифрования каждого его устройства
+    pub fn wrap_new_chat_key(
+        members: &[MemberKey],
+        devices: &[DeviceKey],
+    ) -> Result<(Vec<WrappedChatKey>, Vec<WrappedDeviceChatKey>), WrapError> {
+        let mut chat_key = Zeroizing::new([0u8; CHAT_KEY_SIZE]);
+        OsRng.fill_bytes(chat_key.as_mut());
+
+        let mut wrapped = Vec::with_capacity(members.len());
+        for member in members {
+            // Например, у ключа OpenPGP, зарегистрированного раньше, истёк подключ шифрования
+            let key = parse_client_key(&member.public_key)
+                .map_err(|reason| WrapError::UnusableKey { user_id: member.user_id, reason })?;
+            let (algorithm, encrypted_key) = wrap_chat_key(&key, chat_key.as_ref())
+                .map_err(WrapError::Encryption)?;
+
+            wrapped.push(WrappedChatKey {
+                user_id: member.user_id,
+                fingerprint: member.fingerprint.clone(),
+                algorithm,
+                encrypted_key,
+                expires_at: member.expires_at,
+            });
+        }
+
+        let mut wrapped_devices = Vec::with_capacity(devices.len());
+        for device in devices {
+            let encrypted_key = hybrid::seal(
+                hybrid::PublicKey::X25519(&device.encryption_key),
+                chat_key.as_ref(),
+                &device_chat_key_aad(device.user_id, &device.device_id),
+            )
+            .map_err(|e| WrapError::Encryption(e.to_string()))?;
+
+            wrapped_devices.push(WrappedDeviceChatKey {
+                user_id: device.user_id,
+                device_id: device.device_id.clone(),
+                algorithm: DEVICE_CHAT_KEY_ALGORITHM,
+                encrypted_key,
+            });
+        }
+
+        Ok((wrapped, wrapped_devices))
     }
 
     pub async fn generate_government_key(&self) -> Result<(), Status> {
@@ -237,6 +616,16 @@ impl KeyManager {
             Status::internal("Government key generation error")
         })?;
 
+        // id нужен до вставки: он входит в AAD шифртекста
+        let id = uuid::Builder::from_random_bytes(rand::random()).into_uuid();
+        let encrypted = self
+            .encrypt_data(keypair.private_key.as_bytes(), &government_key_aad(id))
+            .await
+            .map_err(|e| {
+                error!("Government key encryption error: {:?}", e);
+                Status::internal("Government key encryption error")
+            })?;
+
         let valid_from = Utc::now();
         let valid_to = valid_from + ChronoDuration::days(1);
 
@@ -253,11 +642,13 @@ impl KeyManager {
         sqlx::query!(
             r#"
             INSERT INTO government_keys 
-            (public_key, private_key_encrypted, valid_from, valid_to, is_active)
-            VALUES ($1, $2, $3, $4, true)
+            (id, public_key, private_key_encrypted, private_key_kek_id, valid_from, valid_to, is_active)
+            VALUES ($1, $2, $3, $4, $5, $6, true)
             "#,
+            id,
             keypair.public_key,
-            keypair.private_key.to_string(),
+            BASE64.encode(&encrypted.ciphertext),
+            encrypted.kek_id,
             valid_from.naive_utc(),
             valid_to.naive_utc()
         )
@@ -271,9 +662,31 @@ impl KeyManager {
         Ok(())
     }
 
-    pub async fn encrypt_with_government_key(&self, message: &str) -> Result<(String, String), Status> {
+    // Приватный ключ из government_keys; kek_id = None - открытый PEM из старых версий
+    pub async fn government_private_key(
+        &self,
+        id: Uuid,
+        private_key_encrypted: &str,
+        kek_id: Option<&str>,
+    ) -> Result<Zeroizing<Vec<u8>>, KmsError> {
+        let Some(kek_id) = kek_id else {
+            return Ok(Zeroizing::new(private_key_encrypted.as_bytes().to_vec()));
+        };
+
+        let ciphertext = BASE64.decode(private_key_encrypted).map_err(|_| KmsError::Crypto)?;
+        self.decrypt_data(kek_id, &ciphertext, &government_key_aad(id)).await
+    }
+
+    // Гибридное шифрование сообщения активным государственным ключом (формат v2 из hybrid),
+    // AAD привязывает шифртекст к чату и сообщению. Возвращает id ключа и шифртекст
+    pub async fn encrypt_with_government_key(
+        &self,
+        chat_id: Uuid,
+        message_id: Uuid,
+        message: &[u8],
+    ) -> Result<(Uuid, Vec<u8>), Status> {
         let active_key = sqlx::query!(
-            "SELECT public_key FROM government_keys WHERE is_active = true LIMIT 1"
+            "SELECT id, public_key FROM government_keys WHERE is_active = true LIMIT 1"
         )
         .fetch_optional(&self.db)
         .await
@@ -289,77 +702,333 @@ impl KeyManager {
                 Status::internal("Invalid government key format")
             })?;
 
-        let mut rng = OsRng;
-        let encrypted = public_key.encrypt(
-            &mut rng, 
-            rsa::Pkcs1v15Encrypt,
-            message.as_bytes()
+        let ciphertext = hybrid::seal(
+            hybrid::PublicKey::Rsa(&public_key),
+            message,
+            &hybrid::message_aad(chat_id, message_id),
         )
         .map_err(|e| {
             error!("Encryption failed: {:?}", e);
             Status::internal("Encryption failed")
         })?;
 
-        let iv: [u8; 16] = rand::random();
-        Ok((hex::encode(encrypted), hex::encode(iv)))
+        Ok((active_key.id, ciphertext))
     }
 
-    // Восстановление сообщений по приватному ключу
-    pub async fn recover_messages(
+    // Расшифровывает и текущий формат, и шифртексты PKCS#1 v1.5 старых версий
+    pub async fn decrypt_with_government_key(
         &self,
-        user_id: Uuid,
-        private_key_pem: &str,
-    ) -> Result<Vec<(String, String)>, Status> {
-        let fingerprint = Self::generate_fingerprint_from_private(private_key_pem)
-            .map_err(|e| {
-                error!("Fingerprint error: {:?}", e);
-                Status::invalid_argument("Invalid private key")
-            })?;
-
-        let key_exists: bool = sqlx::query_scalar!(
-            "SELECT EXISTS(SELECT 1 FROM user_keys WHERE user_id = $1 AND fingerprint = $2)",
-            user_id,
-            fingerprint
+        key_id: Uuid,
+        chat_id: Uuid,
+        message_id: Uuid,
+        ciphertext: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, Status> {
+        let stored = sqlx::query!(
+            "SELECT private_key_encrypted, private_key_kek_id FROM government_keys WHERE id = $1",
+            key_id
         )
-        .fetch_one(&self.db)
+        .fetch_optional(&self.db)
         .await
         .map_err(|e| {
             error!("Database error: {:?}", e);
             Status::internal("Database error")
-        })?.expect("Erorr db");
+        })?
+        .ok_or_else(|| Status::not_found("Government key not found"))?;
 
-        if !key_exists {
-            return Err(Status::permission_denied("Private key does not belong to user"));
-        }
+        let private_pem = self
+            .government_private_key(key_id, &stored.private_key_encrypted, stored.private_key_kek_id.as_deref())
+            .await
+            .map_err(|e| {
+                error!("Government key decryption error: {:?}", e);
+                Status::internal("Government key decryption error")
+            })?;
+        let private_key = std::str::from_utf8(&private_pem)
+            .ok()
+            .and_then(|pem| RsaPrivateKey::from_pkcs8_pem(pem).ok())
+            .ok_or_else(|| Status::internal("Invalid government key format"))?;
 
-        let messages = sqlx::query!(
-            r#"
-            SELECT m.id, m.encrypted_content 
-            FROM messages m
-            JOIN direct_chats_members dcm ON m.chat_id = dcm.chat_id
-            WHERE dcm.user_id = $1
-            "#,
-            user_id
+        hybrid::open(
+            hybrid::PrivateKey::Rsa(&private_key),
+            ciphertext,
+            &hybrid::message_aad(chat_id, message_id),
         )
-        .fetch_all(&self.db)
-        .await
         .map_err(|e| {
-            error!("Database error: {:?}", e);
-            Status::internal("Database error")
-        })?;
+            error!("Decryption failed: {:?}", e);
+            Status::internal("Decryption failed")
+        })
+    }
+}
+
+// Обёртка ключа чата привязана к отпечатку ключа участника
+fn member_chat_key_aad(fingerprint: &str) -> Vec<u8> {
+    format!("nesfinch-member-chat-key:v1:{}", fingerprint).into_bytes()
+}
+
+fn device_chat_key_aad(user_id: Uuid, device_id: &str) -> Vec<u8> {
+    format!("nesfinch-device-chat-key:v1:{}:{}", user_id, device_id).into_bytes()
+}
+
+pub fn government_key_aad(id: Uuid) -> Vec<u8> {
+    format!("nesfinch-government-key:v1:{}", id).into_bytes()
+}
+
+// PKCS#8 PEM или ASCII-armored ключ OpenPGP
+fn parse_client_key(encoded: &str) -> Result<ClientKey, String> {
+    parse_key(encoded, true)
+}
+
+// Ключ только для проверки подписей: подключ шифрования OpenPGP мог уже истечь
+fn parse_verifying_key(encoded: &str) -> Result<ClientKey, String> {
+    parse_key(encoded, false)
+}
+
+fn parse_key(encoded: &str, require_encryption: bool) -> Result<ClientKey, String> {
+    if encoded.contains(&format!("-----BEGIN {}-----", openpgp::PUBLIC_KEY_BLOCK)) {
+        return parse_openpgp_key(encoded, require_encryption);
+    }
+
+    let public_key = RsaPublicKey::from_public_key_pem(encoded)
+        .map_err(|e| format!("Invalid public key: {}", e))?;
+
+    if public_key.size() * 8 < MIN_USER_RSA_KEY_BITS {
+        return Err(format!("RSA keys must be at least {} bits", MIN_USER_RSA_KEY_BITS));
+    }
+
+    // Отпечаток считается от канонического PEM, а не от присланного текста
+    let pem = public_key.to_public_key_pem(LineEnding::LF)
+        .map_err(|e| format!("Public key encoding error: {}", e))?;
+    let fingerprint = KeyManager::generate_fingerprint(&pem)
+        .map_err(|e| format!("Fingerprint error: {}", e))?;
+
+    Ok(ClientKey { kind: ClientKeyKind::Pkcs8(public_key), encoded: pem, fingerprint, expires_at: None })
+}
+
+// Ключ OpenPGP принимается, только если его user ID и подключи подписаны им самим
+fn parse_openpgp_key(armored: &str, require_encryption: bool) -> Result<ClientKey, String> {
+    let now = Utc::now();
+    let certificate = Certificate::from_armored(armored, now.timestamp().max(0) as u64)
+        .map_err(|e| format!("Invalid OpenPGP key: {}", e))?;
+
+    if require_encryption && certificate.encryption_key().is_none() {
+        return Err("OpenPGP key has no RSA or Curve25519 encryption key".to_string());
+    }
+
+    // Ключ пригоден, пока действует и основной ключ, и подключ, которым шифруются ключи чатов
+    let expires_at = match certificate.encryption_expires_at() {
+        Some(expires_at) => Some(
+            DateTime::<Utc>::from_timestamp(expires_at as i64, 0)
+                .ok_or("Invalid OpenPGP key expiration time")?
+                .naive_utc(),
+        ),
+        None => None,
+    };
+
+    Ok(ClientKey {
+        encoded: certificate.armored(),
+        fingerprint: certificate.fingerprint_hex(),
+        expires_at,
+        kind: ClientKeyKind::OpenPgp(Box::new(certificate)),
+    })
+}
+
+fn decode_signature(signature_b64: &str) -> Option<Signature> {
+    let bytes = BASE64.decode(signature_b64).ok()?;
+    Signature::try_from(bytes.as_slice()).ok()
+}
+
+// None - подпись не разобрана. Для PEM - RSA-PSS в base64, для OpenPGP - отделённая
+// подпись основным ключом (armored или base64 бинарного пакета)
+fn signature_verified(key: &ClientKey, message: &[u8], signature: &str) -> Option<bool> {
+    match &key.kind {
+        ClientKeyKind::Pkcs8(public_key) => {
+            let signature = decode_signature(signature)?;
+            Some(VerifyingKey::<Sha256>::new(public_key.clone()).verify(message, &signature).is_ok())
+        }
+        ClientKeyKind::OpenPgp(certificate) => {
+            let now = Utc::now().timestamp().max(0) as u64;
+            certificate.verify_detached(signature, message, now).ok()
+        }
+    }
+}
+
+// Проверка подписи challenge регистрации самим регистрируемым ключом
+fn possession_verified(user_id: Uuid, key: &ClientKey, signature: &str) -> Option<bool> {
+    let challenge = KeyManager::registration_challenge(user_id, &key.fingerprint);
+    signature_verified(key, challenge.as_bytes(), signature)
+}
 
-        let mut result = Vec::new();
-        for message in messages {
-            result.push((message.id.to_string(), message.encrypted_content));
+fn wrap_chat_key(key: &ClientKey, chat_key: &[u8]) -> Result<(&'static str, Vec<u8>), String> {
+    let aad = member_chat_key_aad(&key.fingerprint);
+    let (algorithm, sealed) = match &key.kind {
+        ClientKeyKind::Pkcs8(public_key) => {
+            (RSA_CHAT_KEY_ALGORITHM, hybrid::seal(hybrid::PublicKey::Rsa(public_key), chat_key, &aad))
         }
+        ClientKeyKind::OpenPgp(certificate) => {
+            let encryption_key = certificate.encryption_key().ok_or("OpenPGP key has no encryption key")?;
+            match &encryption_key.material {
+                PgpKeyMaterial::X25519(point) => {
+                    (X25519_CHAT_KEY_ALGORITHM, hybrid::seal(hybrid::PublicKey::X25519(point), chat_key, &aad))
+                }
+                _ => {
+                    let public_key = encryption_key.rsa_public_key().ok_or("Unsupported OpenPGP encryption key")?;
+                    (RSA_CHAT_KEY_ALGORITHM, hybrid::seal(hybrid::PublicKey::Rsa(&public_key), chat_key, &aad))
+                }
+            }
+        }
+    };
+
+    sealed.map(|encrypted_key| (algorithm, encrypted_key)).map_err(|e| e.to_string())
+}
+
+// Запись нового ключа. Известный ключ возвращается как есть: повторная регистрация
+// не продлевает срок и не снимает отзыв.
+async fn store_user_key(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    key: ClientKey,
+) -> Result<RegisteredKey, Status> {
+    let existing = sqlx::query!(
+        r#"
+        SELECT user_id, is_revoked, expires_at, expires_at <= NOW() AT TIME ZONE 'UTC' AS "is_expired!"
+        FROM user_keys
+        WHERE fingerprint = $1
+        FOR UPDATE
+        "#,
+        key.fingerprint
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| {
+        error!("Database error: {:?}", e);
+        Status::internal("Database error")
+    })?;
 
-        Ok(result)
+    if let Some(existing) = existing {
+        if existing.user_id != user_id {
+            return Err(Status::already_exists("Key is registered to another user"));
+        }
+        if existing.is_revoked {
+            return Err(Status::failed_precondition("Key has been revoked"));
+        }
+        if existing.is_expired {
+            return Err(Status::failed_precondition("Key has expired; register a new key"));
+        }
+
+        return Ok(RegisteredKey {
+            public_key: key.encoded,
+            fingerprint: key.fingerprint,
+            expires_at: existing.expires_at,
+            is_new: false,
+            is_first: false,
+        });
     }
 
-    fn generate_fingerprint_from_private(private_key_pem: &str) -> Result<String, Box<dyn std::error::Error>> {
-        let private_key = RsaPrivateKey::from_pkcs8_pem(private_key_pem)?;
-        let public_key = RsaPublicKey::from(&private_key);
-        let public_pem = public_key.to_public_key_pem(LineEnding::LF)?;
-        Self::generate_fingerprint(&public_pem)
+    let is_first = !sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM user_keys WHERE user_id = $1) AS "exists!""#,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| {
+        error!("Database error: {:?}", e);
+        Status::internal("Database error")
+    })?;
+
+    let now = Utc::now();
+    let created_at = now.naive_utc();
+    // Ключ OpenPGP может истекать раньше серверного срока действия
+    let mut expires_at = (now + ChronoDuration::days(KEY_LIFETIME_DAYS)).naive_utc();
+    if let Some(key_expires_at) = key.expires_at {
+        expires_at = expires_at.min(key_expires_at);
+    }
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO user_keys (user_id, public_key, fingerprint, key_format, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (fingerprint) DO NOTHING
+        "#,
+        user_id,
+        key.encoded,
+        key.fingerprint,
+        key.format(),
+        created_at,
+        expires_at
+    )
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| {
+        error!("Database error: {:?}", e);
+        Status::internal("Database error")
+    })?;
+
+    if inserted.rows_affected() == 0 {
+        return Err(Status::aborted("Concurrent key registration, retry"));
     }
-}
\ No newline at end of file
+
+    // Это единственное место вставки в user_keys, поэтому журнал прозрачности полон
+    key_log::append_key(conn, user_id, &key.fingerprint, &key.encoded, created_at).await?;
+
+    info!("Registered public key {} for user {}", key.fingerprint, user_id);
+
+    Ok(RegisteredKey {
+        public_key: key.encoded,
+        fingerprint: key.fingerprint,
+        expires_at,
+        is_new: true,
+        is_first,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
+
+    #[test]
+    fn chat_keys_are_wrapped_with_hybrid_formats() {
+        let private_key = RsaPrivateKey::new(&mut OsRng, MIN_USER_RSA_KEY_BITS).unwrap();
+        let public_key = private_key.to_public_key().to_public_key_pem(LineEnding::LF).unwrap();
+        let fingerprint = parse_client_key(&public_key).unwrap().fingerprint;
+        let member = MemberKey {
+            user_id: Uuid::from_u128(1),
+            public_key,
+            fingerprint: fingerprint.clone(),
+            expires_at: Utc::now().naive_utc(),
+        };
+
+        let device_secret = StaticSecret::random_from_rng(OsRng);
+        let device = DeviceKey {
+            user_id: Uuid::from_u128(1),
+            device_id: "laptop".to_string(),
+            encryption_key: X25519PublicKey::from(&device_secret).to_bytes(),
+        };
+
+        let (wrapped, wrapped_devices) = KeyManager::wrap_new_chat_key(&[member], &[device]).unwrap();
+        assert_eq!(wrapped[0].algorithm, RSA_CHAT_KEY_ALGORITHM);
+        assert_eq!(wrapped_devices[0].algorithm, DEVICE_CHAT_KEY_ALGORITHM);
+
+        let member_chat_key = hybrid::open(
+            hybrid::PrivateKey::Rsa(&private_key),
+            &wrapped[0].encrypted_key,
+            &member_chat_key_aad(&fingerprint),
+        )
+        .unwrap();
+        let device_chat_key = hybrid::open(
+            hybrid::PrivateKey::X25519(&device_secret),
+            &wrapped_devices[0].encrypted_key,
+            &device_chat_key_aad(Uuid::from_u128(1), "laptop"),
+        )
+        .unwrap();
+        assert_eq!(member_chat_key.len(), CHAT_KEY_SIZE);
+        assert_eq!(member_chat_key, device_chat_key);
+
+        // Обёртка привязана к отпечатку ключа
+        assert!(hybrid::open(
+            hybrid::PrivateKey::Rsa(&private_key),
+            &wrapped[0].encrypted_key,
+            &member_chat_key_aad("other"),
+        )
+        .is_err());
+    }
+}
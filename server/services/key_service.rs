@@ -0,0 +1,610 @@
+use tonic::{Request, Response, Status};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::services::chat_service::{post_system_event, rotate_user_chat_keys, timestamp_from_naive};
+use crate::services::key_backup::{self, KdfParams, NewKeyBackup, UploadProof, KDF_ARGON2ID, KDF_PBKDF2_SHA256};
+use crate::services::key_log::{self, KeyLog};
+use crate::services::key_manager::{KeyManager, KEY_FORMAT_OPENPGP};
+use crate::services::notification_service::{Notifier, NewNotification, NotificationKind};
+
+mod keys {
+    tonic::include_proto!("keys");
+}
+
+pub use keys::key_service_server::{KeyService, KeyServiceServer};
+use keys::{
+    RevokeKeyRequest, RevokeKeyResponse, RotateKeyRequest, RotateKeyResponse,
+    GetPublicKeysRequest, GetPublicKeysResponse, LookupFingerprintRequest, LookupFingerprintResponse,
+    SetKeyLookupPrivacyRequest, SetKeyLookupPrivacyResponse, PublicKeyInfo, KeyLookupPrivacy, KeyFormat,
+    GetSafetyNumberRequest, GetSafetyNumberResponse, SetKeyVerificationRequest, SetKeyVerificationResponse,
+    SignedTreeHead, KeyLogEntry, GetSignedTreeHeadRequest, GetSignedTreeHeadResponse,
+    GetKeyInclusionProofRequest, GetKeyInclusionProofResponse, GetConsistencyProofRequest, GetConsistencyProofResponse,
+    BackupKdf, BackupKdfParams, UploadKeyBackupRequest, UploadKeyBackupResponse,
+    GetKeyBackupParamsRequest, GetKeyBackupParamsResponse, FetchKeyBackupRequest, FetchKeyBackupResponse,
+};
+
+const MAX_REVOCATION_REASON_LEN: usize = 256;
+const SAFETY_NUMBER_CHANGED_EVENT: &str = "SAFETY_NUMBER_CHANGED";
+
+#[derive(Debug)]
+pub struct MyKeyService {
+    db: PgPool,
+    notifier: Notifier,
+    key_log: KeyLog,
+    key_manager: KeyManager,
+}
+
+impl MyKeyService {
+    pub fn new(db: PgPool, notifier: Notifier, key_log: KeyLog, key_manager: KeyManager) -> Self {
+        Self { db, notifier, key_log, key_manager }
+    }
+}
+
+// Смена ключа пользователя: подтверждения его ключа сбрасываются, в его чатах
+// появляется системное сообщение, контакты получают KEY_CHANGED
+pub(crate) async fn handle_key_change(
+    db: &PgPool,
+    notifier: &Notifier,
+    user_id: Uuid,
+    safety_number_changed: bool,
+) -> Result<(), Status> {
+    if safety_number_changed {
+        let mut tx = db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        sqlx::query!(
+            "DELETE FROM key_verifications WHERE contact_id = $1",
+            user_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let chats = sqlx::query_scalar!(
+            "SELECT chat_id FROM direct_chats_members WHERE user_id = $1",
+            user_id
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        for chat_id in chats {
+            post_system_event(&mut tx, chat_id, user_id, SAFETY_NUMBER_CHANGED_EVENT).await?;
+        }
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+    }
+
+    notifier.notify_all(key_change_notifications(db, user_id).await?).await;
+    Ok(())
+}
+
+// Уведомления KEY_CHANGED для друзей и собеседников владельца ключа
+async fn key_change_notifications(
+    db: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<NewNotification>, Status> {
+    let contacts = sqlx::query_scalar!(
+        r#"
+        SELECT target_user_id AS "user_id!" FROM user_relationships
+        WHERE user_id = $1 AND status = 'FRIEND'
+        UNION
+        SELECT user_id FROM user_relationships
+        WHERE target_user_id = $1 AND status = 'FRIEND'
+        UNION
+        SELECT other.user_id FROM direct_chats_members own
+        JOIN direct_chats_members other ON other.chat_id = own.chat_id
+        WHERE own.user_id = $1 AND other.user_id <> $1
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+    .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+    Ok(contacts
+        .into_iter()
+        .map(|contact| NewNotification {
+            user_id: contact,
+            kind: NotificationKind::NotificationKeyChanged,
+            actor_id: Some(user_id),
+            chat_id: None,
+            message_id: None,
+            device_id: None,
+        })
+        .collect())
+}
+
+fn privacy_to_str(privacy: KeyLookupPrivacy) -> &'static str {
+    match privacy {
+        KeyLookupPrivacy::KeyLookupFriends => "FRIENDS",
+        KeyLookupPrivacy::KeyLookupEveryone => "EVERYONE",
+    }
+}
+
+fn key_format_from_str(format: &str) -> KeyFormat {
+    match format {
+        KEY_FORMAT_OPENPGP => KeyFormat::Openpgp,
+        _ => KeyFormat::Pkcs8,
+    }
+}
+
+fn tree_head_to_proto(head: key_log::SignedTreeHead) -> SignedTreeHead {
+    SignedTreeHead {
+        tree_size: head.tree_size as u64,
+        root_hash: head.root_hash,
+        signed_at: Some(timestamp_from_naive(head.signed_at)),
+        signature: head.signature,
+    }
+}
+
+// Значения вне диапазона i32 отбрасываются проверкой параметров KDF
+fn kdf_from_proto(kdf: BackupKdfParams) -> Result<KdfParams, String> {
+    let algorithm = match BackupKdf::try_from(kdf.algorithm) {
+        Ok(BackupKdf::Argon2id) => KDF_ARGON2ID,
+        Ok(BackupKdf::Pbkdf2Sha256) => KDF_PBKDF2_SHA256,
+        Err(_) => return Err("Unsupported KDF".to_string()),
+    };
+
+    Ok(KdfParams {
+        algorithm: algorithm.to_string(),
+        salt: kdf.salt,
+        memory_kib: i32::try_from(kdf.memory_kib).unwrap_or(i32::MAX),
+        iterations: i32::try_from(kdf.iterations).unwrap_or(i32::MAX),
+        parallelism: i32::try_from(kdf.parallelism).unwrap_or(i32::MAX),
+    })
+}
+
+fn kdf_to_proto(kdf: KdfParams) -> BackupKdfParams {
+    let algorithm = match kdf.algorithm.as_str() {
+        KDF_PBKDF2_SHA256 => BackupKdf::Pbkdf2Sha256,
+        _ => BackupKdf::Argon2id,
+    };
+
+    BackupKdfParams {
+        algorithm: algorithm as i32,
+        salt: kdf.salt,
+        memory_kib: kdf.memory_kib as u32,
+        iterations: kdf.iterations as u32,
+        parallelism: kdf.parallelism as u32,
+    }
+}
+
+#[tonic::async_trait]
+impl KeyService for MyKeyService {
+    async fn revoke_key(
+        &self,
+        request: Request<RevokeKeyRequest>,
+    ) -> Result<Response<RevokeKeyResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        if req.reason.len() > MAX_REVOCATION_REASON_LEN {
+            return Err(Status::invalid_argument(format!(
+                "Reason must be at most {} bytes", MAX_REVOCATION_REASON_LEN
+            )));
+        }
+
+        let revoked_at = self.key_manager
+            .revoke_key(user_id, &req.fingerprint, &req.reason, &req.revocation_signature)
+            .await?;
+
+        handle_key_change(&self.db, &self.notifier, user_id, true).await?;
+
+        Ok(Response::new(RevokeKeyResponse {
+            fingerprint: req.fingerprint,
+            revoked_at: Some(timestamp_from_naive(revoked_at)),
+        }))
+    }
+
+    async fn rotate_key(
+        &self,
+        request: Request<RotateKeyRequest>,
+    ) -> Result<Response<RotateKeyResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let registered = self.key_manager
+            .rotate_key(
+                user_id,
+                &req.old_fingerprint,
+                &req.new_public_key,
+                &req.new_key_signature,
+                &req.rotation_signature,
+            )
+            .await?;
+
+        rotate_user_chat_keys(&self.db, &self.key_manager, user_id).await?;
+        handle_key_change(&self.db, &self.notifier, user_id, true).await?;
+
+        Ok(Response::new(RotateKeyResponse {
+            public_key: registered.public_key,
+            fingerprint: registered.fingerprint,
+            expires_at: Some(timestamp_from_naive(registered.expires_at)),
+            revoked_fingerprint: req.old_fingerprint,
+        }))
+    }
+
+    async fn get_public_keys(
+        &self,
+        request: Request<GetPublicKeysRequest>,
+    ) -> Result<Response<GetPublicKeysResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT public_key, fingerprint, key_format, created_at, expires_at, is_revoked, revoked_at, replaced_by,
+                   expires_at <= NOW() AT TIME ZONE 'UTC' AS "is_expired!"
+            FROM user_keys
+            WHERE user_id = $1
+              AND ($2 OR (is_revoked = false AND expires_at > NOW() AT TIME ZONE 'UTC'))
+            ORDER BY created_at DESC
+            "#,
+            user_id,
+            req.include_inactive
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let keys = rows
+            .into_iter()
+            .map(|row| PublicKeyInfo {
+                user_id: user_id.to_string(),
+                public_key: row.public_key,
+                fingerprint: row.fingerprint,
+                created_at: Some(timestamp_from_naive(row.created_at)),
+                expires_at: Some(timestamp_from_naive(row.expires_at)),
+                is_revoked: row.is_revoked,
+                revoked_at: row.revoked_at.map(timestamp_from_naive),
+                replaced_by: row.replaced_by.unwrap_or_default(),
+                is_expired: row.is_expired,
+                key_format: key_format_from_str(&row.key_format) as i32,
+            })
+            .collect();
+
+        Ok(Response::new(GetPublicKeysResponse { keys }))
+    }
+
+    async fn lookup_fingerprint(
+        &self,
+        request: Request<LookupFingerprintRequest>,
+    ) -> Result<Response<LookupFingerprintResponse>, Status> {
+        let req = request.into_inner();
+
+        let requester_id = Uuid::parse_str(&req.requester_id)
+            .map_err(|_| Status::invalid_argument("Invalid requester_id UUID"))?;
+        // gpg выводит отпечатки в верхнем регистре группами через пробел
+        let fingerprint = req.fingerprint.split_whitespace().collect::<String>().to_lowercase();
+
+        // Скрытый настройкой приватности ключ неотличим от несуществующего
+        let row = sqlx::query!(
+            r#"
+            SELECT u.id AS user_id, u.username, u.display_name,
+                   k.public_key, k.fingerprint, k.key_format, k.created_at, k.expires_at, k.is_revoked, k.revoked_at, k.replaced_by,
+                   k.expires_at <= NOW() AT TIME ZONE 'UTC' AS "is_expired!"
+            FROM user_keys k
+            JOIN users u ON u.id = k.user_id
+            WHERE k.fingerprint = $1
+              AND (
+                  u.id = $2
+                  OR u.key_lookup_privacy = 'EVERYONE'
+                  OR EXISTS (
+                      SELECT 1 FROM user_relationships ur
+                      WHERE ur.status = 'FRIEND'
+                        AND ((ur.user_id = u.id AND ur.target_user_id = $2)
+                          OR (ur.user_id = $2 AND ur.target_user_id = u.id))
+                  )
+              )
+            "#,
+            fingerprint,
+            requester_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .ok_or_else(|| Status::not_found("Fingerprint not found"))?;
+
+        Ok(Response::new(LookupFingerprintResponse {
+            user_id: row.user_id.to_string(),
+            username: row.username,
+            display_name: row.display_name.unwrap_or_default(),
+            key: Some(PublicKeyInfo {
+                user_id: row.user_id.to_string(),
+                public_key: row.public_key,
+                fingerprint: row.fingerprint,
+                created_at: Some(timestamp_from_naive(row.created_at)),
+                expires_at: Some(timestamp_from_naive(row.expires_at)),
+                is_revoked: row.is_revoked,
+                revoked_at: row.revoked_at.map(timestamp_from_naive),
+                replaced_by: row.replaced_by.unwrap_or_default(),
+                is_expired: row.is_expired,
+                key_format: key_format_from_str(&row.key_format) as i32,
+            }),
+        }))
+    }
+
+    async fn set_key_lookup_privacy(
+        &self,
+        request: Request<SetKeyLookupPrivacyRequest>,
+    ) -> Result<Response<SetKeyLookupPrivacyResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let privacy = KeyLookupPrivacy::try_from(req.privacy)
+            .map_err(|_| Status::invalid_argument("Invalid privacy setting"))?;
+
+        let result = sqlx::query!(
+            "UPDATE users SET key_lookup_privacy = $1, updated_at = NOW() AT TIME ZONE 'UTC' WHERE id = $2",
+            privacy_to_str(privacy),
+            user_id
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if result.rows_affected() == 0 {
+            return Err(Status::not_found("User not found"));
+        }
+
+        Ok(Response::new(SetKeyLookupPrivacyResponse { privacy: privacy as i32 }))
+    }
+
+    async fn get_safety_number(
+        &self,
+        request: Request<GetSafetyNumberRequest>,
+    ) -> Result<Response<GetSafetyNumberResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let contact_id = Uuid::parse_str(&req.contact_id)
+            .map_err(|_| Status::invalid_argument("Invalid contact_id UUID"))?;
+
+        if user_id == contact_id {
+            return Err(Status::invalid_argument("Cannot compute a safety number with yourself"));
+        }
+
+        let keys = self.key_manager.member_keys(&[user_id, contact_id]).await?;
+        let (user_key, contact_key) = (&keys[0], &keys[1]);
+
+        let safety_number = KeyManager::safety_number(
+            (user_id, &user_key.fingerprint),
+            (contact_id, &contact_key.fingerprint),
+        );
+
+        let is_verified = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM key_verifications
+                WHERE user_id = $1 AND contact_id = $2 AND fingerprint = $3
+            ) AS "exists!"
+            "#,
+            user_id,
+            contact_id,
+            contact_key.fingerprint
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(Response::new(GetSafetyNumberResponse {
+            safety_number,
+            user_fingerprint: user_key.fingerprint.clone(),
+            contact_fingerprint: contact_key.fingerprint.clone(),
+            is_verified,
+        }))
+    }
+
+    async fn set_key_verification(
+        &self,
+        request: Request<SetKeyVerificationRequest>,
+    ) -> Result<Response<SetKeyVerificationResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let contact_id = Uuid::parse_str(&req.contact_id)
+            .map_err(|_| Status::invalid_argument("Invalid contact_id UUID"))?;
+
+        if user_id == contact_id {
+            return Err(Status::invalid_argument("Cannot verify your own key"));
+        }
+
+        if !req.verified {
+            sqlx::query!(
+                "DELETE FROM key_verifications WHERE user_id = $1 AND contact_id = $2",
+                user_id,
+                contact_id
+            )
+            .execute(&self.db)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+            return Ok(Response::new(SetKeyVerificationResponse {
+                is_verified: false,
+                verified_at: None,
+            }));
+        }
+
+        // Подтвердить можно только текущий ключ: пользователь сверял именно его safety number
+        let contact_key = self.key_manager.member_keys(&[contact_id]).await?.remove(0);
+        if contact_key.fingerprint != req.fingerprint {
+            return Err(Status::failed_precondition("Fingerprint does not match the contact's current key"));
+        }
+
+        let verified_at = sqlx::query_scalar!(
+            r#"
+            INSERT INTO key_verifications (user_id, contact_id, fingerprint, verified_at)
+            VALUES ($1, $2, $3, NOW() AT TIME ZONE 'UTC')
+            ON CONFLICT (user_id, contact_id, fingerprint) DO UPDATE
+            SET verified_at = key_verifications.verified_at
+            RETURNING verified_at
+            "#,
+            user_id,
+            contact_id,
+            contact_key.fingerprint
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(Response::new(SetKeyVerificationResponse {
+            is_verified: true,
+            verified_at: Some(timestamp_from_naive(verified_at)),
+        }))
+    }
+
+    async fn get_signed_tree_head(
+        &self,
+        _request: Request<GetSignedTreeHeadRequest>,
+    ) -> Result<Response<GetSignedTreeHeadResponse>, Status> {
+        let head = self.key_log.latest_tree_head().await?;
+
+        Ok(Response::new(GetSignedTreeHeadResponse {
+            tree_head: Some(tree_head_to_proto(head)),
+            log_public_key: self.key_log.public_key().to_vec(),
+        }))
+    }
+
+    async fn get_key_inclusion_proof(
+        &self,
+        request: Request<GetKeyInclusionProofRequest>,
+    ) -> Result<Response<GetKeyInclusionProofResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let (entry, audit_path, head) = self.key_log.inclusion_proof(user_id, req.tree_size).await?;
+
+        Ok(Response::new(GetKeyInclusionProofResponse {
+            entry: Some(KeyLogEntry {
+                leaf_index: entry.leaf_index as u64,
+                user_id: entry.user_id.to_string(),
+                fingerprint: entry.fingerprint,
+                leaf_data: entry.leaf_data,
+                key_hash: entry.key_hash.unwrap_or_default(),
+            }),
+            audit_path: audit_path.iter().map(|hash| hash.to_vec()).collect(),
+            tree_head: Some(tree_head_to_proto(head)),
+        }))
+    }
+
+    async fn get_consistency_proof(
+        &self,
+        request: Request<GetConsistencyProofRequest>,
+    ) -> Result<Response<GetConsistencyProofResponse>, Status> {
+        let req = request.into_inner();
+
+        let (proof, first, second) = self.key_log
+            .consistency_proof(req.first_size, req.second_size)
+            .await?;
+
+        Ok(Response::new(GetConsistencyProofResponse {
+            proof: proof.iter().map(|hash| hash.to_vec()).collect(),
+            first_tree_head: Some(tree_head_to_proto(first)),
+            second_tree_head: Some(tree_head_to_proto(second)),
+        }))
+    }
+
+    async fn upload_key_backup(
+        &self,
+        request: Request<UploadKeyBackupRequest>,
+    ) -> Result<Response<UploadKeyBackupResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let kdf = req.kdf.ok_or_else(|| Status::invalid_argument("kdf is required"))?;
+        let previous_version = i32::try_from(req.previous_version)
+            .map_err(|_| Status::invalid_argument("Invalid previous_version"))?;
+
+        // Подпись проверяется раньше auth_key, чтобы не расходовать попытки
+        let proof = if !req.backup_signature.is_empty() {
+            let version = previous_version.checked_add(1)
+                .ok_or_else(|| Status::invalid_argument("Invalid previous_version"))?;
+            let challenge = KeyManager::backup_challenge(user_id, version, &req.ciphertext);
+            self.key_manager
+                .verify_user_signature(user_id, challenge.as_bytes(), &req.backup_signature)
+                .await?;
+            UploadProof::KeySignature
+        } else if !req.current_auth_key.is_empty() {
+            UploadProof::AuthKey(req.current_auth_key)
+        } else {
+            return Err(Status::unauthenticated("current_auth_key or backup_signature is required"));
+        };
+
+        let key_fingerprint = req.key_fingerprint.trim().to_lowercase();
+        let backup = NewKeyBackup {
+            previous_version,
+            proof,
+            kdf: kdf_from_proto(kdf).map_err(Status::invalid_argument)?,
+            nonce: req.nonce,
+            ciphertext: req.ciphertext,
+            auth_key_hash: req.auth_key_hash,
+            key_fingerprint: (!key_fingerprint.is_empty()).then_some(key_fingerprint),
+        };
+
+        let (version, created_at) = key_backup::store_backup(&self.db, user_id, backup).await?;
+
+        Ok(Response::new(UploadKeyBackupResponse {
+            version: version as u32,
+            created_at: Some(timestamp_from_naive(created_at)),
+        }))
+    }
+
+    async fn get_key_backup_params(
+        &self,
+        request: Request<GetKeyBackupParamsRequest>,
+    ) -> Result<Response<GetKeyBackupParamsResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let version = i32::try_from(req.version)
+            .map_err(|_| Status::invalid_argument("Invalid version"))?;
+
+        let backup = key_backup::find_backup(&self.db, user_id, version).await?;
+
+        Ok(Response::new(GetKeyBackupParamsResponse {
+            version: backup.version as u32,
+            kdf: Some(kdf_to_proto(backup.kdf)),
+            key_fingerprint: backup.key_fingerprint.unwrap_or_default(),
+            created_at: Some(timestamp_from_naive(backup.created_at)),
+        }))
+    }
+
+    async fn fetch_key_backup(
+        &self,
+        request: Request<FetchKeyBackupRequest>,
+    ) -> Result<Response<FetchKeyBackupResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let version = i32::try_from(req.version)
+            .map_err(|_| Status::invalid_argument("Invalid version"))?;
+
+        let backup = key_backup::fetch_backup(&self.db, user_id, version, &req.auth_key).await?;
+
+        Ok(Response::new(FetchKeyBackupResponse {
+            version: backup.version as u32,
+            kdf: Some(kdf_to_proto(backup.kdf)),
+            nonce: backup.nonce,
+            ciphertext: backup.ciphertext,
+            key_fingerprint: backup.key_fingerprint.unwrap_or_default(),
+            created_at: Some(timestamp_from_naive(backup.created_at)),
+        }))
+    }
+}
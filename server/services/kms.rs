@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::sync::Arc;
+use rand::rngs::OsRng;
+use rand::Rng;
+use ring::aead;
+use zeroize::Zeroizing;
+
+// Ключи шифрования ключей (KEK) для секретов, которые сервер хранит в БД.
+// Рядом с каждым шифртекстом хранится идентификатор KEK: при ротации новый ключ
+// становится текущим, а старые остаются для расшифровки, пока KekRotationJob
+// не перешифрует данные.
+
+// Связка ключей в переменной окружения: "<key_id>:<hex>[,<key_id>:<hex>...]"
+const KEK_KEYS_ENV: &str = "KEK_KEYS";
+// Файл связки: по строке "<key_id> <hex>" на ключ, '#' - комментарий
+const KEK_FILE_ENV: &str = "KEK_FILE";
+// Текущий ключ; по умолчанию последний в связке
+const KEK_CURRENT_ID_ENV: &str = "KEK_CURRENT_ID";
+
+const KEK_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const MAX_KEY_ID_LEN: usize = 64;
+
+#[derive(Debug)]
+pub enum KmsError {
+    UnknownKey(String),
+    Crypto,
+}
+
+impl fmt::Display for KmsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KmsError::UnknownKey(key_id) => write!(f, "Unknown key encryption key '{}'", key_id),
+            KmsError::Crypto => write!(f, "Key encryption key operation failed"),
+        }
+    }
+}
+
+impl std::error::Error for KmsError {}
+
+// Внешний KMS подключается реализацией этого трейта
+#[tonic::async_trait]
+pub trait Kms: Send + Sync {
+    fn current_key_id(&self) -> &str;
+
+    async fn encrypt(&self, key_id: &str, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, KmsError>;
+
+    async fn decrypt(&self, key_id: &str, ciphertext: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, KmsError>;
+}
+
+// KMS в памяти процесса: ключи из окружения или из локального файла.
+// Шифртекст: nonce (12) || AES-256-GCM
+pub struct LocalKms {
+    current_key_id: String,
+    keys: HashMap<String, Zeroizing<[u8; KEK_SIZE]>>,
+}
+
+impl LocalKms {
+    // current_key_id = None - последний ключ связки
+    pub fn from_keyring<'a>(
+        entries: impl IntoIterator<Item = (&'a str, &'a str)>,
+        current_key_id: Option<&str>,
+    ) -> Result<Self, String> {
+        let mut keys = HashMap::new();
+        let mut last_key_id = None;
+
+        for (key_id, key_hex) in entries {
+            if key_id.is_empty()
+                || key_id.len() > MAX_KEY_ID_LEN
+                || !key_id.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+            {
+                return Err(format!("Invalid key encryption key id '{}'", key_id));
+            }
+
+            let bytes = Zeroizing::new(
+                hex::decode(key_hex.trim()).map_err(|_| format!("Key '{}' is not valid hex", key_id))?,
+            );
+            let mut key = Zeroizing::new([0u8; KEK_SIZE]);
+            if bytes.len() != KEK_SIZE {
+                return Err(format!("Key '{}' must be {} bytes", key_id, KEK_SIZE));
+            }
+            key.copy_from_slice(&bytes);
+
+            if keys.insert(key_id.to_string(), key).is_some() {
+                return Err(format!("Duplicate key encryption key id '{}'", key_id));
+            }
+            last_key_id = Some(key_id);
+        }
+
+        let current_key_id = current_key_id
+            .or(last_key_id)
+            .ok_or("Key encryption keyring is empty")?
+            .to_string();
+        if !keys.contains_key(&current_key_id) {
+            return Err(format!("Current key encryption key '{}' is not in the keyring", current_key_id));
+        }
+
+        Ok(Self { current_key_id, keys })
+    }
+
+    pub fn from_env_keys(keyring: &str, current_key_id: Option<&str>) -> Result<Self, String> {
+        let entries = keyring
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| entry.split_once(':').ok_or("KEK_KEYS entries must be <key_id>:<hex>"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::from_keyring(entries, current_key_id)
+    }
+
+    pub fn from_file(path: &str, current_key_id: Option<&str>) -> Result<Self, String> {
+        let contents = Zeroizing::new(
+            fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?,
+        );
+        let entries = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.split_once(char::is_whitespace).ok_or("KEK file lines must be <key_id> <hex>"))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::from_keyring(entries, current_key_id)
+    }
+
+    fn key(&self, key_id: &str) -> Result<aead::LessSafeKey, KmsError> {
+        let key = self.keys
+            .get(key_id)
+            .ok_or_else(|| KmsError::UnknownKey(key_id.to_string()))?;
+        let unbound = aead::UnboundKey::new(&aead::AES_256_GCM, &key[..]).map_err(|_| KmsError::Crypto)?;
+        Ok(aead::LessSafeKey::new(unbound))
+    }
+}
+
+#[tonic::async_trait]
+impl Kms for LocalKms {
+    fn current_key_id(&self) -> &str {
+        &self.current_key_id
+    }
+
+    async fn encrypt(&self, key_id: &str, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, KmsError> {
+        let key = self.key(key_id)?;
+
+        let mut nonce = [0u8; NONCE_SIZE];
+        OsRng.try_fill(&mut nonce).map_err(|_| KmsError::Crypto)?;
+
+        let mut in_out = plaintext.to_vec();
+        key.seal_in_place_append_tag(
+            aead::Nonce::assume_unique_for_key(nonce),
+            aead::Aad::from(aad),
+            &mut in_out,
+        )
+        .map_err(|_| KmsError::Crypto)?;
+
+        let mut result = nonce.to_vec();
+        result.extend_from_slice(&in_out);
+        Ok(result)
+    }
+
+    async fn decrypt(&self, key_id: &str, ciphertext: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>, KmsError> {
+        let key = self.key(key_id)?;
+        if ciphertext.len() < NONCE_SIZE {
+            return Err(KmsError::Crypto);
+        }
+
+        let (nonce, sealed) = ciphertext.split_at(NONCE_SIZE);
+        let nonce = aead::Nonce::try_assume_unique_for_key(nonce).map_err(|_| KmsError::Crypto)?;
+
+        let mut in_out = Zeroizing::new(sealed.to_vec());
+        let plaintext_len = key
+            .open_in_place(nonce, aead::Aad::from(aad), &mut in_out)
+            .map_err(|_| KmsError::Crypto)?
+            .len();
+        in_out.truncate(plaintext_len);
+
+        Ok(in_out)
+    }
+}
+
+// KEK_FILE важнее KEK_KEYS; без связки ключей сервер не запускается
+pub fn kms_from_env() -> Result<Arc<dyn Kms>, Box<dyn std::error::Error>> {
+    let current_key_id = env::var(KEK_CURRENT_ID_ENV).ok();
+    let current_key_id = current_key_id.as_deref();
+
+    let kms = if let Ok(path) = env::var(KEK_FILE_ENV) {
+        LocalKms::from_file(&path, current_key_id)?
+    } else if let Ok(keyring) = env::var(KEK_KEYS_ENV) {
+        LocalKms::from_env_keys(&keyring, current_key_id)?
+    } else {
+        return Err(format!("{} or {} must be set", KEK_FILE_ENV, KEK_KEYS_ENV).into());
+    };
+
+    Ok(Arc::new(kms))
+}
@@ -0,0 +1,185 @@
+use std::time::Duration;
+use sqlx::{Connection, PgConnection, PgPool};
+use uuid::Uuid;
+use tonic::{Code, Status};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::services::chat_service::{post_message, NewMessage};
+use crate::services::notification_service::Notifier;
+
+// Как часто планировщик проверяет наступившие сообщения
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BATCH_SIZE: i64 = 50;
+// Временные ошибки доставки: пауза растёт с каждой попыткой, после последней сообщение FAILED
+const MAX_ATTEMPTS: i32 = 5;
+const RETRY_DELAY_SECS: f64 = 30.0;
+
+// Фоновая доставка отложенных сообщений.
+// Состояние хранится только в scheduled_messages, поэтому после рестарта
+// сервер просто доставляет всё, что успело наступить.
+pub struct MessageScheduler {
+    db: PgPool,
+    notifier: Notifier,
+}
+
+impl MessageScheduler {
+    pub fn new(db: PgPool, notifier: Notifier) -> Self {
+        Self { db, notifier }
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.deliver_due().await {
+                    error!("Scheduled delivery failed: {:?}", e);
+                }
+            }
+        })
+    }
+
+    // Доставка всех наступивших сообщений пачками
+    async fn deliver_due(&self) -> Result<(), Status> {
+        loop {
+            let delivered = self.deliver_batch().await?;
+            if delivered < BATCH_SIZE as usize {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn deliver_batch(&self) -> Result<usize, Status> {
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        // SKIP LOCKED позволяет нескольким экземплярам сервера работать с одной таблицей
+        let due = sqlx::query!(
+            r#"
+            SELECT id, chat_id, sender_id, encrypted_content, attempts,
+                   mentioned_user_ids, reply_to_message_id
+            FROM scheduled_messages
+            WHERE status = 'PENDING' AND deliver_at <= NOW() AT TIME ZONE 'UTC'
+            ORDER BY deliver_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+            BATCH_SIZE
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let count = due.len();
+        let mut delivered = count;
+        let mut notifications = Vec::new();
+        for row in due {
+            let message = NewMessage {
+                chat_id: row.chat_id,
+                sender_id: row.sender_id,
+                encrypted_content: row.encrypted_content,
+                forwarded_from: None,
+                mentioned_user_ids: row.mentioned_user_ids,
+                reply_to_message_id: row.reply_to_message_id,
+                envelope_version: None,
+                sender_device_id: None,
+            };
+
+            // Каждая строка в своей точке сохранения: ошибка одного сообщения
+            // не откатывает уже доставленные и не останавливает пачку
+            let mut savepoint = tx.begin().await
+                .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+            let posted = match post_message(&mut savepoint, &message).await {
+                Ok(posted) => {
+                    savepoint.commit().await
+                        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+                    posted
+                }
+                Err(status) => {
+                    savepoint.rollback().await
+                        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+                    delivered -= 1;
+                    mark_failed_attempt(&mut tx, row.id, row.attempts, &status).await?;
+                    continue;
+                }
+            };
+
+            sqlx::query!(
+                r#"
+                UPDATE scheduled_messages
+                SET status = 'SENT', message_id = $2, processed_at = $3
+                WHERE id = $1
+                "#,
+                row.id,
+                posted.id,
+                posted.sent_at
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+            notifications.extend(posted.notifications);
+        }
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        // Уведомления только о зафиксированных сообщениях
+        self.notifier.notify_all(notifications).await;
+
+        if delivered > 0 {
+            info!("Delivered {} scheduled messages", delivered);
+        }
+
+        Ok(count)
+    }
+}
+
+// Ошибка доставки одного сообщения. Отказ по смыслу (отправитель покинул чат, чат
+// удалён и т.п.) окончателен, ошибки сервера откладывают сообщение до MAX_ATTEMPTS.
+async fn mark_failed_attempt(
+    conn: &mut PgConnection,
+    id: Uuid,
+    attempts: i32,
+    status: &Status,
+) -> Result<(), Status> {
+    let attempts = attempts + 1;
+    let retry = matches!(status.code(), Code::Internal | Code::Unavailable | Code::Unknown)
+        && attempts < MAX_ATTEMPTS;
+
+    if retry {
+        warn!("Scheduled message {} delivery attempt {} failed: {}", id, attempts, status.message());
+        sqlx::query!(
+            r#"
+            UPDATE scheduled_messages
+            SET attempts = $2,
+                deliver_at = NOW() AT TIME ZONE 'UTC' + make_interval(secs => $3)
+            WHERE id = $1
+            "#,
+            id,
+            attempts,
+            RETRY_DELAY_SECS * attempts as f64
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+    } else {
+        warn!("Scheduled message {} dropped: {}", id, status.message());
+        sqlx::query!(
+            r#"
+            UPDATE scheduled_messages
+            SET status = 'FAILED', attempts = $2, failure_reason = $3,
+                processed_at = NOW() AT TIME ZONE 'UTC'
+            WHERE id = $1
+            "#,
+            id,
+            attempts,
+            status.message()
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+    }
+    Ok(())
+}
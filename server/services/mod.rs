@@ -3,4 +3,21 @@ pub mod key_manager;
 pub mod relationships_service;
 pub mod seacrh_service;
 pub mod status_user_service;
-pub mod chat_service;
\ No newline at end of file
+pub mod chat_service;
+pub mod message_scheduler;
+pub mod notification_service;
+pub mod chat_export;
+pub mod prekey_service;
+pub mod key_service;
+pub mod key_expiry_monitor;
+pub mod key_log;
+pub mod hkp_server;
+pub mod key_backup;
+pub mod kms;
+pub mod kek_rotation;
+pub mod device_service;
+pub mod call_service;
+pub mod audio_relay;
+pub mod voice_room_service;
+pub mod call_history;
+pub mod turn_server;
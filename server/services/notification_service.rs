@@ -0,0 +1,346 @@
+use tonic::{Request, Response, Status};
+use sqlx::PgPool;
+use tokio::sync::broadcast;
+use std::pin::Pin;
+use futures_core::Stream;
+use uuid::Uuid;
+use tracing::error;
+
+mod notifications {
+    tonic::include_proto!("notifications");
+}
+
+pub use notifications::notification_service_server::{NotificationService, NotificationServiceServer};
+pub use notifications::NotificationKind;
+use notifications::{
+    Notification, SubscribeNotificationsRequest, ListNotificationsRequest, ListNotificationsResponse,
+    MarkNotificationsReadRequest, MarkNotificationsReadResponse,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+// Уведомление, создаваемое другими сервисами
+pub struct NewNotification {
+    pub user_id: Uuid,
+    pub kind: NotificationKind,
+    // None для системных уведомлений
+    pub actor_id: Option<Uuid>,
+    pub chat_id: Option<Uuid>,
+    pub message_id: Option<Uuid>,
+    pub device_id: Option<String>,
+}
+
+// Общая точка создания уведомлений: запись в ленту и рассылка подписчикам
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    db: PgPool,
+    tx: broadcast::Sender<Notification>,
+}
+
+impl Notifier {
+    pub fn new(db: PgPool) -> Self {
+        let (tx, _) = broadcast::channel(256);
+        Self { db, tx }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.tx.subscribe()
+    }
+
+    // Ошибки только логируются: уведомление не должно ломать основное действие
+    pub async fn notify_all(&self, notifications: Vec<NewNotification>) {
+        for notification in notifications {
+            if let Err(e) = self.notify(notification).await {
+                error!("Failed to create notification: {:?}", e);
+            }
+        }
+    }
+
+    pub async fn notify(&self, notification: NewNotification) -> Result<(), Status> {
+        if Some(notification.user_id) == notification.actor_id {
+            return Ok(());
+        }
+
+        let delivery = self.chat_delivery(&notification).await?;
+        if delivery == Delivery::Skip {
+            return Ok(());
+        }
+
+        let record = sqlx::query!(
+            r#"
+            INSERT INTO notifications (user_id, kind, actor_id, chat_id, message_id, device_id, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, NOW() AT TIME ZONE 'UTC')
+            RETURNING id, created_at
+            "#,
+            notification.user_id,
+            kind_to_str(notification.kind),
+            notification.actor_id,
+            notification.chat_id,
+            notification.message_id,
+            notification.device_id
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        // Заглушенный чат и режим "не беспокоить" оставляют уведомление в ленте без доставки в реальном времени
+        if delivery == Delivery::FeedOnly || self.is_do_not_disturb(notification.user_id).await? {
+            return Ok(());
+        }
+
+        let _ = self.tx.send(Notification {
+            id: record.id.to_string(),
+            user_id: notification.user_id.to_string(),
+            kind: notification.kind as i32,
+            actor_id: notification.actor_id.map(|id| id.to_string()).unwrap_or_default(),
+            chat_id: notification.chat_id.map(|id| id.to_string()).unwrap_or_default(),
+            message_id: notification.message_id.map(|id| id.to_string()).unwrap_or_default(),
+            created_at: Some(prost_types::Timestamp {
+                seconds: record.created_at.and_utc().timestamp(),
+                nanos: record.created_at.and_utc().timestamp_subsec_nanos() as i32,
+            }),
+            is_read: false,
+            device_id: notification.device_id.unwrap_or_default(),
+        });
+
+        Ok(())
+    }
+
+    // Учёт настроек участника чата для уведомлений о сообщениях
+    async fn chat_delivery(&self, notification: &NewNotification) -> Result<Delivery, Status> {
+        let Some(chat_id) = notification.chat_id else {
+            return Ok(Delivery::Push);
+        };
+
+        let is_message = matches!(
+            notification.kind,
+            NotificationKind::NotificationMention | NotificationKind::NotificationReply
+        );
+        if !is_message {
+            return Ok(Delivery::Push);
+        }
+
+        let preferences = sqlx::query!(
+            r#"
+            SELECT notification_level, muted_until > NOW() AT TIME ZONE 'UTC' AS "is_muted"
+            FROM direct_chats_members
+            WHERE chat_id = $1 AND user_id = $2
+            "#,
+            chat_id,
+            notification.user_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let Some(preferences) = preferences else {
+            return Ok(Delivery::Skip);
+        };
+
+        // Упоминания и ответы адресованы пользователю напрямую, поэтому уровень MENTIONS их пропускает
+        if preferences.notification_level == "NONE" {
+            return Ok(Delivery::Skip);
+        }
+        if preferences.is_muted.unwrap_or(false) {
+            return Ok(Delivery::FeedOnly);
+        }
+
+        Ok(Delivery::Push)
+    }
+
+    async fn is_do_not_disturb(&self, user_id: Uuid) -> Result<bool, Status> {
+        let status = sqlx::query_scalar!(
+            "SELECT status FROM users WHERE id = $1",
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .flatten();
+
+        Ok(status.as_deref() == Some("do_not_disturb"))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Delivery {
+    Push,
+    FeedOnly,
+    Skip,
+}
+
+fn kind_to_str(kind: NotificationKind) -> &'static str {
+    match kind {
+        NotificationKind::NotificationMention => "MENTION",
+        NotificationKind::NotificationReply => "REPLY",
+        NotificationKind::NotificationFriendRequest => "FRIEND_REQUEST",
+        NotificationKind::NotificationGroupInvite => "GROUP_INVITE",
+        NotificationKind::NotificationPrekeysLow => "PREKEYS_LOW",
+        NotificationKind::NotificationKeyChanged => "KEY_CHANGED",
+        NotificationKind::NotificationKeyExpiring => "KEY_EXPIRING",
+        NotificationKind::NotificationDeviceLinked => "DEVICE_LINKED",
+        NotificationKind::NotificationMissedCall => "MISSED_CALL",
+        NotificationKind::NotificationUnspecified => "UNSPECIFIED",
+    }
+}
+
+fn kind_from_str(kind: &str) -> NotificationKind {
+    match kind {
+        "MENTION" => NotificationKind::NotificationMention,
+        "REPLY" => NotificationKind::NotificationReply,
+        "FRIEND_REQUEST" => NotificationKind::NotificationFriendRequest,
+        "GROUP_INVITE" => NotificationKind::NotificationGroupInvite,
+        "PREKEYS_LOW" => NotificationKind::NotificationPrekeysLow,
+        "KEY_CHANGED" => NotificationKind::NotificationKeyChanged,
+        "KEY_EXPIRING" => NotificationKind::NotificationKeyExpiring,
+        "DEVICE_LINKED" => NotificationKind::NotificationDeviceLinked,
+        "MISSED_CALL" => NotificationKind::NotificationMissedCall,
+        _ => NotificationKind::NotificationUnspecified,
+    }
+}
+
+#[derive(Debug)]
+pub struct MyNotificationService {
+    db: PgPool,
+    notifier: Notifier,
+}
+
+impl MyNotificationService {
+    pub fn new(db: PgPool, notifier: Notifier) -> Self {
+        Self { db, notifier }
+    }
+}
+
+#[tonic::async_trait]
+impl NotificationService for MyNotificationService {
+    type SubscribeNotificationsStream =
+        Pin<Box<dyn Stream<Item = Result<Notification, Status>> + Send + Sync + 'static>>;
+
+    async fn subscribe_notifications(
+        &self,
+        request: Request<SubscribeNotificationsRequest>,
+    ) -> Result<Response<Self::SubscribeNotificationsStream>, Status> {
+        let user_id = Uuid::parse_str(&request.into_inner().user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?
+            .to_string();
+
+        let mut rx = self.notifier.subscribe();
+
+        let output_stream = async_stream::try_stream! {
+            loop {
+                match rx.recv().await {
+                    Ok(notification) if notification.user_id == user_id => yield notification,
+                    Ok(_) => continue,
+                    // Пропущенные при переполнении уведомления клиент получит через ListNotifications
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(output_stream)))
+    }
+
+    async fn list_notifications(
+        &self,
+        request: Request<ListNotificationsRequest>,
+    ) -> Result<Response<ListNotificationsResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let limit = match req.limit as i64 {
+            n if n <= 0 => DEFAULT_PAGE_SIZE,
+            n => n.min(MAX_PAGE_SIZE),
+        };
+        let offset = (req.offset as i64).max(0);
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, kind, actor_id, chat_id, message_id, device_id, created_at, read_at
+            FROM notifications
+            WHERE user_id = $1 AND (NOT $2 OR read_at IS NULL)
+            ORDER BY created_at DESC
+            LIMIT $3 OFFSET $4
+            "#,
+            user_id,
+            req.unread_only,
+            limit,
+            offset
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let unread_count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM notifications WHERE user_id = $1 AND read_at IS NULL"#,
+            user_id
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        let notifications = rows
+            .into_iter()
+            .map(|row| Notification {
+                id: row.id.to_string(),
+                user_id: user_id.to_string(),
+                kind: kind_from_str(&row.kind) as i32,
+                actor_id: row.actor_id.map(|id| id.to_string()).unwrap_or_default(),
+                chat_id: row.chat_id.map(|id| id.to_string()).unwrap_or_default(),
+                message_id: row.message_id.map(|id| id.to_string()).unwrap_or_default(),
+                created_at: Some(prost_types::Timestamp {
+                    seconds: row.created_at.and_utc().timestamp(),
+                    nanos: row.created_at.and_utc().timestamp_subsec_nanos() as i32,
+                }),
+                is_read: row.read_at.is_some(),
+                device_id: row.device_id.unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(Response::new(ListNotificationsResponse {
+            notifications,
+            unread_count: unread_count as i32,
+        }))
+    }
+
+    async fn mark_notifications_read(
+        &self,
+        request: Request<MarkNotificationsReadRequest>,
+    ) -> Result<Response<MarkNotificationsReadResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let mut ids = Vec::with_capacity(req.notification_ids.len());
+        for id in &req.notification_ids {
+            ids.push(Uuid::parse_str(id)
+                .map_err(|_| Status::invalid_argument(format!("Invalid notification UUID: {}", id)))?);
+        }
+
+        if !req.all && ids.is_empty() {
+            return Err(Status::invalid_argument("No notifications specified"));
+        }
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE notifications
+            SET read_at = NOW() AT TIME ZONE 'UTC'
+            WHERE user_id = $1 AND read_at IS NULL AND ($2 OR id = ANY($3))
+            "#,
+            user_id,
+            req.all,
+            &ids
+        )
+        .execute(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(Response::new(MarkNotificationsReadResponse {
+            updated: result.rows_affected() as i32,
+        }))
+    }
+}
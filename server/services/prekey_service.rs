@@ -0,0 +1,377 @@
+use tonic::{Request, Response, Status};
+use sqlx::PgPool;
+use uuid::Uuid;
+use ed25519_dalek::{Signature, VerifyingKey};
+
+use crate::services::device_service::touch_device;
+use crate::services::notification_service::{Notifier, NewNotification, NotificationKind};
+
+mod prekeys {
+    tonic::include_proto!("prekeys");
+}
+
+pub use prekeys::prekey_service_server::{PrekeyService, PrekeyServiceServer};
+use prekeys::{
+    UploadPrekeysRequest, UploadPrekeysResponse, FetchPrekeyBundleRequest, FetchPrekeyBundleResponse,
+    GetPrekeyCountRequest, GetPrekeyCountResponse, PrekeyBundle, SignedPrekey, OneTimePrekey,
+};
+
+const KEY_SIZE: usize = 32;
+const MAX_DEVICE_ID_LEN: usize = 128;
+const MAX_UPLOAD_BATCH: usize = 100;
+const MAX_STORED_PREKEYS: i64 = 500;
+// При достижении этого остатка владелец получает предупреждение
+const LOW_PREKEY_THRESHOLD: i64 = 10;
+
+#[derive(Debug)]
+pub struct MyPrekeyService {
+    db: PgPool,
+    notifier: Notifier,
+}
+
+impl MyPrekeyService {
+    pub fn new(db: PgPool, notifier: Notifier) -> Self {
+        Self { db, notifier }
+    }
+
+    async fn count_prekeys(&self, user_id: Uuid, device_id: &str) -> Result<i64, Status> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!" FROM one_time_prekeys
+            WHERE user_id = $1 AND device_id = $2
+            "#,
+            user_id,
+            device_id
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))
+    }
+}
+
+fn parse_key(bytes: &[u8], what: &str) -> Result<[u8; KEY_SIZE], String> {
+    bytes
+        .try_into()
+        .map_err(|_| format!("{} must be {} bytes", what, KEY_SIZE))
+}
+
+// Подписанный предключ должен быть подписан identity-ключом устройства
+fn verify_signed_prekey(identity_key: &[u8; KEY_SIZE], prekey: &SignedPrekey) -> Result<(), String> {
+    parse_key(&prekey.public_key, "Signed prekey")?;
+
+    let verifying_key = VerifyingKey::from_bytes(identity_key)
+        .map_err(|_| "Invalid Ed25519 identity key".to_string())?;
+    let signature = Signature::from_slice(&prekey.signature)
+        .map_err(|_| "Malformed signed prekey signature".to_string())?;
+
+    verifying_key
+        .verify_strict(&prekey.public_key, &signature)
+        .map_err(|_| "Signed prekey signature verification failed".to_string())
+}
+
+#[tonic::async_trait]
+impl PrekeyService for MyPrekeyService {
+    async fn upload_prekeys(
+        &self,
+        request: Request<UploadPrekeysRequest>,
+    ) -> Result<Response<UploadPrekeysResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        if req.device_id.is_empty() || req.device_id.len() > MAX_DEVICE_ID_LEN {
+            return Err(Status::invalid_argument("Invalid device_id"));
+        }
+        if req.one_time_prekeys.len() > MAX_UPLOAD_BATCH {
+            return Err(Status::invalid_argument(format!(
+                "Cannot upload more than {} one-time prekeys at once", MAX_UPLOAD_BATCH
+            )));
+        }
+
+        let identity_key = parse_key(&req.identity_key, "Identity key")
+            .map_err(Status::invalid_argument)?;
+        if let Some(signed_prekey) = &req.signed_prekey {
+            verify_signed_prekey(&identity_key, signed_prekey)
+                .map_err(Status::invalid_argument)?;
+        }
+        for prekey in &req.one_time_prekeys {
+            parse_key(&prekey.public_key, "One-time prekey")
+                .map_err(Status::invalid_argument)?;
+        }
+
+        let mut tx = self.db.begin().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        // Предключи публикуют только зарегистрированные устройства, подписывая их своим identity-ключом
+        let device_identity = sqlx::query_scalar!(
+            "SELECT identity_key FROM devices WHERE user_id = $1 AND device_id = $2",
+            user_id,
+            req.device_id
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .ok_or_else(|| Status::failed_precondition("Device is not registered"))?;
+
+        if device_identity != identity_key {
+            return Err(Status::invalid_argument("Identity key does not match the registered device"));
+        }
+
+        let stored_identity = sqlx::query_scalar!(
+            "SELECT identity_key FROM prekey_identities WHERE user_id = $1 AND device_id = $2 FOR UPDATE",
+            user_id,
+            req.device_id
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        match stored_identity {
+            Some(stored) if stored == identity_key => {}
+            // Новый identity-ключ делает недействительными все прежние предключи устройства
+            Some(_) => {
+                if req.signed_prekey.is_none() {
+                    return Err(Status::invalid_argument("A new identity key requires a new signed prekey"));
+                }
+                sqlx::query!(
+                    "DELETE FROM one_time_prekeys WHERE user_id = $1 AND device_id = $2",
+                    user_id,
+                    req.device_id
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+                sqlx::query!(
+                    r#"
+                    UPDATE prekey_identities
+                    SET identity_key = $3, updated_at = NOW() AT TIME ZONE 'UTC'
+                    WHERE user_id = $1 AND device_id = $2
+                    "#,
+                    user_id,
+                    req.device_id,
+                    &identity_key[..]
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+            }
+            None => {
+                if req.signed_prekey.is_none() {
+                    return Err(Status::invalid_argument("First upload must include a signed prekey"));
+                }
+                sqlx::query!(
+                    r#"
+                    INSERT INTO prekey_identities (user_id, device_id, identity_key, updated_at)
+                    VALUES ($1, $2, $3, NOW() AT TIME ZONE 'UTC')
+                    "#,
+                    user_id,
+                    req.device_id,
+                    &identity_key[..]
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+            }
+        }
+
+        if let Some(signed_prekey) = &req.signed_prekey {
+            sqlx::query!(
+                r#"
+                INSERT INTO signed_prekeys (user_id, device_id, key_id, public_key, signature, created_at)
+                VALUES ($1, $2, $3, $4, $5, NOW() AT TIME ZONE 'UTC')
+                ON CONFLICT (user_id, device_id) DO UPDATE
+                SET key_id = EXCLUDED.key_id,
+                    public_key = EXCLUDED.public_key,
+                    signature = EXCLUDED.signature,
+                    created_at = EXCLUDED.created_at
+                "#,
+                user_id,
+                req.device_id,
+                signed_prekey.key_id as i32,
+                signed_prekey.public_key,
+                signed_prekey.signature
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+        }
+
+        for prekey in &req.one_time_prekeys {
+            sqlx::query!(
+                r#"
+                INSERT INTO one_time_prekeys (user_id, device_id, key_id, public_key)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (user_id, device_id, key_id) DO NOTHING
+                "#,
+                user_id,
+                req.device_id,
+                prekey.key_id as i32,
+                prekey.public_key
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+        }
+
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!" FROM one_time_prekeys
+            WHERE user_id = $1 AND device_id = $2
+            "#,
+            user_id,
+            req.device_id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if count > MAX_STORED_PREKEYS {
+            return Err(Status::resource_exhausted(format!(
+                "A device may store at most {} one-time prekeys", MAX_STORED_PREKEYS
+            )));
+        }
+
+        // Запас восстановлен: следующее падение ниже порога снова вызовет предупреждение
+        if count >= LOW_PREKEY_THRESHOLD {
+            sqlx::query!(
+                "UPDATE prekey_identities SET low_warned_at = NULL WHERE user_id = $1 AND device_id = $2",
+                user_id,
+                req.device_id
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+        }
+
+        tx.commit().await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        touch_device(&self.db, user_id, &req.device_id).await?;
+
+        Ok(Response::new(UploadPrekeysResponse {
+            one_time_prekey_count: count as u32,
+        }))
+    }
+
+    async fn fetch_prekey_bundle(
+        &self,
+        request: Request<FetchPrekeyBundleRequest>,
+    ) -> Result<Response<FetchPrekeyBundleResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let devices = sqlx::query!(
+            r#"
+            SELECT pi.device_id, pi.identity_key, sp.key_id, sp.public_key, sp.signature
+            FROM prekey_identities pi
+            JOIN signed_prekeys sp ON sp.user_id = pi.user_id AND sp.device_id = pi.device_id
+            WHERE pi.user_id = $1
+            ORDER BY pi.device_id
+            "#,
+            user_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        if devices.is_empty() {
+            return Err(Status::not_found("User has not published any prekeys"));
+        }
+
+        let mut bundles = Vec::with_capacity(devices.len());
+        for device in devices {
+            // Удаление с SKIP LOCKED гарантирует, что предключ не будет выдан дважды
+            let one_time_prekey = sqlx::query!(
+                r#"
+                DELETE FROM one_time_prekeys
+                WHERE id = (
+                    SELECT id FROM one_time_prekeys
+                    WHERE user_id = $1 AND device_id = $2
+                    ORDER BY created_at
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING key_id, public_key
+                "#,
+                user_id,
+                device.device_id
+            )
+            .fetch_optional(&self.db)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+            // Отметка low_warned_at ставится одним UPDATE под блокировкой строки устройства,
+            // поэтому из конкурирующих запросов уведомление отправляет ровно один
+            let warn_low = match one_time_prekey {
+                Some(_) => sqlx::query_scalar!(
+                    r#"
+                    UPDATE prekey_identities
+                    SET low_warned_at = NOW() AT TIME ZONE 'UTC'
+                    WHERE user_id = $1 AND device_id = $2
+                      AND low_warned_at IS NULL
+                      AND (
+                          SELECT COUNT(*) FROM one_time_prekeys
+                          WHERE user_id = $1 AND device_id = $2
+                      ) < $3
+                    RETURNING device_id
+                    "#,
+                    user_id,
+                    device.device_id,
+                    LOW_PREKEY_THRESHOLD
+                )
+                .fetch_optional(&self.db)
+                .await
+                .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+                .is_some(),
+                None => false,
+            };
+            if warn_low {
+                self.notifier.notify_all(vec![NewNotification {
+                    user_id,
+                    kind: NotificationKind::NotificationPrekeysLow,
+                    actor_id: None,
+                    chat_id: None,
+                    message_id: None,
+                    device_id: Some(device.device_id.clone()),
+                }]).await;
+            }
+
+            bundles.push(PrekeyBundle {
+                user_id: user_id.to_string(),
+                device_id: device.device_id,
+                identity_key: device.identity_key,
+                signed_prekey: Some(SignedPrekey {
+                    key_id: device.key_id as u32,
+                    public_key: device.public_key,
+                    signature: device.signature,
+                }),
+                one_time_prekey: one_time_prekey.map(|prekey| OneTimePrekey {
+                    key_id: prekey.key_id as u32,
+                    public_key: prekey.public_key,
+                }),
+            });
+        }
+
+        Ok(Response::new(FetchPrekeyBundleResponse { bundles }))
+    }
+
+    async fn get_prekey_count(
+        &self,
+        request: Request<GetPrekeyCountRequest>,
+    ) -> Result<Response<GetPrekeyCountResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let count = self.count_prekeys(user_id, &req.device_id).await?;
+
+        Ok(Response::new(GetPrekeyCountResponse {
+            one_time_prekey_count: count as u32,
+        }))
+    }
+}
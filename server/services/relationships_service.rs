@@ -3,6 +3,8 @@ use sqlx::PgPool;
 use chrono::Utc;
 use uuid::Uuid;
 
+use crate::services::notification_service::{Notifier, NewNotification, NotificationKind};
+
 mod communication {
     tonic::include_proto!("communication"); 
 }
@@ -15,6 +17,7 @@ use communication::{UpdateRelationshipRequest, UpdateRelationshipResponse, Creat
 #[derive(Debug)]
 pub struct MyRelationshipService {
     db: PgPool,
+    notifier: Notifier,
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -28,8 +31,8 @@ struct RelationshipRow {
 }
 
 impl MyRelationshipService {
-    pub fn new(db: PgPool) -> Self {
-        Self { db }
+    pub fn new(db: PgPool, notifier: Notifier) -> Self {
+        Self { db, notifier }
     }
 }
 
@@ -314,6 +317,15 @@ impl RelationshipService for MyRelationshipService {
         .await
         .map_err(|e| Status::internal(format!("Failed to insert: {}", e)))?;
 
+        self.notifier.notify_all(vec![NewNotification {
+            user_id: to_user,
+            kind: NotificationKind::NotificationFriendRequest,
+            actor_id: Some(from_user),
+            chat_id: None,
+            message_id: None,
+            device_id: None,
+        }]).await;
+
         Ok(Response::new(CreateRelationshipResponse {
             status: 3,
             created_at: Some(prost_types::Timestamp {
@@ -0,0 +1,921 @@
+use std::collections::HashMap;
+use std::env;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use voicechat_pgp::stun::{self, Class, Message};
+
+// Встроенный STUN/TURN-сервер (RFC 5389, RFC 5766) для звонков из-за симметричного NAT.
+// Запрос Binding обслуживается без аутентификации. Выделения TURN - только UDP:
+// Allocate, Refresh, CreatePermission, ChannelBind, Send/Data и ChannelData.
+// Учётные данные выдаёт CallService.GetTurnCredentials по схеме TURN REST API:
+// username = "<unix-время истечения>:<user_id>", password = base64(HMAC-SHA1(TURN_SECRET, username)),
+// поэтому серверу не нужно хранить выданные пароли. Срок учётных данных проверяется при
+// создании выделения: начатый звонок продлевает выделение теми же учётными данными.
+// Пересылка во внутренние сети (частные, link-local, CGNAT, ULA) и на адреса самого сервера
+// запрещена; для локальной проверки нужные сети перечисляются в TURN_ALLOWED_PEERS.
+
+const TURN_ADDR_ENV: &str = "TURN_ADDR";
+const TURN_SECRET_ENV: &str = "TURN_SECRET";
+const TURN_REALM_ENV: &str = "TURN_REALM";
+// Адрес, на котором открываются relay-сокеты и который сообщается клиентам
+const TURN_RELAY_IP_ENV: &str = "TURN_RELAY_IP";
+// Разрешённые внутренние сети пиров через запятую, например "127.0.0.1/32,10.0.0.0/8"
+const TURN_ALLOWED_PEERS_ENV: &str = "TURN_ALLOWED_PEERS";
+const DEFAULT_REALM: &str = "nesfinch";
+const MIN_SECRET_LEN: usize = 16;
+
+const CREDENTIAL_TTL: Duration = Duration::from_secs(60 * 60);
+const NONCE_TTL: Duration = Duration::from_secs(10 * 60);
+// Время жизни выделения в секундах (RFC 5766, раздел 6.2)
+const DEFAULT_ALLOCATION_LIFETIME: u32 = 600;
+const MAX_ALLOCATION_LIFETIME: u32 = 3600;
+const PERMISSION_LIFETIME: Duration = Duration::from_secs(300);
+const CHANNEL_LIFETIME: Duration = Duration::from_secs(600);
+const MAX_ALLOCATIONS_PER_USER: usize = 10;
+const MAX_ALLOCATIONS: usize = 1000;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(30);
+const RECEIVE_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_DATAGRAM: usize = 65535;
+const FORBIDDEN: (u16, &str) = (403, "Forbidden");
+
+// Атрибуты запросов, которые сервер понимает; остальные обязательные к пониманию дают 420
+const KNOWN_ATTRIBUTES: &[u16] = &[
+    stun::ATTR_USERNAME,
+    stun::ATTR_MESSAGE_INTEGRITY,
+    stun::ATTR_CHANNEL_NUMBER,
+    stun::ATTR_LIFETIME,
+    stun::ATTR_XOR_PEER_ADDRESS,
+    stun::ATTR_DATA,
+    stun::ATTR_REALM,
+    stun::ATTR_NONCE,
+    stun::ATTR_REQUESTED_TRANSPORT,
+];
+
+#[derive(Debug, Clone)]
+pub struct TurnConfig {
+    listen_addr: SocketAddr,
+    relay_ip: IpAddr,
+    realm: String,
+    secret: Vec<u8>,
+    allowed_peers: Vec<PeerNetwork>,
+}
+
+// Сеть из TURN_ALLOWED_PEERS: адрес с длиной префикса или отдельный адрес
+#[derive(Debug, Clone, Copy)]
+struct PeerNetwork {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl PeerNetwork {
+    fn parse(entry: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid {} entry: {}", TURN_ALLOWED_PEERS_ENV, entry);
+        let (addr, prefix) = match entry.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (entry, None),
+        };
+        let network: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|p| *p <= max_prefix).ok_or_else(invalid)?,
+            None => max_prefix,
+        };
+        Ok(Self { network, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (u32::from(network) ^ u32::from(ip)).checked_shr(32 - self.prefix).unwrap_or(0) == 0
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                (u128::from(network) ^ u128::from(ip)).checked_shr(128 - self.prefix).unwrap_or(0) == 0
+            }
+            _ => false,
+        }
+    }
+}
+
+// Частные, локальные и служебные сети, недоступные из интернета
+fn is_internal(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                // 0.0.0.0/8
+                || a == 0
+                // 100.64.0.0/10 (CGNAT)
+                || (a == 100 && b & 0xC0 == 64)
+                // 240.0.0.0/4 и широковещательный адрес
+                || a >= 240
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // fc00::/7 (ULA)
+                || segments[0] & 0xFE00 == 0xFC00
+                // fe80::/10 (link-local) и устаревшие site-local fec0::/10
+                || segments[0] & 0xFFC0 == 0xFE80
+                || segments[0] & 0xFFC0 == 0xFEC0
+                // 64:ff9b::/96 (NAT64) с внутренним IPv4-адресом
+                || (segments[..6] == [0x64, 0xFF9B, 0, 0, 0, 0] && is_internal(IpAddr::V4(Ipv4Addr::from(
+                    (u32::from(segments[6]) << 16) | u32::from(segments[7]),
+                ))))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TurnCredentials {
+    pub username: String,
+    pub password: String,
+    pub expires_at: u64,
+    pub uris: Vec<String>,
+}
+
+impl TurnConfig {
+    pub fn from_env() -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let listen_addr: SocketAddr = match env::var(TURN_ADDR_ENV) {
+            Ok(addr) => addr.parse()?,
+            Err(env::VarError::NotPresent) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let secret = env::var(TURN_SECRET_ENV)
+            .map_err(|_| format!("{} is required when {} is set", TURN_SECRET_ENV, TURN_ADDR_ENV))?;
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(format!("{} must be at least {} characters", TURN_SECRET_ENV, MIN_SECRET_LEN).into());
+        }
+
+        let relay_ip = match env::var(TURN_RELAY_IP_ENV) {
+            Ok(ip) => ip.parse()?,
+            Err(env::VarError::NotPresent) if !listen_addr.ip().is_unspecified() => listen_addr.ip(),
+            Err(env::VarError::NotPresent) => {
+                return Err(format!("{} is required when {} is an unspecified address", TURN_RELAY_IP_ENV, TURN_ADDR_ENV).into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let realm = env::var(TURN_REALM_ENV).unwrap_or_else(|_| DEFAULT_REALM.to_string());
+
+        let allowed_peers = match env::var(TURN_ALLOWED_PEERS_ENV) {
+            Ok(list) => list
+                .split(',')
+                .filter(|entry| !entry.trim().is_empty())
+                .map(PeerNetwork::parse)
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(env::VarError::NotPresent) => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some(Self { listen_addr, relay_ip, realm, secret: secret.into_bytes(), allowed_peers }))
+    }
+
+    pub fn listen_addr(&self) -> SocketAddr {
+        self.listen_addr
+    }
+
+    pub fn mint_credentials(&self, user_id: Uuid) -> TurnCredentials {
+        let expires_at = unix_now() + CREDENTIAL_TTL.as_secs();
+        let username = format!("{}:{}", expires_at, user_id);
+        let public_addr = SocketAddr::new(self.relay_ip, self.listen_addr.port());
+        TurnCredentials {
+            password: self.password(&username),
+            username,
+            expires_at,
+            uris: vec![
+                format!("stun:{}", public_addr),
+                format!("turn:{}?transport=udp", public_addr),
+            ],
+        }
+    }
+
+    fn mac(&self, data: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha1>::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn password(&self, username: &str) -> String {
+        BASE64.encode(self.mac(username.as_bytes()))
+    }
+
+    // Nonce без состояния: время выдачи и его подпись
+    fn nonce(&self) -> String {
+        let issued_at = unix_now();
+        format!("{:x}-{}", issued_at, self.nonce_tag(issued_at))
+    }
+
+    fn nonce_tag(&self, issued_at: u64) -> String {
+        hex::encode(&self.mac(format!("nonce:{}", issued_at).as_bytes())[..8])
+    }
+
+    fn nonce_is_fresh(&self, nonce: &str) -> bool {
+        let Some((issued_at, tag)) = nonce.split_once('-') else {
+            return false;
+        };
+        let Ok(issued_at) = u64::from_str_radix(issued_at, 16) else {
+            return false;
+        };
+        unix_now() < issued_at.saturating_add(NONCE_TTL.as_secs())
+            && constant_time_eq::constant_time_eq(tag.as_bytes(), self.nonce_tag(issued_at).as_bytes())
+    }
+
+    // Можно ли пересылать данные пиру: Err - код и причина ошибки STUN. Адреса самого сервера
+    // доступны только как relay-сокеты выделений (оба участника звонка на одном сервере)
+    // или через TURN_ALLOWED_PEERS; порт, который слушает сервер, закрыт всегда.
+    fn check_peer(
+        &self,
+        allocations: &HashMap<SocketAddr, Allocation>,
+        peer: SocketAddr,
+    ) -> Result<(), (u16, &'static str)> {
+        if peer.is_ipv4() != self.relay_ip.is_ipv4() {
+            return Err((443, "Peer Address Family Mismatch"));
+        }
+        let ip = peer.ip().to_canonical();
+        if ip.is_unspecified()
+            || ip.is_multicast()
+            || peer.port() == 0
+            || matches!(ip, IpAddr::V4(ip) if ip.is_broadcast())
+        {
+            return Err(FORBIDDEN);
+        }
+
+        let listen_ip = self.listen_addr.ip().to_canonical();
+        let own_ip = ip == self.relay_ip.to_canonical()
+            || ip == listen_ip
+            || (listen_ip.is_unspecified() && is_internal(ip));
+        if own_ip && peer.port() == self.listen_addr.port() {
+            return Err(FORBIDDEN);
+        }
+
+        if self.allowed_peers.iter().any(|network| network.contains(ip)) {
+            return Ok(());
+        }
+        if own_ip {
+            let is_relay = allocations.values().any(|allocation| {
+                allocation.is_active()
+                    && allocation.relay_addr.ip().to_canonical() == ip
+                    && allocation.relay_addr.port() == peer.port()
+            });
+            return if is_relay { Ok(()) } else { Err(FORBIDDEN) };
+        }
+        if is_internal(ip) {
+            return Err(FORBIDDEN);
+        }
+        Ok(())
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// Проверенные учётные данные запроса
+struct Credentials {
+    username: String,
+    user_id: Uuid,
+    expires_at: u64,
+    key: [u8; 16],
+}
+
+struct Allocation {
+    user_id: Uuid,
+    username: String,
+    // Запрос Allocate, создавший выделение: его повтор получает тот же ответ
+    transaction_id: [u8; 12],
+    relay: Arc<UdpSocket>,
+    relay_addr: SocketAddr,
+    expires_at: Instant,
+    permissions: HashMap<IpAddr, Instant>,
+    channels: HashMap<u16, (SocketAddr, Instant)>,
+    relay_task: JoinHandle<()>,
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        self.relay_task.abort();
+    }
+}
+
+impl Allocation {
+    fn is_active(&self) -> bool {
+        self.expires_at > Instant::now()
+    }
+
+    fn has_permission(&self, ip: IpAddr) -> bool {
+        self.permissions.get(&ip).is_some_and(|expires_at| *expires_at > Instant::now())
+    }
+
+    fn channel_for_peer(&self, peer: SocketAddr) -> Option<u16> {
+        let now = Instant::now();
+        self.channels
+            .iter()
+            .find(|(_, (bound_peer, expires_at))| *bound_peer == peer && *expires_at > now)
+            .map(|(channel, _)| *channel)
+    }
+
+    fn peer_for_channel(&self, channel: u16) -> Option<SocketAddr> {
+        self.channels
+            .get(&channel)
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(peer, _)| *peer)
+    }
+
+    fn remaining_lifetime(&self) -> u32 {
+        self.expires_at.saturating_duration_since(Instant::now()).as_secs() as u32
+    }
+}
+
+struct TurnState {
+    config: TurnConfig,
+    socket: Arc<UdpSocket>,
+    // Выделения по адресу клиента (сервер слушает один сокет)
+    allocations: Mutex<HashMap<SocketAddr, Allocation>>,
+}
+
+pub struct TurnServer {
+    config: TurnConfig,
+}
+
+impl TurnServer {
+    pub fn new(config: TurnConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let addr = self.config.listen_addr;
+            match UdpSocket::bind(addr).await {
+                Ok(socket) => {
+                    info!("STUN/TURN server listening on {}", addr);
+                    serve(self.config, socket).await;
+                }
+                Err(e) => error!("STUN/TURN server failed to bind {}: {:?}", addr, e),
+            }
+        })
+    }
+}
+
+async fn serve(mut config: TurnConfig, socket: UdpSocket) {
+    // Порт 0 в TURN_ADDR заменяется фактическим, чтобы он тоже был закрыт для пиров
+    if let Ok(addr) = socket.local_addr() {
+        config.listen_addr = addr;
+    }
+    let state = Arc::new(TurnState {
+        config,
+        socket: Arc::new(socket),
+        allocations: Mutex::new(HashMap::new()),
+    });
+    tokio::spawn(expire_allocations(state.clone()));
+
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let (len, client) = match state.socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) if is_transient(&e) => continue,
+            Err(e) => {
+                warn!("STUN/TURN receive error: {:?}", e);
+                tokio::time::sleep(RECEIVE_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+        let packet = &buf[..len];
+
+        if stun::is_channel_data(packet) {
+            state.forward_channel_data(client, packet).await;
+        } else if let Some(message) = stun::decode(packet)
+            && let Some(response) = state.handle(client, &message, packet).await
+        {
+            let _ = state.socket.send_to(&response, client).await;
+        }
+    }
+}
+
+// Ошибки из ICMP-ответов на прошлые отправки; на сокет не влияют
+fn is_transient(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset | ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable)
+}
+
+impl TurnState {
+    fn lock(&self) -> MutexGuard<'_, HashMap<SocketAddr, Allocation>> {
+        self.allocations.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn handle(self: &Arc<Self>, client: SocketAddr, message: &Message, raw: &[u8]) -> Option<Vec<u8>> {
+        match message.class {
+            Class::Request => {
+                let unknown: Vec<u16> = message
+                    .attributes
+                    .iter()
+                    .map(|(attribute, _)| *attribute)
+                    .filter(|attribute| *attribute < 0x8000 && !KNOWN_ATTRIBUTES.contains(attribute))
+                    .collect();
+                if !unknown.is_empty() {
+                    let mut response = message.error_response(420, "Unknown Attribute");
+                    response.add(stun::ATTR_UNKNOWN_ATTRIBUTES, unknown.iter().flat_map(|a| a.to_be_bytes()).collect());
+                    return Some(response.encode(None));
+                }
+
+                if message.method == stun::METHOD_BINDING {
+                    let mut response = message.response(Class::Success);
+                    response.add(stun::ATTR_XOR_MAPPED_ADDRESS, stun::encode_xor_address(client, &message.transaction_id));
+                    return Some(response.encode(None));
+                }
+                Some(self.authenticated_request(client, message, raw).await)
+            }
+            Class::Indication if message.method == stun::METHOD_SEND => {
+                self.send_indication(client, message).await;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    async fn authenticated_request(self: &Arc<Self>, client: SocketAddr, message: &Message, raw: &[u8]) -> Vec<u8> {
+        let credentials = match self.authenticate(message, raw) {
+            Ok(credentials) => credentials,
+            Err(response) => return response,
+        };
+
+        let response = match message.method {
+            stun::METHOD_ALLOCATE if credentials.expires_at <= unix_now() => {
+                return self.challenge(message, 401, "Unauthorized");
+            }
+            stun::METHOD_ALLOCATE => self.allocate(client, message, &credentials).await,
+            stun::METHOD_REFRESH => self.refresh(client, message, &credentials),
+            stun::METHOD_CREATE_PERMISSION => self.create_permission(client, message, &credentials),
+            stun::METHOD_CHANNEL_BIND => self.channel_bind(client, message, &credentials),
+            _ => message.error_response(400, "Bad Request"),
+        };
+        response.encode(Some(&credentials.key))
+    }
+
+    // Долговременные учётные данные (RFC 5389, раздел 10.2); ошибки отдаются без MESSAGE-INTEGRITY
+    fn authenticate(&self, message: &Message, raw: &[u8]) -> Result<Credentials, Vec<u8>> {
+        if !message.has_integrity() {
+            return Err(self.challenge(message, 401, "Unauthorized"));
+        }
+        let (Some(username), Some(realm), Some(nonce)) = (
+            message.string_attribute(stun::ATTR_USERNAME),
+            message.string_attribute(stun::ATTR_REALM),
+            message.string_attribute(stun::ATTR_NONCE),
+        ) else {
+            return Err(message.error_response(400, "Bad Request").encode(None));
+        };
+
+        if realm != self.config.realm {
+            return Err(self.challenge(message, 401, "Unauthorized"));
+        }
+        if !self.config.nonce_is_fresh(nonce) {
+            return Err(self.challenge(message, 438, "Stale Nonce"));
+        }
+
+        let parsed = username.split_once(':').and_then(|(expires_at, user_id)| {
+            Some((expires_at.parse::<u64>().ok()?, Uuid::parse_str(user_id).ok()?))
+        });
+        let Some((expires_at, user_id)) = parsed else {
+            return Err(self.challenge(message, 401, "Unauthorized"));
+        };
+
+        let key = stun::long_term_key(username, &self.config.realm, &self.config.password(username));
+        if !message.verify_integrity(raw, &key) {
+            return Err(self.challenge(message, 401, "Unauthorized"));
+        }
+
+        Ok(Credentials { username: username.to_string(), user_id, expires_at, key })
+    }
+
+    fn challenge(&self, message: &Message, code: u16, reason: &str) -> Vec<u8> {
+        let mut response = message.error_response(code, reason);
+        response.add(stun::ATTR_REALM, self.config.realm.as_bytes().to_vec());
+        response.add(stun::ATTR_NONCE, self.config.nonce().into_bytes());
+        response.encode(None)
+    }
+
+    fn peer_error(&self, allocations: &HashMap<SocketAddr, Allocation>, message: &Message, peer: SocketAddr) -> Option<Message> {
+        self.config
+            .check_peer(allocations, peer)
+            .err()
+            .map(|(code, reason)| message.error_response(code, reason))
+    }
+
+    async fn allocate(self: &Arc<Self>, client: SocketAddr, message: &Message, credentials: &Credentials) -> Message {
+        {
+            let allocations = self.lock();
+            if let Some(existing) = allocations.get(&client).filter(|a| a.is_active()) {
+                if existing.transaction_id == message.transaction_id && existing.username == credentials.username {
+                    return allocate_success(message, client, existing.relay_addr, existing.remaining_lifetime());
+                }
+                return message.error_response(437, "Allocation Mismatch");
+            }
+
+            let Some(transport) = message.attribute(stun::ATTR_REQUESTED_TRANSPORT) else {
+                return message.error_response(400, "Bad Request");
+            };
+            if transport.first() != Some(&stun::TRANSPORT_UDP) {
+                return message.error_response(442, "Unsupported Transport Protocol");
+            }
+
+            let active: Vec<&Allocation> = allocations.values().filter(|a| a.is_active()).collect();
+            if active.len() >= MAX_ALLOCATIONS
+                || active.iter().filter(|a| a.user_id == credentials.user_id).count() >= MAX_ALLOCATIONS_PER_USER
+            {
+                return message.error_response(486, "Allocation Quota Reached");
+            }
+        }
+
+        let relay = match UdpSocket::bind(SocketAddr::new(self.config.relay_ip, 0)).await {
+            Ok(relay) => Arc::new(relay),
+            Err(e) => {
+                warn!("Failed to open TURN relay socket: {:?}", e);
+                return message.error_response(508, "Insufficient Capacity");
+            }
+        };
+        let Ok(relay_addr) = relay.local_addr() else {
+            return message.error_response(508, "Insufficient Capacity");
+        };
+
+        let lifetime = message
+            .u32_attribute(stun::ATTR_LIFETIME)
+            .map_or(DEFAULT_ALLOCATION_LIFETIME, |l| l.clamp(DEFAULT_ALLOCATION_LIFETIME, MAX_ALLOCATION_LIFETIME));
+        let relay_task = tokio::spawn(relay_from_peers(self.clone(), relay.clone(), client));
+
+        self.lock().insert(client, Allocation {
+            user_id: credentials.user_id,
+            username: credentials.username.clone(),
+            transaction_id: message.transaction_id,
+            relay,
+            relay_addr,
+            expires_at: Instant::now() + Duration::from_secs(lifetime.into()),
+            permissions: HashMap::new(),
+            channels: HashMap::new(),
+            relay_task,
+        });
+        info!("TURN allocation {} for user {} from {}", relay_addr, credentials.user_id, client);
+
+        allocate_success(message, client, relay_addr, lifetime)
+    }
+
+    fn refresh(&self, client: SocketAddr, message: &Message, credentials: &Credentials) -> Message {
+        let mut allocations = self.lock();
+        let allocation = match owned_allocation(&mut allocations, client, message, credentials) {
+            Ok(allocation) => allocation,
+            Err(response) => return response,
+        };
+
+        let lifetime = match message.u32_attribute(stun::ATTR_LIFETIME) {
+            Some(0) => {
+                allocations.remove(&client);
+                0
+            }
+            requested => {
+                let lifetime = requested.map_or(DEFAULT_ALLOCATION_LIFETIME, |l| l.clamp(DEFAULT_ALLOCATION_LIFETIME, MAX_ALLOCATION_LIFETIME));
+                allocation.expires_at = Instant::now() + Duration::from_secs(lifetime.into());
+                lifetime
+            }
+        };
+
+        let mut response = message.response(Class::Success);
+        response.add(stun::ATTR_LIFETIME, stun::encode_u32(lifetime));
+        response
+    }
+
+    fn create_permission(&self, client: SocketAddr, message: &Message, credentials: &Credentials) -> Message {
+        let peers: Option<Vec<SocketAddr>> = message
+            .attributes_of(stun::ATTR_XOR_PEER_ADDRESS)
+            .map(|value| stun::decode_xor_address(value, &message.transaction_id))
+            .collect();
+        let Some(peers) = peers.filter(|peers| !peers.is_empty()) else {
+            return message.error_response(400, "Bad Request");
+        };
+
+        let mut allocations = self.lock();
+        if let Some(error) = peers.iter().find_map(|peer| self.peer_error(&allocations, message, *peer)) {
+            return error;
+        }
+        let allocation = match owned_allocation(&mut allocations, client, message, credentials) {
+            Ok(allocation) => allocation,
+            Err(response) => return response,
+        };
+        let expires_at = Instant::now() + PERMISSION_LIFETIME;
+        for peer in peers {
+            allocation.permissions.insert(peer.ip(), expires_at);
+        }
+        message.response(Class::Success)
+    }
+
+    fn channel_bind(&self, client: SocketAddr, message: &Message, credentials: &Credentials) -> Message {
+        let channel = message
+            .attribute(stun::ATTR_CHANNEL_NUMBER)
+            .filter(|value| value.len() == 4)
+            .map(|value| u16::from_be_bytes([value[0], value[1]]))
+            .filter(|channel| (stun::MIN_CHANNEL..=stun::MAX_CHANNEL).contains(channel));
+        let (Some(channel), Some(peer)) = (channel, message.xor_address(stun::ATTR_XOR_PEER_ADDRESS)) else {
+            return message.error_response(400, "Bad Request");
+        };
+
+        let mut allocations = self.lock();
+        if let Some(error) = self.peer_error(&allocations, message, peer) {
+            return error;
+        }
+        let allocation = match owned_allocation(&mut allocations, client, message, credentials) {
+            Ok(allocation) => allocation,
+            Err(response) => return response,
+        };
+        // Канал нельзя перепривязать к другому адресу, а адрес - к другому каналу
+        let conflict = allocation
+            .channels
+            .iter()
+            .any(|(bound_channel, (bound_peer, _))| (*bound_channel == channel) != (*bound_peer == peer));
+        if conflict {
+            return message.error_response(400, "Bad Request");
+        }
+
+        let now = Instant::now();
+        allocation.channels.insert(channel, (peer, now + CHANNEL_LIFETIME));
+        allocation.permissions.insert(peer.ip(), now + PERMISSION_LIFETIME);
+        message.response(Class::Success)
+    }
+
+    async fn send_indication(&self, client: SocketAddr, message: &Message) {
+        let (Some(peer), Some(data)) = (message.xor_address(stun::ATTR_XOR_PEER_ADDRESS), message.attribute(stun::ATTR_DATA)) else {
+            return;
+        };
+        let relay = {
+            let allocations = self.lock();
+            // Разрешение выдаётся на IP-адрес, поэтому порт проверяется при каждой отправке
+            if self.config.check_peer(&allocations, peer).is_err() {
+                return;
+            }
+            match allocations.get(&client) {
+                Some(allocation) if allocation.is_active() && allocation.has_permission(peer.ip()) => allocation.relay.clone(),
+                _ => return,
+            }
+        };
+        let _ = relay.send_to(data, peer).await;
+    }
+
+    async fn forward_channel_data(&self, client: SocketAddr, packet: &[u8]) {
+        let Some((channel, data)) = stun::decode_channel_data(packet) else {
+            return;
+        };
+        let (relay, peer) = {
+            let allocations = self.lock();
+            let Some(allocation) = allocations.get(&client).filter(|a| a.is_active()) else {
+                return;
+            };
+            match allocation.peer_for_channel(channel) {
+                Some(peer) if allocation.has_permission(peer.ip()) && self.config.check_peer(&allocations, peer).is_ok() => {
+                    (allocation.relay.clone(), peer)
+                }
+                _ => return,
+            }
+        };
+        let _ = relay.send_to(data, peer).await;
+    }
+}
+
+// Выделение клиента, созданное теми же учётными данными
+fn owned_allocation<'a>(
+    allocations: &'a mut HashMap<SocketAddr, Allocation>,
+    client: SocketAddr,
+    message: &Message,
+    credentials: &Credentials,
+) -> Result<&'a mut Allocation, Message> {
+    match allocations.get_mut(&client) {
+        Some(allocation) if allocation.is_active() && allocation.username == credentials.username => Ok(allocation),
+        Some(allocation) if allocation.is_active() => Err(message.error_response(441, "Wrong Credentials")),
+        _ => Err(message.error_response(437, "Allocation Mismatch")),
+    }
+}
+
+fn allocate_success(message: &Message, client: SocketAddr, relay_addr: SocketAddr, lifetime: u32) -> Message {
+    let mut response = message.response(Class::Success);
+    response.add(stun::ATTR_XOR_RELAYED_ADDRESS, stun::encode_xor_address(relay_addr, &message.transaction_id));
+    response.add(stun::ATTR_LIFETIME, stun::encode_u32(lifetime));
+    response.add(stun::ATTR_XOR_MAPPED_ADDRESS, stun::encode_xor_address(client, &message.transaction_id));
+    response
+}
+
+// Пакеты от пиров с разрешением: по привязанному каналу - ChannelData, иначе Data-индикация
+async fn relay_from_peers(state: Arc<TurnState>, relay: Arc<UdpSocket>, client: SocketAddr) {
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let (len, peer) = match relay.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) if is_transient(&e) => continue,
+            Err(e) => {
+                // Сокет выделения больше не принимает данные; выделение истечёт само
+                warn!("TURN relay {:?} receive error: {:?}", relay.local_addr(), e);
+                break;
+            }
+        };
+        let packet = {
+            let allocations = state.lock();
+            let Some(allocation) = allocations.get(&client).filter(|a| a.is_active()) else {
+                continue;
+            };
+            if !allocation.has_permission(peer.ip()) {
+                continue;
+            }
+            match allocation.channel_for_peer(peer) {
+                Some(channel) => stun::encode_channel_data(channel, &buf[..len]),
+                None => {
+                    let mut indication = Message::new(stun::METHOD_DATA, Class::Indication, rand::random());
+                    indication.add(stun::ATTR_XOR_PEER_ADDRESS, stun::encode_xor_address(peer, &indication.transaction_id));
+                    indication.add(stun::ATTR_DATA, buf[..len].to_vec());
+                    indication.encode(None)
+                }
+            }
+        };
+        let _ = state.socket.send_to(&packet, client).await;
+    }
+}
+
+// Удаление истёкших выделений, разрешений и каналов
+async fn expire_allocations(state: Arc<TurnState>) {
+    let mut ticker = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let now = Instant::now();
+        let mut allocations = state.lock();
+        allocations.retain(|_, allocation| allocation.expires_at > now);
+        for allocation in allocations.values_mut() {
+            allocation.permissions.retain(|_, expires_at| *expires_at > now);
+            allocation.channels.retain(|_, (_, expires_at)| *expires_at > now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(relay_ip: &str, allowed_peers: &[&str]) -> TurnConfig {
+        let relay_ip: IpAddr = relay_ip.parse().unwrap();
+        TurnConfig {
+            listen_addr: SocketAddr::new(relay_ip, 3478),
+            relay_ip,
+            realm: "test".to_string(),
+            secret: b"0123456789abcdef".to_vec(),
+            allowed_peers: allowed_peers.iter().map(|entry| PeerNetwork::parse(entry).unwrap()).collect(),
+        }
+    }
+
+    fn check(config: &TurnConfig, peer: &str) -> Result<(), u16> {
+        config.check_peer(&HashMap::new(), peer.parse().unwrap()).map_err(|(code, _)| code)
+    }
+
+    #[test]
+    fn peer_network_contains() {
+        let network = PeerNetwork::parse("10.1.0.0/16").unwrap();
+        assert!(network.contains("10.1.200.3".parse().unwrap()));
+        assert!(!network.contains("10.2.0.1".parse().unwrap()));
+        assert!(PeerNetwork::parse("0.0.0.0/0").unwrap().contains("192.0.2.1".parse().unwrap()));
+        assert!(PeerNetwork::parse("::1").unwrap().contains("::1".parse().unwrap()));
+        assert!(!PeerNetwork::parse("fd00::/8").unwrap().contains("10.0.0.1".parse().unwrap()));
+        assert!(PeerNetwork::parse("10.0.0.0/33").is_err());
+        assert!(PeerNetwork::parse("not-an-ip").is_err());
+    }
+
+    #[test]
+    fn internal_peers_are_denied() {
+        let config = test_config("203.0.113.10", &[]);
+        assert_eq!(check(&config, "198.51.100.20:5000"), Ok(()));
+        for peer in [
+            "10.0.0.1:5000",
+            "172.16.5.4:5000",
+            "192.168.1.1:5000",
+            "169.254.169.254:80",
+            "100.64.0.1:5000",
+            "127.0.0.1:5000",
+            "0.1.2.3:5000",
+            "255.255.255.255:5000",
+            "224.0.0.1:5000",
+            "198.51.100.20:0",
+        ] {
+            assert_eq!(check(&config, peer), Err(403), "{}", peer);
+        }
+        assert_eq!(check(&config, "[2001:db8::1]:5000"), Err(443));
+
+        let config = test_config("2001:db8::10", &[]);
+        assert_eq!(check(&config, "[2001:db8::20]:5000"), Ok(()));
+        for peer in ["[fd00::1]:5000", "[fe80::1]:5000", "[::1]:5000", "[::ffff:10.0.0.1]:5000", "[64:ff9b::a9fe:a9fe]:80"] {
+            assert_eq!(check(&config, peer), Err(403), "{}", peer);
+        }
+    }
+
+    #[test]
+    fn own_addresses_are_denied() {
+        let config = test_config("203.0.113.10", &[]);
+        assert_eq!(check(&config, "203.0.113.10:3478"), Err(403));
+        // Другие порты сервера - только relay-сокеты выделений
+        assert_eq!(check(&config, "203.0.113.10:50051"), Err(403));
+
+        // Разрешённые сети открывают внутренние адреса, но не порт самого сервера
+        let config = test_config("127.0.0.1", &["127.0.0.0/8", "10.0.0.0/8"]);
+        assert_eq!(check(&config, "127.0.0.1:5000"), Ok(()));
+        assert_eq!(check(&config, "10.20.30.40:5000"), Ok(()));
+        assert_eq!(check(&config, "127.0.0.1:3478"), Err(403));
+        assert_eq!(check(&config, "192.168.0.1:5000"), Err(403));
+    }
+
+    #[test]
+    fn nonce_expires_and_is_signed() {
+        let config = test_config("203.0.113.10", &[]);
+        assert!(config.nonce_is_fresh(&config.nonce()));
+
+        let stale = unix_now() - NONCE_TTL.as_secs() - 1;
+        assert!(!config.nonce_is_fresh(&format!("{:x}-{}", stale, config.nonce_tag(stale))));
+        let forged = format!("{:x}-{}", unix_now(), "00".repeat(8));
+        assert!(!config.nonce_is_fresh(&forged));
+    }
+
+    async fn exchange(socket: &UdpSocket, server: SocketAddr, request: &Message, key: Option<&[u8]>) -> (Message, Vec<u8>) {
+        socket.send_to(&request.encode(key), server).await.unwrap();
+        let mut buf = vec![0u8; 1500];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), socket.recv_from(&mut buf)).await.unwrap().unwrap();
+        (stun::decode(&buf[..len]).unwrap(), buf[..len].to_vec())
+    }
+
+    fn authenticated(method: u16, credentials: &TurnCredentials, realm: &str, nonce: &str) -> Message {
+        let mut request = Message::new(method, Class::Request, rand::random());
+        request.add(stun::ATTR_USERNAME, credentials.username.as_bytes().to_vec());
+        request.add(stun::ATTR_REALM, realm.as_bytes().to_vec());
+        request.add(stun::ATTR_NONCE, nonce.as_bytes().to_vec());
+        request
+    }
+
+    #[tokio::test]
+    async fn loopback_allocate_permission_send() {
+        let config = test_config("127.0.0.1", &["127.0.0.1/32"]);
+        let credentials = config.mint_credentials(Uuid::nil());
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = socket.local_addr().unwrap();
+        tokio::spawn(serve(config, socket));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+
+        // Первый Allocate без учётных данных получает REALM и NONCE
+        let mut allocate = Message::new(stun::METHOD_ALLOCATE, Class::Request, rand::random());
+        allocate.add(stun::ATTR_REQUESTED_TRANSPORT, vec![stun::TRANSPORT_UDP, 0, 0, 0]);
+        let (challenge, _) = exchange(&client, server, &allocate, None).await;
+        assert_eq!(challenge.error_code(), Some(401));
+        let realm = challenge.string_attribute(stun::ATTR_REALM).unwrap().to_string();
+        let nonce = challenge.string_attribute(stun::ATTR_NONCE).unwrap().to_string();
+        let key = stun::long_term_key(&credentials.username, &realm, &credentials.password);
+
+        let mut allocate = authenticated(stun::METHOD_ALLOCATE, &credentials, &realm, &nonce);
+        allocate.add(stun::ATTR_REQUESTED_TRANSPORT, vec![stun::TRANSPORT_UDP, 0, 0, 0]);
+        let (response, raw) = exchange(&client, server, &allocate, Some(&key)).await;
+        assert_eq!(response.class, Class::Success);
+        assert!(response.verify_integrity(&raw, &key));
+        assert_eq!(response.u32_attribute(stun::ATTR_LIFETIME), Some(DEFAULT_ALLOCATION_LIFETIME));
+        let relay_addr = response.xor_address(stun::ATTR_XOR_RELAYED_ADDRESS).unwrap();
+
+        // Порт самого сервера закрыт даже в разрешённой сети
+        let mut permission = authenticated(stun::METHOD_CREATE_PERMISSION, &credentials, &realm, &nonce);
+        permission.add(stun::ATTR_XOR_PEER_ADDRESS, stun::encode_xor_address(server, &permission.transaction_id));
+        let (response, _) = exchange(&client, server, &permission, Some(&key)).await;
+        assert_eq!(response.error_code(), Some(403));
+
+        let mut permission = authenticated(stun::METHOD_CREATE_PERMISSION, &credentials, &realm, &nonce);
+        permission.add(stun::ATTR_XOR_PEER_ADDRESS, stun::encode_xor_address(peer_addr, &permission.transaction_id));
+        let (response, _) = exchange(&client, server, &permission, Some(&key)).await;
+        assert_eq!(response.class, Class::Success);
+
+        let mut send = Message::new(stun::METHOD_SEND, Class::Indication, rand::random());
+        send.add(stun::ATTR_XOR_PEER_ADDRESS, stun::encode_xor_address(peer_addr, &send.transaction_id));
+        send.add(stun::ATTR_DATA, b"to peer".to_vec());
+        client.send_to(&send.encode(None), server).await.unwrap();
+
+        let mut buf = vec![0u8; 1500];
+        let (len, from) = tokio::time::timeout(Duration::from_secs(2), peer.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..len], b"to peer");
+        assert_eq!(from, relay_addr);
+
+        // Ответ пира приходит клиенту Data-индикацией
+        peer.send_to(b"to client", relay_addr).await.unwrap();
+        let (len, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf)).await.unwrap().unwrap();
+        let data = stun::decode(&buf[..len]).unwrap();
+        assert_eq!((data.method, data.class), (stun::METHOD_DATA, Class::Indication));
+        assert_eq!(data.xor_address(stun::ATTR_XOR_PEER_ADDRESS), Some(peer_addr));
+        assert_eq!(data.attribute(stun::ATTR_DATA), Some(&b"to client"[..]));
+    }
+}
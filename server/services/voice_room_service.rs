@@ -0,0 +1,694 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use chrono::{NaiveDateTime, Utc};
+use futures_core::Stream;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status, Streaming};
+use uuid::Uuid;
+
+use crate::services::audio_relay::{FrameQueue, SequenceFilter, MAX_FRAME_PAYLOAD};
+use crate::services::call_history::{CallRecorder, FinishedCall, OUTCOME_COMPLETED};
+use crate::services::chat_service::timestamp_from_naive;
+
+mod voice_rooms {
+    tonic::include_proto!("voice_rooms");
+}
+
+pub use voice_rooms::voice_room_service_server::{VoiceRoomService, VoiceRoomServiceServer};
+use voice_rooms::{
+    Room, Participant, RoomEvent, RoomEventKind, RoomAudioFrame, CreateRoomRequest, CreateRoomResponse,
+    ListRoomsRequest, ListRoomsResponse, DeleteRoomRequest, DeleteRoomResponse, JoinRoomRequest,
+    LeaveRoomRequest, LeaveRoomResponse, ListParticipantsRequest, ListParticipantsResponse,
+    UpdateParticipantStateRequest, UpdateParticipantStateResponse,
+};
+
+const DEFAULT_MAX_PARTICIPANTS: i32 = 25;
+const MAX_PARTICIPANTS: i32 = 50;
+const MAX_ROOM_NAME_LEN: usize = 64;
+const MAX_ROOMS_PER_CHAT: i64 = 20;
+
+type EventSender = mpsc::UnboundedSender<Result<RoomEvent, Status>>;
+type AudioQueue = Arc<FrameQueue<RoomAudioFrame>>;
+
+// Участие пользователя в комнате, привязанное к одному потоку JoinRoom
+#[derive(Debug)]
+struct Session {
+    session_id: u64,
+    muted: bool,
+    deafened: bool,
+    speaking: bool,
+    joined_at: NaiveDateTime,
+    events: EventSender,
+    audio: Option<AudioQueue>,
+}
+
+impl Session {
+    fn participant(&self, user_id: Uuid) -> Participant {
+        Participant {
+            user_id: user_id.to_string(),
+            muted: self.muted,
+            deafened: self.deafened,
+            speaking: self.speaking,
+            joined_at: Some(timestamp_from_naive(self.joined_at)),
+        }
+    }
+
+    fn close(&self) {
+        if let Some(queue) = &self.audio {
+            queue.close();
+        }
+    }
+}
+
+fn room_event(kind: RoomEventKind, room_id: Uuid, participants: Vec<Participant>) -> RoomEvent {
+    RoomEvent {
+        kind: kind as i32,
+        room_id: room_id.to_string(),
+        participants,
+    }
+}
+
+// Сеанс комнаты: от входа первого участника до выхода последнего
+#[derive(Debug)]
+struct ActiveRoom {
+    chat_id: Uuid,
+    started_by: Uuid,
+    started_at: NaiveDateTime,
+    // user_id -> участие
+    sessions: HashMap<Uuid, Session>,
+    // Завершённые участия (user_id, joined_at, left_at) для истории звонков
+    attendance: Vec<(Uuid, NaiveDateTime, NaiveDateTime)>,
+}
+
+#[derive(Debug)]
+struct Rooms {
+    recorder: CallRecorder,
+    next_session_id: u64,
+    rooms: HashMap<Uuid, ActiveRoom>,
+}
+
+impl Rooms {
+    fn participants(&self, room_id: Uuid) -> Vec<Participant> {
+        let mut participants: Vec<Participant> = self
+            .rooms
+            .get(&room_id)
+            .into_iter()
+            .flat_map(|room| room.sessions.iter().map(|(user_id, session)| session.participant(*user_id)))
+            .collect();
+        participants.sort_by_key(|participant| participant.joined_at.as_ref().map(|t| (t.seconds, t.nanos)));
+        participants
+    }
+
+    fn broadcast(&self, room_id: Uuid, event: RoomEvent) {
+        for session in self.rooms.get(&room_id).into_iter().flat_map(|room| room.sessions.values()) {
+            let _ = session.events.send(Ok(event.clone()));
+        }
+    }
+
+    // session_id: None - выход по запросу, иначе только если участие не было заменено
+    fn leave(&mut self, room_id: Uuid, user_id: Uuid, session_id: Option<u64>) -> bool {
+        let Some(room) = self.rooms.get_mut(&room_id) else {
+            return false;
+        };
+        let matches = room
+            .sessions
+            .get(&user_id)
+            .is_some_and(|session| session_id.is_none_or(|id| id == session.session_id));
+        if !matches {
+            return false;
+        }
+
+        if let Some(session) = room.sessions.remove(&user_id) {
+            session.close();
+            room.attendance.push((user_id, session.joined_at, Utc::now().naive_utc()));
+            let left = Participant { user_id: user_id.to_string(), ..Default::default() };
+            self.broadcast(room_id, room_event(RoomEventKind::RoomEventParticipantLeft, room_id, vec![left]));
+        }
+        if self.rooms.get(&room_id).is_some_and(|room| room.sessions.is_empty()) {
+            self.end_room(room_id);
+        }
+        true
+    }
+
+    // Сеанс завершается: оставшиеся участия закрываются, сеанс попадает в историю звонков
+    fn end_room(&mut self, room_id: Uuid) {
+        let Some(mut room) = self.rooms.remove(&room_id) else {
+            return;
+        };
+
+        let ended_at = Utc::now().naive_utc();
+        for (user_id, session) in &room.sessions {
+            session.close();
+            room.attendance.push((*user_id, session.joined_at, ended_at));
+        }
+
+        self.recorder.record(FinishedCall {
+            chat_id: room.chat_id,
+            caller_id: room.started_by,
+            callee_id: None,
+            room_id: Some(room_id),
+            started_at: room.started_at,
+            answered_at: None,
+            ended_at,
+            outcome: OUTCOME_COMPLETED,
+            end_reason: "HANGUP",
+            participants: room.attendance,
+        });
+    }
+}
+
+// Участники комнат в памяти сервера
+#[derive(Debug, Clone)]
+pub struct RoomHub {
+    inner: Arc<Mutex<Rooms>>,
+}
+
+impl RoomHub {
+    pub fn new(recorder: CallRecorder) -> Self {
+        let rooms = Rooms {
+            recorder,
+            next_session_id: 0,
+            rooms: HashMap::new(),
+        };
+        Self { inner: Arc::new(Mutex::new(rooms)) }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Rooms> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn participant_count(&self, room_id: Uuid) -> i32 {
+        self.lock().rooms.get(&room_id).map_or(0, |room| room.sessions.len() as i32)
+    }
+
+    fn participants(&self, room_id: Uuid) -> Vec<Participant> {
+        self.lock().participants(room_id)
+    }
+
+    fn join(
+        &self,
+        room_id: Uuid,
+        chat_id: Uuid,
+        user_id: Uuid,
+        max_participants: i32,
+        muted: bool,
+        deafened: bool,
+    ) -> Result<(u64, mpsc::UnboundedReceiver<Result<RoomEvent, Status>>), String> {
+        let mut rooms = self.lock();
+
+        let (count, rejoining) = rooms
+            .rooms
+            .get(&room_id)
+            .map_or((0, false), |room| (room.sessions.len() as i32, room.sessions.contains_key(&user_id)));
+        if !rejoining && count >= max_participants {
+            return Err("Room is full".to_string());
+        }
+
+        rooms.next_session_id += 1;
+        let session_id = rooms.next_session_id;
+        let now = Utc::now().naive_utc();
+        let room = rooms.rooms.entry(room_id).or_insert_with(|| ActiveRoom {
+            chat_id,
+            started_by: user_id,
+            started_at: now,
+            sessions: HashMap::new(),
+            attendance: Vec::new(),
+        });
+
+        // Прежний поток этого пользователя закрывается без события выхода, время входа сохраняется
+        let previous = room.sessions.remove(&user_id);
+        if let Some(previous) = &previous {
+            previous.close();
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let session = Session {
+            session_id,
+            muted,
+            deafened,
+            speaking: false,
+            joined_at: previous.map_or(now, |previous| previous.joined_at),
+            events: tx,
+            audio: None,
+        };
+        let participant = session.participant(user_id);
+        room.sessions.insert(user_id, session);
+
+        // Снимок уходит вошедшему раньше любых событий комнаты
+        let snapshot = room_event(RoomEventKind::RoomEventSnapshot, room_id, rooms.participants(room_id));
+        let kind = if rejoining {
+            RoomEventKind::RoomEventParticipantUpdated
+        } else {
+            RoomEventKind::RoomEventParticipantJoined
+        };
+        for (other_id, other) in rooms.rooms.get(&room_id).into_iter().flat_map(|room| room.sessions.iter()) {
+            let event = if *other_id == user_id {
+                snapshot.clone()
+            } else {
+                room_event(kind, room_id, vec![participant.clone()])
+            };
+            let _ = other.events.send(Ok(event));
+        }
+
+        Ok((session_id, rx))
+    }
+
+    fn leave(&self, room_id: Uuid, user_id: Uuid, session_id: Option<u64>) -> bool {
+        self.lock().leave(room_id, user_id, session_id)
+    }
+
+    // Исключённый из группы участник покидает все комнаты её чата, его поток JoinRoom завершается ошибкой
+    pub fn evict(&self, chat_id: Uuid, user_id: Uuid) {
+        let mut rooms = self.lock();
+        let room_ids: Vec<Uuid> = rooms
+            .rooms
+            .iter()
+            .filter(|(_, room)| room.chat_id == chat_id)
+            .filter_map(|(room_id, room)| {
+                let session = room.sessions.get(&user_id)?;
+                let _ = session.events.send(Err(Status::permission_denied("Removed from the chat")));
+                Some(*room_id)
+            })
+            .collect();
+        for room_id in room_ids {
+            rooms.leave(room_id, user_id, None);
+        }
+    }
+
+    fn update(&self, room_id: Uuid, user_id: Uuid, muted: bool, deafened: bool, speaking: bool) -> Option<Participant> {
+        let mut rooms = self.lock();
+        let session = rooms.rooms.get_mut(&room_id)?.sessions.get_mut(&user_id)?;
+        session.muted = muted;
+        session.deafened = deafened;
+        // Заглушённый участник не может говорить
+        session.speaking = speaking && !muted;
+        let participant = session.participant(user_id);
+
+        rooms.broadcast(room_id, room_event(RoomEventKind::RoomEventParticipantUpdated, room_id, vec![participant.clone()]));
+        Some(participant)
+    }
+
+    fn close_room(&self, room_id: Uuid) {
+        let mut rooms = self.lock();
+        rooms.broadcast(room_id, room_event(RoomEventKind::RoomEventRoomDeleted, room_id, Vec::new()));
+        rooms.end_room(room_id);
+    }
+
+    fn attach_audio(&self, room_id: Uuid, user_id: Uuid) -> Option<AudioQueue> {
+        let mut rooms = self.lock();
+        let session = rooms.rooms.get_mut(&room_id)?.sessions.get_mut(&user_id)?;
+        let queue = Arc::new(FrameQueue::new());
+        if let Some(previous) = session.audio.replace(queue.clone()) {
+            previous.close();
+        }
+        Some(queue)
+    }
+
+    fn detach_audio(&self, room_id: Uuid, user_id: Uuid, queue: &AudioQueue) {
+        let mut rooms = self.lock();
+        let session = rooms.rooms.get_mut(&room_id).and_then(|room| room.sessions.get_mut(&user_id));
+        if let Some(session) = session
+            && session.audio.as_ref().is_some_and(|current| Arc::ptr_eq(current, queue))
+        {
+            session.audio = None;
+        }
+        queue.close();
+    }
+
+    // Кадр получают все остальные участники, кроме отключивших звук
+    fn forward(&self, room_id: Uuid, from_user_id: Uuid, frame: RoomAudioFrame) {
+        let rooms = self.lock();
+        let Some(sessions) = rooms.rooms.get(&room_id).map(|room| &room.sessions) else {
+            return;
+        };
+        if sessions.get(&from_user_id).is_none_or(|session| session.muted) {
+            return;
+        }
+
+        for (user_id, session) in sessions {
+            if *user_id == from_user_id || session.deafened {
+                continue;
+            }
+            if let Some(queue) = &session.audio {
+                queue.push(frame.clone());
+            }
+        }
+    }
+}
+
+// Выход из комнаты при закрытии потока JoinRoom
+struct SessionGuard {
+    rooms: RoomHub,
+    room_id: Uuid,
+    user_id: Uuid,
+    session_id: u64,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.rooms.leave(self.room_id, self.user_id, Some(self.session_id));
+    }
+}
+
+struct RoomRecord {
+    id: Uuid,
+    chat_id: Uuid,
+    name: String,
+    max_participants: i32,
+    created_by: Option<Uuid>,
+    created_at: NaiveDateTime,
+}
+
+#[derive(Debug)]
+pub struct MyVoiceRoomService {
+    db: PgPool,
+    rooms: RoomHub,
+}
+
+impl MyVoiceRoomService {
+    pub fn new(db: PgPool, rooms: RoomHub) -> Self {
+        Self { db, rooms }
+    }
+
+    fn room_to_proto(&self, room: RoomRecord) -> Room {
+        Room {
+            id: room.id.to_string(),
+            chat_id: room.chat_id.to_string(),
+            name: room.name,
+            max_participants: room.max_participants,
+            created_by: room.created_by.map(|id| id.to_string()).unwrap_or_default(),
+            created_at: Some(timestamp_from_naive(room.created_at)),
+            participant_count: self.rooms.participant_count(room.id),
+        }
+    }
+
+    async fn ensure_group_member(&self, chat_id: Uuid, user_id: Uuid) -> Result<(), Status> {
+        let is_group = sqlx::query_scalar!(
+            r#"
+            SELECT c.is_group
+            FROM direct_chats c
+            JOIN direct_chats_members m ON m.chat_id = c.id AND m.user_id = $2
+            WHERE c.id = $1
+            "#,
+            chat_id,
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        match is_group {
+            None => Err(Status::permission_denied("Not a member of this chat")),
+            Some(false) => Err(Status::failed_precondition("Voice rooms are only available in group chats")),
+            Some(true) => Ok(()),
+        }
+    }
+
+    // Комната, доступная участнику её группового чата
+    async fn room_for_member(&self, room_id: Uuid, user_id: Uuid) -> Result<RoomRecord, Status> {
+        let room = sqlx::query_as!(
+            RoomRecord,
+            r#"
+            SELECT id, chat_id, name, max_participants, created_by, created_at
+            FROM voice_rooms
+            WHERE id = $1
+            "#,
+            room_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .ok_or_else(|| Status::not_found("Room not found"))?;
+
+        self.ensure_group_member(room.chat_id, user_id).await?;
+        Ok(room)
+    }
+}
+
+#[tonic::async_trait]
+impl VoiceRoomService for MyVoiceRoomService {
+    type JoinRoomStream =
+        Pin<Box<dyn Stream<Item = Result<RoomEvent, Status>> + Send + Sync + 'static>>;
+    type RoomAudioStream =
+        Pin<Box<dyn Stream<Item = Result<RoomAudioFrame, Status>> + Send + Sync + 'static>>;
+
+    async fn create_room(
+        &self,
+        request: Request<CreateRoomRequest>,
+    ) -> Result<Response<CreateRoomResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let chat_id = Uuid::parse_str(&req.chat_id)
+            .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?;
+
+        let name = req.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_ROOM_NAME_LEN {
+            return Err(Status::invalid_argument(format!("Room name must be 1-{} characters", MAX_ROOM_NAME_LEN)));
+        }
+        let max_participants = match req.max_participants {
+            0 => DEFAULT_MAX_PARTICIPANTS,
+            n if (2..=MAX_PARTICIPANTS).contains(&n) => n,
+            _ => return Err(Status::invalid_argument(format!("max_participants must be between 2 and {}", MAX_PARTICIPANTS))),
+        };
+
+        self.ensure_group_member(chat_id, user_id).await?;
+
+        let room_count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM voice_rooms WHERE chat_id = $1"#,
+            chat_id
+        )
+        .fetch_one(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+        if room_count >= MAX_ROOMS_PER_CHAT {
+            return Err(Status::resource_exhausted(format!("A chat can have at most {} voice rooms", MAX_ROOMS_PER_CHAT)));
+        }
+
+        let room = sqlx::query_as!(
+            RoomRecord,
+            r#"
+            INSERT INTO voice_rooms (chat_id, name, max_participants, created_by, created_at)
+            VALUES ($1, $2, $3, $4, NOW() AT TIME ZONE 'UTC')
+            ON CONFLICT (chat_id, name) DO NOTHING
+            RETURNING id, chat_id, name, max_participants, created_by, created_at
+            "#,
+            chat_id,
+            name,
+            max_participants,
+            user_id
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?
+        .ok_or_else(|| Status::already_exists("Room with this name already exists"))?;
+
+        Ok(Response::new(CreateRoomResponse {
+            room: Some(self.room_to_proto(room)),
+        }))
+    }
+
+    async fn list_rooms(
+        &self,
+        request: Request<ListRoomsRequest>,
+    ) -> Result<Response<ListRoomsResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let chat_id = Uuid::parse_str(&req.chat_id)
+            .map_err(|_| Status::invalid_argument("Invalid chat_id UUID"))?;
+
+        self.ensure_group_member(chat_id, user_id).await?;
+
+        let rooms = sqlx::query_as!(
+            RoomRecord,
+            r#"
+            SELECT id, chat_id, name, max_participants, created_by, created_at
+            FROM voice_rooms
+            WHERE chat_id = $1
+            ORDER BY created_at, name
+            "#,
+            chat_id
+        )
+        .fetch_all(&self.db)
+        .await
+        .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        Ok(Response::new(ListRoomsResponse {
+            rooms: rooms.into_iter().map(|room| self.room_to_proto(room)).collect(),
+        }))
+    }
+
+    async fn delete_room(
+        &self,
+        request: Request<DeleteRoomRequest>,
+    ) -> Result<Response<DeleteRoomResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let room_id = Uuid::parse_str(&req.room_id)
+            .map_err(|_| Status::invalid_argument("Invalid room_id UUID"))?;
+
+        let room = self.room_for_member(room_id, user_id).await?;
+        if room.created_by != Some(user_id) {
+            return Err(Status::permission_denied("Only the room creator can delete it"));
+        }
+
+        let result = sqlx::query!("DELETE FROM voice_rooms WHERE id = $1", room_id)
+            .execute(&self.db)
+            .await
+            .map_err(|e| Status::internal(format!("DB error: {}", e)))?;
+
+        self.rooms.close_room(room_id);
+
+        Ok(Response::new(DeleteRoomResponse {
+            deleted: result.rows_affected() > 0,
+        }))
+    }
+
+    async fn join_room(
+        &self,
+        request: Request<JoinRoomRequest>,
+    ) -> Result<Response<Self::JoinRoomStream>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let room_id = Uuid::parse_str(&req.room_id)
+            .map_err(|_| Status::invalid_argument("Invalid room_id UUID"))?;
+
+        let room = self.room_for_member(room_id, user_id).await?;
+        let (session_id, mut rx) = self
+            .rooms
+            .join(room_id, room.chat_id, user_id, room.max_participants, req.muted, req.deafened)
+            .map_err(Status::resource_exhausted)?;
+
+        let guard = SessionGuard {
+            rooms: self.rooms.clone(),
+            room_id,
+            user_id,
+            session_id,
+        };
+        let output_stream = async_stream::stream! {
+            let _guard = guard;
+            while let Some(event) = rx.recv().await {
+                yield event;
+            }
+        };
+
+        Ok(Response::new(Box::pin(output_stream)))
+    }
+
+    async fn leave_room(
+        &self,
+        request: Request<LeaveRoomRequest>,
+    ) -> Result<Response<LeaveRoomResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let room_id = Uuid::parse_str(&req.room_id)
+            .map_err(|_| Status::invalid_argument("Invalid room_id UUID"))?;
+
+        Ok(Response::new(LeaveRoomResponse {
+            left: self.rooms.leave(room_id, user_id, None),
+        }))
+    }
+
+    async fn list_participants(
+        &self,
+        request: Request<ListParticipantsRequest>,
+    ) -> Result<Response<ListParticipantsResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let room_id = Uuid::parse_str(&req.room_id)
+            .map_err(|_| Status::invalid_argument("Invalid room_id UUID"))?;
+
+        self.room_for_member(room_id, user_id).await?;
+
+        Ok(Response::new(ListParticipantsResponse {
+            participants: self.rooms.participants(room_id),
+        }))
+    }
+
+    async fn update_participant_state(
+        &self,
+        request: Request<UpdateParticipantStateRequest>,
+    ) -> Result<Response<UpdateParticipantStateResponse>, Status> {
+        let req = request.into_inner();
+
+        let user_id = Uuid::parse_str(&req.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+        let room_id = Uuid::parse_str(&req.room_id)
+            .map_err(|_| Status::invalid_argument("Invalid room_id UUID"))?;
+
+        let participant = self
+            .rooms
+            .update(room_id, user_id, req.muted, req.deafened, req.speaking)
+            .ok_or_else(|| Status::not_found("Not in this room"))?;
+
+        Ok(Response::new(UpdateParticipantStateResponse {
+            participant: Some(participant),
+        }))
+    }
+
+    async fn room_audio(
+        &self,
+        request: Request<Streaming<RoomAudioFrame>>,
+    ) -> Result<Response<Self::RoomAudioStream>, Status> {
+        let mut inbound = request.into_inner();
+
+        let first = inbound
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("Empty audio stream"))?;
+        let room_id = Uuid::parse_str(&first.room_id)
+            .map_err(|_| Status::invalid_argument("Invalid room_id UUID"))?;
+        let user_id = Uuid::parse_str(&first.user_id)
+            .map_err(|_| Status::invalid_argument("Invalid user_id UUID"))?;
+
+        let queue = self
+            .rooms
+            .attach_audio(room_id, user_id)
+            .ok_or_else(|| Status::failed_precondition("Join the room before sending audio"))?;
+
+        let rooms = self.rooms.clone();
+        let own_queue = queue.clone();
+        tokio::spawn(async move {
+            let mut sequence = SequenceFilter::default();
+            while let Ok(Some(frame)) = inbound.message().await {
+                // Очередь закрыта: участник вышел или переподключил аудио
+                if own_queue.is_closed() {
+                    break;
+                }
+                if frame.payload.is_empty() || frame.payload.len() > MAX_FRAME_PAYLOAD || !sequence.accept(frame.sequence) {
+                    continue;
+                }
+                rooms.forward(room_id, user_id, RoomAudioFrame {
+                    room_id: room_id.to_string(),
+                    user_id: String::new(),
+                    sequence: frame.sequence,
+                    timestamp: frame.timestamp,
+                    payload: frame.payload,
+                    from_user_id: user_id.to_string(),
+                });
+            }
+            rooms.detach_audio(room_id, user_id, &own_queue);
+        });
+
+        let output_stream = async_stream::stream! {
+            while let Some(frame) = queue.pop().await {
+                yield Ok(frame);
+            }
+        };
+
+        Ok(Response::new(Box::pin(output_stream)))
+    }
+}
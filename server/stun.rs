@@ -0,0 +1,496 @@
+// Кодек сообщений STUN (RFC 5389) с атрибутами и ChannelData TURN (RFC 5766).
+// Используется встроенным STUN/TURN-сервером и тестовыми клиентами.
+// FINGERPRINT не формируется и не проверяется; атрибуты после MESSAGE-INTEGRITY игнорируются.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+use sha1::Sha1;
+
+type HmacSha1 = Hmac<Sha1>;
+
+pub const MAGIC_COOKIE: u32 = 0x2112_A442;
+pub const HEADER_LEN: usize = 20;
+const INTEGRITY_LEN: usize = 20;
+
+pub const METHOD_BINDING: u16 = 0x001;
+pub const METHOD_ALLOCATE: u16 = 0x003;
+pub const METHOD_REFRESH: u16 = 0x004;
+pub const METHOD_SEND: u16 = 0x006;
+pub const METHOD_DATA: u16 = 0x007;
+pub const METHOD_CREATE_PERMISSION: u16 = 0x008;
+pub const METHOD_CHANNEL_BIND: u16 = 0x009;
+
+pub const ATTR_USERNAME: u16 = 0x0006;
+pub const ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+pub const ATTR_ERROR_CODE: u16 = 0x0009;
+pub const ATTR_UNKNOWN_ATTRIBUTES: u16 = 0x000A;
+pub const ATTR_CHANNEL_NUMBER: u16 = 0x000C;
+pub const ATTR_LIFETIME: u16 = 0x000D;
+pub const ATTR_XOR_PEER_ADDRESS: u16 = 0x0012;
+pub const ATTR_DATA: u16 = 0x0013;
+pub const ATTR_REALM: u16 = 0x0014;
+pub const ATTR_NONCE: u16 = 0x0015;
+pub const ATTR_XOR_RELAYED_ADDRESS: u16 = 0x0016;
+pub const ATTR_REQUESTED_TRANSPORT: u16 = 0x0019;
+pub const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+pub const ATTR_SOFTWARE: u16 = 0x8022;
+pub const ATTR_FINGERPRINT: u16 = 0x8028;
+
+// Номер протокола UDP в REQUESTED-TRANSPORT
+pub const TRANSPORT_UDP: u8 = 17;
+
+// Диапазон номеров каналов ChannelBind
+pub const MIN_CHANNEL: u16 = 0x4000;
+pub const MAX_CHANNEL: u16 = 0x7FFE;
+
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Request = 0,
+    Indication = 1,
+    Success = 2,
+    Error = 3,
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub method: u16,
+    pub class: Class,
+    pub transaction_id: [u8; 12],
+    pub attributes: Vec<(u16, Vec<u8>)>,
+    // Смещение MESSAGE-INTEGRITY в разобранном сообщении
+    integrity_offset: Option<usize>,
+}
+
+impl Message {
+    pub fn new(method: u16, class: Class, transaction_id: [u8; 12]) -> Self {
+        Self { method, class, transaction_id, attributes: Vec::new(), integrity_offset: None }
+    }
+
+    // Ответ на запрос: тот же метод и идентификатор транзакции
+    pub fn response(&self, class: Class) -> Self {
+        Self::new(self.method, class, self.transaction_id)
+    }
+
+    pub fn error_response(&self, code: u16, reason: &str) -> Self {
+        let mut response = self.response(Class::Error);
+        response.add(ATTR_ERROR_CODE, encode_error_code(code, reason));
+        response
+    }
+
+    pub fn add(&mut self, attribute: u16, value: Vec<u8>) {
+        self.attributes.push((attribute, value));
+    }
+
+    pub fn attribute(&self, attribute: u16) -> Option<&[u8]> {
+        self.attributes.iter().find(|(ty, _)| *ty == attribute).map(|(_, value)| value.as_slice())
+    }
+
+    pub fn attributes_of(&self, attribute: u16) -> impl Iterator<Item = &[u8]> {
+        self.attributes.iter().filter(move |(ty, _)| *ty == attribute).map(|(_, value)| value.as_slice())
+    }
+
+    pub fn string_attribute(&self, attribute: u16) -> Option<&str> {
+        self.attribute(attribute).and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    pub fn u32_attribute(&self, attribute: u16) -> Option<u32> {
+        let value: [u8; 4] = self.attribute(attribute)?.try_into().ok()?;
+        Some(u32::from_be_bytes(value))
+    }
+
+    pub fn xor_address(&self, attribute: u16) -> Option<SocketAddr> {
+        decode_xor_address(self.attribute(attribute)?, &self.transaction_id)
+    }
+
+    pub fn error_code(&self) -> Option<u16> {
+        let value = self.attribute(ATTR_ERROR_CODE)?;
+        if value.len() < 4 {
+            return None;
+        }
+        Some(u16::from(value[2] & 0x07) * 100 + u16::from(value[3]))
+    }
+
+    pub fn has_integrity(&self) -> bool {
+        self.integrity_offset.is_some()
+    }
+
+    // Проверка MESSAGE-INTEGRITY разобранного сообщения; raw - исходные байты
+    pub fn verify_integrity(&self, raw: &[u8], key: &[u8]) -> bool {
+        let (Some(offset), Some(expected)) = (self.integrity_offset, self.attribute(ATTR_MESSAGE_INTEGRITY)) else {
+            return false;
+        };
+        if expected.len() != INTEGRITY_LEN || raw.len() < offset {
+            return false;
+        }
+
+        // Длина в заголовке считается так, будто сообщение заканчивается на MESSAGE-INTEGRITY
+        let mut signed = raw[..offset].to_vec();
+        let length = (offset + 4 + INTEGRITY_LEN - HEADER_LEN) as u16;
+        signed[2..4].copy_from_slice(&length.to_be_bytes());
+
+        let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(&signed);
+        mac.verify_slice(expected).is_ok()
+    }
+
+    // Сериализация; с ключом в конец добавляется MESSAGE-INTEGRITY
+    pub fn encode(&self, integrity_key: Option<&[u8]>) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN + 64);
+        buf.extend_from_slice(&message_type(self.method, self.class).to_be_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        buf.extend_from_slice(&self.transaction_id);
+
+        for (attribute, value) in &self.attributes {
+            if *attribute != ATTR_MESSAGE_INTEGRITY && *attribute != ATTR_FINGERPRINT {
+                push_attribute(&mut buf, *attribute, value);
+            }
+        }
+
+        if let Some(key) = integrity_key {
+            set_length(&mut buf, 4 + INTEGRITY_LEN);
+            let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(&buf);
+            let integrity = mac.finalize().into_bytes();
+            push_attribute(&mut buf, ATTR_MESSAGE_INTEGRITY, &integrity);
+        }
+        set_length(&mut buf, 0);
+        buf
+    }
+}
+
+fn message_type(method: u16, class: Class) -> u16 {
+    let class = class as u16;
+    (method & 0x000F) | ((method & 0x0070) << 1) | ((method & 0x0F80) << 2) | ((class & 1) << 4) | ((class & 2) << 7)
+}
+
+// Длина в заголовке: атрибуты в буфере и ещё extra байт
+fn set_length(buf: &mut [u8], extra: usize) {
+    let length = buf.len() - HEADER_LEN + extra;
+    buf[2..4].copy_from_slice(&(length as u16).to_be_bytes());
+}
+
+fn push_attribute(buf: &mut Vec<u8>, attribute: u16, value: &[u8]) {
+    buf.extend_from_slice(&attribute.to_be_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value);
+    buf.resize(buf.len() + padding(value.len()), 0);
+}
+
+fn padding(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+// Первые два бита сообщения STUN нулевые, у ChannelData - 01
+pub fn is_stun(buf: &[u8]) -> bool {
+    buf.len() >= HEADER_LEN && buf[0] & 0xC0 == 0 && buf[4..8] == MAGIC_COOKIE.to_be_bytes()
+}
+
+pub fn decode(buf: &[u8]) -> Option<Message> {
+    if !is_stun(buf) {
+        return None;
+    }
+    let message_type = u16::from_be_bytes([buf[0], buf[1]]);
+    let length = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+    if !length.is_multiple_of(4) || HEADER_LEN + length != buf.len() {
+        return None;
+    }
+
+    let method = (message_type & 0x000F) | ((message_type >> 1) & 0x0070) | ((message_type >> 2) & 0x0F80);
+    let class = match ((message_type >> 4) & 1) | ((message_type >> 7) & 2) {
+        0 => Class::Request,
+        1 => Class::Indication,
+        2 => Class::Success,
+        _ => Class::Error,
+    };
+    let mut message = Message::new(method, class, buf[8..HEADER_LEN].try_into().ok()?);
+
+    let mut offset = HEADER_LEN;
+    while offset < buf.len() {
+        if offset + 4 > buf.len() {
+            return None;
+        }
+        let attribute = u16::from_be_bytes([buf[offset], buf[offset + 1]]);
+        let len = u16::from_be_bytes([buf[offset + 2], buf[offset + 3]]) as usize;
+        let start = offset + 4;
+        if start + len > buf.len() {
+            return None;
+        }
+        if message.integrity_offset.is_none() {
+            if attribute == ATTR_MESSAGE_INTEGRITY {
+                message.integrity_offset = Some(offset);
+            }
+            message.add(attribute, buf[start..start + len].to_vec());
+        }
+        offset = start + len + padding(len);
+    }
+    if offset != buf.len() {
+        return None;
+    }
+    Some(message)
+}
+
+pub fn encode_error_code(code: u16, reason: &str) -> Vec<u8> {
+    let mut value = vec![0, 0, (code / 100) as u8, (code % 100) as u8];
+    value.extend_from_slice(reason.as_bytes());
+    value
+}
+
+pub fn encode_u32(value: u32) -> Vec<u8> {
+    value.to_be_bytes().to_vec()
+}
+
+pub fn encode_xor_address(addr: SocketAddr, transaction_id: &[u8; 12]) -> Vec<u8> {
+    let mut value = vec![0];
+    let port = addr.port() ^ (MAGIC_COOKIE >> 16) as u16;
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            value.push(FAMILY_IPV4);
+            value.extend_from_slice(&port.to_be_bytes());
+            value.extend_from_slice(&(u32::from(ip) ^ MAGIC_COOKIE).to_be_bytes());
+        }
+        IpAddr::V6(ip) => {
+            value.push(FAMILY_IPV6);
+            value.extend_from_slice(&port.to_be_bytes());
+            let mask = xor_mask(transaction_id);
+            value.extend(ip.octets().iter().zip(mask).map(|(byte, mask)| byte ^ mask));
+        }
+    }
+    value
+}
+
+pub fn decode_xor_address(value: &[u8], transaction_id: &[u8; 12]) -> Option<SocketAddr> {
+    if value.len() < 4 {
+        return None;
+    }
+    let port = u16::from_be_bytes([value[2], value[3]]) ^ (MAGIC_COOKIE >> 16) as u16;
+    let ip = match (value[1], value.len()) {
+        (FAMILY_IPV4, 8) => {
+            let ip = u32::from_be_bytes(value[4..8].try_into().ok()?) ^ MAGIC_COOKIE;
+            IpAddr::V4(Ipv4Addr::from(ip))
+        }
+        (FAMILY_IPV6, 20) => {
+            let mask = xor_mask(transaction_id);
+            let mut octets = [0u8; 16];
+            for (i, octet) in octets.iter_mut().enumerate() {
+                *octet = value[4 + i] ^ mask[i];
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+fn xor_mask(transaction_id: &[u8; 12]) -> [u8; 16] {
+    let mut mask = [0u8; 16];
+    mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    mask[4..].copy_from_slice(transaction_id);
+    mask
+}
+
+// Ключ долговременных учётных данных: MD5(username:realm:password)
+pub fn long_term_key(username: &str, realm: &str, password: &str) -> [u8; 16] {
+    Md5::digest(format!("{}:{}:{}", username, realm, password).as_bytes()).into()
+}
+
+pub fn is_channel_data(buf: &[u8]) -> bool {
+    buf.len() >= 4 && buf[0] & 0xC0 == 0x40
+}
+
+// По UDP сообщение ChannelData можно не дополнять до кратной 4 длины
+pub fn encode_channel_data(channel: u16, data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4 + data.len());
+    buf.extend_from_slice(&channel.to_be_bytes());
+    buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    buf.extend_from_slice(data);
+    buf
+}
+
+pub fn decode_channel_data(buf: &[u8]) -> Option<(u16, &[u8])> {
+    if !is_channel_data(buf) {
+        return None;
+    }
+    let channel = u16::from_be_bytes([buf[0], buf[1]]);
+    let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+    buf.get(4..4 + len).map(|data| (channel, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Тестовые векторы RFC 5769 (без FINGERPRINT: он не проверяется)
+    const SHORT_TERM_PASSWORD: &[u8] = b"VOkJxbRl1RmTxUk/WvJxBt";
+    const SAMPLE_REQUEST: &str = "000100582112a442b7e7a701bc34d686fa87dfae\
+        802200105354554e207465737420636c69656e74\
+        002400046e0001ff80290008932ff9b151263b36\
+        000600096576746a3a68367659202020\
+        000800149aeaa70cbfd8cb56781ef2b5b2d3f249c1b571a2\
+        80280004e57a3bcf";
+    const SAMPLE_IPV4_RESPONSE: &str = "0101003c2112a442b7e7a701bc34d686fa87dfae\
+        8022000b7465737420766563746f7220\
+        002000080001a147e112a643\
+        000800142b91f599fd9e90c38c7489f92af9ba53f06be7d7\
+        80280004c07d4c96";
+    const SAMPLE_IPV6_RESPONSE: &str = "010100482112a442b7e7a701bc34d686fa87dfae\
+        8022000b7465737420766563746f7220\
+        002000140002a1470113a9faa5d3f179bc25f4b5bed2b9d9\
+        00080014a382954e4be67bf11784c97c8292c275bfe3ed41\
+        80280004c8fb0b4c";
+    const SAMPLE_LONG_TERM_REQUEST: &str = "000100602112a44278ad3433c6ad72c029da412e\
+        00060012e3839ee38388e383aae38383e382afe382b90000\
+        0015001c662f2f3439396b39353464364f4c33346f4c39465354767936347341\
+        0014000b6578616d706c652e6f726700\
+        00080014f67024656dd64a3e02b8e0712e85c9a28ca89666";
+
+    fn vector(hex_str: &str) -> Vec<u8> {
+        hex::decode(hex_str.split_whitespace().collect::<String>()).unwrap()
+    }
+
+    #[test]
+    fn rfc5769_sample_request() {
+        let raw = vector(SAMPLE_REQUEST);
+        let message = decode(&raw).unwrap();
+        assert_eq!(message.method, METHOD_BINDING);
+        assert_eq!(message.class, Class::Request);
+        // Значение USERNAME без дополнения пробелами
+        assert_eq!(message.string_attribute(ATTR_USERNAME), Some("evtj:h6vY"));
+        assert!(message.verify_integrity(&raw, SHORT_TERM_PASSWORD));
+        assert!(!message.verify_integrity(&raw, b"wrong password"));
+    }
+
+    #[test]
+    fn rfc5769_sample_responses() {
+        let raw = vector(SAMPLE_IPV4_RESPONSE);
+        let message = decode(&raw).unwrap();
+        assert_eq!(message.class, Class::Success);
+        assert_eq!(message.xor_address(ATTR_XOR_MAPPED_ADDRESS), Some("192.0.2.1:32853".parse().unwrap()));
+        assert!(message.verify_integrity(&raw, SHORT_TERM_PASSWORD));
+
+        let raw = vector(SAMPLE_IPV6_RESPONSE);
+        let message = decode(&raw).unwrap();
+        assert_eq!(
+            message.xor_address(ATTR_XOR_MAPPED_ADDRESS),
+            Some("[2001:db8:1234:5678:11:2233:4455:6677]:32853".parse().unwrap())
+        );
+        assert!(message.verify_integrity(&raw, SHORT_TERM_PASSWORD));
+    }
+
+    #[test]
+    fn rfc5769_long_term_request() {
+        let raw = vector(SAMPLE_LONG_TERM_REQUEST);
+        let message = decode(&raw).unwrap();
+        let username = message.string_attribute(ATTR_USERNAME).unwrap();
+        let realm = message.string_attribute(ATTR_REALM).unwrap();
+        assert_eq!(username, "\u{30DE}\u{30C8}\u{30EA}\u{30C3}\u{30AF}\u{30B9}");
+        assert_eq!(realm, "example.org");
+        assert_eq!(message.string_attribute(ATTR_NONCE), Some("f//499k954d6OL34oL9FSTvy64sA"));
+        // Пароль после SASLprep
+        let key = long_term_key(username, realm, "TheMatrIX");
+        assert!(message.verify_integrity(&raw, &key));
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let key = long_term_key("user", "realm", "password");
+        for class in [Class::Request, Class::Indication, Class::Success, Class::Error] {
+            for method in [METHOD_BINDING, METHOD_ALLOCATE, METHOD_CHANNEL_BIND, 0x0FFF] {
+                let mut message = Message::new(method, class, [7; 12]);
+                message.add(ATTR_USERNAME, b"user".to_vec());
+                // Длина не кратна 4: проверка дополнения
+                message.add(ATTR_DATA, b"hello".to_vec());
+                message.add(ATTR_LIFETIME, encode_u32(600));
+                let raw = message.encode(Some(&key));
+
+                let decoded = decode(&raw).unwrap();
+                assert_eq!(decoded.method, method);
+                assert_eq!(decoded.class, class);
+                assert_eq!(decoded.transaction_id, [7; 12]);
+                assert_eq!(decoded.attribute(ATTR_DATA), Some(&b"hello"[..]));
+                assert_eq!(decoded.u32_attribute(ATTR_LIFETIME), Some(600));
+                assert!(decoded.verify_integrity(&raw, &key));
+            }
+        }
+    }
+
+    #[test]
+    fn integrity_detects_tampering() {
+        let key = long_term_key("user", "realm", "password");
+        let mut message = Message::new(METHOD_ALLOCATE, Class::Request, [1; 12]);
+        message.add(ATTR_LIFETIME, encode_u32(600));
+        let mut raw = message.encode(Some(&key));
+        raw[HEADER_LEN + 7] ^= 1;
+        assert!(!decode(&raw).unwrap().verify_integrity(&raw, &key));
+
+        // Без MESSAGE-INTEGRITY проверка не проходит
+        let raw = message.encode(None);
+        let decoded = decode(&raw).unwrap();
+        assert!(!decoded.has_integrity());
+        assert!(!decoded.verify_integrity(&raw, &key));
+    }
+
+    #[test]
+    fn error_response_code() {
+        let request = Message::new(METHOD_ALLOCATE, Class::Request, [2; 12]);
+        let raw = request.error_response(438, "Stale Nonce").encode(None);
+        let decoded = decode(&raw).unwrap();
+        assert_eq!(decoded.class, Class::Error);
+        assert_eq!(decoded.error_code(), Some(438));
+    }
+
+    #[test]
+    fn decode_rejects_malformed() {
+        let raw = Message::new(METHOD_BINDING, Class::Request, [3; 12]).encode(None);
+        assert!(decode(&raw[..HEADER_LEN - 1]).is_none());
+
+        let mut bad_cookie = raw.clone();
+        bad_cookie[4] ^= 0xFF;
+        assert!(decode(&bad_cookie).is_none());
+
+        // Длина атрибута выходит за сообщение
+        let mut message = Message::new(METHOD_BINDING, Class::Request, [3; 12]);
+        message.add(ATTR_DATA, vec![0; 8]);
+        let mut truncated = message.encode(None);
+        truncated[HEADER_LEN + 3] = 64;
+        assert!(decode(&truncated).is_none());
+    }
+
+    #[test]
+    fn xor_address_round_trip() {
+        let transaction_id = [0x5A; 12];
+        for addr in ["203.0.113.7:3478", "[2001:db8::1]:49152", "0.0.0.0:1", "[::ffff:192.0.2.1]:65535"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let value = encode_xor_address(addr, &transaction_id);
+            assert_eq!(decode_xor_address(&value, &transaction_id), Some(addr));
+        }
+
+        let value = encode_xor_address("[2001:db8::1]:1".parse().unwrap(), &transaction_id);
+        // IPv6 зависит от идентификатора транзакции
+        assert_ne!(decode_xor_address(&value, &[0; 12]), Some("[2001:db8::1]:1".parse().unwrap()));
+        assert_eq!(decode_xor_address(&value[..8], &transaction_id), None);
+    }
+
+    #[test]
+    fn channel_data_round_trip() {
+        let packet = encode_channel_data(MIN_CHANNEL, b"audio");
+        assert!(is_channel_data(&packet));
+        assert!(!is_stun(&packet));
+        assert_eq!(decode_channel_data(&packet), Some((MIN_CHANNEL, &b"audio"[..])));
+
+        // Дополнение до 4 байт после данных допустимо
+        let mut padded = packet.clone();
+        padded.extend_from_slice(&[0, 0, 0]);
+        assert_eq!(decode_channel_data(&padded), Some((MIN_CHANNEL, &b"audio"[..])));
+
+        assert_eq!(decode_channel_data(&packet[..packet.len() - 1]), None);
+        assert_eq!(decode_channel_data(&[0x80, 0, 0, 0]), None);
+        let stun = Message::new(METHOD_BINDING, Class::Request, [0; 12]).encode(None);
+        assert_eq!(decode_channel_data(&stun), None);
+    }
+}